@@ -1,11 +1,47 @@
 use log::debug;
+use parking_lot::RwLock;
 use serde::Deserialize;
 
+use crate::{messages::dto, utils::glob_matches};
+
+/// How much a client's session is worth keeping when the server is overloaded. Sessions of the
+/// lowest class are shed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionPriority {
+    /// Clients without a known API key.
+    Anonymous,
+    Member,
+    Owner,
+}
+
+impl SessionPriority {
+    /// Known clients are members unless their key says otherwise.
+    fn member() -> Self {
+        Self::Member
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 #[serde(default)]
 pub struct ApiPermissions {
     pub connect: bool,
     pub host: bool,
+
+    /// Allows joining rooms as an invisible observer, for bots and moderation tools. Unlike the
+    /// other permissions, this is never granted by the access policy.
+    pub observe: bool,
+
+    /// Allows inspecting and moderating the whole server through the admin messages. Like
+    /// observing, this is never granted by the access policy.
+    pub admin: bool,
+
+    /// Glob patterns for the names of rooms that may be created or joined. If empty, all rooms
+    /// are allowed.
+    pub rooms: Vec<String>,
+
+    #[serde(default = "SessionPriority::member")]
+    pub priority: SessionPriority,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
@@ -28,6 +64,10 @@ impl ApiPermissions {
         Self {
             connect: false,
             host: false,
+            observe: false,
+            admin: false,
+            rooms: Vec::new(),
+            priority: SessionPriority::Anonymous,
         }
     }
 
@@ -35,6 +75,10 @@ impl ApiPermissions {
         Self {
             connect: true,
             host: false,
+            observe: false,
+            admin: false,
+            rooms: Vec::new(),
+            priority: SessionPriority::Anonymous,
         }
     }
 
@@ -42,13 +86,37 @@ impl ApiPermissions {
         Self {
             connect: false,
             host: true,
+            observe: false,
+            admin: false,
+            rooms: Vec::new(),
+            priority: SessionPriority::Anonymous,
         }
     }
 
+    /// What a key with `connect` and `host` grants, which makes its clients members.
     pub const fn all() -> Self {
         Self {
             connect: true,
             host: true,
+            observe: false,
+            admin: false,
+            rooms: Vec::new(),
+            priority: SessionPriority::Member,
+        }
+    }
+
+    pub fn allows_room(&self, name: &str) -> bool {
+        self.rooms.is_empty() || self.rooms.iter().any(|pattern| glob_matches(pattern, name))
+    }
+}
+
+impl From<ApiPermissions> for dto::ConnectionPermissionsV1 {
+    fn from(value: ApiPermissions) -> Self {
+        Self {
+            host: value.host,
+            observe: value.observe,
+            rooms: value.rooms,
+            admin: value.admin,
         }
     }
 }
@@ -86,18 +154,35 @@ pub struct ApiAccessConfig {
 }
 
 pub struct ApiAccessManager {
-    config: ApiAccessConfig,
+    config: RwLock<ApiAccessConfig>,
 }
 
 impl ApiAccessManager {
     pub fn new(config: ApiAccessConfig) -> Self {
-        Self { config }
+        Self {
+            config: RwLock::new(config),
+        }
+    }
+
+    /// Swaps in new API keys and policies. Connections that are already logged in keep their
+    /// permissions.
+    pub fn replace_config(&self, config: ApiAccessConfig) {
+        *self.config.write() = config;
+    }
+
+    pub fn is_known_key(&self, key: &str) -> bool {
+        self.config.read().api_keys.iter().any(|k| k.key == key)
     }
 
     pub fn get_permissions(&self, key: Option<&str>) -> ApiPermissions {
+        let config = self.config.read();
         let default_perms = ApiPermissions {
-            connect: !self.config.api_policy.restrict_connect,
-            host: !self.config.api_policy.restrict_host,
+            connect: !config.api_policy.restrict_connect,
+            host: !config.api_policy.restrict_host,
+            observe: false,
+            admin: false,
+            rooms: Vec::new(),
+            priority: SessionPriority::Anonymous,
         };
         debug!("Default permissions are {default_perms:?}");
 
@@ -106,14 +191,18 @@ impl ApiAccessManager {
             return default_perms;
         };
 
-        let Some(key_config) = self.config.api_keys.iter().find(|k| k.key == key) else {
+        let Some(key_config) = config.api_keys.iter().find(|k| k.key == key) else {
             debug!("Invalid API key provided; Using default permissions");
             return default_perms;
         };
 
         let permissions = ApiPermissions {
-            connect: !self.config.api_policy.restrict_connect || key_config.permissions.connect,
-            host: !self.config.api_policy.restrict_host || key_config.permissions.host,
+            connect: !config.api_policy.restrict_connect || key_config.permissions.connect,
+            host: !config.api_policy.restrict_host || key_config.permissions.host,
+            observe: key_config.permissions.observe,
+            admin: key_config.permissions.admin,
+            rooms: key_config.permissions.rooms.clone(),
+            priority: key_config.permissions.priority,
         };
         debug!("Valid API key provided; Permissions are {permissions:?}");
         permissions
@@ -186,4 +275,113 @@ mod tests {
         // then
         assert_eq!(permissions, ApiPermissions::all());
     }
+
+    #[test]
+    fn should_restrict_rooms_to_key_scope() {
+        // given
+        let config = ApiAccessConfig {
+            api_policy: ApiAccessPolicy {
+                restrict_host: true,
+                restrict_connect: true,
+            },
+            api_keys: vec![ApiKey {
+                key: "AAAAA".to_string(),
+                permissions: ApiPermissions {
+                    rooms: vec!["community-a/*".to_string()],
+                    ..ApiPermissions::all()
+                },
+            }],
+        };
+        let manager = ApiAccessManager::new(config);
+
+        // when
+        let permissions = manager.get_permissions(Some("AAAAA"));
+
+        // then
+        assert!(permissions.allows_room("community-a/movie night"));
+        assert!(!permissions.allows_room("community-b/movie night"));
+    }
+
+    #[test]
+    fn should_only_grant_observing_through_key() {
+        // given
+        let config = ApiAccessConfig {
+            api_policy: ApiAccessPolicy {
+                restrict_host: false,
+                restrict_connect: false,
+            },
+            api_keys: vec![ApiKey {
+                key: "AAAAA".to_string(),
+                permissions: ApiPermissions {
+                    observe: true,
+                    ..ApiPermissions::connect()
+                },
+            }],
+        };
+        let manager = ApiAccessManager::new(config);
+
+        // when
+        let without_key = manager.get_permissions(None);
+        let with_key = manager.get_permissions(Some("AAAAA"));
+
+        // then
+        assert!(!without_key.observe);
+        assert!(with_key.observe);
+    }
+
+    #[test]
+    fn should_treat_known_keys_as_members_by_default() {
+        // given
+        let config: ApiAccessConfig = toml::from_str(
+            r#"
+            [[api_keys]]
+            key = "AAAAA"
+            connect = true
+
+            [[api_keys]]
+            key = "BBBBB"
+            connect = true
+            priority = "owner"
+            "#,
+        )
+        .unwrap();
+        let manager = ApiAccessManager::new(config);
+
+        // when
+        let anonymous = manager.get_permissions(None);
+        let member = manager.get_permissions(Some("AAAAA"));
+        let owner = manager.get_permissions(Some("BBBBB"));
+
+        // then
+        assert_eq!(anonymous.priority, SessionPriority::Anonymous);
+        assert_eq!(member.priority, SessionPriority::Member);
+        assert_eq!(owner.priority, SessionPriority::Owner);
+    }
+
+    #[test]
+    fn should_use_replaced_keys() {
+        // given
+        let old_key = ApiKey {
+            key: "AAAAA".to_string(),
+            permissions: ApiPermissions::all(),
+        };
+        let new_key = ApiKey {
+            key: "BBBBB".to_string(),
+            ..old_key.clone()
+        };
+        let manager = ApiAccessManager::new(ApiAccessConfig {
+            api_policy: ApiAccessPolicy::default(),
+            api_keys: vec![old_key],
+        });
+
+        // when
+        manager.replace_config(ApiAccessConfig {
+            api_policy: ApiAccessPolicy::default(),
+            api_keys: vec![new_key],
+        });
+
+        // then
+        assert!(!manager.is_known_key("AAAAA"));
+        assert!(manager.is_known_key("BBBBB"));
+    }
 }
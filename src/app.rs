@@ -1,12 +1,29 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use anyhow::Context;
 use clap::Parser;
-use log::LevelFilter;
-use tokio::sync;
+use tokio::{io, time};
 
+#[cfg(unix)]
+use crate::rpc::RpcServer;
 use crate::{
-    api_access::ApiAccessManager, config::Config, connection::ConnectionListener,
-    room::RoomManager, session::Session,
+    api_access::ApiAccessManager,
+    auth::{self, AuthProvider},
+    config::{Config, ConfigReloader},
+    connection::{
+        CloseReason, Connection, ConnectionListener, ListenerMetrics, Login, RedirectListener,
+        ServerConfig,
+    },
+    content_filter::ContentFilter,
+    features::FeatureFlags,
+    guest_names::GuestNames,
+    http::HttpServer,
+    logging::{self, LogController},
+    overload::LoadShedder,
+    room::RoomManager,
+    session::{KeepaliveConfig, Session, SuspendedSessions},
+    storage::{self, Storage},
+    tls,
 };
 
 #[derive(Debug, Parser)]
@@ -25,35 +42,303 @@ pub struct Cli {
         help = "The path to the config file. The default is `config.toml`."
     )]
     pub config: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "The profile from the config file to use. This overrides the `profile` key in the config file."
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+        long,
+        help = "Serve a single session over standard input and output instead of listening for connections, for applications that embed the server."
+    )]
+    pub stdio: bool,
+}
+
+/// Everything that sessions share, so that they can be started for any connection.
+#[derive(Clone)]
+pub struct SessionServices {
+    pub auth_provider: Arc<dyn AuthProvider>,
+    pub room_mgr: Arc<RoomManager>,
+    pub suspended_sessions: Arc<SuspendedSessions>,
+    pub load_shedder: Arc<LoadShedder>,
+    pub storage: Arc<dyn Storage>,
+    pub keepalive: KeepaliveConfig,
+    pub guest_names: Arc<GuestNames>,
+    pub features: Arc<FeatureFlags>,
+    pub log_controller: LogController,
+}
+
+impl SessionServices {
+    /// How much an in-process stream buffers in each direction.
+    const IN_PROCESS_BUFFER_SIZE: usize = 64 * 1024;
+
+    /// Sets up sessions the way [`start`] does, but without restoring rooms or running any of the
+    /// other servers, for applications that embed the server and for tests.
+    pub async fn from_config(config: Config) -> anyhow::Result<Self> {
+        let access_mgr = Arc::new(ApiAccessManager::new(config.api_access));
+        let content_filter = Arc::new(ContentFilter::load(&config.rooms.content_filter)?);
+        let storage = storage::create_storage(config.persistence.storage).await?;
+        Ok(Self {
+            auth_provider: auth::create_provider(config.auth.auth, access_mgr)?,
+            room_mgr: Arc::new(RoomManager::new(
+                config.rooms,
+                content_filter,
+                Arc::clone(&storage),
+            )?),
+            suspended_sessions: Arc::new(SuspendedSessions::new(config.sessions.resume)),
+            load_shedder: Arc::new(LoadShedder::new(config.sessions.overload)),
+            storage,
+            keepalive: config.sessions.keepalive,
+            guest_names: Arc::new(GuestNames::new(config.sessions.guest_names)),
+            features: Arc::new(FeatureFlags::new(config.features.features)),
+            log_controller: LogController::detached(),
+        })
+    }
+
+    /// Starts a session for a client in the same process, and returns the client's end of the
+    /// stream. The client speaks WebSocket frames over it, without an HTTP handshake first.
+    pub fn connect_in_process(&self, config: &ServerConfig) -> io::DuplexStream {
+        let (server, client) = io::duplex(Self::IN_PROCESS_BUFFER_SIZE);
+        let services = self.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            let conn = Connection::over_stream("in-process".to_string(), server, &config).await;
+            if let Err(err) = services.serve(conn).await {
+                log::error!("In-process session failed: {err:?}");
+            }
+        });
+        client
+    }
+
+    pub async fn serve(self, mut conn: Connection) -> anyhow::Result<()> {
+        let resume_token = self.suspended_sessions.issue_token();
+        match conn
+            .init(&*self.auth_provider, &self.guest_names, resume_token)
+            .await?
+        {
+            Login::New => {
+                let mut session = Session::new(conn, &self);
+                session.run().await;
+            }
+            Login::Resume(token) => {
+                if let Err(mut conn) = self.suspended_sessions.resume(&token, conn) {
+                    conn.close(
+                        CloseReason::Unauthorized,
+                        "The session can't be resumed anymore",
+                    )
+                    .await?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 pub async fn start() -> anyhow::Result<()> {
-    pretty_env_logger::formatted_builder()
-        .filter_level(LevelFilter::Info)
-        .parse_env("PALANTIR_LOG")
-        .init();
+    let log_controller = logging::init();
 
     let cli = Cli::parse();
     let config = Config::from_cli_args(&cli)?;
 
+    log_controller.configure(&config.logging);
+    tokio::spawn(logging::handle_signals(log_controller.clone()));
+
     let access_mgr = Arc::new(ApiAccessManager::new(config.api_access));
-    let room_mgr = Arc::new(sync::Mutex::new(RoomManager::new()));
+    let features = Arc::new(FeatureFlags::new(config.features.features));
+    if let Some(config_path) = Config::path_from_cli_args(&cli) {
+        let reloader = ConfigReloader::new(
+            config_path,
+            cli.profile.clone(),
+            Arc::clone(&access_mgr),
+            Arc::clone(&features),
+        );
+        tokio::spawn(reloader.handle_signals());
+    }
+    let auth_provider = auth::create_provider(config.auth.auth, access_mgr)?;
+    let content_filter = Arc::new(ContentFilter::load(&config.rooms.content_filter)?);
+    let storage = storage::create_storage(config.persistence.storage).await?;
+    let room_mgr = Arc::new(RoomManager::new(
+        config.rooms,
+        content_filter,
+        Arc::clone(&storage),
+    )?);
 
-    let listener = ConnectionListener::bind(config.server).await?;
-    listener
-        .listen(move |mut conn| {
-            let access_mgr = Arc::clone(&access_mgr);
-            let room_mgr = Arc::clone(&room_mgr);
-            async move {
-                conn.init(&access_mgr).await?;
+    // probes should be answered while rooms are still being restored
+    let listener_metrics = Arc::new(ListenerMetrics::default());
+    if let Some(health_server) = HttpServer::bind_health(
+        &config.http,
+        Arc::clone(&room_mgr),
+        Arc::clone(&listener_metrics),
+    )
+    .await?
+    {
+        tokio::spawn(health_server.serve());
+    }
 
-                let mut session = Session::new(conn, room_mgr);
-                session.run().await;
+    let snapshot_config = config.persistence.room_snapshots;
+    let snapshots = storage
+        .load_rooms()
+        .await
+        .context("Failed to restore rooms")?;
+    room_mgr
+        .restore_rooms(
+            snapshots,
+            Duration::from_secs(snapshot_config.restore_timeout),
+        )
+        .await;
+    tokio::spawn(storage::snapshot_rooms_periodically(
+        Arc::clone(&storage),
+        Arc::clone(&room_mgr),
+        Duration::from_secs(snapshot_config.interval),
+    ));
+
+    if let Some(http_server) = HttpServer::bind(
+        config.http,
+        Arc::clone(&room_mgr),
+        Arc::clone(&features),
+        Arc::clone(&listener_metrics),
+    )
+    .await?
+    {
+        tokio::spawn(http_server.serve());
+    }
 
-                Ok(())
+    let suspended_sessions = Arc::new(SuspendedSessions::new(config.sessions.resume));
+    let load_shedder = Arc::new(LoadShedder::new(config.sessions.overload));
+    tokio::spawn(Arc::clone(&load_shedder).watch_cpu());
+    #[cfg(unix)]
+    if let Some(rpc_server) =
+        RpcServer::bind(config.rpc, Arc::clone(&room_mgr), Arc::clone(&load_shedder)).await?
+    {
+        tokio::spawn(rpc_server.serve());
+    }
+    #[cfg(not(unix))]
+    if config.rpc.rpc_socket.is_some() {
+        log::warn!("The RPC socket is only supported on unix systems");
+    }
+    let shutdown = Shutdown {
+        load_shedder: Arc::clone(&load_shedder),
+        room_mgr: Arc::clone(&room_mgr),
+        storage: Arc::clone(&storage),
+    };
+    let services = SessionServices {
+        auth_provider,
+        room_mgr,
+        suspended_sessions,
+        load_shedder,
+        storage,
+        keepalive: config.sessions.keepalive,
+        guest_names: Arc::new(GuestNames::new(config.sessions.guest_names)),
+        features,
+        log_controller,
+    };
+    if cli.stdio {
+        let stdio = io::join(io::stdin(), io::stdout());
+        let conn = Connection::over_stream("stdio".to_string(), stdio, &config.server).await;
+        return services.serve(conn).await;
+    }
+
+    let tls_acceptor = tls::create_acceptor(config.tls)?;
+    let plaintext_redirect = config.server.plaintext_redirect.clone();
+    let handshake_timeout = Duration::from_secs(config.server.handshakes.timeout);
+    if plaintext_redirect.is_some() && tls_acceptor.is_none() {
+        anyhow::bail!("A plaintext redirect listener can only be used if TLS is enabled");
+    }
+    let listener = ConnectionListener::bind(config.server, tls_acceptor, listener_metrics).await?;
+    if let Some(redirect_config) = plaintext_redirect {
+        let redirect_listener =
+            RedirectListener::bind(redirect_config, listener.local_addr()?, handshake_timeout)
+                .await?;
+        tokio::spawn(redirect_listener.listen());
+    }
+    tokio::select! {
+        result = listener.listen(move |conn| services.clone().serve(conn)) => result?,
+        () = shutdown_requested() => shutdown.run().await,
+    }
+
+    Ok(())
+}
+
+/// Resolves once the server is asked to stop, by Ctrl+C or, on unix, by SIGTERM.
+async fn shutdown_requested() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(err) => log::error!("Failed to install SIGTERM handler: {err:?}"),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        log::error!("Failed to listen for Ctrl+C: {err:?}");
+        std::future::pending::<()>().await;
+    }
+}
+
+/// What the server needs to shut down gracefully.
+struct Shutdown {
+    load_shedder: Arc<LoadShedder>,
+    room_mgr: Arc<RoomManager>,
+    storage: Arc<dyn Storage>,
+}
+
+impl Shutdown {
+    /// How long sessions get to close their connections.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Tells every client that the server is restarting, and saves the rooms with everyone
+    /// still in them, so that clients can rejoin once the server is back.
+    async fn run(self) {
+        log::info!("Shutting down...");
+        self.load_shedder.shut_down();
+        let ended = time::timeout(Self::TIMEOUT, async {
+            while self.load_shedder.session_count() != 0 {
+                time::sleep(Duration::from_millis(50)).await;
             }
         })
-        .await?;
+        .await;
+        if ended.is_err() {
+            log::warn!(
+                "{} sessions didn't end in time",
+                self.load_shedder.session_count()
+            );
+        }
+        let rooms = self.room_mgr.snapshot_rooms().await;
+        if let Err(err) = self.storage.save_rooms(rooms).await {
+            log::error!("Failed to save room snapshots: {err:?}");
+        }
+    }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use crate::testing::StreamClient;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_serve_sessions_in_process() {
+        // given
+        let config = Config::default();
+        let server_config = config.server.clone();
+        let services = SessionServices::from_config(config).await.unwrap();
+
+        // when
+        let stream = services.connect_in_process(&server_config);
+        let mut client = StreamClient::over(stream).await;
+        let ack = client.login("alice").await;
+
+        // then
+        assert_eq!(ack.name, "alice");
+    }
 }
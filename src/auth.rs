@@ -0,0 +1,565 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context};
+use futures::future::BoxFuture;
+use hmac::{Hmac, KeyInit, Mac};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::{
+    api_access::{ApiAccessManager, ApiPermissions, SessionPriority},
+    messages::dto,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: String,
+
+    /// How long to wait for the webhook to respond, in seconds.
+    pub timeout: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            timeout: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct JwtConfig {
+    /// Shared secret for HS256 signed tokens.
+    pub secret: Option<String>,
+
+    /// PEM encoded public key for RS256 signed tokens.
+    pub public_key: Option<String>,
+
+    /// URL of the JSON Web Key Set of an OpenID Connect provider.
+    pub jwks_url: Option<String>,
+
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+
+    /// The claim that contains the user's display name.
+    pub name_claim: String,
+
+    /// The claim that contains the list of granted permissions (`"connect"`, `"host"`,
+    /// `"observe"`, `"admin"`).
+    pub permissions_claim: String,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            public_key: None,
+            jwks_url: None,
+            issuer: None,
+            audience: None,
+            name_claim: "preferred_username".to_string(),
+            permissions_claim: "palantir_permissions".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum AuthProviderConfig {
+    #[default]
+    ApiKeys,
+    Webhook(WebhookConfig),
+    Jwt(JwtConfig),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub auth: AuthProviderConfig,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Credentials {
+    pub username: String,
+    pub api_key: Option<String>,
+    pub token: Option<String>,
+}
+
+impl From<dto::ConnectionLoginMsgBodyV1> for Credentials {
+    fn from(value: dto::ConnectionLoginMsgBodyV1) -> Self {
+        Self {
+            username: value.username,
+            api_key: value.api_key,
+            token: value.token,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub display_name: String,
+    pub permissions: ApiPermissions,
+
+    /// Identifies the account behind the credentials across connections, if there is one.
+    pub subject: Option<String>,
+}
+
+impl Identity {
+    fn unauthorized(credentials: &Credentials) -> Self {
+        Self {
+            display_name: credentials.username.clone(),
+            permissions: ApiPermissions::none(),
+            subject: None,
+        }
+    }
+}
+
+/// Decides who a connecting client is and what they are allowed to do.
+///
+/// Rejected credentials should result in an identity without permissions; errors are reserved
+/// for failures of the provider itself.
+pub trait AuthProvider: Send + Sync {
+    fn authenticate<'a>(
+        &'a self,
+        credentials: &'a Credentials,
+    ) -> BoxFuture<'a, anyhow::Result<Identity>>;
+}
+
+pub fn create_provider(
+    config: AuthProviderConfig,
+    access_mgr: Arc<ApiAccessManager>,
+) -> anyhow::Result<Arc<dyn AuthProvider>> {
+    Ok(match config {
+        AuthProviderConfig::ApiKeys => Arc::new(ApiKeyProvider { access_mgr }),
+        AuthProviderConfig::Webhook(config) => Arc::new(WebhookProvider::new(config)?),
+        AuthProviderConfig::Jwt(config) => Arc::new(JwtProvider::new(config)?),
+    })
+}
+
+struct ApiKeyProvider {
+    access_mgr: Arc<ApiAccessManager>,
+}
+
+impl AuthProvider for ApiKeyProvider {
+    fn authenticate<'a>(
+        &'a self,
+        credentials: &'a Credentials,
+    ) -> BoxFuture<'a, anyhow::Result<Identity>> {
+        Box::pin(async move {
+            let api_key = credentials.api_key.as_deref();
+            Ok(Identity {
+                display_name: credentials.username.clone(),
+                permissions: self.access_mgr.get_permissions(api_key),
+                subject: api_key
+                    .filter(|key| self.access_mgr.is_known_key(key))
+                    .map(|key| api_key_subject(key, &credentials.username)),
+            })
+        })
+    }
+}
+
+/// Keys are often shared by a whole community, so the key alone doesn't tell users apart. The
+/// subject is a hash of the name, keyed by the API key, which neither gives away the key nor
+/// lets anyone else with it pass as another user.
+fn api_key_subject(api_key: &str, username: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(api_key.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(username.as_bytes());
+    let hash: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("key:{hash}")
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookResponse {
+    #[serde(default)]
+    display_name: Option<String>,
+
+    #[serde(default)]
+    subject: Option<String>,
+
+    #[serde(flatten)]
+    permissions: ApiPermissions,
+}
+
+/// Asks an external service about every login by POSTing the credentials to it as JSON.
+struct WebhookProvider {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookProvider {
+    fn new(config: WebhookConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .context("Failed to create auth webhook client")?;
+        Ok(Self {
+            url: config.url,
+            client,
+        })
+    }
+}
+
+impl AuthProvider for WebhookProvider {
+    fn authenticate<'a>(
+        &'a self,
+        credentials: &'a Credentials,
+    ) -> BoxFuture<'a, anyhow::Result<Identity>> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .post(&self.url)
+                .json(credentials)
+                .send()
+                .await
+                .context("Auth webhook request failed")?;
+            if response.status().is_client_error() {
+                debug!("Auth webhook rejected login with {}", response.status());
+                return Ok(Identity::unauthorized(credentials));
+            }
+            let response: WebhookResponse = response
+                .error_for_status()
+                .context("Auth webhook returned an error")?
+                .json()
+                .await
+                .context("Auth webhook returned an invalid response")?;
+            Ok(Identity {
+                display_name: response
+                    .display_name
+                    .unwrap_or_else(|| credentials.username.clone()),
+                permissions: response.permissions,
+                subject: response.subject,
+            })
+        })
+    }
+}
+
+enum JwtKeys {
+    Static(DecodingKey, Algorithm),
+    Jwks {
+        url: String,
+        client: reqwest::Client,
+        cache: RwLock<Option<(Instant, JwkSet)>>,
+    },
+}
+
+/// Validates bearer tokens issued by an OAuth2/OpenID Connect provider, or any other JWT issuer.
+struct JwtProvider {
+    keys: JwtKeys,
+    issuer: Option<String>,
+    audience: Option<String>,
+    name_claim: String,
+    permissions_claim: String,
+}
+
+impl JwtProvider {
+    const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+    fn new(config: JwtConfig) -> anyhow::Result<Self> {
+        let keys = match (config.secret, config.public_key, config.jwks_url) {
+            (Some(secret), None, None) => {
+                JwtKeys::Static(DecodingKey::from_secret(secret.as_bytes()), Algorithm::HS256)
+            }
+            (None, Some(public_key), None) => JwtKeys::Static(
+                DecodingKey::from_rsa_pem(public_key.as_bytes())
+                    .context("Invalid JWT public key")?,
+                Algorithm::RS256,
+            ),
+            (None, None, Some(url)) => JwtKeys::Jwks {
+                url,
+                client: reqwest::Client::new(),
+                cache: RwLock::new(None),
+            },
+            _ => {
+                return Err(anyhow!(
+                    "Exactly one of `secret`, `public_key` or `jwks_url` must be configured for JWT authentication"
+                ))
+            }
+        };
+        Ok(Self {
+            keys,
+            issuer: config.issuer,
+            audience: config.audience,
+            name_claim: config.name_claim,
+            permissions_claim: config.permissions_claim,
+        })
+    }
+
+    async fn fetch_jwks(url: &str, client: &reqwest::Client) -> anyhow::Result<JwkSet> {
+        debug!("Fetching JWKS from {url}");
+        client
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("Failed to fetch JWKS")?
+            .json()
+            .await
+            .context("Failed to parse JWKS")
+    }
+
+    async fn decoding_key(&self, token: &str) -> anyhow::Result<Option<(DecodingKey, Algorithm)>> {
+        let (url, client, cache) = match &self.keys {
+            JwtKeys::Static(key, algorithm) => return Ok(Some((key.clone(), *algorithm))),
+            JwtKeys::Jwks { url, client, cache } => (url, client, cache),
+        };
+        let Ok(header) = jsonwebtoken::decode_header(token) else {
+            return Ok(None);
+        };
+        let Some(kid) = header.kid else {
+            return Ok(None);
+        };
+
+        let find_key = |jwks: &JwkSet| {
+            jwks.find(&kid)
+                .and_then(|jwk| DecodingKey::from_jwk(jwk).ok())
+                .map(|key| (key, header.alg))
+        };
+
+        if let Some((_, jwks)) = &*cache.read().await {
+            if let Some(key) = find_key(jwks) {
+                return Ok(Some(key));
+            }
+        }
+
+        // The key may have been rotated; refresh the key set, but not too often, since the
+        // key id is controlled by the client.
+        let mut cache = cache.write().await;
+        let refresh_due = cache
+            .as_ref()
+            .is_none_or(|(fetched_at, _)| fetched_at.elapsed() > Self::JWKS_MIN_REFRESH_INTERVAL);
+        if refresh_due {
+            *cache = Some((Instant::now(), Self::fetch_jwks(url, client).await?));
+        }
+        Ok(cache.as_ref().and_then(|(_, jwks)| find_key(jwks)))
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        validation
+    }
+
+    fn identity_from_claims(
+        &self,
+        claims: &serde_json::Map<String, serde_json::Value>,
+        credentials: &Credentials,
+    ) -> Identity {
+        let display_name = claims
+            .get(&self.name_claim)
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| credentials.username.clone());
+
+        let granted: Vec<&str> = claims
+            .get(&self.permissions_claim)
+            .and_then(serde_json::Value::as_array)
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(serde_json::Value::as_str)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Identity {
+            display_name,
+            subject: claims
+                .get("sub")
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_string),
+            permissions: ApiPermissions {
+                connect: granted.contains(&"connect"),
+                host: granted.contains(&"host"),
+                observe: granted.contains(&"observe"),
+                admin: granted.contains(&"admin"),
+                // anyone with a valid token has an account
+                priority: SessionPriority::Member,
+                ..ApiPermissions::none()
+            },
+        }
+    }
+}
+
+impl AuthProvider for JwtProvider {
+    fn authenticate<'a>(
+        &'a self,
+        credentials: &'a Credentials,
+    ) -> BoxFuture<'a, anyhow::Result<Identity>> {
+        Box::pin(async move {
+            let Some(token) = &credentials.token else {
+                debug!("No token provided");
+                return Ok(Identity::unauthorized(credentials));
+            };
+            let Some((key, algorithm)) = self.decoding_key(token).await? else {
+                debug!("No key found for the provided token");
+                return Ok(Identity::unauthorized(credentials));
+            };
+            let claims = match jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(
+                token,
+                &key,
+                &self.validation(algorithm),
+            ) {
+                Ok(data) => data.claims,
+                Err(err) => {
+                    debug!("Invalid token provided: {err}");
+                    return Ok(Identity::unauthorized(credentials));
+                }
+            };
+            Ok(self.identity_from_claims(&claims, credentials))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+    use crate::api_access::{ApiAccessConfig, ApiKey, ApiPermissions};
+
+    const SECRET: &str = "super secret";
+
+    fn jwt_provider() -> JwtProvider {
+        JwtProvider::new(JwtConfig {
+            secret: Some(SECRET.to_string()),
+            issuer: Some("https://auth.example.com".to_string()),
+            ..JwtConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn credentials(token: Option<String>) -> Credentials {
+        Credentials {
+            username: "anonymous".to_string(),
+            api_key: None,
+            token,
+        }
+    }
+
+    fn sign(claims: serde_json::Value, secret: &str) -> String {
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_map_jwt_claims_to_identity() {
+        // given
+        let provider = jwt_provider();
+        let token = sign(
+            json!({
+                "iss": "https://auth.example.com",
+                "exp": 4_000_000_000u64,
+                "sub": "alice@example.com",
+                "preferred_username": "alice",
+                "palantir_permissions": ["connect"]
+            }),
+            SECRET,
+        );
+
+        // when
+        let identity = provider
+            .authenticate(&credentials(Some(token)))
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(
+            identity,
+            Identity {
+                display_name: "alice".to_string(),
+                permissions: ApiPermissions {
+                    priority: SessionPriority::Member,
+                    ..ApiPermissions::connect()
+                },
+                subject: Some("alice@example.com".to_string())
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_jwt_with_wrong_signature() {
+        // given
+        let provider = jwt_provider();
+        let token = sign(
+            json!({
+                "iss": "https://auth.example.com",
+                "exp": 4_000_000_000u64,
+                "palantir_permissions": ["connect", "host"]
+            }),
+            "wrong secret",
+        );
+
+        // when
+        let identity = provider
+            .authenticate(&credentials(Some(token)))
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(identity.permissions, ApiPermissions::none());
+    }
+
+    #[tokio::test]
+    async fn should_give_users_of_a_shared_api_key_their_own_subject() {
+        // given
+        let access_mgr = Arc::new(ApiAccessManager::new(ApiAccessConfig {
+            api_keys: vec![ApiKey {
+                key: "AAAAA".to_string(),
+                permissions: ApiPermissions::all(),
+            }],
+            ..ApiAccessConfig::default()
+        }));
+        let provider = ApiKeyProvider { access_mgr };
+        let login = |username: &str| Credentials {
+            username: username.to_string(),
+            api_key: Some("AAAAA".to_string()),
+            token: None,
+        };
+
+        // when
+        let alice = provider.authenticate(&login("alice")).await.unwrap();
+        let bob = provider.authenticate(&login("bob")).await.unwrap();
+        let alice_again = provider.authenticate(&login("alice")).await.unwrap();
+
+        // then
+        let subject = alice.subject.unwrap();
+        assert!(!subject.contains("AAAAA"));
+        assert_ne!(Some(&subject), bob.subject.as_ref());
+        assert_eq!(Some(subject), alice_again.subject);
+    }
+
+    #[tokio::test]
+    async fn should_reject_login_without_jwt() {
+        // given
+        let provider = jwt_provider();
+
+        // when
+        let identity = provider.authenticate(&credentials(None)).await.unwrap();
+
+        // then
+        assert_eq!(identity.permissions, ApiPermissions::none());
+    }
+}
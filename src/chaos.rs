@@ -0,0 +1,179 @@
+//! Fault injection, for trying out locally how the server copes with failures, like sessions
+//! resuming after lost messages or rooms crashing. It is only built with the `chaos` feature,
+//! and is controlled through the admin API. Everything is off until it is turned on there.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context};
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::time;
+
+use crate::session::{SessionHandle, SessionMsg, SessionSink};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ChaosSettings {
+    /// The longest a room request is held back before it is sent, in milliseconds. Each
+    /// request is delayed by a random amount up to this.
+    pub request_delay: u64,
+
+    /// The share of room requests that fail instead of reaching the room, from 0 to 1.
+    pub request_failure_rate: f64,
+
+    /// The share of messages from rooms to sessions that are silently dropped, from 0 to 1.
+    pub message_drop_rate: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct Chaos {
+    settings: Mutex<ChaosSettings>,
+}
+
+impl Chaos {
+    pub fn settings(&self) -> ChaosSettings {
+        *self.settings.lock()
+    }
+
+    /// Changes a single setting, given by its name.
+    pub fn set(&self, name: &str, value: &str) -> anyhow::Result<()> {
+        let mut settings = self.settings.lock();
+        match name {
+            "request_delay" => {
+                settings.request_delay = value.parse().context("Invalid delay")?;
+            }
+            "request_failure_rate" => settings.request_failure_rate = parse_rate(value)?,
+            "message_drop_rate" => settings.message_drop_rate = parse_rate(value)?,
+            _ => return Err(anyhow!("Unknown chaos setting '{name}'")),
+        }
+        log::warn!("Chaos setting {name} is now {value}");
+        Ok(())
+    }
+
+    pub fn reset(&self) {
+        *self.settings.lock() = ChaosSettings::default();
+        log::warn!("Chaos settings were reset");
+    }
+
+    /// Holds back a room request for a while, or fails it, as the settings say.
+    pub async fn disturb_request(&self) -> anyhow::Result<()> {
+        let settings = self.settings();
+        if settings.request_delay != 0 {
+            let delay = fastrand::u64(0..=settings.request_delay);
+            time::sleep(Duration::from_millis(delay)).await;
+        }
+        if fastrand::f64() < settings.request_failure_rate {
+            return Err(anyhow!("Injected room request failure"));
+        }
+        Ok(())
+    }
+
+    /// Makes messages to the session go through the chaos, so that some of them get lost.
+    pub fn wrap_session(self: &Arc<Self>, session: SessionHandle) -> SessionHandle {
+        let chaos = Arc::clone(self);
+        session.wrap_sink(|inner| Arc::new(ChaosSink { inner, chaos }))
+    }
+}
+
+fn parse_rate(value: &str) -> anyhow::Result<f64> {
+    value
+        .parse()
+        .ok()
+        .filter(|rate| (0.0..=1.0).contains(rate))
+        .ok_or_else(|| anyhow!("Rates need to be between 0 and 1"))
+}
+
+struct ChaosSink {
+    inner: Arc<dyn SessionSink>,
+    chaos: Arc<Chaos>,
+}
+
+impl fmt::Debug for ChaosSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChaosSink")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionSink for ChaosSink {
+    /// Dropped messages count as delivered, like ones that get lost on the way to the client.
+    fn send_message(&self, msg: SessionMsg) -> BoxFuture<'_, anyhow::Result<bool>> {
+        if fastrand::f64() < self.chaos.settings().message_drop_rate {
+            log::debug!("Dropping message for chaos: {msg:?}");
+            return Box::pin(async { Ok(true) });
+        }
+        self.inner.send_message(msg)
+    }
+
+    fn deliver_message(&self, msg: SessionMsg) -> BoxFuture<'_, anyhow::Result<bool>> {
+        if fastrand::f64() < self.chaos.settings().message_drop_rate {
+            log::debug!("Dropping message for chaos: {msg:?}");
+            return Box::pin(async { Ok(true) });
+        }
+        self.inner.deliver_message(msg)
+    }
+
+    fn try_send_message(&self, msg: SessionMsg) -> Result<bool, Box<SessionMsg>> {
+        if fastrand::f64() < self.chaos.settings().message_drop_rate {
+            log::debug!("Dropping message for chaos: {msg:?}");
+            return Ok(true);
+        }
+        self.inner.try_send_message(msg)
+    }
+
+    fn time_offset(&self) -> i64 {
+        self.inner.time_offset()
+    }
+
+    fn latency(&self) -> u64 {
+        self.inner.latency()
+    }
+
+    fn jitter(&self) -> u64 {
+        self.inner.jitter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeSession;
+
+    #[tokio::test]
+    async fn should_drop_messages_once_turned_on() {
+        // given
+        let chaos = Arc::new(Chaos::default());
+        let fake = FakeSession::new(0);
+        let session = chaos.wrap_session(fake.handle(1, "alice"));
+
+        // when
+        session
+            .send_message(SessionMsg::PlaybackConnected)
+            .await
+            .unwrap();
+        chaos.set("message_drop_rate", "1").unwrap();
+        let dropped = session.send_message(SessionMsg::PlaybackConnected).await;
+
+        // then
+        assert!(dropped.unwrap());
+        assert_eq!(fake.take_messages().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_fail_requests_once_turned_on() {
+        // given
+        let chaos = Chaos::default();
+
+        // when
+        let before = chaos.disturb_request().await;
+        chaos.set("request_failure_rate", "1").unwrap();
+        let after = chaos.disturb_request().await;
+
+        // then
+        assert!(before.is_ok());
+        assert!(after.is_err());
+        assert!(chaos.set("request_failure_rate", "2").is_err());
+        assert!(chaos.set("tornado", "1").is_err());
+    }
+}
@@ -2,58 +2,123 @@ use std::{
     fs::File,
     io::Read,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use anyhow::Context;
+use anyhow::{anyhow, bail, Context};
 use serde::Deserialize;
+use toml::{Table, Value};
 
-use crate::{api_access::ApiAccessConfig, app::Cli, connection::ServerConfig};
+use crate::{
+    api_access::{ApiAccessConfig, ApiAccessManager},
+    app::Cli,
+    auth::AuthConfig,
+    connection::ServerConfig,
+    features::{FeatureFlags, FeaturesConfig},
+    http::HttpConfig,
+    logging::LoggingConfig,
+    room::RoomConfig,
+    rpc::RpcConfig,
+    session::SessionConfig,
+    storage::PersistenceConfig,
+    tls::TlsConfig,
+};
 
 const DEFAULT_CONFIG_PATH: &str = "config.toml";
 
+/// The key that selects a profile if none is given on the command line.
+const PROFILE_KEY: &str = "profile";
+
+/// The table that holds the profiles, by name.
+const PROFILES_KEY: &str = "profiles";
+
 #[derive(Debug, Default, PartialEq, Eq, Deserialize)]
 #[serde(default)]
 pub struct Config {
     #[serde(flatten)]
     pub api_access: ApiAccessConfig,
 
+    #[serde(flatten)]
+    pub auth: AuthConfig,
+
     #[serde(flatten)]
     pub server: ServerConfig,
+
+    #[serde(flatten)]
+    pub logging: LoggingConfig,
+
+    #[serde(flatten)]
+    pub rooms: RoomConfig,
+
+    #[serde(flatten)]
+    pub http: HttpConfig,
+
+    #[serde(flatten)]
+    pub rpc: RpcConfig,
+
+    #[serde(flatten)]
+    pub persistence: PersistenceConfig,
+
+    #[serde(flatten)]
+    pub tls: TlsConfig,
+
+    #[serde(flatten)]
+    pub sessions: SessionConfig,
+
+    #[serde(flatten)]
+    pub features: FeaturesConfig,
 }
 
 impl Config {
-    pub fn read(file: &mut impl Read) -> anyhow::Result<Self> {
+    /// Reads a config file, with the settings of the given profile, or else the one named in the
+    /// file, layered on top.
+    pub fn read(file: &mut impl Read, profile: Option<&str>) -> anyhow::Result<Self> {
         let mut contents = String::new();
         file.read_to_string(&mut contents)
             .context("Failed to read config file")?;
 
-        let config = toml::from_str(&contents).context("Failed to parse config file")?;
+        let mut table: Table = toml::from_str(&contents).context("Failed to parse config file")?;
+        apply_profile(&mut table, profile)?;
+        let config = Value::Table(table)
+            .try_into()
+            .context("Failed to parse config file")?;
         Ok(config)
     }
 
-    pub fn read_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    pub fn read_path(path: impl AsRef<Path>, profile: Option<&str>) -> anyhow::Result<Self> {
         let mut file = File::open(path).context("Failed to open config file")?;
-        Self::read(&mut file)
+        Self::read(&mut file, profile)
     }
 
-    pub fn from_cli_args(args: &Cli) -> anyhow::Result<Self> {
-        let mut config = match &args.config {
-            Some(config_path) => Self::read_path(config_path)?,
+    /// The config file that the arguments point to, if there is one.
+    pub fn path_from_cli_args(args: &Cli) -> Option<PathBuf> {
+        match &args.config {
+            Some(config_path) => Some(PathBuf::from(config_path)),
             None => {
                 let default_config = PathBuf::from(DEFAULT_CONFIG_PATH);
-                if default_config.exists() {
-                    log::info!("Using default config file {DEFAULT_CONFIG_PATH}");
-                    Self::read_path(default_config)?
-                } else {
-                    log::warn!("No config file found; using default config");
+                default_config.exists().then_some(default_config)
+            }
+        }
+    }
 
-                    #[cfg(debug_assertions)]
-                    {
-                        log::warn!("DEBUG DEFAULT CONFIG IS INSECURE! You are running a debug build, which uses an insecure default configuration for development purposes.");
-                    }
+    pub fn from_cli_args(args: &Cli) -> anyhow::Result<Self> {
+        let profile = args.profile.as_deref();
+        let mut config = match Self::path_from_cli_args(args) {
+            Some(config_path) => {
+                if args.config.is_none() {
+                    log::info!("Using default config file {DEFAULT_CONFIG_PATH}");
+                }
+                Self::read_path(config_path, profile)?
+            }
+            None => {
+                log::warn!("No config file found; using default config");
 
-                    Config::default()
+                #[cfg(debug_assertions)]
+                {
+                    log::warn!("DEBUG DEFAULT CONFIG IS INSECURE! You are running a debug build, which uses an insecure default configuration for development purposes.");
                 }
+
+                Config::default()
             }
         };
         if let Some(listen_on) = &args.listen_on {
@@ -63,16 +128,131 @@ impl Config {
     }
 }
 
+/// Re-reads the config file at runtime, so that API keys can be rotated and features turned off
+/// without a restart. All other settings only take effect after restarting the server.
+pub struct ConfigReloader {
+    path: PathBuf,
+    profile: Option<String>,
+    access_mgr: Arc<ApiAccessManager>,
+    features: Arc<FeatureFlags>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        path: PathBuf,
+        profile: Option<String>,
+        access_mgr: Arc<ApiAccessManager>,
+        features: Arc<FeatureFlags>,
+    ) -> Self {
+        Self {
+            path,
+            profile,
+            access_mgr,
+            features,
+        }
+    }
+
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let config = Config::read_path(&self.path, self.profile.as_deref())?;
+        self.access_mgr.replace_config(config.api_access);
+        self.features.replace_config(config.features.features);
+        log::info!(
+            "Reloaded API keys, access policy and features from {}",
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    /// Reloads the config file on every SIGHUP. If it can't be read, the old config stays.
+    #[cfg(unix)]
+    pub async fn handle_signals(self) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => sighup,
+            Err(err) => {
+                log::error!("Failed to install SIGHUP handler: {err:?}");
+                return;
+            }
+        };
+        while sighup.recv().await.is_some() {
+            if let Err(err) = self.reload() {
+                log::error!("Failed to reload config; keeping the old one: {err:?}");
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub async fn handle_signals(self) {}
+}
+
+/// Removes the profiles from a config table, and merges the selected one into it.
+fn apply_profile(table: &mut Table, profile: Option<&str>) -> anyhow::Result<()> {
+    let default_profile = match table.remove(PROFILE_KEY) {
+        Some(Value::String(name)) => Some(name),
+        Some(_) => bail!("`{PROFILE_KEY}` has to be the name of a profile"),
+        None => None,
+    };
+    let mut profiles = match table.remove(PROFILES_KEY) {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => bail!("`{PROFILES_KEY}` has to be a table of profiles"),
+        None => Table::new(),
+    };
+    let Some(name) = profile.map(str::to_string).or(default_profile) else {
+        return Ok(());
+    };
+    let overrides = match profiles.remove(&name) {
+        Some(Value::Table(overrides)) => overrides,
+        Some(_) => bail!("Profile '{name}' has to be a table"),
+        None => return Err(anyhow!("There is no profile '{name}' in the config file")),
+    };
+    log::info!("Using config profile '{name}'");
+    merge(table, overrides);
+    Ok(())
+}
+
+/// Merges tables key by key; anything else, including arrays, is replaced as a whole.
+fn merge(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overrides)) => merge(base, overrides),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
 
-    use crate::api_access::{ApiAccessPolicy, ApiKey, ApiPermissions};
+    use crate::{
+        api_access::{ApiAccessPolicy, ApiKey, ApiPermissions},
+        auth::{AuthProviderConfig, WebhookConfig},
+        connection::{CompressionConfig, HandshakeConfig, MessageLimitsConfig},
+        content_filter::ContentFilterConfig,
+        features::{Feature, FeatureConfig},
+        http::{AdminApiConfig, MetricsConfig, RoomFeedConfig},
+        ids::IdFormat,
+        ip_filter::{IpFilterConfig, IpRange},
+        playback::PlaybackConfig,
+        room::{
+            BroadcastEchoConfig, ChatConfig, DuplicateNamePolicy, EchoPolicy, EventLogConfig,
+            HeavyRoomConfig, LinkSharingConfig, RoomApprovalConfig, RoomLimits,
+        },
+        storage::{FileStorageConfig, RoomSnapshotConfig, StorageConfig},
+    };
 
     use super::*;
 
     const TEST_CONFIG: &str = r#"
 listen_on = "127.0.0.1:6969"
+http_listen_on = "127.0.0.1:6970"
+health_listen_on = "127.0.0.1:6971"
+rpc_socket = "/run/palantir/rpc.sock"
+room_ids = "time_ordered"
+duplicate_names = "reject"
 
 [api_policy]
 restrict_connect = false
@@ -82,6 +262,39 @@ restrict_host = true
 key = "AAAAA"
 connect = true
 host = true
+
+[auth]
+provider = "webhook"
+url = "https://auth.example.com/palantir"
+
+[room_limits]
+max_users = 10
+
+[broadcast_echo]
+room_state = "ack"
+
+[chat]
+max_length = 280
+
+[room_feed]
+enabled = true
+
+[handshakes]
+max_concurrent = 64
+
+[ip_filter]
+deny = ["203.0.113.0/24"]
+max_connections_per_ip = 8
+
+[metrics]
+enabled = true
+
+[storage]
+backend = "file"
+path = "rooms.json"
+
+[features]
+queue = false
 "#;
 
     #[test]
@@ -90,14 +303,26 @@ host = true
         let mut config_file = Cursor::new(TEST_CONFIG);
 
         // when
-        let config = Config::read(&mut config_file).unwrap();
+        let config = Config::read(&mut config_file, None).unwrap();
 
         // then
         assert_eq!(
             config,
             Config {
                 server: ServerConfig {
-                    listen_on: "127.0.0.1:6969".to_string()
+                    listen_on: "127.0.0.1:6969".to_string(),
+                    plaintext_redirect: None,
+                    handshakes: HandshakeConfig {
+                        max_concurrent: 64,
+                        ..HandshakeConfig::default()
+                    },
+                    compression: CompressionConfig::default(),
+                    message_limits: MessageLimitsConfig::default(),
+                    ip_filter: IpFilterConfig {
+                        deny: vec![IpRange::try_from("203.0.113.0/24".to_string()).unwrap()],
+                        max_connections_per_ip: Some(8),
+                        ..IpFilterConfig::default()
+                    },
                 },
                 api_access: ApiAccessConfig {
                     api_policy: ApiAccessPolicy {
@@ -109,6 +334,64 @@ host = true
                         permissions: ApiPermissions::all()
                     }]
                 },
+                auth: AuthConfig {
+                    auth: AuthProviderConfig::Webhook(WebhookConfig {
+                        url: "https://auth.example.com/palantir".to_string(),
+                        timeout: 5
+                    })
+                },
+                logging: LoggingConfig::default(),
+                rooms: RoomConfig {
+                    room_limits: RoomLimits {
+                        max_users: 10,
+                        max_pending_requests: 32,
+                        max_observers: 8,
+                        idle_timeout: 0,
+                    },
+                    duplicate_names: DuplicateNamePolicy::Reject,
+                    broadcast_echo: BroadcastEchoConfig {
+                        room_state: EchoPolicy::Ack,
+                        chat: EchoPolicy::Full,
+                    },
+                    chat: ChatConfig {
+                        max_length: 280,
+                        ..ChatConfig::default()
+                    },
+                    link_sharing: LinkSharingConfig::default(),
+                    playback: PlaybackConfig::default(),
+                    heavy_rooms: HeavyRoomConfig::default(),
+                    content_filter: ContentFilterConfig::default(),
+                    room_approval: RoomApprovalConfig::default(),
+                    room_ids: IdFormat::TimeOrdered,
+                    event_log: EventLogConfig::default(),
+                },
+                http: HttpConfig {
+                    http_listen_on: Some("127.0.0.1:6970".to_string()),
+                    health_listen_on: Some("127.0.0.1:6971".to_string()),
+                    room_feed: RoomFeedConfig {
+                        enabled: true,
+                        ..RoomFeedConfig::default()
+                    },
+                    metrics: MetricsConfig { enabled: true },
+                    admin_api: AdminApiConfig::default(),
+                },
+                rpc: RpcConfig {
+                    rpc_socket: Some(PathBuf::from("/run/palantir/rpc.sock")),
+                },
+                persistence: PersistenceConfig {
+                    storage: StorageConfig::File(FileStorageConfig {
+                        path: PathBuf::from("rooms.json"),
+                    }),
+                    room_snapshots: RoomSnapshotConfig::default(),
+                },
+                tls: TlsConfig::default(),
+                sessions: SessionConfig::default(),
+                features: FeaturesConfig {
+                    features: FeatureConfig {
+                        queue: false,
+                        ..FeatureConfig::default()
+                    },
+                },
             }
         )
     }
@@ -119,7 +402,107 @@ host = true
         let mut config_file = Cursor::new("listen_on = ");
 
         // when
-        let result = Config::read(&mut config_file);
+        let result = Config::read(&mut config_file, None);
+
+        // then
+        assert!(result.is_err());
+    }
+
+    const PROFILE_CONFIG: &str = r#"
+listen_on = "0.0.0.0:8080"
+profile = "prod"
+
+[room_limits]
+max_users = 50
+max_observers = 2
+
+[profiles.prod.room_limits]
+max_users = 20
+
+[profiles.dev]
+listen_on = "127.0.0.1:8080"
+"#;
+
+    #[test]
+    fn should_layer_profile_named_in_file() {
+        // given
+        let mut config_file = Cursor::new(PROFILE_CONFIG);
+
+        // when
+        let config = Config::read(&mut config_file, None).unwrap();
+
+        // then
+        assert_eq!(config.server.listen_on, "0.0.0.0:8080");
+        assert_eq!(config.rooms.room_limits.max_users, 20);
+        assert_eq!(config.rooms.room_limits.max_observers, 2);
+    }
+
+    #[test]
+    fn should_prefer_given_profile() {
+        // given
+        let mut config_file = Cursor::new(PROFILE_CONFIG);
+
+        // when
+        let config = Config::read(&mut config_file, Some("dev")).unwrap();
+
+        // then
+        assert_eq!(config.server.listen_on, "127.0.0.1:8080");
+        assert_eq!(config.rooms.room_limits.max_users, 50);
+    }
+
+    const LISTEN_ON: &str = "listen_on = \"127.0.0.1:8080\"\n";
+
+    #[test]
+    fn should_reload_api_keys() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, format!("{LISTEN_ON}[[api_keys]]\nkey = \"AAAAA\"\n")).unwrap();
+        let access_mgr = Arc::new(ApiAccessManager::new(
+            Config::read_path(&path, None).unwrap().api_access,
+        ));
+        let reloader =
+            ConfigReloader::new(path.clone(), None, Arc::clone(&access_mgr), Arc::default());
+        std::fs::write(&path, format!("{LISTEN_ON}[[api_keys]]\nkey = \"BBBBB\"\n")).unwrap();
+
+        // when
+        reloader.reload().unwrap();
+
+        // then
+        assert!(!access_mgr.is_known_key("AAAAA"));
+        assert!(access_mgr.is_known_key("BBBBB"));
+    }
+
+    #[test]
+    fn should_reload_features() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, LISTEN_ON).unwrap();
+        let features = Arc::new(FeatureFlags::default());
+        let reloader = ConfigReloader::new(
+            path.clone(),
+            None,
+            Arc::new(ApiAccessManager::new(ApiAccessConfig::default())),
+            Arc::clone(&features),
+        );
+        std::fs::write(&path, format!("{LISTEN_ON}[features]\nchat = false\n")).unwrap();
+
+        // when
+        reloader.reload().unwrap();
+
+        // then
+        assert!(!features.is_enabled(Feature::Chat));
+        assert!(features.is_enabled(Feature::Queue));
+    }
+
+    #[test]
+    fn should_return_error_on_unknown_profile() {
+        // given
+        let mut config_file = Cursor::new(PROFILE_CONFIG);
+
+        // when
+        let result = Config::read(&mut config_file, Some("staging"));
 
         // then
         assert!(result.is_err());
@@ -1,48 +1,200 @@
 use std::{
     collections::VecDeque,
     fmt::Display,
+    mem,
     net::{SocketAddr, ToSocketAddrs},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
 use anyhow::{anyhow, Context};
-use futures::executor;
+use futures::{executor, future::BoxFuture};
 use futures_util::Future;
 use log::{debug, error, info};
 use serde::Deserialize;
 use tokio::{
     net::{TcpListener, TcpStream},
-    time::timeout,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{timeout, Instant},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::server::{Callback, ErrorResponse, Request, Response},
+        http::header::{HOST, SEC_WEBSOCKET_PROTOCOL},
+        protocol::{frame::coding::CloseCode, CloseFrame, Role, WebSocketConfig},
+    },
+    WebSocketStream,
 };
-use tokio_tungstenite::WebSocketStream;
 
 use crate::{
-    api_access::{ApiAccessManager, ApiPermissions},
-    messages::{dto, Message, MessageBody, MessageChannel},
-    utils::timestamp,
+    api_access::ApiPermissions,
+    auth::{AuthProvider, Credentials},
+    errors::{error_code, ClientError, ErrorCode},
+    guest_names::{GuestName, GuestNames},
+    ip_filter::{IpFilter, IpFilterConfig, IpPermit},
+    messages::{
+        dto, Compression, FloodError, Message, MessageBody, MessageChannel, MessageMetrics,
+        DEPRECATIONS, MAX_PROTOCOL_VERSION, MIN_PROTOCOL_VERSION,
+    },
+    tls::{ClientStream, TlsAcceptor},
+    utils::{timestamp, TokenBucket},
 };
 
+/// What happens to new connections while the maximum number of handshakes is in progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// New connections are closed right away.
+    #[default]
+    Reject,
+
+    /// The server stops accepting connections until a handshake finishes, leaving new
+    /// connections in the operating system's backlog.
+    Wait,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HandshakeConfig {
+    /// How many TLS and WebSocket handshakes may be in progress at the same time.
+    pub max_concurrent: usize,
+
+    /// How long a handshake may take, in seconds.
+    pub timeout: u64,
+
+    pub overflow: OverflowPolicy,
+}
+
+/// Limits on what clients may send, to keep a single client from tying up the server.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct MessageLimitsConfig {
+    /// The largest message a client may send, in bytes.
+    pub max_message_size: usize,
+
+    /// How many bytes a client may send per second in the long run, or 0 for no limit.
+    pub bytes_per_second: u32,
+
+    /// How many bytes a client may send in quick succession. A message of the maximum size is
+    /// always allowed.
+    pub burst_bytes: u32,
+}
+
+impl Default for MessageLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: 64 * 1024,
+            bytes_per_second: 16 * 1024,
+            burst_bytes: 512 * 1024,
+        }
+    }
+}
+
+impl MessageLimitsConfig {
+    fn websocket_config(&self) -> WebSocketConfig {
+        WebSocketConfig {
+            max_message_size: Some(self.max_message_size),
+            max_frame_size: Some(self.max_message_size),
+            ..WebSocketConfig::default()
+        }
+    }
+
+    fn bandwidth(&self) -> Option<TokenBucket> {
+        if self.bytes_per_second == 0 {
+            return None;
+        }
+        let max_message_size = u32::try_from(self.max_message_size).unwrap_or(u32::MAX);
+        Some(TokenBucket::new(
+            self.burst_bytes.max(max_message_size),
+            f64::from(self.bytes_per_second),
+        ))
+    }
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 256,
+            timeout: 10,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Compresses large messages like room states for clients that support it.
+    pub enabled: bool,
+
+    /// Messages smaller than this many bytes are always sent uncompressed.
+    pub min_size: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size: 4096,
+        }
+    }
+}
+
+/// A plaintext listener that tells clients to use TLS instead, for clients that were configured
+/// with a `ws://` URL by mistake.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PlaintextRedirectConfig {
+    pub listen_on: String,
+
+    /// The URL clients are told to use. By default, it is made up of the host the client
+    /// connected to and the port of the TLS listener.
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct ServerConfig {
     pub listen_on: String,
+
+    /// Only allowed if TLS is enabled.
+    #[serde(default)]
+    pub plaintext_redirect: Option<PlaintextRedirectConfig>,
+
+    #[serde(default)]
+    pub handshakes: HandshakeConfig,
+
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    #[serde(default)]
+    pub message_limits: MessageLimitsConfig,
+
+    #[serde(default)]
+    pub ip_filter: IpFilterConfig,
+}
+
+/// Resolves a `listen_on` config value, which is either an address or just a port number.
+pub fn resolve_listen_addrs(listen_on: &str) -> anyhow::Result<Vec<SocketAddr>> {
+    if let Ok(addrs) = listen_on.to_socket_addrs() {
+        return Ok(addrs.collect());
+    }
+    if let Ok(port) = listen_on.parse::<u16>() {
+        let addrs = ("0.0.0.0", port)
+            .to_socket_addrs()
+            .context("Invalid port number")?;
+        return Ok(addrs.collect());
+    }
+    Err(anyhow!(
+        "Cannot listen on '{listen_on}': must be either a valid address or a port number"
+    ))
 }
 
 impl ServerConfig {
     fn get_socket_addrs(&self) -> anyhow::Result<Vec<SocketAddr>> {
-        if let Ok(addrs) = self.listen_on.to_socket_addrs() {
-            return Ok(addrs.collect());
-        }
-        if let Ok(port) = self.listen_on.parse::<u16>() {
-            let addrs = ("0.0.0.0", port)
-                .to_socket_addrs()
-                .context("Invalid port number")?;
-            return Ok(addrs.collect());
-        }
-        Err(anyhow!(
-            "Cannot listen on '{}': must be either a valid address or a port number",
-            self.listen_on
-        ))
+        resolve_listen_addrs(&self.listen_on)
     }
 }
 
@@ -50,36 +202,111 @@ impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             listen_on: "127.0.0.1:8069".to_string(),
+            plaintext_redirect: None,
+            handshakes: HandshakeConfig::default(),
+            compression: CompressionConfig::default(),
+            message_limits: MessageLimitsConfig::default(),
+            ip_filter: IpFilterConfig::default(),
         }
     }
 }
 
+/// Counters about incoming connections.
+#[derive(Debug, Default)]
+pub struct ListenerMetrics {
+    /// Whether the listener has started accepting connections.
+    pub listening: AtomicBool,
+    pub accepted: AtomicU64,
+    pub rejected: AtomicU64,
+    pub handshakes_in_flight: AtomicU64,
+    pub handshake_failures: AtomicU64,
+    pub messages: Arc<MessageMetrics>,
+}
+
+/// Allows a single handshake to run. The handshake counts as in flight until this is dropped.
+struct HandshakePermit {
+    _permit: OwnedSemaphorePermit,
+    metrics: Arc<ListenerMetrics>,
+}
+
+impl HandshakePermit {
+    fn new(permit: OwnedSemaphorePermit, metrics: Arc<ListenerMetrics>) -> Self {
+        metrics.accepted.fetch_add(1, Ordering::Relaxed);
+        metrics.handshakes_in_flight.fetch_add(1, Ordering::Relaxed);
+        Self {
+            _permit: permit,
+            metrics,
+        }
+    }
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        self.metrics
+            .handshakes_in_flight
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 pub struct ConnectionListener {
     listener: TcpListener,
+    tls: Option<Arc<dyn TlsAcceptor>>,
+    config: Arc<ServerConfig>,
+    handshakes: Arc<Semaphore>,
+    ip_filter: Arc<IpFilter>,
+    metrics: Arc<ListenerMetrics>,
 }
 
 impl ConnectionListener {
-    pub async fn bind(config: ServerConfig) -> anyhow::Result<Self> {
+    pub async fn bind(
+        config: ServerConfig,
+        tls: Option<Arc<dyn TlsAcceptor>>,
+        metrics: Arc<ListenerMetrics>,
+    ) -> anyhow::Result<Self> {
         let addrs = config.get_socket_addrs()?;
         let listener = TcpListener::bind(&*addrs)
             .await
             .context("Failed to start TCP server")?;
-        Ok(Self { listener })
+        Ok(Self {
+            listener,
+            tls,
+            handshakes: Arc::new(Semaphore::new(config.handshakes.max_concurrent)),
+            ip_filter: Arc::new(IpFilter::new(config.ip_filter.clone())),
+            config: Arc::new(config),
+            metrics,
+        })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        self.listener
+            .local_addr()
+            .context("Failed to determine bound address")
     }
 
     pub async fn listen<F: Future<Output = anyhow::Result<()>> + Send>(
         &self,
         handler: impl Fn(Connection) -> F + Send + Sync + 'static,
     ) -> anyhow::Result<()> {
-        let local_addr = self
-            .listener
-            .local_addr()
-            .context("Failed to determine bound address")?;
-        info!("Server listening on {}...", local_addr);
+        let local_addr = self.local_addr()?;
+        if self.tls.is_some() {
+            info!("Server listening on {} using TLS...", local_addr);
+        } else {
+            info!("Server listening on {}...", local_addr);
+        }
 
         let handler = Arc::new(handler);
+        self.metrics.listening.store(true, Ordering::Relaxed);
 
         loop {
+            let waited_permit = match self.config.handshakes.overflow {
+                OverflowPolicy::Wait => Some(
+                    Arc::clone(&self.handshakes)
+                        .acquire_owned()
+                        .await
+                        .context("Handshake limit was closed")?,
+                ),
+                OverflowPolicy::Reject => None,
+            };
             let (stream, addr) = match self.listener.accept().await {
                 Ok(val) => val,
                 Err(err) => {
@@ -87,10 +314,41 @@ impl ConnectionListener {
                     continue;
                 }
             };
+            // refused addresses don't get to take up a handshake
+            let ip_permit = match self.ip_filter.admit(addr.ip()) {
+                Ok(permit) => permit,
+                Err(refusal) => {
+                    self.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                    debug!("Rejected connection from {addr}: {refusal}");
+                    continue;
+                }
+            };
+            let permit = match waited_permit {
+                Some(permit) => permit,
+                None => match Arc::clone(&self.handshakes).try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(..) => {
+                        self.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                        debug!("Rejected connection from {addr}: too many handshakes in progress");
+                        continue;
+                    }
+                },
+            };
+            let permit = HandshakePermit::new(permit, Arc::clone(&self.metrics));
             let handler_ref = Arc::clone(&handler);
+            let tls = self.tls.clone();
+            let config = Arc::clone(&self.config);
             tokio::spawn(async move {
-                if let Err(err) =
-                    Self::handle_connection(addr.to_string(), stream, handler_ref).await
+                if let Err(err) = Self::handle_connection(
+                    addr.to_string(),
+                    stream,
+                    tls,
+                    permit,
+                    ip_permit,
+                    config,
+                    handler_ref,
+                )
+                .await
                 {
                     error!("Error during connection with {addr}: {err:?}");
                 }
@@ -101,38 +359,447 @@ impl ConnectionListener {
     async fn handle_connection<F: Future<Output = anyhow::Result<()>>>(
         name: String,
         stream: TcpStream,
+        tls: Option<Arc<dyn TlsAcceptor>>,
+        permit: HandshakePermit,
+        ip_permit: IpPermit,
+        config: Arc<ServerConfig>,
         handler: Arc<impl Fn(Connection) -> F>,
     ) -> anyhow::Result<()> {
-        let ws = tokio_tungstenite::accept_async(stream)
+        let handshake_timeout = Duration::from_secs(config.handshakes.timeout);
+        let handshake = timeout(
+            handshake_timeout,
+            Self::handshake(stream, tls, &config.message_limits),
+        )
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("Handshake timed out")));
+        if handshake.is_err() {
+            permit
+                .metrics
+                .handshake_failures
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        let metrics = Arc::clone(&permit.metrics.messages);
+        drop(permit);
+        let Some((ws, format)) = handshake? else {
+            return Ok(());
+        };
+
+        let mut channel = MessageChannel::new(ws);
+        channel.set_metrics(metrics);
+        channel.set_bandwidth(config.message_limits.bandwidth());
+        if let Some(format) = format {
+            channel.pin_format(format);
+        }
+        let mut connection = Connection::new(name, channel, config.compression.clone());
+        connection.ip_permit = Some(ip_permit);
+        handler(connection).await?;
+
+        Ok(())
+    }
+
+    async fn handshake(
+        stream: TcpStream,
+        tls: Option<Arc<dyn TlsAcceptor>>,
+        limits: &MessageLimitsConfig,
+    ) -> anyhow::Result<
+        Option<(
+            WebSocketStream<Box<dyn ClientStream>>,
+            Option<dto::MessageFormatV1>,
+        )>,
+    > {
+        let stream: Box<dyn ClientStream> = match tls {
+            Some(tls) => match tls.accept(stream).await? {
+                Some(stream) => stream,
+                None => return Ok(None),
+            },
+            None => Box::new(stream),
+        };
+        let mut format = None;
+        let ws = tokio_tungstenite::accept_hdr_async_with_config(
+            stream,
+            SubprotocolSelection(&mut format),
+            Some(limits.websocket_config()),
+        )
+        .await
+        .context("Failed to accept websocket connection")?;
+        Ok(Some((ws, format)))
+    }
+}
+
+/// Accepts plaintext WebSocket connections only to close them again with the URL of the TLS
+/// listener.
+pub struct RedirectListener {
+    listener: TcpListener,
+    url: Option<String>,
+    tls_addr: SocketAddr,
+    handshake_timeout: Duration,
+}
+
+impl RedirectListener {
+    pub async fn bind(
+        config: PlaintextRedirectConfig,
+        tls_addr: SocketAddr,
+        handshake_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let addrs = resolve_listen_addrs(&config.listen_on)?;
+        let listener = TcpListener::bind(&*addrs)
+            .await
+            .context("Failed to start plaintext redirect listener")?;
+        Ok(Self {
+            listener,
+            url: config.url,
+            tls_addr,
+            handshake_timeout,
+        })
+    }
+
+    pub async fn listen(self) {
+        match self.listener.local_addr() {
+            Ok(addr) => info!("Redirecting plaintext connections on {addr} to TLS..."),
+            Err(err) => error!("Failed to determine bound redirect address: {err:?}"),
+        }
+        let listener = Arc::new(self);
+        loop {
+            let (stream, addr) = match listener.listener.accept().await {
+                Ok(val) => val,
+                Err(err) => {
+                    error!("TCP connection failed: {err:?}");
+                    continue;
+                }
+            };
+            let listener = Arc::clone(&listener);
+            tokio::spawn(async move {
+                let redirect = timeout(listener.handshake_timeout, listener.redirect(stream))
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow!("Handshake timed out")));
+                if let Err(err) = redirect {
+                    debug!("Failed to redirect plaintext connection from {addr}: {err:?}");
+                }
+            });
+        }
+    }
+
+    async fn redirect(&self, stream: TcpStream) -> anyhow::Result<()> {
+        let name = stream.peer_addr()?.to_string();
+        let mut host = None;
+        let stream: Box<dyn ClientStream> = Box::new(stream);
+        let ws = tokio_tungstenite::accept_hdr_async(stream, HostCapture(&mut host))
             .await
             .context("Failed to accept websocket connection")?;
+        let url = match &self.url {
+            Some(url) => url.clone(),
+            None => tls_url(host.as_deref(), self.tls_addr),
+        };
+        debug!("Redirecting plaintext connection from {name} to {url}");
+        Connection::new(name, MessageChannel::new(ws), CompressionConfig::default())
+            .redirect(url)
+            .await
+    }
+}
 
-        handler(Connection::new(name, ws)).await?;
+/// Carries messages between the server and a client, which is a WebSocket everywhere but in tests.
+pub trait ClientTransport: Send {
+    fn send(&mut self, message: Message) -> BoxFuture<'_, anyhow::Result<()>>;
 
-        Ok(())
+    /// Returns `None` once the client is gone.
+    fn recv(&mut self) -> BoxFuture<'_, Option<anyhow::Result<Message>>>;
+
+    fn close(&mut self, frame: Option<CloseFrame<'static>>) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    /// Sends a transport-level ping, if the transport has those.
+    fn send_ping(&mut self) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    /// The round trip time of the last transport-level ping, once it was answered.
+    fn transport_latency(&self) -> Option<Duration>;
+
+    fn set_compression(&mut self, compression: Option<Compression>);
+
+    fn compression(&self) -> Option<Compression>;
+
+    fn pin_format(&mut self, format: dto::MessageFormatV1);
+
+    fn pinned_format(&self) -> Option<dto::MessageFormatV1>;
+
+    fn set_protocol_version(&mut self, version: u32);
+
+    fn protocol_version(&self) -> u32;
+
+    /// Whether the client closed the transport on purpose, rather than the connection being lost.
+    fn close_received(&self) -> bool;
+}
+
+impl ClientTransport for MessageChannel<WebSocketStream<Box<dyn ClientStream>>> {
+    fn send(&mut self, message: Message) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(MessageChannel::send(self, message))
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Option<anyhow::Result<Message>>> {
+        Box::pin(MessageChannel::recv(self))
+    }
+
+    fn close(&mut self, frame: Option<CloseFrame<'static>>) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(MessageChannel::close(self, frame))
+    }
+
+    fn send_ping(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(MessageChannel::send_ping(self))
+    }
+
+    fn transport_latency(&self) -> Option<Duration> {
+        MessageChannel::transport_latency(self)
+    }
+
+    fn set_compression(&mut self, compression: Option<Compression>) {
+        MessageChannel::set_compression(self, compression)
+    }
+
+    fn compression(&self) -> Option<Compression> {
+        MessageChannel::compression(self)
+    }
+
+    fn pin_format(&mut self, format: dto::MessageFormatV1) {
+        MessageChannel::pin_format(self, format)
+    }
+
+    fn pinned_format(&self) -> Option<dto::MessageFormatV1> {
+        MessageChannel::pinned_format(self)
+    }
+
+    fn set_protocol_version(&mut self, version: u32) {
+        MessageChannel::set_protocol_version(self, version)
+    }
+
+    fn protocol_version(&self) -> u32 {
+        MessageChannel::protocol_version(self)
+    }
+
+    fn close_received(&self) -> bool {
+        MessageChannel::close_received(self)
+    }
+}
+
+/// Remembers the `Host` header of a WebSocket handshake.
+struct HostCapture<'a>(&'a mut Option<String>);
+
+impl Callback for HostCapture<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        *self.0 = request
+            .headers()
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(str::to_string);
+        Ok(response)
     }
 }
 
+/// Lets clients pin the message format by asking for the `palantir.msgpack` or `palantir.json`
+/// WebSocket subprotocol.
+struct SubprotocolSelection<'a>(&'a mut Option<dto::MessageFormatV1>);
+
+impl SubprotocolSelection<'_> {
+    fn format(protocol: &str) -> Option<dto::MessageFormatV1> {
+        match protocol.trim() {
+            "palantir.msgpack" => Some(dto::MessageFormatV1::Msgpack),
+            "palantir.json" => Some(dto::MessageFormatV1::Json),
+            _ => None,
+        }
+    }
+}
+
+impl Callback for SubprotocolSelection<'_> {
+    fn on_request(
+        self,
+        request: &Request,
+        mut response: Response,
+    ) -> Result<Response, ErrorResponse> {
+        let selected = request
+            .headers()
+            .get_all(SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .find(|protocol| Self::format(protocol).is_some());
+        if let Some(protocol) = selected {
+            *self.0 = Self::format(protocol);
+            if let Ok(value) = protocol.trim().parse() {
+                response.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, value);
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Builds the URL of the TLS listener from the `Host` header of a request to another port.
+fn tls_url(host: Option<&str>, tls_addr: SocketAddr) -> String {
+    let host = match host {
+        // IPv6 addresses are in brackets, which keeps their colons apart from the port
+        Some(host) if host.starts_with('[') => host.split_inclusive(']').next().unwrap_or(host),
+        Some(host) => host.split(':').next().unwrap_or(host),
+        None => return format!("wss://{tls_addr}"),
+    };
+    format!("wss://{host}:{}", tls_addr.port())
+}
+
 pub struct Connection {
     open: bool,
     name: String,
     username: Option<String>,
+    /// Holds on to the user's generated name, if they got one, so that no other guest gets it.
+    guest_name: Option<GuestName>,
+    /// The user's name came from their account rather than the client, so it can't be changed.
+    name_from_identity: bool,
     permissions: ApiPermissions,
-    channel: MessageChannel<WebSocketStream<TcpStream>>,
+    subject: Option<String>,
+    fingerprint: Option<String>,
+    channel: Box<dyn ClientTransport>,
     interrupted_message_buffer: VecDeque<Message>,
+    /// The login came at the start of a batch that was passed on to the session, which still has
+    /// to report it.
+    batched_login: bool,
+    connected_at: Instant,
+    last_ping: Option<PingResult>,
+    errors_sent: u32,
+    compression_config: CompressionConfig,
+    resume_token: Option<String>,
+    /// Counts towards the limits of the client's address until the connection closes.
+    ip_permit: Option<IpPermit>,
+}
+
+/// How a client started its connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Login {
+    New,
+
+    /// The client wants to resume the session with the given token.
+    Resume(String),
+}
+
+/// What the server knows about the health of a connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    pub connected_for: Duration,
+    pub last_ping: Option<PingResult>,
+    pub errors_sent: u32,
 }
 
 #[derive(Debug, Clone)]
 pub struct PingResult {
-    #[allow(unused)]
     pub latency: u64,
+    /// The round trip time of the WebSocket ping sent alongside, without the time the client
+    /// took to answer.
+    pub transport_latency: Option<u64>,
     pub time_offset: i64,
 }
 
+impl PingResult {
+    /// Works out the client's time offset from a ping sent at `start_time` and answered with
+    /// `client_timestamp` at `end_time`. Clients stamp their pong right before sending it, so
+    /// with a transport round trip time the pong's flight is known, and whatever time the client
+    /// took to get to the ping doesn't skew the offset. Without one, the pong is assumed to have
+    /// been stamped halfway through.
+    fn new(
+        start_time: u64,
+        end_time: u64,
+        client_timestamp: u64,
+        transport_latency: Option<u64>,
+    ) -> Self {
+        let latency = u64::saturating_sub(end_time, start_time);
+        let transport_latency = transport_latency.filter(|rtt| *rtt <= latency);
+        let expected_timestamp = match transport_latency {
+            Some(rtt) => end_time - rtt / 2,
+            None => start_time + latency / 2,
+        };
+        Self {
+            latency,
+            transport_latency,
+            time_offset: u64::wrapping_sub(client_timestamp, expected_timestamp) as i64,
+        }
+    }
+
+    pub fn sync_quality(&self) -> SyncQuality {
+        SyncQuality::from_latency(self.latency)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncQuality {
+    Good,
+    Fair,
+    Poor,
+}
+
+impl SyncQuality {
+    const GOOD_LATENCY: u64 = 100;
+    const FAIR_LATENCY: u64 = 300;
+
+    /// Estimates how well a client will be able to stay in sync, based on the
+    /// round trip time of a ping in milliseconds.
+    pub fn from_latency(latency: u64) -> Self {
+        if latency <= Self::GOOD_LATENCY {
+            Self::Good
+        } else if latency <= Self::FAIR_LATENCY {
+            Self::Fair
+        } else {
+            Self::Poor
+        }
+    }
+}
+
+impl From<SyncQuality> for dto::SyncQualityV1 {
+    fn from(value: SyncQuality) -> Self {
+        match value {
+            SyncQuality::Good => Self::Good,
+            SyncQuality::Fair => Self::Fair,
+            SyncQuality::Poor => Self::Poor,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CloseReason {
     ServerError,
     Unauthorized,
+    UseTls,
+    /// The client stopped answering pings.
+    Timeout,
+    UnsupportedProtocol,
+    Overloaded,
+    /// An operator ended the session.
+    Kicked,
+    /// The client sent more data than it may.
+    Flooding,
+    /// The server is shutting down.
+    Restart,
+}
+
+impl CloseReason {
+    /// Control frames are limited to 125 bytes, two of which are taken by the close code.
+    const MAX_CLOSE_REASON_LEN: usize = 123;
+
+    fn close_code(self) -> CloseCode {
+        match self {
+            Self::ServerError => CloseCode::Error,
+            Self::Unauthorized => CloseCode::Library(4001),
+            Self::UseTls => CloseCode::Library(4002),
+            Self::Timeout => CloseCode::Library(4003),
+            Self::UnsupportedProtocol => CloseCode::Library(4004),
+            Self::Overloaded => CloseCode::Library(4005),
+            Self::Kicked => CloseCode::Library(4006),
+            Self::Flooding => CloseCode::Library(4007),
+            Self::Restart => CloseCode::Restart,
+        }
+    }
+
+    fn close_frame(self, message: &str) -> CloseFrame<'static> {
+        let mut len = message.len().min(Self::MAX_CLOSE_REASON_LEN);
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+        CloseFrame {
+            code: self.close_code(),
+            reason: message[..len].to_string().into(),
+        }
+    }
 }
 
 impl From<CloseReason> for dto::ConnectionClosedReasonV1 {
@@ -140,26 +807,78 @@ impl From<CloseReason> for dto::ConnectionClosedReasonV1 {
         match value {
             CloseReason::ServerError => dto::ConnectionClosedReasonV1::ServerError,
             CloseReason::Unauthorized => dto::ConnectionClosedReasonV1::Unauthorized,
+            CloseReason::UseTls => dto::ConnectionClosedReasonV1::UseTls,
+            CloseReason::Timeout => dto::ConnectionClosedReasonV1::Timeout,
+            CloseReason::UnsupportedProtocol => dto::ConnectionClosedReasonV1::UnsupportedProtocol,
+            CloseReason::Overloaded => dto::ConnectionClosedReasonV1::Overloaded,
+            CloseReason::Kicked => dto::ConnectionClosedReasonV1::Kicked,
+            CloseReason::Flooding => dto::ConnectionClosedReasonV1::Flooding,
+            CloseReason::Restart => dto::ConnectionClosedReasonV1::Restart,
         }
     }
 }
 
+/// Picks the newest protocol version that both sides speak, if there is one.
+fn negotiate_protocol_version(supported: &[u32]) -> Option<u32> {
+    if supported.is_empty() {
+        return Some(MIN_PROTOCOL_VERSION);
+    }
+    supported
+        .iter()
+        .copied()
+        .filter(|version| (MIN_PROTOCOL_VERSION..=MAX_PROTOCOL_VERSION).contains(version))
+        .max()
+}
+
 impl Connection {
     const LOGIN_TIMEOUT: Duration = Duration::from_secs(3);
     const PING_TIMEOUT: Duration = Duration::from_secs(5);
 
-    pub fn new(name: String, ws: WebSocketStream<TcpStream>) -> Self {
+    pub fn new(
+        name: String,
+        transport: impl ClientTransport + 'static,
+        compression_config: CompressionConfig,
+    ) -> Self {
         debug!("Creating connection {name}");
         Self {
             open: true,
             name,
             username: None,
+            guest_name: None,
+            name_from_identity: false,
             permissions: ApiPermissions::default(),
-            channel: MessageChannel::new(ws),
+            subject: None,
+            fingerprint: None,
+            channel: Box::new(transport),
             interrupted_message_buffer: VecDeque::new(),
+            batched_login: false,
+            connected_at: Instant::now(),
+            last_ping: None,
+            errors_sent: 0,
+            compression_config,
+            resume_token: None,
+            ip_permit: None,
         }
     }
 
+    /// A connection over any byte stream, such as an in-process duplex stream or a pipe. The
+    /// stream carries WebSocket frames, just without the HTTP handshake that would come first on
+    /// a socket, so that tests and embedding applications can talk to a full session.
+    pub async fn over_stream(
+        name: String,
+        stream: impl ClientStream + 'static,
+        config: &ServerConfig,
+    ) -> Self {
+        let stream: Box<dyn ClientStream> = Box::new(stream);
+        let limits = &config.message_limits;
+        let ws =
+            WebSocketStream::from_raw_socket(stream, Role::Server, Some(limits.websocket_config()))
+                .await;
+        let mut channel = MessageChannel::new(ws);
+        channel.set_bandwidth(limits.bandwidth());
+        Connection::new(name, channel, config.compression.clone())
+    }
+
     pub fn is_open(&self) -> bool {
         self.open
     }
@@ -171,38 +890,121 @@ impl Connection {
             .unwrap_or("Not logged in")
     }
 
+    /// Changes the name the user goes by for the rest of the connection, unless it came from
+    /// their account or the server makes everyone go by a generated one.
+    pub fn rename(&mut self, name: String) -> Result<(), ClientError> {
+        self.check_rename()?;
+        self.username = Some(name);
+        self.guest_name = None;
+        Ok(())
+    }
+
+    /// Fails if the user isn't allowed to change their name.
+    pub fn check_rename(&self) -> Result<(), ClientError> {
+        if self.name_from_identity {
+            return Err(ClientError::not_authorized(
+                "Your name comes from your account and can't be changed",
+            ));
+        }
+        if self.guest_name.as_ref().is_some_and(GuestName::is_enforced) {
+            return Err(ClientError::not_authorized(
+                "Everyone goes by a generated name on this server",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the user goes by a generated name.
+    pub fn is_guest(&self) -> bool {
+        self.guest_name.is_some()
+    }
+
     pub fn permissions(&self) -> &ApiPermissions {
         &self.permissions
     }
 
-    pub async fn init(&mut self, access_mgr: &ApiAccessManager) -> anyhow::Result<()> {
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    pub fn fingerprint(&self) -> Option<&str> {
+        self.fingerprint.as_deref()
+    }
+
+    pub fn resume_token(&self) -> Option<&str> {
+        self.resume_token.as_deref()
+    }
+
+    /// Whether the client closed the connection on purpose, rather than losing it.
+    pub fn closed_by_client(&self) -> bool {
+        self.channel.close_received()
+    }
+
+    /// How well the client can stay in sync, if it has answered a ping yet.
+    pub fn sync_quality(&self) -> Option<SyncQuality> {
+        self.last_ping.as_ref().map(PingResult::sync_quality)
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            connected_for: self.connected_at.elapsed(),
+            last_ping: self.last_ping.clone(),
+            errors_sent: self.errors_sent,
+        }
+    }
+
+    /// Waits for the client to either log in or ask to resume a session. `resume_token` is
+    /// handed out on a successful login.
+    pub async fn init(
+        &mut self,
+        auth_provider: &dyn AuthProvider,
+        guest_names: &Arc<GuestNames>,
+        resume_token: Option<String>,
+    ) -> anyhow::Result<Login> {
         debug!("Waiting for login message on connection {}...", self.name);
-        'wait_for_login: loop {
+        loop {
             match timeout(Self::LOGIN_TIMEOUT, self.raw_recv()).await {
                 Ok(None) => return Err(anyhow!("Connection closed before logging in")),
                 Ok(Some(Message {
                     body: MessageBody::ConnectionLoginV1(body),
                     ..
                 })) => {
-                    self.username = Some(body.username);
-                    self.permissions = access_mgr.get_permissions(body.api_key.as_deref());
-                    debug!(
-                        "Connection with {} has permissions {:?}",
-                        self.name, self.permissions
-                    );
-                    if !self.permissions.connect {
-                        self.close(CloseReason::Unauthorized, "Unauthorized")
-                            .await
-                            .context("Failed to close unauthorized connection")?;
-                        return Err(anyhow!("Unauthorized"));
-                    } else {
-                        self.send(Message::new(MessageBody::ConnectionLoginAckV1))
-                            .await
-                            .context("Failed to send login ack message")?;
-                        break 'wait_for_login;
-                    }
+                    self.login(auth_provider, guest_names, body, resume_token)
+                        .await?;
+                    return Ok(Login::New);
+                }
+                Ok(Some(Message {
+                    body: MessageBody::BatchV1(batch),
+                    ..
+                })) => {
+                    let Some(Message {
+                        body: MessageBody::ConnectionLoginV1(body),
+                        ..
+                    }) = batch.messages.first()
+                    else {
+                        self.send_error(ErrorCode::InvalidRequest, "Expected login message")
+                            .await;
+                        continue;
+                    };
+                    self.login(auth_provider, guest_names, body.clone(), resume_token)
+                        .await?;
+                    // the session handles the rest of the batch, and reports the login as part
+                    // of it
+                    self.forward_message_from_interrupt(Message::new(MessageBody::BatchV1(batch)));
+                    self.batched_login = true;
+                    return Ok(Login::New);
+                }
+                Ok(Some(Message {
+                    body: MessageBody::ConnectionResumeV1(body),
+                    ..
+                })) => {
+                    debug!("Connection {} wants to resume a session", self.name);
+                    return Ok(Login::Resume(body.token));
+                }
+                Ok(Some(Message { .. })) => {
+                    self.send_error(ErrorCode::InvalidRequest, "Expected login message")
+                        .await
                 }
-                Ok(Some(Message { .. })) => self.send_error("Expected login message").await,
                 Err(timeout_err) => {
                     let err = anyhow!(timeout_err).context("Login message not received in time!");
                     self.close(CloseReason::Unauthorized, &err)
@@ -212,19 +1014,175 @@ impl Connection {
                 }
             }
         }
+    }
+
+    /// Authenticates the client and acknowledges its login.
+    async fn login(
+        &mut self,
+        auth_provider: &dyn AuthProvider,
+        guest_names: &Arc<GuestNames>,
+        body: dto::ConnectionLoginMsgBodyV1,
+        resume_token: Option<String>,
+    ) -> anyhow::Result<()> {
+        let Some(protocol_version) = negotiate_protocol_version(&body.protocol_versions) else {
+            let message = format!(
+                "This server speaks protocol versions {MIN_PROTOCOL_VERSION} to \
+                     {MAX_PROTOCOL_VERSION}"
+            );
+            self.close(CloseReason::UnsupportedProtocol, &message)
+                .await
+                .context("Failed to close connection with unsupported protocol")?;
+            return Err(anyhow!(message));
+        };
+        let compression = self.negotiate_compression(&body.compression);
+        let format = body.format;
+        let fingerprint = body.fingerprint.clone();
+        let requested_name = body.username.clone();
+        let identity = auth_provider
+            .authenticate(&body.into())
+            .await
+            .context("Failed to authenticate connection")?;
+        self.name_from_identity =
+            identity.subject.is_some() || identity.display_name != requested_name;
+        self.guest_name = guest_names
+            .applies_to(&identity.display_name)
+            .then(|| guest_names.assign());
+        self.username = Some(match &self.guest_name {
+            Some(guest_name) => guest_name.as_str().to_string(),
+            None => identity.display_name,
+        });
+        self.permissions = identity.permissions;
+        self.subject = identity.subject;
+        self.fingerprint = fingerprint;
+        debug!(
+            "Connection with {} has permissions {:?}",
+            self.name, self.permissions
+        );
+        if !self.permissions.connect {
+            self.close(CloseReason::Unauthorized, "Unauthorized")
+                .await
+                .context("Failed to close unauthorized connection")?;
+            return Err(anyhow!("Unauthorized"));
+        }
+        self.channel.set_compression(compression);
+        self.channel.set_protocol_version(protocol_version);
+        if let Some(format) = format {
+            self.channel.pin_format(format);
+        }
+        self.resume_token = resume_token.clone();
+        self.send(Message::new(MessageBody::ConnectionLoginAckV1(
+            dto::ConnectionLoginAckMsgBodyV1 {
+                resume_token,
+                protocol_version,
+                name: self.username().to_string(),
+            },
+        )))
+        .await
+        .context("Failed to send login ack message")?;
         debug!("Connection {} logged in successfully", self.name);
         Ok(())
     }
 
+    /// Replaces the permissions of a logged-in connection with those granted by new credentials.
+    /// If they don't allow connecting at all, the old permissions are kept.
+    pub async fn reauth(
+        &mut self,
+        auth_provider: &dyn AuthProvider,
+        api_key: Option<String>,
+        token: Option<String>,
+    ) -> anyhow::Result<()> {
+        let credentials = Credentials {
+            username: self.username().to_string(),
+            api_key,
+            token,
+        };
+        let identity = auth_provider
+            .authenticate(&credentials)
+            .await
+            .context("Failed to authenticate new credentials")?;
+        if !identity.permissions.connect {
+            return Err(ClientError::not_authorized(
+                "The new credentials don't permit connecting; keeping the old ones",
+            )
+            .into());
+        }
+        self.permissions = identity.permissions;
+        debug!(
+            "Connection with {} now has permissions {:?}",
+            self.name, self.permissions
+        );
+        Ok(())
+    }
+
+    /// Takes over the WebSocket of a new connection that resumed this connection's session, and
+    /// acknowledges the resumption with a fresh resume token.
+    pub async fn reattach(
+        &mut self,
+        mut other: Connection,
+        resume_token: Option<String>,
+    ) -> anyhow::Result<()> {
+        debug!("Connection {} is resuming {}", other.name, self.name);
+        let compression = self.channel.compression();
+        let protocol_version = self.channel.protocol_version();
+        let format = self.channel.pinned_format();
+        mem::swap(&mut self.channel, &mut other.channel);
+        mem::swap(&mut self.name, &mut other.name);
+        mem::swap(&mut self.ip_permit, &mut other.ip_permit);
+        // the old websocket is already closed, so `other` shouldn't try to close it again
+        other.open = false;
+        self.open = true;
+        self.channel.set_compression(compression);
+        self.channel.set_protocol_version(protocol_version);
+        // a format pinned through the new connection's subprotocol takes precedence
+        if let (Some(format), None) = (format, self.channel.pinned_format()) {
+            self.channel.pin_format(format);
+        }
+        self.interrupted_message_buffer.clear();
+        self.connected_at = other.connected_at;
+        self.last_ping = None;
+        self.errors_sent = 0;
+        self.resume_token = resume_token.clone();
+        self.send(Message::new(MessageBody::ConnectionResumeAckV1(
+            dto::ConnectionResumeAckMsgBodyV1 { resume_token },
+        )))
+        .await
+        .context("Failed to send resume ack message")
+    }
+
+    /// Picks the best compression algorithm that the client supports, if any.
+    fn negotiate_compression(&self, supported: &[dto::CompressionV1]) -> Option<Compression> {
+        if !self.compression_config.enabled {
+            return None;
+        }
+        let algorithm = [dto::CompressionV1::Zstd, dto::CompressionV1::Zlib]
+            .into_iter()
+            .find(|algorithm| supported.contains(algorithm))?;
+        debug!(
+            "Using {algorithm:?} compression on connection {}",
+            self.name
+        );
+        Some(Compression {
+            algorithm,
+            min_size: self.compression_config.min_size,
+        })
+    }
+
+    /// Messages that changed in a newer protocol version than the client uses are sent in the
+    /// form the client understands, and possibly the newer one as well.
     pub async fn send(&mut self, message: Message) -> anyhow::Result<()> {
-        self.channel.send(message).await?;
+        let protocol_version = self.channel.protocol_version();
+        for message in message.for_protocol_version(protocol_version, DEPRECATIONS) {
+            self.channel.send(message).await?;
+        }
         Ok(())
     }
 
-    pub async fn send_error(&mut self, message: impl Display) {
+    pub async fn send_error(&mut self, code: ErrorCode, message: impl Display) {
+        self.errors_sent = self.errors_sent.saturating_add(1);
         let _ = self
             .send(Message::new(MessageBody::ConnectionClientErrorV1(
                 dto::ConnectionClientErrorMsgBodyV1 {
+                    code: code.into(),
                     message: message.to_string(),
                 },
             )))
@@ -242,17 +1200,33 @@ impl Connection {
             };
             match msg_res {
                 Ok(msg) => return Some(msg),
+                Err(err) if err.downcast_ref::<FloodError>().is_some() => {
+                    info!("Disconnecting client {}: {err}", self.name);
+                    if let Err(err) = self.close(CloseReason::Flooding, &err).await {
+                        debug!("Failed to close flooding connection {}: {err:?}", self.name);
+                    }
+                    return None;
+                }
                 Err(err) => {
                     log::debug!(
                         "Received malformed message from client {}: {err:?}",
                         self.name
                     );
-                    self.send_error(err.to_string()).await;
+                    let code = match error_code(&err) {
+                        ErrorCode::Other => ErrorCode::InvalidRequest,
+                        code => code,
+                    };
+                    self.send_error(code, err).await;
                 }
             }
         }
     }
 
+    /// Whether the login came at the start of a batch, which the session hasn't reported yet.
+    pub fn take_batched_login(&mut self) -> bool {
+        mem::take(&mut self.batched_login)
+    }
+
     fn forward_message_from_interrupt(&mut self, message: Message) {
         self.interrupted_message_buffer.push_back(message);
     }
@@ -272,15 +1246,23 @@ impl Connection {
                     }
                 }
                 Message {
-                    body: MessageBody::ConnectionKeepaliveV1,
+                    body:
+                        MessageBody::ConnectionKeepaliveV1(dto::ConnectionKeepaliveMsgBodyV1 {
+                            telemetry: None,
+                        }),
                     ..
                 } => {
                     // do nothing
                 }
                 Message {
                     body:
-                        MessageBody::ConnectionLoginAckV1
+                        MessageBody::ConnectionLoginAckV1(..)
+                        | MessageBody::ConnectionResumeV1(..)
+                        | MessageBody::ConnectionResumeAckV1(..)
                         | MessageBody::ConnectionPongV1
+                        | MessageBody::ConnectionProbeV1(..)
+                        | MessageBody::ConnectionStatsV1(..)
+                        | MessageBody::ConnectionMyStatsAckV1(..)
                         | MessageBody::ConnectionLoginV1(..)
                         | MessageBody::ConnectionClosedV1(..)
                         | MessageBody::ConnectionClientErrorV1(..),
@@ -311,6 +1293,9 @@ impl Connection {
     pub async fn ping(&mut self) -> anyhow::Result<Option<PingResult>> {
         let ping = Message::new(MessageBody::ConnectionPingV1);
         let start_time = ping.timestamp;
+        if let Err(err) = self.channel.send_ping().await {
+            debug!("Failed to send WebSocket ping to {}: {err:?}", self.name);
+        }
         self.send(ping).await?;
 
         let pong_result = timeout(
@@ -324,18 +1309,18 @@ impl Connection {
         match pong_result {
             Ok(None) => Ok(None),
             Ok(Some(actual_timestamp)) => {
-                let end_time = timestamp();
-                let latency = u64::saturating_sub(end_time, start_time);
-                let expected_timestamp = start_time + latency / 2;
-                let time_offset = u64::wrapping_sub(actual_timestamp, expected_timestamp) as i64;
+                let transport_latency = self
+                    .channel
+                    .transport_latency()
+                    .map(|rtt| rtt.as_millis() as u64);
+                let result =
+                    PingResult::new(start_time, timestamp(), actual_timestamp, transport_latency);
                 debug!(
-                    "Pinged client {}, and found a time offset of {time_offset}ms",
-                    self.name
+                    "Pinged client {}, and found a time offset of {}ms",
+                    self.name, result.time_offset
                 );
-                Ok(Some(PingResult {
-                    latency,
-                    time_offset,
-                }))
+                self.last_ping = Some(result.clone());
+                Ok(Some(result))
             }
             Err(timeout_err) => {
                 let err = anyhow!(timeout_err).context("Pong message not received in time!");
@@ -348,25 +1333,49 @@ impl Connection {
         &mut self,
         reason: CloseReason,
         message: impl Display,
+    ) -> anyhow::Result<()> {
+        self.close_with_redirect(reason, message, None).await
+    }
+
+    /// Closes the connection, telling the client to connect to the given URL instead.
+    pub async fn redirect(mut self, url: String) -> anyhow::Result<()> {
+        let message = format!("This server requires TLS; connect to {url} instead");
+        self.close_with_redirect(CloseReason::UseTls, message, Some(url))
+            .await
+    }
+
+    async fn close_with_redirect(
+        &mut self,
+        reason: CloseReason,
+        message: impl Display,
+        redirect_to: Option<String>,
     ) -> anyhow::Result<()> {
         if !self.is_open() {
             return Ok(());
         }
+        let message = message.to_string();
         let result = self
             .send(Message::new(MessageBody::ConnectionClosedV1(
                 dto::ConnectionClosedMsgBodyV1 {
                     reason: reason.into(),
-                    message: message.to_string(),
+                    message: message.clone(),
+                    redirect_to,
                 },
             )))
             .await;
-        self.close_silent().await;
+        self.close_with_frame(Some(reason.close_frame(&message)))
+            .await;
         result
     }
 
     async fn close_silent(&mut self) {
+        self.close_with_frame(None).await;
+    }
+
+    async fn close_with_frame(&mut self, frame: Option<CloseFrame<'static>>) {
         self.open = false;
-        if let Err(err) = self.channel.close().await {
+        self.ip_permit = None;
+        if let Err(err) = self.channel.close(frame).await {
             error!("Failed to close websocket {}: {err:?}", self.name);
         }
     }
@@ -383,3 +1392,443 @@ impl Drop for Connection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api_access::{ApiAccessConfig, ApiAccessManager, ApiAccessPolicy, ApiKey},
+        auth::{self, AuthProviderConfig},
+        guest_names::GuestNameConfig,
+        testing::{FakeTransport, StreamClient},
+    };
+
+    #[test]
+    fn should_truncate_close_frame_reason() {
+        // given
+        let message = "ä".repeat(100);
+
+        // when
+        let frame = CloseReason::Unauthorized.close_frame(&message);
+
+        // then
+        assert_eq!(frame.code, CloseCode::Library(4001));
+        assert_eq!(frame.reason.len(), 122);
+    }
+
+    #[test]
+    fn should_close_with_the_standard_code_for_restarts() {
+        // when
+        let frame = CloseReason::Restart.close_frame("The server is restarting");
+
+        // then
+        assert_eq!(u16::from(frame.code), 1012);
+    }
+
+    #[test]
+    fn should_negotiate_newest_common_protocol_version() {
+        // given
+        let legacy = [];
+        let newer = [MAX_PROTOCOL_VERSION, MAX_PROTOCOL_VERSION + 1];
+        let unsupported = [MAX_PROTOCOL_VERSION + 1];
+
+        // when
+        let legacy = negotiate_protocol_version(&legacy);
+        let newer = negotiate_protocol_version(&newer);
+        let unsupported = negotiate_protocol_version(&unsupported);
+
+        // then
+        assert_eq!(legacy, Some(MIN_PROTOCOL_VERSION));
+        assert_eq!(newer, Some(MAX_PROTOCOL_VERSION));
+        assert_eq!(unsupported, None);
+    }
+
+    #[test]
+    fn should_use_transport_latency_for_time_offset() {
+        // given
+        let (start, end, client_timestamp) = (1000, 1300, 1250);
+
+        // when
+        let precise = PingResult::new(start, end, client_timestamp, Some(100));
+        let rough = PingResult::new(start, end, client_timestamp, None);
+        let implausible = PingResult::new(start, end, client_timestamp, Some(400));
+
+        // then
+        assert_eq!(precise.time_offset, 0);
+        assert_eq!(rough.time_offset, 100);
+        assert_eq!(implausible.transport_latency, None);
+        assert_eq!(implausible.time_offset, 100);
+    }
+
+    #[tokio::test]
+    async fn should_answer_pings_while_receiving() {
+        // given
+        let (transport, mut client) = FakeTransport::new();
+        let mut connection =
+            Connection::new("test".to_string(), transport, CompressionConfig::default());
+        for body in [MessageBody::ConnectionPingV1, MessageBody::RoomListV1] {
+            client.outgoing.send(Message::new(body)).unwrap();
+        }
+
+        // when
+        let received = connection.recv().await.unwrap();
+
+        // then
+        assert_eq!(received.body, MessageBody::RoomListV1);
+        let reply = client.incoming.recv().await.unwrap();
+        assert_eq!(reply.body, MessageBody::ConnectionPongV1);
+    }
+
+    #[tokio::test]
+    async fn should_update_permissions_on_reauth() {
+        // given
+        let access_mgr = ApiAccessManager::new(ApiAccessConfig {
+            api_policy: ApiAccessPolicy {
+                restrict_connect: true,
+                restrict_host: true,
+            },
+            api_keys: vec![
+                ApiKey {
+                    key: "HOST".to_string(),
+                    permissions: ApiPermissions::all(),
+                },
+                ApiKey {
+                    key: "BANNED".to_string(),
+                    permissions: ApiPermissions::none(),
+                },
+            ],
+        });
+        let auth_provider =
+            auth::create_provider(AuthProviderConfig::ApiKeys, Arc::new(access_mgr)).unwrap();
+        let (transport, _client) = FakeTransport::new();
+        let mut connection =
+            Connection::new("test".to_string(), transport, CompressionConfig::default());
+        connection.permissions = ApiPermissions::connect();
+
+        // when
+        let upgraded = connection
+            .reauth(&*auth_provider, Some("HOST".to_string()), None)
+            .await;
+        let banned = connection
+            .reauth(&*auth_provider, Some("BANNED".to_string()), None)
+            .await;
+
+        // then
+        assert!(upgraded.is_ok());
+        assert!(banned.is_err());
+        assert!(connection.permissions().host);
+    }
+
+    #[tokio::test]
+    async fn should_not_rename_users_whose_name_comes_from_their_account() {
+        // given
+        let access_mgr = ApiAccessManager::new(ApiAccessConfig {
+            api_keys: vec![ApiKey {
+                key: "AAAAA".to_string(),
+                permissions: ApiPermissions::all(),
+            }],
+            ..ApiAccessConfig::default()
+        });
+        let auth_provider =
+            auth::create_provider(AuthProviderConfig::ApiKeys, Arc::new(access_mgr)).unwrap();
+        let mut connections = Vec::new();
+        for api_key in [Some("AAAAA"), None] {
+            let (transport, client) = FakeTransport::new();
+            let mut connection =
+                Connection::new("test".to_string(), transport, CompressionConfig::default());
+            client
+                .outgoing
+                .send(Message::new(MessageBody::ConnectionLoginV1(
+                    dto::ConnectionLoginMsgBodyV1 {
+                        username: "alice".to_string(),
+                        api_key: api_key.map(str::to_string),
+                        token: None,
+                        compression: Vec::new(),
+                        protocol_versions: Vec::new(),
+                        format: None,
+                        fingerprint: None,
+                    },
+                )))
+                .unwrap();
+            connection
+                .init(&*auth_provider, &Arc::default(), None)
+                .await
+                .unwrap();
+            connections.push((connection, client));
+        }
+        let (mut anonymous, _anonymous_client) = connections.pop().unwrap();
+        let (mut member, _member_client) = connections.pop().unwrap();
+
+        // when
+        let member_renamed = member.rename("bob".to_string());
+        let anonymous_renamed = anonymous.rename("bob".to_string());
+
+        // then
+        assert_eq!(member_renamed.unwrap_err().code, ErrorCode::NotAuthorized);
+        assert_eq!(member.username(), "alice");
+        assert!(anonymous_renamed.is_ok());
+        assert_eq!(anonymous.username(), "bob");
+    }
+
+    #[tokio::test]
+    async fn should_not_let_guests_drop_enforced_generated_names() {
+        // given
+        let auth_provider = auth::create_provider(
+            AuthProviderConfig::ApiKeys,
+            Arc::new(ApiAccessManager::new(ApiAccessConfig::default())),
+        )
+        .unwrap();
+        let guest_names = Arc::new(GuestNames::new(GuestNameConfig { always: true }));
+        let (transport, client) = FakeTransport::new();
+        let mut connection =
+            Connection::new("test".to_string(), transport, CompressionConfig::default());
+        client
+            .outgoing
+            .send(Message::new(MessageBody::ConnectionLoginV1(
+                dto::ConnectionLoginMsgBodyV1 {
+                    username: "alice".to_string(),
+                    api_key: None,
+                    token: None,
+                    compression: Vec::new(),
+                    protocol_versions: Vec::new(),
+                    format: None,
+                    fingerprint: None,
+                },
+            )))
+            .unwrap();
+        connection
+            .init(&*auth_provider, &guest_names, None)
+            .await
+            .unwrap();
+        let generated = connection.username().to_string();
+
+        // when
+        let renamed = connection.rename("alice".to_string());
+
+        // then
+        assert_eq!(renamed.unwrap_err().code, ErrorCode::NotAuthorized);
+        assert!(connection.is_guest());
+        assert_eq!(connection.username(), generated);
+    }
+
+    #[tokio::test]
+    async fn should_pass_batches_starting_with_a_login_on_to_the_session() {
+        // given
+        let auth_provider = auth::create_provider(
+            AuthProviderConfig::ApiKeys,
+            Arc::new(ApiAccessManager::new(ApiAccessConfig::default())),
+        )
+        .unwrap();
+        let (transport, mut client) = FakeTransport::new();
+        let mut connection =
+            Connection::new("test".to_string(), transport, CompressionConfig::default());
+        let batch = MessageBody::BatchV1(dto::BatchMsgBodyV1 {
+            messages: vec![
+                Message::new(MessageBody::ConnectionLoginV1(
+                    dto::ConnectionLoginMsgBodyV1 {
+                        username: "alice".to_string(),
+                        api_key: None,
+                        token: None,
+                        compression: Vec::new(),
+                        protocol_versions: Vec::new(),
+                        format: None,
+                        fingerprint: None,
+                    },
+                )),
+                Message::new(MessageBody::RoomListV1),
+            ],
+        });
+        client.outgoing.send(Message::new(batch.clone())).unwrap();
+
+        // when
+        let login = connection
+            .init(&*auth_provider, &Arc::default(), None)
+            .await
+            .unwrap();
+        let received = connection.recv().await.unwrap();
+
+        // then
+        assert_eq!(login, Login::New);
+        assert_eq!(connection.username(), "alice");
+        assert!(matches!(
+            client.incoming.recv().await.unwrap().body,
+            MessageBody::ConnectionLoginAckV1(..)
+        ));
+        assert_eq!(received.body, batch);
+    }
+
+    #[tokio::test]
+    async fn should_log_in_over_in_process_streams() {
+        // given
+        let auth_provider = auth::create_provider(
+            AuthProviderConfig::ApiKeys,
+            Arc::new(ApiAccessManager::new(ApiAccessConfig::default())),
+        )
+        .unwrap();
+        let (mut connection, mut client) = StreamClient::connect().await;
+        client
+            .send(MessageBody::ConnectionLoginV1(
+                dto::ConnectionLoginMsgBodyV1 {
+                    username: "alice".to_string(),
+                    api_key: None,
+                    token: None,
+                    compression: Vec::new(),
+                    protocol_versions: Vec::new(),
+                    format: None,
+                    fingerprint: None,
+                },
+            ))
+            .await;
+
+        // when
+        let login = connection
+            .init(&*auth_provider, &Arc::default(), None)
+            .await
+            .unwrap();
+        connection.close(CloseReason::Kicked, "Bye").await.unwrap();
+
+        // then
+        assert_eq!(login, Login::New);
+        assert!(matches!(
+            client.recv().await.unwrap().body,
+            MessageBody::ConnectionLoginAckV1(..)
+        ));
+        assert!(matches!(
+            client.recv().await.unwrap().body,
+            MessageBody::ConnectionClosedV1(..)
+        ));
+        assert!(client.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_name_guests_who_log_in_without_a_name() {
+        // given
+        let auth_provider = auth::create_provider(
+            AuthProviderConfig::ApiKeys,
+            Arc::new(ApiAccessManager::new(ApiAccessConfig::default())),
+        )
+        .unwrap();
+        let (mut connection, mut client) = StreamClient::connect().await;
+        client
+            .send(MessageBody::ConnectionLoginV1(
+                dto::ConnectionLoginMsgBodyV1 {
+                    username: String::new(),
+                    api_key: None,
+                    token: None,
+                    compression: Vec::new(),
+                    protocol_versions: Vec::new(),
+                    format: None,
+                    fingerprint: None,
+                },
+            ))
+            .await;
+
+        // when
+        connection
+            .init(&*auth_provider, &Arc::default(), None)
+            .await
+            .unwrap();
+
+        // then
+        let MessageBody::ConnectionLoginAckV1(ack) = client.recv().await.unwrap().body else {
+            panic!("Expected a login ack");
+        };
+        assert_eq!(ack.name, connection.username());
+        assert_eq!(ack.name.split('-').count(), 3);
+        assert!(connection.is_guest());
+    }
+
+    #[tokio::test]
+    async fn should_disconnect_clients_that_send_oversized_messages() {
+        // given
+        let auth_provider = auth::create_provider(
+            AuthProviderConfig::ApiKeys,
+            Arc::new(ApiAccessManager::new(ApiAccessConfig::default())),
+        )
+        .unwrap();
+        let config = ServerConfig {
+            message_limits: MessageLimitsConfig {
+                max_message_size: 1024,
+                ..MessageLimitsConfig::default()
+            },
+            ..ServerConfig::default()
+        };
+        let (mut connection, mut client) = StreamClient::connect_with_config(&config).await;
+        client
+            .send(MessageBody::ConnectionLoginV1(
+                dto::ConnectionLoginMsgBodyV1 {
+                    username: "a".repeat(2048),
+                    api_key: None,
+                    token: None,
+                    compression: Vec::new(),
+                    protocol_versions: Vec::new(),
+                    format: None,
+                    fingerprint: None,
+                },
+            ))
+            .await;
+
+        // when
+        let login = connection
+            .init(&*auth_provider, &Arc::default(), None)
+            .await;
+
+        // then
+        assert!(login.is_err());
+        let MessageBody::ConnectionClosedV1(closed) = client.recv().await.unwrap().body else {
+            panic!("Expected the connection to be closed");
+        };
+        assert_eq!(closed.reason, dto::ConnectionClosedReasonV1::Flooding);
+        assert!(client.recv().await.is_none());
+    }
+
+    #[test]
+    fn should_redirect_to_tls_port_on_same_host() {
+        // given
+        let tls_addr: SocketAddr = "0.0.0.0:8069".parse().unwrap();
+
+        // when
+        let urls = [
+            tls_url(Some("example.com:8080"), tls_addr),
+            tls_url(Some("[::1]:8080"), tls_addr),
+            tls_url(Some("example.com"), tls_addr),
+            tls_url(None, tls_addr),
+        ];
+
+        // then
+        assert_eq!(
+            urls,
+            [
+                "wss://example.com:8069",
+                "wss://[::1]:8069",
+                "wss://example.com:8069",
+                "wss://0.0.0.0:8069",
+            ]
+        );
+    }
+
+    #[test]
+    fn should_estimate_sync_quality_from_latency() {
+        // given
+        let latencies = [0, 100, 101, 300, 301, 5000];
+
+        // when
+        let qualities: Vec<SyncQuality> = latencies
+            .into_iter()
+            .map(SyncQuality::from_latency)
+            .collect();
+
+        // then
+        assert_eq!(
+            qualities,
+            vec![
+                SyncQuality::Good,
+                SyncQuality::Good,
+                SyncQuality::Fair,
+                SyncQuality::Fair,
+                SyncQuality::Poor,
+                SyncQuality::Poor
+            ]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::{borrow::Cow, collections::HashSet, fs, path::PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+
+/// What happens to text that contains a filtered word.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterMode {
+    /// The text is rejected with an error.
+    #[default]
+    Reject,
+
+    /// Filtered words are replaced with asterisks.
+    Mask,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ContentFilterConfig {
+    /// A file with one filtered word per line. Nothing is filtered if this isn't set.
+    pub word_list: Option<PathBuf>,
+    pub mode: FilterMode,
+}
+
+/// Filters chat messages and room names against a list of words. Words are matched as a whole
+/// and regardless of case.
+#[derive(Debug, Clone, Default)]
+pub struct ContentFilter {
+    words: HashSet<String>,
+    mode: FilterMode,
+}
+
+impl ContentFilter {
+    pub fn load(config: &ContentFilterConfig) -> anyhow::Result<Self> {
+        let Some(path) = &config.word_list else {
+            return Ok(Self::default());
+        };
+        let contents = fs::read_to_string(path).context(format!(
+            "Failed to read content filter word list {}",
+            path.display()
+        ))?;
+        let filter = Self::new(contents.lines(), config.mode);
+        log::info!(
+            "Loaded {} words into the content filter",
+            filter.words.len()
+        );
+        Ok(filter)
+    }
+
+    pub fn new<'a>(words: impl IntoIterator<Item = &'a str>, mode: FilterMode) -> Self {
+        Self {
+            words: words
+                .into_iter()
+                .map(str::trim)
+                .filter(|word| !word.is_empty() && !word.starts_with('#'))
+                .map(str::to_lowercase)
+                .collect(),
+            mode,
+        }
+    }
+
+    /// Checks the given text, returning it with filtered words masked if necessary. `what`
+    /// describes the text for error messages, like "Chat messages".
+    pub fn apply<'a>(&self, text: &'a str, what: &str) -> anyhow::Result<Cow<'a, str>> {
+        let matches: Vec<(usize, &str)> = words(text)
+            .filter(|(_, word)| self.words.contains(&word.to_lowercase()))
+            .collect();
+        if matches.is_empty() {
+            return Ok(Cow::Borrowed(text));
+        }
+        match self.mode {
+            FilterMode::Reject => Err(anyhow!("{what} can't contain filtered words")),
+            FilterMode::Mask => {
+                let mut masked = String::with_capacity(text.len());
+                let mut end = 0;
+                for (start, word) in matches {
+                    masked.push_str(&text[end..start]);
+                    masked.extend(word.chars().map(|_| '*'));
+                    end = start + word.len();
+                }
+                masked.push_str(&text[end..]);
+                Ok(Cow::Owned(masked))
+            }
+        }
+    }
+}
+
+/// Splits text into its words along with their byte offsets.
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut start = None;
+    text.char_indices()
+        .chain([(text.len(), ' ')])
+        .filter_map(move |(i, c)| match (c.is_alphanumeric(), start) {
+            (true, None) => {
+                start = Some(i);
+                None
+            }
+            (false, Some(s)) => {
+                start = None;
+                Some((s, &text[s..i]))
+            }
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_mask_filtered_words() {
+        // given
+        let filter = ContentFilter::new(["darn"], FilterMode::Mask);
+
+        // when
+        let result = filter.apply("Darn, the darned popcorn burned. darn!", "Chat messages");
+
+        // then
+        assert_eq!(result.unwrap(), "****, the darned popcorn burned. ****!");
+    }
+
+    #[test]
+    fn should_reject_filtered_words() {
+        // given
+        let filter = ContentFilter::new(["darn", "# a comment"], FilterMode::Reject);
+
+        // when
+        let rejected = filter.apply("oh DARN", "Room names");
+        let accepted = filter.apply("a comment", "Room names");
+
+        // then
+        assert!(rejected.is_err());
+        assert_eq!(accepted.unwrap(), "a comment");
+    }
+}
@@ -0,0 +1,145 @@
+use std::{error::Error, fmt};
+
+use crate::{messages::dto, room::RoomError};
+
+/// What went wrong with a client's request, in a way that clients can react to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NotInRoom,
+    RoomNotFound,
+    WrongPassword,
+    NotAuthorized,
+    RoomFull,
+    RoomBusy,
+    InvalidRequest,
+    RateLimited,
+    UnsupportedVersion,
+    /// Someone in the room already goes by the name.
+    NameTaken,
+    ChatTooLong,
+    /// The chat message doesn't fit the format the room allows.
+    ChatFormatNotAllowed,
+    /// The operator turned off the feature the request needs.
+    FeatureDisabled,
+    /// Anything that doesn't have a more specific code.
+    Other,
+}
+
+impl From<ErrorCode> for dto::ClientErrorCodeV1 {
+    fn from(value: ErrorCode) -> Self {
+        match value {
+            ErrorCode::NotInRoom => Self::NotInRoom,
+            ErrorCode::RoomNotFound => Self::RoomNotFound,
+            ErrorCode::WrongPassword => Self::WrongPassword,
+            ErrorCode::NotAuthorized => Self::NotAuthorized,
+            ErrorCode::RoomFull => Self::RoomFull,
+            ErrorCode::RoomBusy => Self::RoomBusy,
+            ErrorCode::InvalidRequest => Self::InvalidRequest,
+            ErrorCode::RateLimited => Self::RateLimited,
+            ErrorCode::UnsupportedVersion => Self::UnsupportedVersion,
+            ErrorCode::NameTaken => Self::NameTaken,
+            ErrorCode::ChatTooLong => Self::ChatTooLong,
+            ErrorCode::ChatFormatNotAllowed => Self::ChatFormatNotAllowed,
+            ErrorCode::FeatureDisabled => Self::FeatureDisabled,
+            ErrorCode::Other => Self::Other,
+        }
+    }
+}
+
+/// An error caused by a client's request, with the code it is reported with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientError {
+    pub code: ErrorCode,
+    message: String,
+}
+
+impl ClientError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn not_in_room() -> Self {
+        Self::new(ErrorCode::NotInRoom, "Not currently in a room")
+    }
+
+    pub fn not_authorized(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotAuthorized, message)
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidRequest, message)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ClientError {}
+
+/// Finds the code of the first error in the chain that has one.
+pub fn error_code(err: &anyhow::Error) -> ErrorCode {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<ClientError>() {
+            return err.code;
+        }
+        if let Some(err) = cause.downcast_ref::<RoomError>() {
+            return match err {
+                RoomError::Full => ErrorCode::RoomFull,
+                RoomError::Busy => ErrorCode::RoomBusy,
+                RoomError::Closed(..) => ErrorCode::NotInRoom,
+            };
+        }
+    }
+    ErrorCode::Other
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn should_find_codes_behind_context() {
+        // given
+        let err = anyhow::Error::from(ClientError::not_in_room()).context("Failed to send chat");
+
+        // when
+        let code = error_code(&err);
+
+        // then
+        assert_eq!(code, ErrorCode::NotInRoom);
+    }
+
+    #[test]
+    fn should_map_room_errors() {
+        // given
+        let err = Err::<(), _>(RoomError::Full)
+            .context("Failed to join room")
+            .unwrap_err();
+
+        // when
+        let code = error_code(&err);
+
+        // then
+        assert_eq!(code, ErrorCode::RoomFull);
+    }
+
+    #[test]
+    fn should_fall_back_to_other() {
+        // given
+        let err = anyhow!("Something broke");
+
+        // when
+        let code = error_code(&err);
+
+        // then
+        assert_eq!(code, ErrorCode::Other);
+    }
+}
@@ -0,0 +1,139 @@
+//! Switches for whole features, so that operators can turn off one that misbehaves without
+//! redeploying. They are re-read whenever the config is reloaded, and take effect for the next
+//! message that uses the feature.
+
+use std::fmt;
+
+use parking_lot::RwLock;
+use serde::Deserialize;
+
+use crate::{
+    errors::{ClientError, ErrorCode},
+    messages::MessageBody,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FeatureConfig {
+    /// Sending chat messages in rooms.
+    pub chat: bool,
+
+    /// Adding, removing and reordering sources in playback queues.
+    pub queue: bool,
+
+    /// Listing public rooms, over the WebSocket and in the room feed.
+    pub public_listing: bool,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        Self {
+            chat: true,
+            queue: true,
+            public_listing: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FeaturesConfig {
+    pub features: FeatureConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Chat,
+    Queue,
+    PublicListing,
+}
+
+impl Feature {
+    /// The feature that handles a client message, if it is one that can be turned off.
+    pub fn of(body: &MessageBody) -> Option<Self> {
+        match body {
+            MessageBody::RoomChatV1(..) => Some(Self::Chat),
+            MessageBody::PlaybackQueueAddV1(..)
+            | MessageBody::PlaybackQueueRemoveV1(..)
+            | MessageBody::PlaybackQueueMoveV1(..) => Some(Self::Queue),
+            MessageBody::RoomListV1 => Some(Self::PublicListing),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Chat => write!(f, "Chat"),
+            Self::Queue => write!(f, "The playback queue"),
+            Self::PublicListing => write!(f, "Listing public rooms"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FeatureFlags {
+    config: RwLock<FeatureConfig>,
+}
+
+impl FeatureFlags {
+    pub fn new(config: FeatureConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+        }
+    }
+
+    /// Swaps in new switches. Requests that are already being handled aren't affected.
+    pub fn replace_config(&self, config: FeatureConfig) {
+        *self.config.write() = config;
+    }
+
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        let config = self.config.read();
+        match feature {
+            Feature::Chat => config.chat,
+            Feature::Queue => config.queue,
+            Feature::PublicListing => config.public_listing,
+        }
+    }
+
+    /// Fails with an error for the client if the feature is turned off.
+    pub fn check(&self, feature: Feature) -> Result<(), ClientError> {
+        if self.is_enabled(feature) {
+            return Ok(());
+        }
+        Err(ClientError::new(
+            ErrorCode::FeatureDisabled,
+            format!("{feature} is turned off on this server"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::dto;
+
+    #[test]
+    fn should_reject_messages_of_disabled_features() {
+        // given
+        let flags = FeatureFlags::default();
+        let chat = MessageBody::RoomChatV1(dto::RoomChatMsgBodyV1 {
+            text: "hi".to_string(),
+        });
+
+        // when
+        let before = flags.check(Feature::of(&chat).unwrap());
+        flags.replace_config(FeatureConfig {
+            chat: false,
+            ..FeatureConfig::default()
+        });
+        let after = flags.check(Feature::of(&chat).unwrap());
+
+        // then
+        assert!(before.is_ok());
+        assert_eq!(after.unwrap_err().code, ErrorCode::FeatureDisabled);
+        assert!(flags.is_enabled(Feature::Queue));
+    }
+}
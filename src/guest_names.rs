@@ -0,0 +1,164 @@
+//! Names for users who log in without one, like `brisk-otter-42`.
+
+use std::{collections::HashSet, sync::Arc};
+
+use parking_lot::Mutex;
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct GuestNameConfig {
+    /// Gives every user a generated name, even if they picked their own, so that nobody can be
+    /// recognized by their name.
+    pub always: bool,
+}
+
+// Both lists are picked by hand, so that no combination reads as an insult.
+const ADJECTIVES: &[&str] = &[
+    "amber", "bold", "brave", "breezy", "bright", "brisk", "calm", "cheery", "clever", "cosmic",
+    "cozy", "crisp", "curious", "dapper", "eager", "fancy", "gentle", "glad", "golden", "happy",
+    "jolly", "keen", "kind", "lively", "lucky", "mellow", "merry", "misty", "nimble", "noble",
+    "plucky", "polite", "quick", "quiet", "rosy", "snappy", "sunny", "swift", "tidy", "witty",
+];
+
+const ANIMALS: &[&str] = &[
+    "badger", "beaver", "bison", "crane", "dolphin", "falcon", "ferret", "finch", "gecko", "heron",
+    "ibis", "koala", "lemur", "lynx", "marten", "moose", "narwhal", "newt", "ocelot", "orca",
+    "otter", "owl", "panda", "parrot", "pelican", "puffin", "quokka", "rabbit", "raven", "robin",
+    "salmon", "seal", "sparrow", "squid", "stoat", "swan", "tapir", "toucan", "walrus", "wombat",
+];
+
+/// Keeps track of the generated names that are in use, so that no two guests get the same one.
+#[derive(Debug, Default)]
+pub struct GuestNames {
+    config: GuestNameConfig,
+    in_use: Mutex<HashSet<String>>,
+}
+
+impl GuestNames {
+    /// How many names are tried before the number at the end of the name gets another digit.
+    const ATTEMPTS: usize = 8;
+
+    pub fn new(config: GuestNameConfig) -> Self {
+        Self {
+            config,
+            in_use: Mutex::default(),
+        }
+    }
+
+    /// Whether a user who logged in with the given name gets a generated one instead.
+    pub fn applies_to(&self, username: &str) -> bool {
+        self.config.always || username.trim().is_empty()
+    }
+
+    /// Generates a name that no other guest has right now.
+    pub fn assign(self: &Arc<Self>) -> GuestName {
+        let mut in_use = self.in_use.lock();
+        let mut numbers = 100;
+        loop {
+            for _ in 0..Self::ATTEMPTS {
+                let name = random_name(numbers);
+                if in_use.insert(name.clone()) {
+                    return GuestName {
+                        name,
+                        names: Arc::clone(self),
+                    };
+                }
+            }
+            numbers *= 10;
+        }
+    }
+
+    fn release(&self, name: &str) {
+        self.in_use.lock().remove(name);
+    }
+}
+
+fn random_name(numbers: u64) -> String {
+    // the lower bits of a random UUID are all random, apart from the two variant bits on top
+    let (_, mut bits) = Uuid::new_v4().as_u64_pair();
+    bits &= u64::MAX >> 2;
+    let mut pick = |choices: u64| {
+        let choice = bits % choices;
+        bits /= choices;
+        choice
+    };
+    let adjective = ADJECTIVES[pick(ADJECTIVES.len() as u64) as usize];
+    let animal = ANIMALS[pick(ANIMALS.len() as u64) as usize];
+    let number = pick(numbers);
+    format!("{adjective}-{animal}-{number}")
+}
+
+/// A generated name, which becomes available to other guests again once it is dropped.
+#[derive(Debug)]
+pub struct GuestName {
+    name: String,
+    names: Arc<GuestNames>,
+}
+
+impl GuestName {
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the user has to keep the name, because everyone goes by a generated one.
+    pub fn is_enforced(&self) -> bool {
+        self.names.config.always
+    }
+}
+
+impl Drop for GuestName {
+    fn drop(&mut self) {
+        self.names.release(&self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_not_hand_out_names_twice() {
+        // given
+        let names = Arc::new(GuestNames::default());
+
+        // when
+        let assigned: Vec<GuestName> = (0..1000).map(|_| names.assign()).collect();
+
+        // then
+        let unique: HashSet<&str> = assigned.iter().map(GuestName::as_str).collect();
+        assert_eq!(unique.len(), assigned.len());
+    }
+
+    #[test]
+    fn should_free_names_that_are_no_longer_used() {
+        // given
+        let names = Arc::new(GuestNames::default());
+        let name = names.assign();
+        let taken = name.as_str().to_string();
+
+        // when
+        drop(name);
+
+        // then
+        assert!(!names.in_use.lock().contains(&taken));
+    }
+
+    #[test]
+    fn should_only_replace_empty_names_unless_configured() {
+        // given
+        let default = GuestNames::default();
+        let always = GuestNames::new(GuestNameConfig { always: true });
+
+        // when
+        let applies = [
+            default.applies_to("  "),
+            default.applies_to("alice"),
+            always.applies_to("alice"),
+        ];
+
+        // then
+        assert_eq!(applies, [true, false, true]);
+    }
+}
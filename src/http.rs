@@ -0,0 +1,875 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use log::{debug, error, info};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync, task,
+    time::{timeout, Instant},
+};
+use uuid::Uuid;
+
+use crate::{
+    connection::{resolve_listen_addrs, ListenerMetrics},
+    errors::{error_code, ErrorCode},
+    features::{Feature, FeatureFlags},
+    room::{PendingRoom, PublicRoomInfo, RoomId, RoomManager},
+    utils::TokenBucket,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RoomFeedConfig {
+    pub enabled: bool,
+
+    /// How long clients and the server may cache the feed, in seconds.
+    pub max_age: u64,
+
+    pub requests_per_minute: u32,
+}
+
+impl Default for RoomFeedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age: 10,
+            requests_per_minute: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Serves connection metrics in the Prometheus text format at `/metrics`.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct AdminApiConfig {
+    /// Serves the admin API under `/admin` to requests that carry this bearer token. Without a
+    /// token, the admin API is disabled.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// The port or URL of the auxiliary HTTP server. It is only started if this is set.
+    pub http_listen_on: Option<String>,
+
+    /// The port or URL of a separate HTTP server for load balancer probes, with `/healthz` and
+    /// `/readyz`. It is only started if this is set.
+    pub health_listen_on: Option<String>,
+
+    pub room_feed: RoomFeedConfig,
+
+    pub metrics: MetricsConfig,
+
+    pub admin_api: AdminApiConfig,
+}
+
+/// The parts of an HTTP request that the endpoints care about.
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    ip: IpAddr,
+    /// The token from a bearer `Authorization` header.
+    bearer_token: Option<String>,
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    reason: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Response {
+    fn new(status: u16, reason: &'static str, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            reason,
+            headers: vec![("Content-Type", "text/plain; charset=utf-8".to_string())],
+            body: body.into(),
+        }
+    }
+
+    fn json(body: String) -> Self {
+        Self {
+            status: 200,
+            reason: "OK",
+            headers: vec![("Content-Type", "application/json".to_string())],
+            body,
+        }
+    }
+
+    fn not_found() -> Self {
+        Self::new(404, "Not Found", "Not found")
+    }
+
+    fn method_not_allowed(allow: &'static str) -> Self {
+        Self::new(405, "Method Not Allowed", "Method not allowed").header("Allow", allow)
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        ));
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(self.body.as_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RoomFeedEntry {
+    id: String,
+    name: String,
+    topic: Option<String>,
+    tags: Vec<String>,
+    users: usize,
+    now_playing: Option<String>,
+}
+
+impl From<PublicRoomInfo> for RoomFeedEntry {
+    fn from(value: PublicRoomInfo) -> Self {
+        Self {
+            id: value.id.to_string(),
+            name: value.name,
+            topic: value.metadata.topic,
+            tags: value.metadata.tags,
+            users: value.users,
+            now_playing: value.now_playing,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RoomFeed {
+    rooms: Vec<RoomFeedEntry>,
+}
+
+struct RoomFeedRoute {
+    config: RoomFeedConfig,
+    room_mgr: Arc<RoomManager>,
+    features: Arc<FeatureFlags>,
+    cache: sync::Mutex<Option<(Instant, String)>>,
+    rate_limits: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl RoomFeedRoute {
+    fn new(
+        config: RoomFeedConfig,
+        room_mgr: Arc<RoomManager>,
+        features: Arc<FeatureFlags>,
+    ) -> Self {
+        Self {
+            config,
+            room_mgr,
+            features,
+            cache: sync::Mutex::new(None),
+            rate_limits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn check_rate_limit(&self, ip: IpAddr) -> bool {
+        let mut rate_limits = self.rate_limits.lock();
+        rate_limits.retain(|_, bucket| !bucket.is_full());
+        let requests_per_minute = self.config.requests_per_minute;
+        rate_limits
+            .entry(ip)
+            .or_insert_with(|| {
+                TokenBucket::new(requests_per_minute, f64::from(requests_per_minute) / 60.0)
+            })
+            .try_take()
+    }
+
+    async fn render(&self) -> anyhow::Result<String> {
+        let mut cache = self.cache.lock().await;
+        if let Some((rendered_at, feed)) = &*cache {
+            if rendered_at.elapsed() < Duration::from_secs(self.config.max_age) {
+                return Ok(feed.clone());
+            }
+        }
+
+        let rooms = self.room_mgr.list_public_rooms().await;
+        let feed = serde_json::to_string(&RoomFeed {
+            rooms: rooms.into_iter().map(RoomFeedEntry::from).collect(),
+        })
+        .context("Failed to serialize room feed")?;
+        *cache = Some((Instant::now(), feed.clone()));
+        Ok(feed)
+    }
+
+    async fn handle(&self, ip: IpAddr) -> Response {
+        if !self.check_rate_limit(ip) {
+            return Response::new(429, "Too Many Requests", "Too many requests")
+                .header("Retry-After", "60");
+        }
+        if !self.features.is_enabled(Feature::PublicListing) {
+            return Response::new(503, "Service Unavailable", "The room feed is turned off");
+        }
+        match self.render().await {
+            Ok(feed) => Response::json(feed)
+                .header(
+                    "Cache-Control",
+                    format!("public, max-age={}", self.config.max_age),
+                )
+                .header("Access-Control-Allow-Origin", "*"),
+            Err(err) => {
+                error!("Failed to render room feed: {err:?}");
+                Response::new(500, "Internal Server Error", "Internal server error")
+            }
+        }
+    }
+}
+
+struct MetricsRoute {
+    listener: Arc<ListenerMetrics>,
+}
+
+impl MetricsRoute {
+    fn handle(&self) -> Response {
+        let metrics = [
+            (
+                "palantir_connections_accepted_total",
+                "counter",
+                "Connections that were allowed to start a handshake.",
+                &self.listener.accepted,
+            ),
+            (
+                "palantir_connections_rejected_total",
+                "counter",
+                "Connections that were closed because too many handshakes were in progress.",
+                &self.listener.rejected,
+            ),
+            (
+                "palantir_handshakes_in_flight",
+                "gauge",
+                "Handshakes that are currently in progress.",
+                &self.listener.handshakes_in_flight,
+            ),
+            (
+                "palantir_handshake_failures_total",
+                "counter",
+                "Handshakes that failed or timed out.",
+                &self.listener.handshake_failures,
+            ),
+            (
+                "palantir_messages_sent_total",
+                "counter",
+                "Messages that were sent to clients.",
+                &self.listener.messages.sent,
+            ),
+            (
+                "palantir_message_serialized_bytes_total",
+                "counter",
+                "Size of the messages sent to clients, before compression.",
+                &self.listener.messages.serialized_bytes,
+            ),
+            (
+                "palantir_message_sent_bytes_total",
+                "counter",
+                "Size of the messages sent to clients, after compression.",
+                &self.listener.messages.sent_bytes,
+            ),
+            (
+                "palantir_message_json_bytes_total",
+                "counter",
+                "Size of the messages sent to clients as JSON, which is never compressed.",
+                &self.listener.messages.json_bytes,
+            ),
+        ];
+        let mut body = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = write!(
+                body,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {}\n",
+                value.load(Ordering::Relaxed)
+            );
+        }
+        Response::new(200, "OK", body).header("Cache-Control", "no-store")
+    }
+}
+
+struct HealthRoute {
+    room_mgr: Arc<RoomManager>,
+    listener: Arc<ListenerMetrics>,
+}
+
+impl HealthRoute {
+    /// How long the room manager may take to respond before the server counts as not ready.
+    const ROOM_MANAGER_TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn live(&self) -> Response {
+        Response::new(200, "OK", "ok").header("Cache-Control", "no-store")
+    }
+
+    async fn ready(&self) -> Response {
+        let response = if !self.listener.listening.load(Ordering::Relaxed) {
+            Response::new(
+                503,
+                "Service Unavailable",
+                "WebSocket listener is not running yet",
+            )
+        } else if !self.room_mgr_responds().await {
+            Response::new(503, "Service Unavailable", "Room manager is not responding")
+        } else {
+            Response::new(200, "OK", "ready")
+        };
+        response.header("Cache-Control", "no-store")
+    }
+
+    /// The shard locks are blocking, so they are probed off the async runtime.
+    async fn room_mgr_responds(&self) -> bool {
+        let room_mgr = Arc::clone(&self.room_mgr);
+        task::spawn_blocking(move || room_mgr.is_responsive(Self::ROOM_MANAGER_TIMEOUT))
+            .await
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PendingRoomList {
+    rooms: Vec<PendingRoom>,
+}
+
+/// Lets operators moderate the server, for example from a moderation bot.
+struct AdminRoute {
+    token: String,
+    room_mgr: Arc<RoomManager>,
+}
+
+impl AdminRoute {
+    /// Handles a request to the given path below `/admin/`.
+    async fn handle(&self, request: &Request, path: &str) -> Response {
+        let authorized = request
+            .bearer_token
+            .as_deref()
+            .is_some_and(|token| tokens_match(token, &self.token));
+        if !authorized {
+            return Response::new(401, "Unauthorized", "Unauthorized")
+                .header("WWW-Authenticate", "Bearer");
+        }
+        let segments: Vec<&str> = path.split('/').collect();
+        let response = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["rooms", "pending"]) => self.list_pending_rooms().await,
+            ("POST", ["rooms", id, "approve"]) => self.decide(id, true).await,
+            ("POST", ["rooms", id, "deny"]) => self.decide(id, false).await,
+            (_, ["rooms", "pending"]) => Response::method_not_allowed("GET"),
+            (_, ["rooms", _, "approve" | "deny"]) => Response::method_not_allowed("POST"),
+            #[cfg(feature = "chaos")]
+            (method, ["chaos", ..]) => self.handle_chaos(method, &segments[1..]).await,
+            _ => Response::not_found(),
+        };
+        response.header("Cache-Control", "no-store")
+    }
+
+    async fn list_pending_rooms(&self) -> Response {
+        let rooms = self.room_mgr.list_pending_rooms().await;
+        match serde_json::to_string(&PendingRoomList { rooms }) {
+            Ok(body) => Response::json(body),
+            Err(err) => {
+                error!("Failed to serialize pending rooms: {err:?}");
+                Response::new(500, "Internal Server Error", "Internal server error")
+            }
+        }
+    }
+
+    async fn decide(&self, id: &str, approve: bool) -> Response {
+        let Ok(id) = id.parse::<Uuid>().map(RoomId::from) else {
+            return Response::new(400, "Bad Request", "Invalid room id");
+        };
+        let result = if approve {
+            self.room_mgr.approve_room(id).await
+        } else {
+            self.room_mgr.deny_room(id).await
+        };
+        match result {
+            Ok(()) => Response::new(200, "OK", if approve { "approved" } else { "denied" }),
+            Err(err) => match error_code(&err) {
+                ErrorCode::RoomNotFound => Response::new(404, "Not Found", err.to_string()),
+                ErrorCode::InvalidRequest => Response::new(409, "Conflict", err.to_string()),
+                _ => {
+                    error!("Failed to moderate room {id}: {err:?}");
+                    Response::new(500, "Internal Server Error", "Internal server error")
+                }
+            },
+        }
+    }
+
+    /// Turns fault injection on and off, below `/admin/chaos`.
+    #[cfg(feature = "chaos")]
+    async fn handle_chaos(&self, method: &str, segments: &[&str]) -> Response {
+        let chaos = self.room_mgr.chaos();
+        match (method, segments) {
+            ("GET", []) => match serde_json::to_string(&chaos.settings()) {
+                Ok(body) => Response::json(body),
+                Err(err) => {
+                    error!("Failed to serialize chaos settings: {err:?}");
+                    Response::new(500, "Internal Server Error", "Internal server error")
+                }
+            },
+            ("DELETE", []) => {
+                chaos.reset();
+                Response::new(200, "OK", "reset")
+            }
+            ("POST", ["rooms", id, "kill"]) => {
+                let Ok(id) = id.parse::<Uuid>().map(RoomId::from) else {
+                    return Response::new(400, "Bad Request", "Invalid room id");
+                };
+                match self.room_mgr.kill_room(id) {
+                    Ok(()) => Response::new(200, "OK", "killed"),
+                    Err(err) => Response::new(404, "Not Found", err.to_string()),
+                }
+            }
+            ("POST", [name, value]) => match chaos.set(name, value) {
+                Ok(()) => Response::new(200, "OK", "set"),
+                Err(err) => Response::new(400, "Bad Request", err.to_string()),
+            },
+            (_, []) => Response::method_not_allowed("GET, DELETE"),
+            (_, ["rooms", _, "kill"] | [_, _]) => Response::method_not_allowed("POST"),
+            _ => Response::not_found(),
+        }
+    }
+}
+
+/// Compares two tokens in constant time, so that response times don't give away how much of a
+/// guessed token is right.
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+struct Routes {
+    room_feed: Option<RoomFeedRoute>,
+    metrics: Option<MetricsRoute>,
+    health: Option<HealthRoute>,
+    admin: Option<AdminRoute>,
+}
+
+impl Routes {
+    async fn handle(&self, request: &Request) -> Response {
+        if let Some(path) = request.path.strip_prefix("/admin/") {
+            return match &self.admin {
+                Some(admin) => admin.handle(request, path).await,
+                None => Response::not_found(),
+            };
+        }
+        if request.method != "GET" {
+            return Response::method_not_allowed("GET");
+        }
+        match request.path.as_str() {
+            "/rooms.json" => match &self.room_feed {
+                Some(room_feed) => room_feed.handle(request.ip).await,
+                None => Response::not_found(),
+            },
+            "/metrics" => match &self.metrics {
+                Some(metrics) => metrics.handle(),
+                None => Response::not_found(),
+            },
+            "/healthz" => match &self.health {
+                Some(health) => health.live(),
+                None => Response::not_found(),
+            },
+            "/readyz" => match &self.health {
+                Some(health) => health.ready().await,
+                None => Response::not_found(),
+            },
+            _ => Response::not_found(),
+        }
+    }
+}
+
+/// A minimal HTTP/1.1 server for auxiliary endpoints that don't belong on the WebSocket protocol.
+pub struct HttpServer {
+    listener: TcpListener,
+    routes: Arc<Routes>,
+}
+
+impl HttpServer {
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+    const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+    pub async fn bind(
+        config: HttpConfig,
+        room_mgr: Arc<RoomManager>,
+        features: Arc<FeatureFlags>,
+        listener_metrics: Arc<ListenerMetrics>,
+    ) -> anyhow::Result<Option<Self>> {
+        let Some(listen_on) = config.http_listen_on else {
+            return Ok(None);
+        };
+        let routes = Routes {
+            room_feed: config
+                .room_feed
+                .enabled
+                .then(|| RoomFeedRoute::new(config.room_feed, Arc::clone(&room_mgr), features)),
+            metrics: config.metrics.enabled.then_some(MetricsRoute {
+                listener: listener_metrics,
+            }),
+            health: None,
+            admin: config
+                .admin_api
+                .token
+                .map(|token| AdminRoute { token, room_mgr }),
+        };
+        Self::bind_routes(&listen_on, routes).await.map(Some)
+    }
+
+    /// Binds the server that only answers health probes, if one is configured.
+    pub async fn bind_health(
+        config: &HttpConfig,
+        room_mgr: Arc<RoomManager>,
+        listener_metrics: Arc<ListenerMetrics>,
+    ) -> anyhow::Result<Option<Self>> {
+        let Some(listen_on) = &config.health_listen_on else {
+            return Ok(None);
+        };
+        let routes = Routes {
+            room_feed: None,
+            metrics: None,
+            health: Some(HealthRoute {
+                room_mgr,
+                listener: listener_metrics,
+            }),
+            admin: None,
+        };
+        Self::bind_routes(listen_on, routes).await.map(Some)
+    }
+
+    async fn bind_routes(listen_on: &str, routes: Routes) -> anyhow::Result<Self> {
+        let addrs = resolve_listen_addrs(listen_on)?;
+        let listener = TcpListener::bind(&*addrs)
+            .await
+            .context("Failed to start HTTP server")?;
+        Ok(Self {
+            listener,
+            routes: Arc::new(routes),
+        })
+    }
+
+    pub async fn serve(self) {
+        match self.listener.local_addr() {
+            Ok(addr) => info!("HTTP server listening on {addr}..."),
+            Err(err) => error!("Failed to determine bound HTTP address: {err:?}"),
+        }
+        loop {
+            let (stream, addr) = match self.listener.accept().await {
+                Ok(val) => val,
+                Err(err) => {
+                    error!("HTTP connection failed: {err:?}");
+                    continue;
+                }
+            };
+            let routes = Arc::clone(&self.routes);
+            tokio::spawn(async move {
+                if let Err(err) = Self::handle_connection(stream, addr, &routes).await {
+                    debug!("Error during HTTP request from {addr}: {err:?}");
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(
+        mut stream: TcpStream,
+        addr: SocketAddr,
+        routes: &Routes,
+    ) -> anyhow::Result<()> {
+        let (request_line, bearer_token) =
+            timeout(Self::REQUEST_TIMEOUT, Self::read_head(&mut stream))
+                .await
+                .context("HTTP request timed out")??;
+
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(anyhow!("Malformed HTTP request line"));
+        };
+        let request = Request {
+            method: method.to_string(),
+            path: target.split('?').next().unwrap_or(target).to_string(),
+            ip: addr.ip(),
+            bearer_token,
+        };
+
+        let response = routes.handle(&request).await;
+        debug!(
+            "HTTP {} {} from {addr}: {}",
+            request.method, request.path, response.status
+        );
+        stream.write_all(&response.to_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    /// Reads the request head and returns its request line, along with the bearer token if
+    /// there is one. No other headers are needed by any of the endpoints, so they are discarded.
+    async fn read_head(stream: &mut TcpStream) -> anyhow::Result<(String, Option<String>)> {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        let mut bearer_token = None;
+        let mut line = String::new();
+        let mut head_size = 0;
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line).await?;
+            head_size += read;
+            if read == 0 || head_size > Self::MAX_HEAD_SIZE {
+                return Err(anyhow!("Incomplete or oversized HTTP request head"));
+            }
+            let line = line.trim_end();
+            if request_line.is_empty() {
+                request_line = line.to_string();
+            } else if line.is_empty() {
+                return Ok((request_line, bearer_token));
+            } else if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("authorization") {
+                    bearer_token = value.trim().strip_prefix("Bearer ").map(str::to_string);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::dto,
+        room::{RoomApprovalConfig, RoomConfig, RoomSettings},
+        storage::{self, StorageConfig},
+        testing::FakeSession,
+    };
+
+    async fn room_manager(config: RoomConfig) -> Arc<RoomManager> {
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        Arc::new(RoomManager::new(config, Arc::default(), storage).unwrap())
+    }
+
+    fn request(method: &str, path: &str, bearer_token: Option<&str>) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            ip: IpAddr::from([127, 0, 0, 1]),
+            bearer_token: bearer_token.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_only_be_ready_once_listening() {
+        // given
+        let listener = Arc::new(ListenerMetrics::default());
+        let routes = Routes {
+            room_feed: None,
+            metrics: None,
+            health: Some(HealthRoute {
+                room_mgr: room_manager(RoomConfig::default()).await,
+                listener: Arc::clone(&listener),
+            }),
+            admin: None,
+        };
+
+        // when
+        let live = routes.handle(&request("GET", "/healthz", None)).await;
+        let starting = routes.handle(&request("GET", "/readyz", None)).await;
+        listener.listening.store(true, Ordering::Relaxed);
+        let ready = routes.handle(&request("GET", "/readyz", None)).await;
+
+        // then
+        assert_eq!(live.status, 200);
+        assert_eq!(starting.status, 503);
+        assert_eq!(ready.status, 200);
+    }
+
+    #[tokio::test]
+    async fn should_list_public_rooms_with_their_topic_and_tags() {
+        // given
+        let room_mgr = room_manager(RoomConfig::default()).await;
+        let settings = RoomSettings::from(dto::RoomCreateMsgBodyV1 {
+            name: "Movie night".to_string(),
+            password: None,
+            public: true,
+            host_succession: Vec::new(),
+            default_role: None,
+            topic: Some("Classic westerns".to_string()),
+            tags: vec!["western".to_string(), "classics".to_string()],
+            image_url: None,
+            utc_offset: None,
+            vanity_id: None,
+            chat: None,
+            ready_quorum: None,
+        });
+        room_mgr
+            .create_room(
+                settings.into(),
+                None,
+                FakeSession::new(0).handle(1, "alice"),
+            )
+            .await
+            .unwrap();
+        let routes = Routes {
+            room_feed: Some(RoomFeedRoute::new(
+                RoomFeedConfig::default(),
+                room_mgr,
+                Arc::default(),
+            )),
+            metrics: None,
+            health: None,
+            admin: None,
+        };
+
+        // when
+        let response = routes.handle(&request("GET", "/rooms.json", None)).await;
+
+        // then
+        assert_eq!(response.status, 200);
+        let feed: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        let room = &feed["rooms"][0];
+        assert_eq!(room["name"], "Movie night");
+        assert_eq!(room["topic"], "Classic westerns");
+        assert_eq!(room["tags"], serde_json::json!(["western", "classics"]));
+    }
+
+    #[tokio::test]
+    async fn should_let_admins_approve_pending_rooms() {
+        // given
+        let room_mgr = room_manager(RoomConfig {
+            room_approval: RoomApprovalConfig {
+                required: true,
+                webhook: None,
+            },
+            ..RoomConfig::default()
+        })
+        .await;
+        let settings = RoomSettings::from(dto::RoomCreateMsgBodyV1 {
+            name: "Movie night".to_string(),
+            password: None,
+            public: true,
+            host_succession: Vec::new(),
+            default_role: None,
+            topic: None,
+            tags: Vec::new(),
+            image_url: None,
+            utc_offset: None,
+            vanity_id: None,
+            chat: None,
+            ready_quorum: None,
+        });
+        let room = room_mgr
+            .create_room(
+                settings.into(),
+                None,
+                FakeSession::new(0).handle(1, "alice"),
+            )
+            .await
+            .unwrap();
+        let routes = Routes {
+            room_feed: None,
+            metrics: None,
+            health: None,
+            admin: Some(AdminRoute {
+                token: "secret".to_string(),
+                room_mgr: Arc::clone(&room_mgr),
+            }),
+        };
+        let approve = format!("/admin/rooms/{}/approve", room.id);
+
+        // when
+        let unauthorized = routes
+            .handle(&request("POST", &approve, Some("guess")))
+            .await;
+        let pending = routes
+            .handle(&request("GET", "/admin/rooms/pending", Some("secret")))
+            .await;
+        let approved = routes
+            .handle(&request("POST", &approve, Some("secret")))
+            .await;
+        let again = routes
+            .handle(&request("POST", &approve, Some("secret")))
+            .await;
+
+        // then
+        assert_eq!(unauthorized.status, 401);
+        assert!(pending.body.contains("Movie night"));
+        assert_eq!(approved.status, 200);
+        assert_eq!(again.status, 409);
+        assert_eq!(room_mgr.list_public_rooms().await.len(), 1);
+    }
+
+    #[cfg(feature = "chaos")]
+    #[tokio::test]
+    async fn should_let_admins_configure_chaos() {
+        // given
+        let room_mgr = room_manager(RoomConfig::default()).await;
+        let routes = Routes {
+            room_feed: None,
+            metrics: None,
+            health: None,
+            admin: Some(AdminRoute {
+                token: "secret".to_string(),
+                room_mgr: Arc::clone(&room_mgr),
+            }),
+        };
+
+        // when
+        let set = routes
+            .handle(&request(
+                "POST",
+                "/admin/chaos/request_delay/250",
+                Some("secret"),
+            ))
+            .await;
+        let invalid = routes
+            .handle(&request(
+                "POST",
+                "/admin/chaos/message_drop_rate/7",
+                Some("secret"),
+            ))
+            .await;
+        let settings = routes
+            .handle(&request("GET", "/admin/chaos", Some("secret")))
+            .await;
+        let missing = routes
+            .handle(&request(
+                "POST",
+                "/admin/chaos/rooms/00000000-0000-0000-0000-000000000001/kill",
+                Some("secret"),
+            ))
+            .await;
+
+        // then
+        assert_eq!(set.status, 200);
+        assert_eq!(invalid.status, 400);
+        assert!(settings.body.contains(r#""request_delay":250"#));
+        assert_eq!(missing.status, 404);
+    }
+}
@@ -0,0 +1,47 @@
+use serde::Deserialize;
+use uuid::Uuid;
+
+/// Creates the ids of new objects, like rooms.
+pub trait IdGenerator: Send + Sync {
+    fn generate(&self) -> Uuid;
+}
+
+/// Which kind of UUIDs the server creates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdFormat {
+    /// Random version 4 UUIDs.
+    #[default]
+    Random,
+
+    /// Version 7 UUIDs, which start with a timestamp. They sort in the order they were created
+    /// in, which keeps the indexes of storage backends compact.
+    TimeOrdered,
+}
+
+impl IdGenerator for IdFormat {
+    fn generate(&self) -> Uuid {
+        match self {
+            Self::Random => Uuid::new_v4(),
+            Self::TimeOrdered => Uuid::now_v7(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_create_ids_of_the_configured_version() {
+        // when
+        let random = IdFormat::Random.generate();
+        let first = IdFormat::TimeOrdered.generate();
+        let second = IdFormat::TimeOrdered.generate();
+
+        // then
+        assert_eq!(random.get_version_num(), 4);
+        assert_eq!(first.get_version_num(), 7);
+        assert!(first < second);
+    }
+}
@@ -0,0 +1,262 @@
+use std::{collections::HashMap, fmt, net::IpAddr, sync::Arc};
+
+use anyhow::{anyhow, Context};
+use parking_lot::Mutex;
+use serde::Deserialize;
+
+use crate::utils::TokenBucket;
+
+/// A single address, or a network in CIDR notation like `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (network, prefix_len) = match value.split_once('/') {
+            Some((network, prefix_len)) => (network, Some(prefix_len)),
+            None => (value.as_str(), None),
+        };
+        let network: IpAddr = network
+            .parse()
+            .with_context(|| format!("'{value}' is not a valid address or network"))?;
+        let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or_else(|| anyhow!("'{value}' has an invalid prefix length"))?,
+            None => max_prefix_len,
+        };
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct IpFilterConfig {
+    /// If not empty, only connections from these addresses and networks are accepted.
+    pub allow: Vec<IpRange>,
+
+    /// Connections from these addresses and networks are always refused.
+    pub deny: Vec<IpRange>,
+
+    /// How many connections a single address may have open at the same time.
+    pub max_connections_per_ip: Option<usize>,
+
+    /// How many new connections a single address may open per minute.
+    pub connections_per_minute: Option<u32>,
+}
+
+/// Why a connection was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    Denied,
+    TooManyConnections,
+    TooFrequent,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Denied => write!(f, "the address is not allowed"),
+            Self::TooManyConnections => write!(f, "too many open connections from the address"),
+            Self::TooFrequent => write!(f, "too many new connections from the address"),
+        }
+    }
+}
+
+/// Decides which addresses may connect, before any handshake is done with them.
+#[derive(Debug)]
+pub struct IpFilter {
+    config: IpFilterConfig,
+    open: Mutex<HashMap<IpAddr, usize>>,
+    rate_limits: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl IpFilter {
+    pub fn new(config: IpFilterConfig) -> Self {
+        Self {
+            config,
+            open: Mutex::new(HashMap::new()),
+            rate_limits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks whether a new connection from the address is accepted. The connection counts as
+    /// open until the returned permit is dropped.
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<IpPermit, Refusal> {
+        // IPv4 clients of a dual-stack listener show up as mapped IPv6 addresses
+        let ip = ip.to_canonical();
+        if self.config.deny.iter().any(|range| range.contains(ip))
+            || (!self.config.allow.is_empty()
+                && !self.config.allow.iter().any(|range| range.contains(ip)))
+        {
+            return Err(Refusal::Denied);
+        }
+
+        let mut open = self.open.lock();
+        let count = open.get(&ip).copied().unwrap_or(0);
+        if self
+            .config
+            .max_connections_per_ip
+            .is_some_and(|max| count >= max)
+        {
+            return Err(Refusal::TooManyConnections);
+        }
+        if let Some(per_minute) = self.config.connections_per_minute {
+            let mut rate_limits = self.rate_limits.lock();
+            rate_limits.retain(|_, bucket| !bucket.is_full());
+            let allowed = rate_limits
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::new(per_minute, f64::from(per_minute) / 60.0))
+                .try_take();
+            if !allowed {
+                return Err(Refusal::TooFrequent);
+            }
+        }
+        open.insert(ip, count + 1);
+        Ok(IpPermit {
+            filter: Arc::clone(self),
+            ip,
+        })
+    }
+}
+
+/// Counts as an open connection from an address until it is dropped.
+#[derive(Debug)]
+pub struct IpPermit {
+    filter: Arc<IpFilter>,
+    ip: IpAddr,
+}
+
+impl Drop for IpPermit {
+    fn drop(&mut self) {
+        let mut open = self.filter.open.lock();
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(range: &str) -> IpRange {
+        IpRange::try_from(range.to_string()).unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn should_match_addresses_within_networks() {
+        // given
+        let network = range("10.1.0.0/16");
+        let v6_network = range("2001:db8::/32");
+        let single = range("192.168.0.7");
+
+        // then
+        assert!(network.contains(ip("10.1.200.3")));
+        assert!(!network.contains(ip("10.2.0.1")));
+        assert!(v6_network.contains(ip("2001:db8::1")));
+        assert!(!v6_network.contains(ip("10.1.0.1")));
+        assert!(single.contains(ip("192.168.0.7")));
+        assert!(!single.contains(ip("192.168.0.8")));
+        assert!(range("0.0.0.0/0").contains(ip("8.8.8.8")));
+        assert!(IpRange::try_from("10.0.0.0/33".to_string()).is_err());
+    }
+
+    #[test]
+    fn should_deny_addresses_even_if_allowed() {
+        // given
+        let filter = Arc::new(IpFilter::new(IpFilterConfig {
+            allow: vec![range("10.0.0.0/8")],
+            deny: vec![range("10.6.6.0/24")],
+            ..IpFilterConfig::default()
+        }));
+
+        // when
+        let allowed = filter.admit(ip("10.1.2.3"));
+        let denied = filter.admit(ip("10.6.6.6"));
+        let outside = filter.admit(ip("::ffff:192.168.0.1"));
+
+        // then
+        assert!(allowed.is_ok());
+        assert_eq!(denied.unwrap_err(), Refusal::Denied);
+        assert_eq!(outside.unwrap_err(), Refusal::Denied);
+    }
+
+    #[test]
+    fn should_limit_open_connections_per_address() {
+        // given
+        let filter = Arc::new(IpFilter::new(IpFilterConfig {
+            max_connections_per_ip: Some(2),
+            ..IpFilterConfig::default()
+        }));
+        let first = filter.admit(ip("10.0.0.1")).unwrap();
+        let _second = filter.admit(ip("10.0.0.1")).unwrap();
+
+        // when
+        let third = filter.admit(ip("10.0.0.1"));
+        let other = filter.admit(ip("10.0.0.2"));
+        drop(first);
+        let after_close = filter.admit(ip("10.0.0.1"));
+
+        // then
+        assert_eq!(third.unwrap_err(), Refusal::TooManyConnections);
+        assert!(other.is_ok());
+        assert!(after_close.is_ok());
+    }
+
+    #[test]
+    fn should_throttle_new_connections_per_address() {
+        // given
+        let filter = Arc::new(IpFilter::new(IpFilterConfig {
+            connections_per_minute: Some(2),
+            ..IpFilterConfig::default()
+        }));
+
+        // when
+        let results: Vec<_> = (0..3)
+            .map(|_| filter.admit(ip("10.0.0.1")).map(drop))
+            .collect();
+
+        // then
+        assert_eq!(results, vec![Ok(()), Ok(()), Err(Refusal::TooFrequent)]);
+    }
+}
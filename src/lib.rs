@@ -0,0 +1,35 @@
+//! A server that lets people watch videos together, in sync.
+//!
+//! Besides the `palantir-server` binary, applications can embed the server and talk to sessions
+//! over in-process streams, see [`SessionServices::connect_in_process`].
+
+mod api_access;
+mod app;
+mod auth;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod config;
+mod connection;
+mod content_filter;
+mod errors;
+mod features;
+mod guest_names;
+mod http;
+mod ids;
+mod ip_filter;
+mod logging;
+mod messages;
+mod overload;
+mod playback;
+mod room;
+mod rpc;
+mod session;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod utils;
+
+pub use app::{start, SessionServices};
+pub use config::Config;
+pub use connection::ServerConfig;
@@ -0,0 +1,192 @@
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use env_logger::filter::{self, Filter};
+use log::{LevelFilter, Log, Metadata, Record};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+const LOG_ENV_VAR: &str = "PALANTIR_LOG";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// The log filter that is toggled on by SIGUSR1, in `env_logger` syntax. Operators can set
+    /// any other filter with `admin::set_log_filter/v1`.
+    pub debug_log_filter: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            debug_log_filter: "palantir_server=debug".to_string(),
+        }
+    }
+}
+
+fn parse_filter(spec: &str) -> Filter {
+    filter::Builder::new()
+        .filter_level(LevelFilter::Info)
+        .parse(spec)
+        .build()
+}
+
+/// Wraps the formatting logger and applies a filter that can be swapped at runtime.
+struct ReloadableLogger {
+    inner: env_logger::Logger,
+    filter: RwLock<Filter>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.read().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filter.read().matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[derive(Clone)]
+pub struct LogController {
+    logger: &'static ReloadableLogger,
+    base_filter: String,
+    debug_filter: Arc<Mutex<String>>,
+    debug_enabled: Arc<AtomicBool>,
+}
+
+impl LogController {
+    fn apply_filter(&self, spec: &str) {
+        let filter = parse_filter(spec);
+        log::set_max_level(filter.filter());
+        *self.logger.filter.write() = filter;
+        log::info!("Log filter set to '{spec}'");
+    }
+
+    /// Sets a log filter in `env_logger` syntax, e.g. `palantir_server::playback=debug`, or goes
+    /// back to the filter the server was started with.
+    pub fn set_filter(&self, spec: Option<&str>) {
+        self.debug_enabled.store(false, Ordering::Relaxed);
+        match spec.map(str::trim) {
+            None | Some("") => self.apply_filter(&self.base_filter),
+            Some(spec) => self.apply_filter(spec),
+        }
+    }
+
+    pub fn configure(&self, config: &LoggingConfig) {
+        config
+            .debug_log_filter
+            .clone_into(&mut self.debug_filter.lock());
+    }
+
+    /// Switches between the log filter the server was started with and the debug log filter.
+    pub fn toggle_debug(&self) {
+        let debug_enabled = !self.debug_enabled.fetch_xor(true, Ordering::Relaxed);
+        if debug_enabled {
+            let debug_filter = self.debug_filter.lock().clone();
+            self.apply_filter(&debug_filter);
+        } else {
+            self.apply_filter(&self.base_filter);
+        }
+    }
+
+    /// A controller for a logger that isn't installed, for applications that embed the server
+    /// and for tests.
+    pub fn detached() -> Self {
+        Self::new(
+            Box::leak(Box::new(ReloadableLogger {
+                inner: env_logger::Builder::new().build(),
+                filter: RwLock::new(parse_filter("info")),
+            })),
+            "info".to_string(),
+        )
+    }
+
+    fn new(logger: &'static ReloadableLogger, base_filter: String) -> Self {
+        Self {
+            logger,
+            base_filter,
+            debug_filter: Arc::new(Mutex::new(LoggingConfig::default().debug_log_filter)),
+            debug_enabled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+pub fn init() -> LogController {
+    let base_filter = env::var(LOG_ENV_VAR).unwrap_or_else(|_| "info".to_string());
+    let logger: &'static ReloadableLogger = Box::leak(Box::new(ReloadableLogger {
+        inner: pretty_env_logger::formatted_builder()
+            .filter_level(LevelFilter::Trace)
+            .build(),
+        filter: RwLock::new(parse_filter(&base_filter)),
+    }));
+
+    log::set_logger(logger).expect("Logger was already initialized");
+    log::set_max_level(logger.filter.read().filter());
+
+    LogController::new(logger, base_filter)
+}
+
+#[cfg(unix)]
+pub async fn handle_signals(controller: LogController) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+        Ok(sigusr1) => sigusr1,
+        Err(err) => {
+            log::error!("Failed to install SIGUSR1 handler: {err:?}");
+            return;
+        }
+    };
+    while sigusr1.recv().await.is_some() {
+        controller.toggle_debug();
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn handle_signals(_controller: LogController) {}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+
+    fn enabled(controller: &LogController, target: &str) -> bool {
+        controller.logger.enabled(
+            &Metadata::builder()
+                .level(Level::Debug)
+                .target(target)
+                .build(),
+        )
+    }
+
+    #[test]
+    fn should_set_any_log_filter_and_go_back_to_the_base_filter() {
+        // given
+        let controller = LogController::detached();
+
+        // when
+        controller.set_filter(Some("palantir_server::playback=debug"));
+        let playback = enabled(&controller, "palantir_server::playback");
+        let room = enabled(&controller, "palantir_server::room");
+        controller.set_filter(None);
+        let reset = enabled(&controller, "palantir_server::playback");
+
+        // then
+        assert!(playback);
+        assert!(!room);
+        assert!(!reset);
+    }
+}
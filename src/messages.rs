@@ -1,21 +1,145 @@
-use std::{error::Error, io::Cursor};
+use std::{
+    error::Error,
+    fmt,
+    io::{Cursor, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Context};
+use flate2::write::ZlibEncoder;
 use futures_util::{Sink, SinkExt, Stream, StreamExt};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use tokio_tungstenite::tungstenite;
 
-use crate::utils::timestamp;
+use crate::{
+    errors::{ClientError, ErrorCode},
+    utils::{timestamp, TokenBucket},
+};
+
+mod deprecation;
+
+pub use deprecation::DEPRECATIONS;
+
+/// The oldest and newest protocol versions this server speaks. Message types are suffixed with
+/// the protocol version they were introduced or last changed in.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+pub const MAX_PROTOCOL_VERSION: u32 = 2;
 
 pub mod dto {
     use crate::id_type;
 
     use super::*;
 
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum CompressionV1 {
+        #[serde(rename = "zlib")]
+        Zlib,
+
+        #[serde(rename = "zstd")]
+        Zstd,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum MessageFormatV1 {
+        #[serde(rename = "msgpack")]
+        Msgpack,
+
+        #[serde(rename = "json")]
+        Json,
+    }
+
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub struct ConnectionLoginMsgBodyV1 {
         pub username: String,
         pub api_key: Option<String>,
+
+        #[serde(default)]
+        pub token: Option<String>,
+
+        /// The compression algorithms the client can decode.
+        #[serde(default)]
+        pub compression: Vec<CompressionV1>,
+
+        /// The protocol versions the client speaks. Clients that don't say are assumed to speak
+        /// version 1.
+        #[serde(default)]
+        pub protocol_versions: Vec<u32>,
+
+        /// The format the server sends messages in for the rest of the connection. Without it,
+        /// the server answers in whichever format the client last used.
+        #[serde(default)]
+        pub format: Option<MessageFormatV1>,
+
+        /// A stable identifier the client generated for itself. The server remembers which
+        /// fingerprint first used a name and warns room hosts if another one shows up with it.
+        #[serde(default)]
+        pub fingerprint: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionLoginAckMsgBodyV1 {
+        /// Allows resuming the session with `connection::resume/v1` if the connection drops.
+        pub resume_token: Option<String>,
+
+        /// The protocol version both sides speak from now on.
+        pub protocol_version: u32,
+
+        /// The name the user goes by, which is generated for guests who didn't give one.
+        #[serde(default)]
+        pub name: String,
+    }
+
+    /// New credentials for a client that is already logged in, e.g. after its API key was
+    /// upgraded.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionReauthMsgBodyV1 {
+        pub api_key: Option<String>,
+
+        #[serde(default)]
+        pub token: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionPermissionsV1 {
+        pub host: bool,
+        pub observe: bool,
+
+        /// Glob patterns for the rooms that may be created or joined; all rooms if empty.
+        pub rooms: Vec<String>,
+
+        #[serde(default)]
+        pub admin: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionReauthAckMsgBodyV1 {
+        pub permissions: ConnectionPermissionsV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionRenameMsgBodyV1 {
+        pub name: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionRenameAckMsgBodyV1 {
+        /// The new name, as the server will use it from now on.
+        pub name: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionResumeMsgBodyV1 {
+        pub token: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionResumeAckMsgBodyV1 {
+        /// A fresh token, since each token can only be used once.
+        pub resume_token: Option<String>,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -32,6 +156,31 @@ pub mod dto {
         #[serde(rename = "timeout")]
         Timeout,
 
+        /// The client connected without TLS to a server that requires it.
+        #[serde(rename = "use_tls")]
+        UseTls,
+
+        /// The server speaks none of the protocol versions the client does.
+        #[serde(rename = "unsupported_protocol")]
+        UnsupportedProtocol,
+
+        /// The server shed the session to keep more important clients connected.
+        #[serde(rename = "overloaded")]
+        Overloaded,
+
+        /// An operator ended the session.
+        #[serde(rename = "kicked")]
+        Kicked,
+
+        /// The client sent more data than the server accepts, either in one message or over
+        /// time.
+        #[serde(rename = "flooding")]
+        Flooding,
+
+        /// The server is shutting down, usually to restart. Clients can reconnect shortly.
+        #[serde(rename = "restart")]
+        Restart,
+
         #[serde(rename = "unknown")]
         Unknown,
     }
@@ -40,25 +189,291 @@ pub mod dto {
     pub struct ConnectionClosedMsgBodyV1 {
         pub reason: ConnectionClosedReasonV1,
         pub message: String,
+
+        /// Where the client should connect instead, if anywhere.
+        #[serde(default)]
+        pub redirect_to: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum ClientErrorCodeV1 {
+        #[serde(rename = "not_in_room")]
+        NotInRoom,
+
+        #[serde(rename = "room_not_found")]
+        RoomNotFound,
+
+        #[serde(rename = "wrong_password")]
+        WrongPassword,
+
+        #[serde(rename = "not_authorized")]
+        NotAuthorized,
+
+        #[serde(rename = "room_full")]
+        RoomFull,
+
+        #[serde(rename = "room_busy")]
+        RoomBusy,
+
+        #[serde(rename = "invalid_request")]
+        InvalidRequest,
+
+        #[serde(rename = "rate_limited")]
+        RateLimited,
+
+        /// The message belongs to a newer protocol version than the connection uses.
+        #[serde(rename = "unsupported_version")]
+        UnsupportedVersion,
+
+        #[serde(rename = "name_taken")]
+        NameTaken,
+
+        #[serde(rename = "chat_too_long")]
+        ChatTooLong,
+
+        /// The chat message doesn't fit the format the room allows, like emoji only.
+        #[serde(rename = "chat_format_not_allowed")]
+        ChatFormatNotAllowed,
+
+        /// The server's operator turned off the feature the message needs.
+        #[serde(rename = "feature_disabled")]
+        FeatureDisabled,
+
+        #[default]
+        #[serde(rename = "other")]
+        Other,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub struct ConnectionClientErrorMsgBodyV1 {
+        #[serde(default)]
+        pub code: ClientErrorCodeV1,
         pub message: String,
     }
 
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct BatchMsgBodyV1 {
+        /// Handled in order, as if they had been sent one by one. A login may only come first.
+        pub messages: Vec<super::Message>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct BatchResultV1 {
+        /// Why the message failed, if it did.
+        pub error: Option<ConnectionClientErrorMsgBodyV1>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct BatchAckMsgBodyV1 {
+        /// One result for each message of the batch, in the same order.
+        pub results: Vec<BatchResultV1>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum SyncQualityV1 {
+        #[serde(rename = "good")]
+        Good,
+
+        #[serde(rename = "fair")]
+        Fair,
+
+        #[serde(rename = "poor")]
+        Poor,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionProbeMsgBodyV1 {
+        pub latency: u64,
+        /// The round trip time of the WebSocket ping, if the client's WebSocket answered it.
+        pub transport_latency: Option<u64>,
+        pub time_offset: i64,
+        pub sync_quality: SyncQualityV1,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum PlaybackSubscriptionV1 {
+        #[serde(rename = "none")]
+        None,
+
+        #[serde(rename = "host")]
+        Host,
+
+        #[serde(rename = "subscriber")]
+        Subscriber,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionRequestStatsMsgBodyV1 {
+        /// Whether to keep sending stats after every ping, or to stop doing so.
+        #[serde(default)]
+        pub periodic: bool,
+    }
+
+    /// The quality of the connection, as measured by the server's pings.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionStatsMsgBodyV1 {
+        pub latency: u64,
+        /// How much the latency varies between pings.
+        pub jitter: u64,
+        pub time_offset: i64,
+        pub sync_quality: SyncQualityV1,
+    }
+
+    /// The server's view of a client, meant for diagnostics.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ConnectionMyStatsAckMsgBodyV1 {
+        pub user_id: UserIdV1,
+        pub connected_for: u64,
+
+        /// Only known once the client has answered a ping.
+        pub latency: Option<u64>,
+        pub transport_latency: Option<u64>,
+        pub time_offset: i64,
+        pub sync_quality: Option<SyncQualityV1>,
+
+        /// How many errors the server has reported to the client so far.
+        pub errors: u32,
+        pub room: Option<RoomIdV1>,
+        pub role: Option<RoomUserRoleV1>,
+        pub playback: PlaybackSubscriptionV1,
+    }
+
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub struct RoomCreateMsgBodyV1 {
         pub name: String,
-        pub password: String,
+
+        /// Left out for an open room, which anyone can join without a password.
+        #[serde(default)]
+        pub password: Option<String>,
+
+        #[serde(default)]
+        pub public: bool,
+
+        /// The roles that are preferred when a new host has to be chosen, in order of priority.
+        /// If empty, guests are preferred over spectators.
+        #[serde(default)]
+        pub host_succession: Vec<RoomUserRoleV1>,
+
+        /// The role of users who join without an invite. Defaults to guest.
+        #[serde(default)]
+        pub default_role: Option<RoomUserRoleV1>,
+
+        #[serde(default)]
+        pub topic: Option<String>,
+
+        #[serde(default)]
+        pub tags: Vec<String>,
+
+        /// A cover image for the room, as an http or https link.
+        #[serde(default)]
+        pub image_url: Option<String>,
+
+        /// The room's offset from UTC in minutes, e.g. 60 for CET. Times are shown in it for
+        /// the room's users.
+        #[serde(default)]
+        pub utc_offset: Option<i16>,
+
+        /// A memorable id, like `movie-night`, that the room can be joined by instead of its
+        /// UUID. It has to be unique among open rooms.
+        #[serde(default)]
+        pub vanity_id: Option<String>,
+
+        /// Restrictions on chat in this room, on top of the server's own.
+        #[serde(default)]
+        pub chat: Option<RoomChatPolicyV1>,
+
+        /// How many subscribers have to be ready before a playback start goes out. Without one,
+        /// playback starts right away.
+        #[serde(default)]
+        pub ready_quorum: Option<PlaybackReadyQuorumV1>,
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum ChatFormatV1 {
+        #[default]
+        #[serde(rename = "markdown")]
+        Markdown,
+
+        #[serde(rename = "plain_text")]
+        PlainText,
+
+        #[serde(rename = "emoji_only")]
+        EmojiOnly,
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomChatPolicyV1 {
+        /// The maximum length of a chat message, in characters. Can't exceed the server's limit.
+        #[serde(default)]
+        pub max_length: Option<usize>,
+
+        #[serde(default)]
+        pub format: ChatFormatV1,
+    }
+
+    /// A room's setup as it is kept in a template file, for setting up recurring events again.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomTemplateV1 {
+        pub settings: RoomCreateMsgBodyV1,
+
+        #[serde(default)]
+        pub quiet_hours: Vec<RoomQuietWindowV1>,
+
+        /// The queue of the first playback in the room.
+        #[serde(default)]
+        pub queue: Vec<PlaybackSourceV1>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomCreateFromTemplateMsgBodyV1 {
+        /// The contents of a template file, which is JSON in the form of `RoomTemplateV1`.
+        pub template: String,
     }
 
     id_type!(RoomIdV1, Serialize, Deserialize);
 
+    /// Either a room's id, or its vanity id.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(untagged)]
+    pub enum RoomRefV1 {
+        Id(RoomIdV1),
+        Vanity(String),
+    }
+
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub struct RoomJoinMsgBodyV1 {
-        pub id: RoomIdV1,
-        pub password: String,
+        pub id: RoomRefV1,
+
+        /// Not needed for open rooms.
+        #[serde(default)]
+        pub password: Option<String>,
+
+        /// An invite token, which replaces the password and decides the role of the new user.
+        #[serde(default)]
+        pub invite: Option<String>,
+
+        /// Join as a spectator who is only counted, not listed by name.
+        #[serde(default)]
+        pub anonymous: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomCreateInviteMsgBodyV1 {
+        pub role: RoomUserRoleV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomInviteMsgBodyV1 {
+        pub room_id: RoomIdV1,
+        /// Valid for a day, or until the password changes. Only the newest invites of a room
+        /// are kept.
+        pub token: String,
+        pub role: RoomUserRoleV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomObserveMsgBodyV1 {
+        pub id: RoomRefV1,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -75,6 +490,11 @@ pub mod dto {
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub struct RoomUserPermissionsV1 {
         pub can_host: bool,
+
+        /// Whether the user may connect to synced playback.
+        #[serde(default)]
+        pub can_watch: bool,
+
         pub can_close: bool,
         pub can_set_roles: bool,
         pub can_kick: bool,
@@ -93,15 +513,74 @@ pub mod dto {
     pub struct RoomPlaybackInfoV1 {
         pub host: String,
         pub source: Option<PlaybackSourceV1>,
+
+        /// The host is syncing a clock instead of media, e.g. to watch a DVD together.
+        #[serde(default)]
+        pub stopwatch: bool,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub struct RoomStateMsgBodyV1 {
         pub id: RoomIdV1,
         pub name: String,
+        /// Empty for open rooms.
         pub password: String,
+
+        /// Whether anyone can join the room without a password.
+        #[serde(default)]
+        pub open: bool,
+
         pub users: Vec<RoomUserV1>,
         pub playback_info: Option<RoomPlaybackInfoV1>,
+
+        /// The most recently shared links, oldest first.
+        #[serde(default)]
+        pub recent_links: Vec<RoomSharedLinkV1>,
+
+        #[serde(default)]
+        pub intermission: Option<RoomIntermissionV1>,
+
+        /// How many anonymous spectators are watching, who aren't part of `users`.
+        #[serde(default)]
+        pub spectator_count: u32,
+
+        #[serde(default)]
+        pub topic: Option<String>,
+
+        #[serde(default)]
+        pub tags: Vec<String>,
+
+        #[serde(default)]
+        pub image_url: Option<String>,
+
+        #[serde(default)]
+        pub utc_offset: Option<i16>,
+
+        #[serde(default)]
+        pub vanity_id: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomIntermissionV1 {
+        /// Pinned in the room for as long as the intermission lasts.
+        pub notice: String,
+
+        /// When the host expects to resume, in server time.
+        pub ends_at: Option<u64>,
+
+        /// `ends_at` as an ISO 8601 time in the room's time zone, so clients can show it
+        /// without a time zone database.
+        #[serde(default)]
+        pub ends_at_iso: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomIntermissionMsgBodyV1 {
+        pub notice: String,
+
+        /// How long the intermission is expected to last, in seconds.
+        #[serde(default)]
+        pub countdown: Option<u64>,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -122,115 +601,809 @@ pub mod dto {
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-    pub enum RoomDisconnectedReasonV1 {
-        #[serde(rename = "closed_by_host")]
-        ClosedByHost,
-
-        #[serde(rename = "unauthorized")]
-        Unauthorized,
-
-        #[serde(rename = "server_error")]
-        ServerError,
+    pub struct RoomTransferHostMsgBodyV1 {
+        /// The user who becomes the host. The current host becomes a guest.
+        pub user_id: UserIdV1,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-    pub struct RoomDisconnectedMsgBodyV1 {
-        pub reason: RoomDisconnectedReasonV1,
+    pub struct RoomKickedMsgBodyV1 {
+        /// The name of the user who did the kicking.
+        pub kicked_by: String,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-    pub struct PlaybackSourceV1 {
-        pub title: String,
-        pub page_href: String,
-        pub frame_href: String,
-        pub element_query: String,
-    }
+    pub struct RoomListingV1 {
+        pub id: RoomIdV1,
+        pub name: String,
+        pub users: u32,
+        pub playback_active: bool,
 
-    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-    pub struct PlaybackStateV1 {
-        pub timestamp: u64,
-        pub playing: bool,
-        pub time: f32,
+        #[serde(default)]
+        pub topic: Option<String>,
+
+        #[serde(default)]
+        pub tags: Vec<String>,
+
+        #[serde(default)]
+        pub image_url: Option<String>,
+
+        #[serde(default)]
+        pub vanity_id: Option<String>,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-    pub struct PlaybackAvailableMsgBodyV1 {
-        pub info: RoomPlaybackInfoV1,
+    pub struct RoomListAckMsgBodyV1 {
+        pub rooms: Vec<RoomListingV1>,
+
+        /// How well the client can expect to stay in sync in any of the rooms, going by its
+        /// last ping. Missing if it hasn't been pinged yet.
+        #[serde(default)]
+        pub sync_quality: Option<SyncQualityV1>,
     }
 
+    /// Looks at a room before joining it, e.g. to warn about a bad connection up front.
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-    pub struct PlaybackStartMsgBodyV1 {
-        pub source: PlaybackSourceV1,
+    pub struct RoomPeekMsgBodyV1 {
+        pub id: RoomRefV1,
     }
 
-    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-    pub struct PlaybackSyncMsgBodyV1 {
-        pub state: PlaybackStateV1,
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomPeekAckMsgBodyV1 {
+        pub room: RoomListingV1,
+
+        /// How well the client can expect to stay in sync in the room, going by its last ping.
+        /// Missing if it hasn't been pinged yet.
+        #[serde(default)]
+        pub sync_quality: Option<SyncQualityV1>,
     }
 
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-    pub enum PlaybackStopReasonV1 {
-        #[serde(rename = "host_error")]
-        HostError,
-
-        #[serde(rename = "stopped_by_host")]
-        StoppedByHost,
+    pub enum RoomBroadcastEventV1 {
+        #[serde(rename = "state")]
+        State,
 
-        #[serde(rename = "superseded")]
-        Superseded,
+        #[serde(rename = "chat")]
+        Chat,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-    pub struct PlaybackStoppedMsgBodyV1 {
-        pub reason: PlaybackStopReasonV1,
+    pub struct RoomBroadcastAckMsgBodyV1 {
+        pub event: RoomBroadcastEventV1,
     }
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-    pub enum PlaybackDisconnectReasonV1 {
-        #[serde(rename = "user")]
-        User,
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomChatMsgBodyV1 {
+        pub text: String,
+    }
 
-        #[serde(rename = "subscriber_error")]
-        SubscriberError,
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomChatMessageMsgBodyV1 {
+        pub user_id: UserIdV1,
+        pub name: String,
+        pub text: String,
 
-        #[serde(untagged)]
-        Stopped(PlaybackStopReasonV1),
+        /// When the server received the message.
+        pub sent_at: u64,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-    pub struct PlaybackDisconnectedMsgBodyV1 {
-        pub reason: PlaybackDisconnectReasonV1,
+    pub struct RoomShareLinkMsgBodyV1 {
+        pub url: String,
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "m")]
-#[non_exhaustive]
-pub enum MessageBody {
-    #[serde(rename = "connection::login/v1")]
-    ConnectionLoginV1(dto::ConnectionLoginMsgBodyV1),
+    /// Something that was played in a room.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomPlayedSourceV1 {
+        pub host_id: UserIdV1,
+        pub host_name: String,
+        pub title: String,
+        pub page_href: String,
+        pub started_at: u64,
 
-    #[serde(rename = "connection::login_ack/v1")]
-    ConnectionLoginAckV1,
+        /// Not set while it's still playing.
+        pub ended_at: Option<u64>,
+    }
 
-    #[serde(rename = "connection::ping/v1")]
-    ConnectionPingV1,
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomPlaybackHistoryMsgBodyV1 {
+        /// Everything played since the room was created, oldest first.
+        pub entries: Vec<RoomPlayedSourceV1>,
+    }
 
-    #[serde(rename = "connection::pong/v1")]
-    ConnectionPongV1,
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomModerationLogMsgBodyV1 {
+        /// Only entries older than the one with this id are returned; the newest ones if unset.
+        #[serde(default)]
+        pub before: Option<u64>,
 
-    #[serde(rename = "connection::client_error/v1")]
+        #[serde(default)]
+        pub limit: Option<u32>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum RoomModerationActionV1 {
+        #[serde(rename = "kicked")]
+        Kicked,
+
+        #[serde(rename = "role_changed")]
+        RoleChanged,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomModerationEntryV1 {
+        pub id: u64,
+        pub action: RoomModerationActionV1,
+
+        /// The new role, if the action changed it.
+        pub role: Option<RoomUserRoleV1>,
+        pub user_id: UserIdV1,
+        pub user_name: String,
+        pub by_id: UserIdV1,
+        pub by_name: String,
+        pub at: u64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomModerationLogAckMsgBodyV1 {
+        /// Newest first.
+        pub entries: Vec<RoomModerationEntryV1>,
+
+        /// Whether there are older entries.
+        pub more: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomSharedLinkV1 {
+        pub user_id: UserIdV1,
+        pub name: String,
+        pub url: String,
+
+        /// When the server received the link.
+        pub shared_at: u64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomHostChangedMsgBodyV1 {
+        pub user_id: UserIdV1,
+        pub name: String,
+    }
+
+    /// Tells hosts that a user joined under a name that was first used from a different client.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomIdentityWarningMsgBodyV1 {
+        pub user_id: UserIdV1,
+        pub name: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomUserJoinedMsgBodyV1 {
+        pub user_id: UserIdV1,
+        pub name: String,
+        pub role: RoomUserRoleV1,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum RoomUserLeftReasonV1 {
+        #[serde(rename = "left")]
+        Left,
+
+        #[serde(rename = "kicked")]
+        Kicked,
+
+        /// The client stopped answering pings.
+        #[serde(rename = "timeout")]
+        Timeout,
+
+        #[serde(rename = "connection_lost")]
+        ConnectionLost,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomUserLeftMsgBodyV1 {
+        pub user_id: UserIdV1,
+        pub name: String,
+        pub reason: RoomUserLeftReasonV1,
+    }
+
+    /// A daily window in UTC, given in minutes since midnight. Windows may wrap around midnight.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomQuietWindowV1 {
+        pub start: u16,
+        pub end: u16,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomSetQuietHoursMsgBodyV1 {
+        pub windows: Vec<RoomQuietWindowV1>,
+    }
+
+    /// Replaces the room's metadata; whatever is left out is cleared.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomUpdateMsgBodyV1 {
+        #[serde(default)]
+        pub topic: Option<String>,
+
+        #[serde(default)]
+        pub tags: Vec<String>,
+
+        #[serde(default)]
+        pub image_url: Option<String>,
+
+        #[serde(default)]
+        pub utc_offset: Option<i16>,
+    }
+
+    /// Replaces the room's password. Users who are already in the room stay.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomSetPasswordMsgBodyV1 {
+        /// Left out to open the room to anyone, which also takes `open`.
+        #[serde(default)]
+        pub password: Option<String>,
+
+        /// Has to be set to open the room, so that a missing password never opens it by
+        /// accident.
+        #[serde(default)]
+        pub open: bool,
+    }
+
+    /// The timestamps in peer probes are readings of the clients' own clocks; the server relays
+    /// them unchanged.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomPeerProbeMsgBodyV1 {
+        /// The recipient when sent by a client; the original sender when relayed by the server.
+        pub peer: UserIdV1,
+        pub probe_id: u32,
+        pub sent_at: u64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomPeerProbeReplyMsgBodyV1 {
+        /// The recipient when sent by a client; the original sender when relayed by the server.
+        pub peer: UserIdV1,
+        pub probe_id: u32,
+        pub sent_at: u64,
+        pub received_at: u64,
+        pub replied_at: u64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum RoomDisconnectedReasonV1 {
+        #[serde(rename = "closed_by_host")]
+        ClosedByHost,
+
+        #[serde(rename = "unauthorized")]
+        Unauthorized,
+
+        #[serde(rename = "expired")]
+        Expired,
+
+        #[serde(rename = "denied")]
+        Denied,
+
+        #[serde(rename = "closed_by_operator")]
+        ClosedByOperator,
+
+        #[serde(rename = "server_error")]
+        ServerError,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RoomDisconnectedMsgBodyV1 {
+        pub reason: RoomDisconnectedReasonV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackSourceV1 {
+        pub title: String,
+        pub page_href: String,
+        pub frame_href: String,
+        pub element_query: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackStateV1 {
+        pub timestamp: u64,
+        pub playing: bool,
+        pub time: f32,
+
+        #[serde(default)]
+        pub duration: Option<f32>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackAvailableMsgBodyV1 {
+        pub info: RoomPlaybackInfoV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackStartMsgBodyV1 {
+        pub source: PlaybackSourceV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackStartMsgBodyV2 {
+        /// Without a source, the playback is a stopwatch that the host starts, pauses and seeks.
+        #[serde(default)]
+        pub source: Option<PlaybackSourceV1>,
+    }
+
+    /// When to begin playing, so that everyone starts at the same moment.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackStartAtMsgBodyV1 {
+        /// The moment to start at, in the client's own clock.
+        pub timestamp: u64,
+
+        /// How long after receiving this message to start, in milliseconds, for clients that
+        /// would rather not rely on their clock.
+        pub delay: u64,
+    }
+
+    /// Where the user was in the current source when they left the room, so that they can be
+    /// offered to continue from there.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackLastPositionMsgBodyV1 {
+        /// In seconds.
+        pub position: f32,
+    }
+
+    id_type!(QueueEntryIdV1, Serialize, Deserialize);
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackQueueEntryV1 {
+        pub id: QueueEntryIdV1,
+        pub source: PlaybackSourceV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackQueueAddMsgBodyV1 {
+        pub source: PlaybackSourceV1,
+
+        /// Where in the queue to insert the source; at the end if not given.
+        #[serde(default)]
+        pub index: Option<usize>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackQueueRemoveMsgBodyV1 {
+        pub id: QueueEntryIdV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackQueueMoveMsgBodyV1 {
+        pub id: QueueEntryIdV1,
+        pub index: usize,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackQueueStateMsgBodyV1 {
+        pub entries: Vec<PlaybackQueueEntryV1>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackSourceChangedMsgBodyV1 {
+        pub source: PlaybackSourceV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackSourceChangedMsgBodyV2 {
+        /// Without a source, the playback switched to a stopwatch.
+        #[serde(default)]
+        pub source: Option<PlaybackSourceV1>,
+    }
+
+    /// Positions in the media that can be seeked to quickly, like keyframes or chapter starts,
+    /// in seconds.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackSeekHintsMsgBodyV1 {
+        pub points: Vec<f32>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum PlaybackStatusV1 {
+        #[serde(rename = "idle")]
+        Idle,
+
+        #[serde(rename = "loading")]
+        Loading,
+
+        #[serde(rename = "playing")]
+        Playing,
+
+        #[serde(rename = "paused")]
+        Paused,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackInfoMsgBodyV1 {
+        pub info: Option<RoomPlaybackInfoV1>,
+        pub status: PlaybackStatusV1,
+
+        /// The estimated current playback position in seconds.
+        pub position: Option<f32>,
+        pub duration: Option<f32>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackSyncMsgBodyV1 {
+        pub state: PlaybackStateV1,
+    }
+
+    /// Sent by the host to ask every subscriber where their player is, and relayed by the server
+    /// to the subscribers.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackRollCallMsgBodyV1 {
+        pub roll_call_id: u32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackRollCallReplyMsgBodyV1 {
+        pub roll_call_id: u32,
+        pub state: PlaybackStateV1,
+    }
+
+    /// What a subscriber's player is doing, sent along with keepalives.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackTelemetryV1 {
+        pub state: PlaybackStateV1,
+
+        /// How much of the media after the current position is buffered, in seconds.
+        #[serde(default)]
+        pub buffered: Option<f32>,
+
+        /// How many frames the player dropped since the current source started.
+        #[serde(default)]
+        pub dropped_frames: Option<u32>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct ConnectionKeepaliveMsgBodyV1 {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub telemetry: Option<PlaybackTelemetryV1>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum PlaybackQualityHintV1 {
+        /// The player keeps running out of buffered media.
+        #[serde(rename = "low_buffer")]
+        LowBuffer,
+
+        #[serde(rename = "dropping_frames")]
+        DroppingFrames,
+    }
+
+    /// Suggests that a subscriber switches to a lower quality to keep up.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackQualityHintMsgBodyV1 {
+        pub hint: PlaybackQualityHintV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackDelegateTimingMsgBodyV1 {
+        /// Whether the server should pick a subscriber whose syncs everyone follows, instead of
+        /// the host's.
+        pub delegate: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackSubscriberStatsV1 {
+        pub user_id: UserIdV1,
+        pub name: String,
+
+        /// How far ahead of the playback the subscriber's player was when it last reported, in
+        /// seconds; negative if behind. Missing if it hasn't reported yet.
+        #[serde(default)]
+        pub drift: Option<f32>,
+
+        /// The round trip time of the subscriber's connection, in milliseconds.
+        pub latency: u64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackStatsMsgBodyV1 {
+        pub subscribers: Vec<PlaybackSubscriberStatsV1>,
+    }
+
+    /// How many subscribers have to report that they are ready.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum PlaybackReadyQuorumV1 {
+        /// A number of subscribers, or all of them if there are fewer.
+        #[serde(rename = "count")]
+        Count(u32),
+
+        /// A percentage of the subscribers, rounded up, and at least one of them.
+        #[serde(rename = "percent")]
+        Percent(u8),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackUnreadySubscriberV1 {
+        pub user_id: UserIdV1,
+        pub name: String,
+    }
+
+    /// Who a playback start is still waiting for. Once the start goes out, the host gets this
+    /// once more with nobody left to wait for.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackWaitingForMsgBodyV1 {
+        pub subscribers: Vec<PlaybackUnreadySubscriberV1>,
+        pub ready: u32,
+        pub needed: u32,
+    }
+
+    /// Turns delta syncs on or off for this session. Turning them on again starts over, e.g.
+    /// after the client lost track of its states.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackRequestDeltaSyncMsgBodyV1 {
+        pub enabled: bool,
+    }
+
+    /// A playback sync for sessions that turned on delta syncs, with only the fields that changed
+    /// since the state the client last acknowledged.
+    ///
+    /// The delta starts with a byte of flags: `0x01` if playing, `0x02` if the timestamp is
+    /// included, `0x04` if the time is, `0x08` if the duration is, and `0x10` if there is no
+    /// duration. The included fields follow in that order, as LEB128 varints. The timestamp is
+    /// zigzag encoded as the difference to the base, and the time and duration are the bits of
+    /// the `f32` XORed with those of the base. Without a base, everything is relative to zeroes.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackSyncDeltaMsgBodyV1 {
+        pub seq: u32,
+
+        /// The sequence number of the state the delta is relative to. If there is none, the delta
+        /// is a full state.
+        pub base: Option<u32>,
+
+        #[serde(with = "binary")]
+        pub delta: Vec<u8>,
+    }
+
+    /// Sent by the client once it applied a delta sync, so that later ones can be relative to it.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackSyncAckMsgBodyV1 {
+        pub seq: u32,
+    }
+
+    /// Binary data as such, e.g. `bin` in msgpack, instead of a list of numbers. JSON has no
+    /// binary type, so it still gets a list of numbers there.
+    mod binary {
+        use std::fmt;
+
+        use serde::{
+            de::{SeqAccess, Visitor},
+            Deserializer, Serializer,
+        };
+
+        pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(data)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<u8>, D::Error> {
+            deserializer.deserialize_byte_buf(BinaryVisitor)
+        }
+
+        struct BinaryVisitor;
+
+        impl<'de> Visitor<'de> for BinaryVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("binary data")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Ok(v.to_vec())
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(v)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    data.push(byte);
+                }
+                Ok(data)
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackLeaderMsgBodyV1 {
+        /// The subscriber whose syncs everyone follows, or none if it's the host again.
+        pub user_id: Option<UserIdV1>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackRollCallEntryV1 {
+        pub user_id: UserIdV1,
+        pub name: String,
+
+        /// Missing if the subscriber didn't answer in time.
+        #[serde(default)]
+        pub state: Option<PlaybackStateV1>,
+
+        /// How far ahead of the playback the subscriber is, in seconds; negative if behind.
+        #[serde(default)]
+        pub drift: Option<f32>,
+
+        /// From the subscriber's last heartbeat, if it sends telemetry.
+        #[serde(default)]
+        pub buffered: Option<f32>,
+
+        #[serde(default)]
+        pub dropped_frames: Option<u32>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct PlaybackRollCallReportMsgBodyV1 {
+        pub roll_call_id: u32,
+        pub entries: Vec<PlaybackRollCallEntryV1>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum PlaybackStopReasonV1 {
+        #[serde(rename = "host_error")]
+        HostError,
+
+        #[serde(rename = "stopped_by_host")]
+        StoppedByHost,
+
+        #[serde(rename = "superseded")]
+        Superseded,
+
+        #[serde(rename = "finished")]
+        Finished,
+
+        #[serde(rename = "host_left")]
+        HostLeft,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackStoppedMsgBodyV1 {
+        pub reason: PlaybackStopReasonV1,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum PlaybackDisconnectReasonV1 {
+        #[serde(rename = "user")]
+        User,
+
+        #[serde(rename = "subscriber_error")]
+        SubscriberError,
+
+        #[serde(untagged)]
+        Stopped(PlaybackStopReasonV1),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PlaybackDisconnectedMsgBodyV1 {
+        pub reason: PlaybackDisconnectReasonV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct AdminRoomV1 {
+        pub id: RoomIdV1,
+        pub name: String,
+        pub public: bool,
+        pub pending_approval: bool,
+        pub creator: Option<String>,
+        pub users: u32,
+        pub now_playing: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct AdminListRoomsAckMsgBodyV1 {
+        pub rooms: Vec<AdminRoomV1>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct AdminCloseRoomMsgBodyV1 {
+        pub room_id: RoomIdV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct AdminKickSessionMsgBodyV1 {
+        /// The session's id, which other clients know as its user id.
+        pub user_id: UserIdV1,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct AdminSetLogFilterMsgBodyV1 {
+        /// In `env_logger` syntax, e.g. `palantir_server::playback=debug`. Left out to go back
+        /// to the filter the server was started with.
+        #[serde(default)]
+        pub filter: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct AdminStatsAckMsgBodyV1 {
+        pub sessions: u32,
+        pub rooms: u32,
+        pub pending_rooms: u32,
+        /// How many users are in rooms, across all rooms.
+        pub room_users: u32,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "m")]
+#[non_exhaustive]
+pub enum MessageBody {
+    #[serde(rename = "connection::login/v1")]
+    ConnectionLoginV1(dto::ConnectionLoginMsgBodyV1),
+
+    #[serde(rename = "connection::login_ack/v1")]
+    ConnectionLoginAckV1(dto::ConnectionLoginAckMsgBodyV1),
+
+    #[serde(rename = "connection::reauth/v1")]
+    ConnectionReauthV1(dto::ConnectionReauthMsgBodyV1),
+
+    #[serde(rename = "connection::reauth_ack/v1")]
+    ConnectionReauthAckV1(dto::ConnectionReauthAckMsgBodyV1),
+
+    #[serde(rename = "connection::resume/v1")]
+    ConnectionResumeV1(dto::ConnectionResumeMsgBodyV1),
+
+    #[serde(rename = "connection::resume_ack/v1")]
+    ConnectionResumeAckV1(dto::ConnectionResumeAckMsgBodyV1),
+
+    #[serde(rename = "connection::ping/v1")]
+    ConnectionPingV1,
+
+    #[serde(rename = "connection::pong/v1")]
+    ConnectionPongV1,
+
+    #[serde(rename = "connection::client_error/v1")]
     ConnectionClientErrorV1(dto::ConnectionClientErrorMsgBodyV1),
 
     #[serde(rename = "connection::closed/v1")]
     ConnectionClosedV1(dto::ConnectionClosedMsgBodyV1),
 
+    /// Keeps the connection alive, and may tell subscribers' playback along the way.
     #[serde(rename = "connection::keepalive/v1")]
-    ConnectionKeepaliveV1,
+    ConnectionKeepaliveV1(dto::ConnectionKeepaliveMsgBodyV1),
+
+    #[serde(rename = "connection::request_probe/v1")]
+    ConnectionRequestProbeV1,
+
+    #[serde(rename = "connection::probe/v1")]
+    ConnectionProbeV1(dto::ConnectionProbeMsgBodyV1),
+
+    #[serde(rename = "connection::request_stats/v1")]
+    ConnectionRequestStatsV1(dto::ConnectionRequestStatsMsgBodyV1),
+
+    #[serde(rename = "connection::stats/v1")]
+    ConnectionStatsV1(dto::ConnectionStatsMsgBodyV1),
+
+    #[serde(rename = "connection::my_stats/v1")]
+    ConnectionMyStatsV1,
+
+    #[serde(rename = "connection::my_stats_ack/v1")]
+    ConnectionMyStatsAckV1(dto::ConnectionMyStatsAckMsgBodyV1),
+
+    /// Changes the name the user goes by, including in the room they are in.
+    #[serde(rename = "connection::rename/v1")]
+    ConnectionRenameV1(dto::ConnectionRenameMsgBodyV1),
+
+    #[serde(rename = "connection::rename_ack/v1")]
+    ConnectionRenameAckV1(dto::ConnectionRenameAckMsgBodyV1),
+
+    #[serde(rename = "batch/v1")]
+    BatchV1(dto::BatchMsgBodyV1),
+
+    #[serde(rename = "batch_ack/v1")]
+    BatchAckV1(dto::BatchAckMsgBodyV1),
 
     #[serde(rename = "room::create/v1")]
     RoomCreateV1(dto::RoomCreateMsgBodyV1),
 
+    #[serde(rename = "room::create_from_template/v1")]
+    RoomCreateFromTemplateV1(dto::RoomCreateFromTemplateMsgBodyV1),
+
     #[serde(rename = "room::create_ack/v1")]
     RoomCreateAckV1,
 
@@ -243,32 +1416,130 @@ pub enum MessageBody {
     #[serde(rename = "room::join/v1")]
     RoomJoinV1(dto::RoomJoinMsgBodyV1),
 
-    #[serde(rename = "room::join_ack/v1")]
-    RoomJoinAckV1,
+    #[serde(rename = "room::join_ack/v1")]
+    RoomJoinAckV1,
+
+    #[serde(rename = "room::create_invite/v1")]
+    RoomCreateInviteV1(dto::RoomCreateInviteMsgBodyV1),
+
+    #[serde(rename = "room::invite/v1")]
+    RoomInviteV1(dto::RoomInviteMsgBodyV1),
+
+    #[serde(rename = "room::observe/v1")]
+    RoomObserveV1(dto::RoomObserveMsgBodyV1),
+
+    #[serde(rename = "room::observe_ack/v1")]
+    RoomObserveAckV1,
+
+    #[serde(rename = "room::leave/v1")]
+    RoomLeaveV1,
+
+    #[serde(rename = "room::leave_ack/v1")]
+    RoomLeaveAckV1,
+
+    #[serde(rename = "room::disconnected/v1")]
+    RoomDisconnectedV1(dto::RoomDisconnectedMsgBodyV1),
+
+    /// The room is waiting for a moderator's approval, and isn't open to others until then.
+    #[serde(rename = "room::pending_approval/v1")]
+    RoomPendingApprovalV1,
+
+    #[serde(rename = "room::approved/v1")]
+    RoomApprovedV1,
+
+    #[serde(rename = "room::request_state/v1")]
+    RoomRequestStateV1,
+
+    #[serde(rename = "room::state/v1")]
+    RoomStateV1(Arc<dto::RoomStateMsgBodyV1>),
+
+    #[serde(rename = "room::request_permissions/v1")]
+    RoomRequestPermissionsV1,
+
+    #[serde(rename = "room::set_user_role/v1")]
+    RoomSetUserRole(dto::RoomSetUserRoleMsgBodyV1),
+
+    #[serde(rename = "room::kick_user/v1")]
+    RoomKickUser(dto::RoomKickUserMsgBodyV1),
+
+    #[serde(rename = "room::transfer_host/v1")]
+    RoomTransferHostV1(dto::RoomTransferHostMsgBodyV1),
+
+    #[serde(rename = "room::intermission/v1")]
+    RoomIntermissionV1(dto::RoomIntermissionMsgBodyV1),
+
+    #[serde(rename = "room::end_intermission/v1")]
+    RoomEndIntermissionV1,
+
+    #[serde(rename = "room::kicked/v1")]
+    RoomKickedV1(dto::RoomKickedMsgBodyV1),
+
+    #[serde(rename = "room::list/v1")]
+    RoomListV1,
+
+    #[serde(rename = "room::list_ack/v1")]
+    RoomListAckV1(dto::RoomListAckMsgBodyV1),
+
+    #[serde(rename = "room::peek/v1")]
+    RoomPeekV1(dto::RoomPeekMsgBodyV1),
+
+    #[serde(rename = "room::peek_ack/v1")]
+    RoomPeekAckV1(dto::RoomPeekAckMsgBodyV1),
+
+    #[serde(rename = "room::broadcast_ack/v1")]
+    RoomBroadcastAckV1(dto::RoomBroadcastAckMsgBodyV1),
+
+    #[serde(rename = "room::host_changed/v1")]
+    RoomHostChangedV1(dto::RoomHostChangedMsgBodyV1),
+
+    #[serde(rename = "room::identity_warning/v1")]
+    RoomIdentityWarningV1(dto::RoomIdentityWarningMsgBodyV1),
+
+    #[serde(rename = "room::user_joined/v1")]
+    RoomUserJoinedV1(dto::RoomUserJoinedMsgBodyV1),
+
+    #[serde(rename = "room::user_left/v1")]
+    RoomUserLeftV1(dto::RoomUserLeftMsgBodyV1),
+
+    #[serde(rename = "room::chat/v1")]
+    RoomChatV1(dto::RoomChatMsgBodyV1),
+
+    #[serde(rename = "room::chat_message/v1")]
+    RoomChatMessageV1(dto::RoomChatMessageMsgBodyV1),
+
+    #[serde(rename = "room::share_link/v1")]
+    RoomShareLinkV1(dto::RoomShareLinkMsgBodyV1),
+
+    #[serde(rename = "room::link_shared/v1")]
+    RoomLinkSharedV1(dto::RoomSharedLinkV1),
 
-    #[serde(rename = "room::leave/v1")]
-    RoomLeaveV1,
+    #[serde(rename = "room::request_playback_history/v1")]
+    RoomRequestPlaybackHistoryV1,
 
-    #[serde(rename = "room::leave_ack/v1")]
-    RoomLeaveAckV1,
+    #[serde(rename = "room::playback_history/v1")]
+    RoomPlaybackHistoryV1(dto::RoomPlaybackHistoryMsgBodyV1),
 
-    #[serde(rename = "room::disconnected/v1")]
-    RoomDisconnectedV1(dto::RoomDisconnectedMsgBodyV1),
+    /// Asks for who was kicked or given another role in the room, which only moderators may see.
+    #[serde(rename = "room::moderation_log/v1")]
+    RoomModerationLogV1(dto::RoomModerationLogMsgBodyV1),
 
-    #[serde(rename = "room::request_state/v1")]
-    RoomRequestStateV1,
+    #[serde(rename = "room::moderation_log_ack/v1")]
+    RoomModerationLogAckV1(dto::RoomModerationLogAckMsgBodyV1),
 
-    #[serde(rename = "room::state/v1")]
-    RoomStateV1(dto::RoomStateMsgBodyV1),
+    #[serde(rename = "room::set_quiet_hours/v1")]
+    RoomSetQuietHoursV1(dto::RoomSetQuietHoursMsgBodyV1),
 
-    #[serde(rename = "room::request_permissions/v1")]
-    RoomRequestPermissionsV1,
+    #[serde(rename = "room::update/v1")]
+    RoomUpdateV1(dto::RoomUpdateMsgBodyV1),
 
-    #[serde(rename = "room::set_user_role/v1")]
-    RoomSetUserRole(dto::RoomSetUserRoleMsgBodyV1),
+    #[serde(rename = "room::set_password/v1")]
+    RoomSetPasswordV1(dto::RoomSetPasswordMsgBodyV1),
 
-    #[serde(rename = "room::kick_user/v1")]
-    RoomKickUser(dto::RoomKickUserMsgBodyV1),
+    #[serde(rename = "room::peer_probe/v1")]
+    RoomPeerProbeV1(dto::RoomPeerProbeMsgBodyV1),
+
+    #[serde(rename = "room::peer_probe_reply/v1")]
+    RoomPeerProbeReplyV1(dto::RoomPeerProbeReplyMsgBodyV1),
 
     #[serde(rename = "room::permissions/v1")]
     RoomPermissionsV1(dto::RoomPermissionsMsgBodyV1),
@@ -276,6 +1547,12 @@ pub enum MessageBody {
     #[serde(rename = "playback::available/v1")]
     PlaybackAvailableV1(dto::PlaybackAvailableMsgBodyV1),
 
+    #[serde(rename = "playback::request_info/v1")]
+    PlaybackRequestInfoV1,
+
+    #[serde(rename = "playback::info/v1")]
+    PlaybackInfoV1(dto::PlaybackInfoMsgBodyV1),
+
     #[serde(rename = "playback::request_host/v1")]
     PlaybackRequestHostV1,
 
@@ -285,29 +1562,140 @@ pub enum MessageBody {
     #[serde(rename = "playback::request_start/v1")]
     PlaybackRequestStartV1(dto::PlaybackStartMsgBodyV1),
 
+    #[serde(rename = "playback::request_start/v2")]
+    PlaybackRequestStartV2(dto::PlaybackStartMsgBodyV2),
+
     #[serde(rename = "playback::started/v1")]
     PlaybackStartedV1,
 
+    #[serde(rename = "playback::start_at/v1")]
+    PlaybackStartAtV1(dto::PlaybackStartAtMsgBodyV1),
+
+    #[serde(rename = "playback::last_position/v1")]
+    PlaybackLastPositionV1(dto::PlaybackLastPositionMsgBodyV1),
+
     #[serde(rename = "playback::request_connect/v1")]
     PlaybackRequestConnectV1,
 
     #[serde(rename = "playback::connected/v1")]
     PlaybackConnectedV1,
 
+    #[serde(rename = "playback::source_changed/v1")]
+    PlaybackSourceChangedV1(dto::PlaybackSourceChangedMsgBodyV1),
+
+    #[serde(rename = "playback::source_changed/v2")]
+    PlaybackSourceChangedV2(dto::PlaybackSourceChangedMsgBodyV2),
+
     #[serde(rename = "playback::sync/v1")]
     PlaybackSyncV1(dto::PlaybackSyncMsgBodyV1),
 
+    #[serde(rename = "playback::seek_hints/v1")]
+    PlaybackSeekHintsV1(dto::PlaybackSeekHintsMsgBodyV1),
+
     #[serde(rename = "playback::request_stop/v1")]
     PlaybackRequestStopV1,
 
     #[serde(rename = "playback::stopped/v1")]
     PlaybackStoppedV1(dto::PlaybackStoppedMsgBodyV1),
 
+    #[serde(rename = "playback::finished/v1")]
+    PlaybackFinishedV1,
+
+    #[serde(rename = "playback::ended/v1")]
+    PlaybackEndedV1,
+
+    #[serde(rename = "playback::queue_add/v1")]
+    PlaybackQueueAddV1(dto::PlaybackQueueAddMsgBodyV1),
+
+    #[serde(rename = "playback::queue_remove/v1")]
+    PlaybackQueueRemoveV1(dto::PlaybackQueueRemoveMsgBodyV1),
+
+    #[serde(rename = "playback::queue_move/v1")]
+    PlaybackQueueMoveV1(dto::PlaybackQueueMoveMsgBodyV1),
+
+    #[serde(rename = "playback::queue_state/v1")]
+    PlaybackQueueStateV1(dto::PlaybackQueueStateMsgBodyV1),
+
+    #[serde(rename = "playback::roll_call/v1")]
+    PlaybackRollCallV1(dto::PlaybackRollCallMsgBodyV1),
+
+    #[serde(rename = "playback::roll_call_reply/v1")]
+    PlaybackRollCallReplyV1(dto::PlaybackRollCallReplyMsgBodyV1),
+
+    #[serde(rename = "playback::roll_call_report/v1")]
+    PlaybackRollCallReportV1(dto::PlaybackRollCallReportMsgBodyV1),
+
     #[serde(rename = "playback::request_disconnect/v1")]
     PlaybackRequestDisconnectV1,
 
     #[serde(rename = "playback::disconnected/v1")]
     PlaybackDisconnectedV1(dto::PlaybackDisconnectedMsgBodyV1),
+
+    #[serde(rename = "playback::quality_hint/v1")]
+    PlaybackQualityHintV1(dto::PlaybackQualityHintMsgBodyV1),
+
+    /// Lets the host hand timing authority to the subscriber with the steadiest connection.
+    #[serde(rename = "playback::delegate_timing/v1")]
+    PlaybackDelegateTimingV1(dto::PlaybackDelegateTimingMsgBodyV1),
+
+    #[serde(rename = "playback::leader/v1")]
+    PlaybackLeaderV1(dto::PlaybackLeaderMsgBodyV1),
+
+    /// Asks how far each subscriber is off the playback, which only the host may see.
+    #[serde(rename = "playback::request_stats/v1")]
+    PlaybackRequestStatsV1,
+
+    #[serde(rename = "playback::stats/v1")]
+    PlaybackStatsV1(dto::PlaybackStatsMsgBodyV1),
+
+    /// Sent by a subscriber once its player has loaded the source and can start playing.
+    #[serde(rename = "playback::ready/v1")]
+    PlaybackReadyV1,
+
+    #[serde(rename = "playback::waiting_for/v1")]
+    PlaybackWaitingForV1(dto::PlaybackWaitingForMsgBodyV1),
+
+    #[serde(rename = "playback::request_delta_sync/v1")]
+    PlaybackRequestDeltaSyncV1(dto::PlaybackRequestDeltaSyncMsgBodyV1),
+
+    #[serde(rename = "playback::sync_delta/v1")]
+    PlaybackSyncDeltaV1(dto::PlaybackSyncDeltaMsgBodyV1),
+
+    #[serde(rename = "playback::sync_ack/v1")]
+    PlaybackSyncAckV1(dto::PlaybackSyncAckMsgBodyV1),
+
+    /// Lists all rooms, including private ones and those waiting for approval.
+    #[serde(rename = "admin::list_rooms/v1")]
+    AdminListRoomsV1,
+
+    #[serde(rename = "admin::list_rooms_ack/v1")]
+    AdminListRoomsAckV1(dto::AdminListRoomsAckMsgBodyV1),
+
+    #[serde(rename = "admin::close_room/v1")]
+    AdminCloseRoomV1(dto::AdminCloseRoomMsgBodyV1),
+
+    #[serde(rename = "admin::close_room_ack/v1")]
+    AdminCloseRoomAckV1,
+
+    /// Ends a session. Unlike a dropped connection, a kicked session can't be resumed.
+    #[serde(rename = "admin::kick_session/v1")]
+    AdminKickSessionV1(dto::AdminKickSessionMsgBodyV1),
+
+    #[serde(rename = "admin::kick_session_ack/v1")]
+    AdminKickSessionAckV1,
+
+    #[serde(rename = "admin::stats/v1")]
+    AdminStatsV1,
+
+    #[serde(rename = "admin::stats_ack/v1")]
+    AdminStatsAckV1(dto::AdminStatsAckMsgBodyV1),
+
+    /// Changes which logs the server writes, to look into a live issue without a restart.
+    #[serde(rename = "admin::set_log_filter/v1")]
+    AdminSetLogFilterV1(dto::AdminSetLogFilterMsgBodyV1),
+
+    #[serde(rename = "admin::set_log_filter_ack/v1")]
+    AdminSetLogFilterAckV1,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -315,17 +1703,33 @@ pub struct Message {
     #[serde(rename = "t")]
     pub timestamp: u64,
 
+    /// Counts the broadcasts of the room the message was broadcast to, so that clients can tell
+    /// when they missed one and ask for the room's state. Not set on anything else.
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+
     #[serde(flatten)]
     pub body: MessageBody,
 }
 
+impl MessageBody {
+    /// Whether this is a message that can get large enough to be worth compressing.
+    fn is_compressible(&self) -> bool {
+        matches!(self, Self::RoomStateV1(..))
+    }
+}
+
 impl Message {
     pub fn new(body: MessageBody) -> Self {
         Self::new_with_timestamp(body, timestamp())
     }
 
     pub fn new_with_timestamp(body: MessageBody, timestamp: u64) -> Self {
-        Self { body, timestamp }
+        Self {
+            body,
+            timestamp,
+            seq: None,
+        }
     }
 }
 
@@ -337,8 +1741,121 @@ enum MessageFormat {
     Msgpack,
 }
 
+impl From<dto::MessageFormatV1> for MessageFormat {
+    fn from(value: dto::MessageFormatV1) -> Self {
+        match value {
+            dto::MessageFormatV1::Msgpack => Self::Msgpack,
+            dto::MessageFormatV1::Json => Self::Json,
+        }
+    }
+}
+
+impl From<MessageFormat> for dto::MessageFormatV1 {
+    fn from(value: MessageFormat) -> Self {
+        match value {
+            MessageFormat::Msgpack => Self::Msgpack,
+            MessageFormat::Json => Self::Json,
+        }
+    }
+}
+
+/// Settings for compressing large outgoing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub algorithm: dto::CompressionV1,
+
+    /// Smaller messages are sent uncompressed.
+    pub min_size: usize,
+}
+
+impl Compression {
+    fn compress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self.algorithm {
+            dto::CompressionV1::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            dto::CompressionV1::Zstd => Ok(zstd::encode_all(data, 0)?),
+        }
+    }
+}
+
+/// The envelope of a compressed message. `d` contains the complete message, serialized and then
+/// compressed with the algorithm in `z`.
+#[derive(Debug, Serialize)]
+struct CompressedMessage {
+    #[serde(rename = "z")]
+    algorithm: dto::CompressionV1,
+
+    #[serde(rename = "d", serialize_with = "serialize_bytes")]
+    data: Vec<u8>,
+}
+
+fn serialize_bytes<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(data)
+}
+
+/// Counts the outgoing messages of all connections, so that operators can see how much
+/// compression saves.
+#[derive(Debug, Default)]
+pub struct MessageMetrics {
+    pub sent: AtomicU64,
+    /// The size of the messages as they were serialized, before compression.
+    pub serialized_bytes: AtomicU64,
+    /// The size of the messages as they were sent.
+    pub sent_bytes: AtomicU64,
+    /// The size of the messages that were sent as JSON, which is never compressed.
+    pub json_bytes: AtomicU64,
+}
+
+impl MessageMetrics {
+    fn record(&self, serialized_len: usize, sent: &tungstenite::Message) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        self.serialized_bytes
+            .fetch_add(serialized_len as u64, Ordering::Relaxed);
+        self.sent_bytes
+            .fetch_add(sent.len() as u64, Ordering::Relaxed);
+        if sent.is_text() {
+            self.json_bytes
+                .fetch_add(sent.len() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A client sent more data than it may, which gets its connection closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodError {
+    MessageTooLarge,
+    TooMuchData,
+}
+
+impl fmt::Display for FloodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MessageTooLarge => write!(f, "The message is too large"),
+            Self::TooMuchData => write!(f, "Too much data was sent in too little time"),
+        }
+    }
+}
+
+impl Error for FloodError {}
+
 pub struct MessageChannel<S> {
     format: MessageFormat,
+    /// Whether the client chose a format, rather than it following the last received message.
+    format_pinned: bool,
+    compression: Option<Compression>,
+    close_received: bool,
+    /// The payload of the last WebSocket ping we sent and when we sent it, until its pong
+    /// arrives.
+    pending_ping: Option<(u32, Instant)>,
+    next_ping: u32,
+    transport_latency: Option<Duration>,
+    protocol_version: u32,
+    metrics: Arc<MessageMetrics>,
+    /// Limits how many bytes the client may send, if set.
+    bandwidth: Option<TokenBucket>,
     ws: S,
 }
 
@@ -346,23 +1863,160 @@ impl<S> MessageChannel<S> {
     pub fn new(ws: S) -> Self {
         Self {
             format: MessageFormat::default(),
+            format_pinned: false,
+            compression: None,
+            close_received: false,
+            pending_ping: None,
+            next_ping: 0,
+            transport_latency: None,
+            protocol_version: MIN_PROTOCOL_VERSION,
+            metrics: Arc::default(),
+            bandwidth: None,
             ws,
         }
     }
+
+    /// Counts every byte the client sends against the given bucket from now on.
+    pub fn set_bandwidth(&mut self, bandwidth: Option<TokenBucket>) {
+        self.bandwidth = bandwidth;
+    }
+
+    /// Counts the messages sent through this channel in the given metrics from now on.
+    pub fn set_metrics(&mut self, metrics: Arc<MessageMetrics>) {
+        self.metrics = metrics;
+    }
+
+    /// Compresses large messages from now on. Only MsgPack messages are compressed, since JSON
+    /// has no way to carry binary data.
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compression = compression;
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Sends all messages in the given format from now on, regardless of the format of the
+    /// messages the client sends.
+    pub fn pin_format(&mut self, format: dto::MessageFormatV1) {
+        self.format = format.into();
+        self.format_pinned = true;
+    }
+
+    pub fn pinned_format(&self) -> Option<dto::MessageFormatV1> {
+        self.format_pinned.then_some(self.format.into())
+    }
+
+    /// Rejects messages from newer protocol versions from now on.
+    pub fn set_protocol_version(&mut self, version: u32) {
+        self.protocol_version = version;
+    }
+
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// Whether the other side closed the channel on purpose, rather than the connection being
+    /// lost.
+    pub fn close_received(&self) -> bool {
+        self.close_received
+    }
+
+    /// The round trip time of the last WebSocket ping, if its pong has arrived. Unlike
+    /// application-level pings, this isn't affected by how quickly the client gets around to
+    /// answering.
+    pub fn transport_latency(&self) -> Option<Duration> {
+        self.transport_latency
+    }
+
+    /// Answers in the format the client last used, unless it pinned one.
+    fn receive_format(&mut self, format: MessageFormat) {
+        if !self.format_pinned {
+            self.format = format;
+        }
+    }
+
+    fn receive_pong(&mut self, payload: &[u8]) {
+        let Some((id, sent_at)) = self.pending_ping else {
+            return;
+        };
+        if payload == id.to_be_bytes() {
+            self.transport_latency = Some(sent_at.elapsed());
+            self.pending_ping = None;
+        }
+    }
+}
+
+/// Just the type of a message, to explain why the rest of it couldn't be read.
+#[derive(Debug, Deserialize)]
+struct MessageType {
+    #[serde(rename = "m")]
+    kind: String,
+}
+
+impl MessageType {
+    fn version(&self) -> Option<u32> {
+        self.kind.rsplit_once("/v")?.1.parse().ok()
+    }
+}
+
+/// Replaces the deserialization error for messages from a newer protocol version than the
+/// connection uses, which couldn't have been understood anyway.
+fn explain_rejection(
+    message_type: Option<MessageType>,
+    protocol_version: u32,
+    err: anyhow::Error,
+) -> anyhow::Error {
+    let Some(message_type) = message_type else {
+        return err;
+    };
+    match message_type.version() {
+        Some(version) if version > protocol_version => ClientError::new(
+            ErrorCode::UnsupportedVersion,
+            format!(
+                "{} belongs to protocol version {version}, but this connection uses version \
+                 {protocol_version}",
+                message_type.kind
+            ),
+        )
+        .into(),
+        _ => err,
+    }
 }
 
-fn serialize_msgpack(message: Message) -> anyhow::Result<tungstenite::Message> {
+fn serialize_msgpack_value(value: &impl Serialize) -> anyhow::Result<Vec<u8>> {
     let mut writer = Cursor::new(Vec::new());
     // we represent structs as maps to get compatibility with the JS frontend that has no
     // sophisticated data schema mechanism during deserialization
     let mut serializer = rmp_serde::Serializer::new(&mut writer).with_struct_map();
 
-    message
+    value
         .serialize(&mut serializer)
         .context("Failed to serialize message as MsgPack")?;
 
-    let tungstenite_message = tungstenite::Message::binary(writer.into_inner());
-    Ok(tungstenite_message)
+    Ok(writer.into_inner())
+}
+
+/// Also returns the size of the message before it was compressed.
+fn serialize_msgpack(
+    message: Message,
+    compression: Option<Compression>,
+) -> anyhow::Result<(tungstenite::Message, usize)> {
+    let mut data = serialize_msgpack_value(&message)?;
+    let serialized_len = data.len();
+    if let Some(compression) = compression {
+        if message.body.is_compressible() && data.len() >= compression.min_size {
+            data = serialize_msgpack_value(&CompressedMessage {
+                algorithm: compression.algorithm,
+                data: compression
+                    .compress(&data)
+                    .context("Failed to compress message")?,
+            })?;
+        }
+    }
+
+    let tungstenite_message = tungstenite::Message::binary(data);
+    Ok((tungstenite_message, serialized_len))
 }
 
 fn serialize_json(message: Message) -> anyhow::Result<tungstenite::Message> {
@@ -378,10 +2032,15 @@ where
 {
     pub async fn send(&mut self, message: Message) -> Result<(), anyhow::Error> {
         log::debug!("Sending message {message:?}");
-        let serialized_msg = match self.format {
-            MessageFormat::Msgpack => serialize_msgpack(message)?,
-            MessageFormat::Json => serialize_json(message)?,
+        let (serialized_msg, serialized_len) = match self.format {
+            MessageFormat::Msgpack => serialize_msgpack(message, self.compression)?,
+            MessageFormat::Json => {
+                let serialized_msg = serialize_json(message)?;
+                let serialized_len = serialized_msg.len();
+                (serialized_msg, serialized_len)
+            }
         };
+        self.metrics.record(serialized_len, &serialized_msg);
 
         self.ws
             .send(serialized_msg)
@@ -389,7 +2048,28 @@ where
             .map_err(anyhow::Error::from)
     }
 
-    pub async fn close(&mut self) -> Result<(), anyhow::Error> {
+    /// Sends a WebSocket ping frame, to measure the transport round trip time once the pong
+    /// arrives.
+    pub async fn send_ping(&mut self) -> Result<(), anyhow::Error> {
+        let id = self.next_ping;
+        self.next_ping = self.next_ping.wrapping_add(1);
+        self.transport_latency = None;
+        self.pending_ping = Some((id, Instant::now()));
+        self.ws
+            .send(tungstenite::Message::Ping(id.to_be_bytes().to_vec()))
+            .await
+            .map_err(anyhow::Error::from)
+    }
+
+    pub async fn close(
+        &mut self,
+        frame: Option<tungstenite::protocol::CloseFrame<'static>>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(frame) = frame {
+            self.ws
+                .send(tungstenite::Message::Close(Some(frame)))
+                .await?;
+        }
         self.ws.close().await?;
         Ok(())
     }
@@ -400,25 +2080,49 @@ where
     S: Stream<Item = tungstenite::Result<tungstenite::Message>> + Unpin,
 {
     pub async fn recv(&mut self) -> Option<Result<Message, anyhow::Error>> {
-        let msg = match self.ws.next().await? {
-            Ok(msg) => msg,
-            Err(err) => return Some(Err(anyhow!(err))),
+        let msg = loop {
+            match self.ws.next().await? {
+                Ok(tungstenite::Message::Pong(payload)) => self.receive_pong(&payload),
+                // tungstenite answers pings on its own
+                Ok(tungstenite::Message::Ping(_)) => (),
+                Ok(msg) => break msg,
+                Err(tungstenite::Error::Capacity(err)) => {
+                    log::debug!("Received a message that exceeds the limits: {err}");
+                    return Some(Err(FloodError::MessageTooLarge.into()));
+                }
+                Err(err) => return Some(Err(anyhow!(err))),
+            }
         };
+        let len = u32::try_from(msg.len()).unwrap_or(u32::MAX);
+        if let Some(bandwidth) = &mut self.bandwidth {
+            if !bandwidth.try_take_many(len) {
+                return Some(Err(FloodError::TooMuchData.into()));
+            }
+        }
         let deserialized_msg: anyhow::Result<Message> = match msg {
             tungstenite::Message::Binary(data) => {
-                self.format = MessageFormat::Msgpack;
+                self.receive_format(MessageFormat::Msgpack);
                 rmp_serde::from_slice(&data).map_err(|err| {
-                    anyhow!(err).context("Failed to deserialize binary message as MsgPack")
+                    explain_rejection(
+                        rmp_serde::from_slice(&data).ok(),
+                        self.protocol_version,
+                        anyhow!(err).context("Failed to deserialize binary message as MsgPack"),
+                    )
                 })
             }
             tungstenite::Message::Text(data) => {
-                self.format = MessageFormat::Json;
+                self.receive_format(MessageFormat::Json);
                 serde_json::from_str(&data).map_err(|err| {
-                    anyhow!(err).context("Failed to deserialize text message as JSON")
+                    explain_rejection(
+                        serde_json::from_str(&data).ok(),
+                        self.protocol_version,
+                        anyhow!(err).context("Failed to deserialize text message as JSON"),
+                    )
                 })
             }
             tungstenite::Message::Close(frame) => {
                 log::debug!("Received close frame: {frame:?}");
+                self.close_received = true;
                 return None;
             }
             _ => return Some(Err(anyhow!("Only binary and text messages are accepted."))),
@@ -428,8 +2132,14 @@ where
     }
 }
 
+#[cfg(test)]
+mod conformance;
+
 #[cfg(test)]
 mod tests {
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
     use futures::stream;
     use serde_json::json;
 
@@ -464,6 +2174,42 @@ mod tests {
         assert_eq!(obj_received, obj_expected);
     }
 
+    #[test]
+    fn should_only_number_broadcasts() {
+        // given
+        let mut broadcast = Message::new_with_timestamp(MessageBody::RoomApprovedV1, 69420);
+        broadcast.seq = Some(7);
+        let ping = Message::new_with_timestamp(MessageBody::ConnectionPingV1, 69420);
+
+        // when
+        let broadcast = serde_json::to_value(broadcast).unwrap();
+        let ping = serde_json::to_value(ping).unwrap();
+
+        // then
+        assert_eq!(
+            broadcast,
+            json!({ "t": 69420, "s": 7, "m": "room::approved/v1" })
+        );
+        assert_eq!(ping, json!({ "t": 69420, "m": "connection::ping/v1" }));
+    }
+
+    #[tokio::test]
+    async fn should_send_close_frame() {
+        // given
+        let mut messages = Vec::new();
+        let mut channel = MessageChannel::new(&mut messages);
+        let frame = tungstenite::protocol::CloseFrame {
+            code: tungstenite::protocol::frame::coding::CloseCode::Library(4001),
+            reason: "Unauthorized".into(),
+        };
+
+        // when
+        channel.close(Some(frame.clone())).await.unwrap();
+
+        // then
+        assert_eq!(messages, vec![tungstenite::Message::Close(Some(frame))]);
+    }
+
     #[tokio::test]
     async fn should_receive_message() {
         // given
@@ -487,6 +2233,98 @@ mod tests {
         assert!(channel.recv().await.is_none());
     }
 
+    #[tokio::test]
+    async fn should_receive_keepalives_without_telemetry() {
+        // given
+        let messages = vec![tungstenite::Result::Ok(tungstenite::Message::binary(
+            rmp_serde::to_vec(&json!({
+                "t": 42069,
+                "m": "connection::keepalive/v1"
+            }))
+            .unwrap(),
+        ))];
+        let mut channel = MessageChannel::new(stream::iter(messages));
+
+        // when
+        let msg = channel.recv().await.unwrap().unwrap();
+
+        // then
+        assert_eq!(
+            msg.body,
+            MessageBody::ConnectionKeepaliveV1(dto::ConnectionKeepaliveMsgBodyV1 {
+                telemetry: None
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn should_measure_transport_latency_from_pong_frames() {
+        // given
+        let messages = vec![
+            tungstenite::Result::Ok(tungstenite::Message::Pong(7u32.to_be_bytes().to_vec())),
+            tungstenite::Result::Ok(tungstenite::Message::binary(
+                rmp_serde::to_vec(&json!({
+                    "t": 42069,
+                    "m": "connection::pong/v1"
+                }))
+                .unwrap(),
+            )),
+        ];
+        let mut channel = MessageChannel::new(stream::iter(messages));
+        channel.pending_ping = Some((7, Instant::now()));
+
+        // when
+        let msg = channel.recv().await.unwrap().unwrap();
+
+        // then
+        assert_eq!(msg.body, MessageBody::ConnectionPongV1);
+        assert!(channel.transport_latency().is_some());
+        assert!(channel.pending_ping.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_reject_messages_from_newer_protocol_versions() {
+        // given
+        let messages = vec![tungstenite::Result::Ok(tungstenite::Message::text(
+            json!({
+                "t": 42069,
+                "m": "connection::ping/v2"
+            })
+            .to_string(),
+        ))];
+        let mut channel = MessageChannel::new(stream::iter(messages));
+
+        // when
+        let err = channel.recv().await.unwrap().unwrap_err();
+
+        // then
+        assert_eq!(
+            crate::errors::error_code(&err),
+            ErrorCode::UnsupportedVersion
+        );
+    }
+
+    #[tokio::test]
+    async fn should_keep_pinned_format() {
+        // given
+        let messages = vec![tungstenite::Result::Ok(tungstenite::Message::binary(
+            rmp_serde::to_vec(&json!({
+                "t": 42069,
+                "m": "connection::pong/v1"
+            }))
+            .unwrap(),
+        ))];
+        let mut channel = MessageChannel::new(stream::iter(messages));
+        channel.pin_format(dto::MessageFormatV1::Json);
+
+        // when
+        channel.recv().await.unwrap().unwrap();
+
+        // then
+        assert_eq!(channel.format, MessageFormat::Json);
+        assert_eq!(channel.pinned_format(), Some(dto::MessageFormatV1::Json));
+    }
+
     #[tokio::test]
     async fn should_handle_malformed_messages() {
         // given
@@ -506,4 +2344,81 @@ mod tests {
         assert!(result.is_err());
         assert!(channel.recv().await.is_none());
     }
+
+    #[derive(Deserialize)]
+    struct CompressedEnvelope<'a> {
+        z: dto::CompressionV1,
+        d: &'a [u8],
+    }
+
+    #[tokio::test]
+    async fn should_compress_large_room_states() {
+        // given
+        let mut messages = Vec::new();
+        let mut channel = MessageChannel::new(&mut messages);
+        channel.set_compression(Some(Compression {
+            algorithm: dto::CompressionV1::Zlib,
+            min_size: 1024,
+        }));
+        let state = MessageBody::RoomStateV1(Arc::new(dto::RoomStateMsgBodyV1 {
+            id: uuid::Uuid::nil().into(),
+            name: "Movie night".to_string(),
+            password: "hunter2".to_string(),
+            open: false,
+            users: (0..100)
+                .map(|i| dto::RoomUserV1 {
+                    id: uuid::Uuid::from_u128(i).into(),
+                    name: format!("user{i}"),
+                    role: dto::RoomUserRoleV1::Guest,
+                })
+                .collect(),
+            playback_info: None,
+            recent_links: Vec::new(),
+            intermission: None,
+            spectator_count: 0,
+            topic: None,
+            tags: Vec::new(),
+            image_url: None,
+            utc_offset: None,
+            vanity_id: None,
+        }));
+
+        // when
+        channel
+            .send(Message::new_with_timestamp(state.clone(), 69420))
+            .await
+            .unwrap();
+        channel
+            .send(Message::new_with_timestamp(
+                MessageBody::ConnectionPingV1,
+                69420,
+            ))
+            .await
+            .unwrap();
+
+        // then
+        let metrics = Arc::clone(&channel.metrics);
+        let tungstenite::Message::Binary(compressed) = &messages[0] else {
+            panic!("Data received should be binary");
+        };
+        let envelope: CompressedEnvelope = rmp_serde::from_slice(compressed).unwrap();
+        assert_eq!(envelope.z, dto::CompressionV1::Zlib);
+        let mut decompressed = Vec::new();
+        ZlibDecoder::new(envelope.d)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        let message: Message = rmp_serde::from_slice(&decompressed).unwrap();
+        assert_eq!(message, Message::new_with_timestamp(state, 69420));
+        assert_eq!(metrics.sent.load(Ordering::Relaxed), 2);
+        assert!(
+            metrics.sent_bytes.load(Ordering::Relaxed)
+                < metrics.serialized_bytes.load(Ordering::Relaxed)
+        );
+
+        let tungstenite::Message::Binary(uncompressed) = &messages[1] else {
+            panic!("Data received should be binary");
+        };
+        let ping: serde_json::Value = rmp_serde::from_slice(uncompressed).unwrap();
+        assert_eq!(ping["m"], "connection::ping/v1");
+    }
 }
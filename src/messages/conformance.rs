@@ -0,0 +1,767 @@
+//! Wire format conformance tests.
+//!
+//! Every message variant has a golden file in `fixtures/messages` that pins both its JSON
+//! representation and its exact MsgPack bytes. If a change to the message definitions makes
+//! these tests fail, it breaks deployed clients. Only regenerate the golden files (by running
+//! the tests with `PALANTIR_BLESS_FIXTURES=1`) if the wire format change is intentional.
+
+use std::{fs, path::PathBuf};
+
+use serde_json::json;
+use uuid::Uuid;
+
+use super::*;
+
+const TIMESTAMP: u64 = 1_700_000_000_000;
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/messages")
+}
+
+fn room_id() -> dto::RoomIdV1 {
+    Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef).into()
+}
+
+fn user_id() -> dto::UserIdV1 {
+    Uuid::from_u128(0xfedc_ba98_7654_3210_fedc_ba98_7654_3210).into()
+}
+
+fn playback_source() -> dto::PlaybackSourceV1 {
+    dto::PlaybackSourceV1 {
+        title: "Big Buck Bunny".to_string(),
+        page_href: "https://example.com/watch".to_string(),
+        frame_href: "https://player.example.com/embed".to_string(),
+        element_query: "video".to_string(),
+    }
+}
+
+fn queue_entry_id() -> dto::QueueEntryIdV1 {
+    Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef).into()
+}
+
+fn playback_info() -> dto::RoomPlaybackInfoV1 {
+    dto::RoomPlaybackInfoV1 {
+        host: "alice".to_string(),
+        source: Some(playback_source()),
+        stopwatch: false,
+    }
+}
+
+fn shared_link() -> dto::RoomSharedLinkV1 {
+    dto::RoomSharedLinkV1 {
+        user_id: user_id(),
+        name: "alice".to_string(),
+        url: "https://example.com/trailer".to_string(),
+        shared_at: 1_700_000_000_000,
+    }
+}
+
+fn playback_state() -> dto::PlaybackStateV1 {
+    dto::PlaybackStateV1 {
+        timestamp: TIMESTAMP,
+        playing: true,
+        time: 42.5,
+        duration: Some(5400.0),
+    }
+}
+
+/// Fails to compile when a message variant is added, as a reminder to add a golden case for it
+/// to [`cases`].
+fn assert_covered(body: &MessageBody) {
+    match body {
+        MessageBody::ConnectionLoginV1(..)
+        | MessageBody::ConnectionLoginAckV1(..)
+        | MessageBody::ConnectionReauthV1(..)
+        | MessageBody::ConnectionReauthAckV1(..)
+        | MessageBody::ConnectionResumeV1(..)
+        | MessageBody::ConnectionResumeAckV1(..)
+        | MessageBody::ConnectionPingV1
+        | MessageBody::ConnectionPongV1
+        | MessageBody::ConnectionClientErrorV1(..)
+        | MessageBody::BatchV1(..)
+        | MessageBody::BatchAckV1(..)
+        | MessageBody::ConnectionClosedV1(..)
+        | MessageBody::ConnectionKeepaliveV1(..)
+        | MessageBody::ConnectionRequestProbeV1
+        | MessageBody::ConnectionProbeV1(..)
+        | MessageBody::ConnectionRequestStatsV1(..)
+        | MessageBody::ConnectionStatsV1(..)
+        | MessageBody::ConnectionMyStatsV1
+        | MessageBody::ConnectionMyStatsAckV1(..)
+        | MessageBody::ConnectionRenameV1(..)
+        | MessageBody::ConnectionRenameAckV1(..)
+        | MessageBody::RoomCreateV1(..)
+        | MessageBody::RoomCreateFromTemplateV1(..)
+        | MessageBody::RoomCreateAckV1
+        | MessageBody::RoomCloseV1
+        | MessageBody::RoomCloseAckV1
+        | MessageBody::RoomJoinV1(..)
+        | MessageBody::RoomJoinAckV1
+        | MessageBody::RoomCreateInviteV1(..)
+        | MessageBody::RoomInviteV1(..)
+        | MessageBody::RoomObserveV1(..)
+        | MessageBody::RoomObserveAckV1
+        | MessageBody::RoomLeaveV1
+        | MessageBody::RoomLeaveAckV1
+        | MessageBody::RoomKickedV1(..)
+        | MessageBody::RoomDisconnectedV1(..)
+        | MessageBody::RoomPendingApprovalV1
+        | MessageBody::RoomApprovedV1
+        | MessageBody::RoomRequestStateV1
+        | MessageBody::RoomStateV1(..)
+        | MessageBody::RoomRequestPermissionsV1
+        | MessageBody::RoomSetUserRole(..)
+        | MessageBody::RoomKickUser(..)
+        | MessageBody::RoomTransferHostV1(..)
+        | MessageBody::RoomIntermissionV1(..)
+        | MessageBody::RoomEndIntermissionV1
+        | MessageBody::RoomListV1
+        | MessageBody::RoomListAckV1(..)
+        | MessageBody::RoomPeekV1(..)
+        | MessageBody::RoomPeekAckV1(..)
+        | MessageBody::RoomBroadcastAckV1(..)
+        | MessageBody::RoomHostChangedV1(..)
+        | MessageBody::RoomIdentityWarningV1(..)
+        | MessageBody::RoomUserJoinedV1(..)
+        | MessageBody::RoomUserLeftV1(..)
+        | MessageBody::RoomChatV1(..)
+        | MessageBody::RoomChatMessageV1(..)
+        | MessageBody::RoomShareLinkV1(..)
+        | MessageBody::RoomLinkSharedV1(..)
+        | MessageBody::RoomRequestPlaybackHistoryV1
+        | MessageBody::RoomPlaybackHistoryV1(..)
+        | MessageBody::RoomModerationLogV1(..)
+        | MessageBody::RoomModerationLogAckV1(..)
+        | MessageBody::RoomSetQuietHoursV1(..)
+        | MessageBody::RoomUpdateV1(..)
+        | MessageBody::RoomSetPasswordV1(..)
+        | MessageBody::RoomPeerProbeV1(..)
+        | MessageBody::RoomPeerProbeReplyV1(..)
+        | MessageBody::RoomPermissionsV1(..)
+        | MessageBody::PlaybackAvailableV1(..)
+        | MessageBody::PlaybackRequestInfoV1
+        | MessageBody::PlaybackInfoV1(..)
+        | MessageBody::PlaybackRequestHostV1
+        | MessageBody::PlaybackHosting
+        | MessageBody::PlaybackRequestStartV1(..)
+        | MessageBody::PlaybackRequestStartV2(..)
+        | MessageBody::PlaybackStartedV1
+        | MessageBody::PlaybackStartAtV1(..)
+        | MessageBody::PlaybackLastPositionV1(..)
+        | MessageBody::PlaybackRequestConnectV1
+        | MessageBody::PlaybackConnectedV1
+        | MessageBody::PlaybackSourceChangedV1(..)
+        | MessageBody::PlaybackSourceChangedV2(..)
+        | MessageBody::PlaybackSyncV1(..)
+        | MessageBody::PlaybackSeekHintsV1(..)
+        | MessageBody::PlaybackRequestStopV1
+        | MessageBody::PlaybackStoppedV1(..)
+        | MessageBody::PlaybackFinishedV1
+        | MessageBody::PlaybackEndedV1
+        | MessageBody::PlaybackQueueAddV1(..)
+        | MessageBody::PlaybackQueueRemoveV1(..)
+        | MessageBody::PlaybackQueueMoveV1(..)
+        | MessageBody::PlaybackQueueStateV1(..)
+        | MessageBody::PlaybackRollCallV1(..)
+        | MessageBody::PlaybackRollCallReplyV1(..)
+        | MessageBody::PlaybackRollCallReportV1(..)
+        | MessageBody::PlaybackRequestDisconnectV1
+        | MessageBody::PlaybackDisconnectedV1(..)
+        | MessageBody::PlaybackQualityHintV1(..)
+        | MessageBody::PlaybackDelegateTimingV1(..)
+        | MessageBody::PlaybackLeaderV1(..)
+        | MessageBody::PlaybackRequestStatsV1
+        | MessageBody::PlaybackStatsV1(..)
+        | MessageBody::PlaybackReadyV1
+        | MessageBody::PlaybackWaitingForV1(..)
+        | MessageBody::PlaybackRequestDeltaSyncV1(..)
+        | MessageBody::PlaybackSyncDeltaV1(..)
+        | MessageBody::PlaybackSyncAckV1(..)
+        | MessageBody::AdminListRoomsV1
+        | MessageBody::AdminListRoomsAckV1(..)
+        | MessageBody::AdminCloseRoomV1(..)
+        | MessageBody::AdminCloseRoomAckV1
+        | MessageBody::AdminKickSessionV1(..)
+        | MessageBody::AdminKickSessionAckV1
+        | MessageBody::AdminStatsV1
+        | MessageBody::AdminStatsAckV1(..)
+        | MessageBody::AdminSetLogFilterV1(..)
+        | MessageBody::AdminSetLogFilterAckV1 => (),
+    }
+}
+
+fn cases() -> Vec<MessageBody> {
+    vec![
+        MessageBody::ConnectionLoginV1(dto::ConnectionLoginMsgBodyV1 {
+            username: "alice".to_string(),
+            api_key: Some("AAAAA".to_string()),
+            token: None,
+            compression: vec![dto::CompressionV1::Zstd, dto::CompressionV1::Zlib],
+            protocol_versions: vec![1],
+            format: Some(dto::MessageFormatV1::Json),
+            fingerprint: Some("6f1c2a9e4b7d4e0f".to_string()),
+        }),
+        MessageBody::ConnectionLoginAckV1(dto::ConnectionLoginAckMsgBodyV1 {
+            resume_token: Some("0b6e7c39c0bb4b5c9b0e6f3a8d2e4f71".to_string()),
+            protocol_version: 1,
+            name: "brisk-otter-42".to_string(),
+        }),
+        MessageBody::ConnectionReauthV1(dto::ConnectionReauthMsgBodyV1 {
+            api_key: Some("BBBBB".to_string()),
+            token: None,
+        }),
+        MessageBody::ConnectionReauthAckV1(dto::ConnectionReauthAckMsgBodyV1 {
+            permissions: dto::ConnectionPermissionsV1 {
+                host: true,
+                observe: false,
+                rooms: vec!["movie-*".to_string()],
+                admin: false,
+            },
+        }),
+        MessageBody::ConnectionResumeV1(dto::ConnectionResumeMsgBodyV1 {
+            token: "0b6e7c39c0bb4b5c9b0e6f3a8d2e4f71".to_string(),
+        }),
+        MessageBody::ConnectionResumeAckV1(dto::ConnectionResumeAckMsgBodyV1 {
+            resume_token: Some("5d1f0a9e2c7b4e8f9a3d6c0b1e2f4a57".to_string()),
+        }),
+        MessageBody::ConnectionPingV1,
+        MessageBody::ConnectionPongV1,
+        MessageBody::ConnectionClientErrorV1(dto::ConnectionClientErrorMsgBodyV1 {
+            code: dto::ClientErrorCodeV1::NotInRoom,
+            message: "Not currently in a room".to_string(),
+        }),
+        MessageBody::BatchV1(dto::BatchMsgBodyV1 {
+            messages: vec![
+                Message::new_with_timestamp(MessageBody::RoomLeaveV1, TIMESTAMP),
+                Message::new_with_timestamp(MessageBody::RoomListV1, TIMESTAMP),
+            ],
+        }),
+        MessageBody::BatchAckV1(dto::BatchAckMsgBodyV1 {
+            results: vec![
+                dto::BatchResultV1 {
+                    error: Some(dto::ConnectionClientErrorMsgBodyV1 {
+                        code: dto::ClientErrorCodeV1::NotInRoom,
+                        message: "Not currently in a room".to_string(),
+                    }),
+                },
+                dto::BatchResultV1 { error: None },
+            ],
+        }),
+        MessageBody::ConnectionClosedV1(dto::ConnectionClosedMsgBodyV1 {
+            reason: dto::ConnectionClosedReasonV1::Timeout,
+            message: "Connection timed out".to_string(),
+            redirect_to: None,
+        }),
+        MessageBody::ConnectionKeepaliveV1(dto::ConnectionKeepaliveMsgBodyV1 {
+            telemetry: Some(dto::PlaybackTelemetryV1 {
+                state: playback_state(),
+                buffered: Some(12.5),
+                dropped_frames: Some(3),
+            }),
+        }),
+        MessageBody::ConnectionRequestProbeV1,
+        MessageBody::ConnectionProbeV1(dto::ConnectionProbeMsgBodyV1 {
+            latency: 120,
+            transport_latency: Some(80),
+            time_offset: -35,
+            sync_quality: dto::SyncQualityV1::Fair,
+        }),
+        MessageBody::ConnectionRequestStatsV1(dto::ConnectionRequestStatsMsgBodyV1 {
+            periodic: true,
+        }),
+        MessageBody::ConnectionStatsV1(dto::ConnectionStatsMsgBodyV1 {
+            latency: 120,
+            jitter: 15,
+            time_offset: -35,
+            sync_quality: dto::SyncQualityV1::Fair,
+        }),
+        MessageBody::ConnectionMyStatsV1,
+        MessageBody::ConnectionMyStatsAckV1(dto::ConnectionMyStatsAckMsgBodyV1 {
+            user_id: user_id(),
+            connected_for: 90_000,
+            latency: Some(120),
+            transport_latency: Some(80),
+            time_offset: -35,
+            sync_quality: Some(dto::SyncQualityV1::Fair),
+            errors: 2,
+            room: Some(room_id()),
+            role: Some(dto::RoomUserRoleV1::Guest),
+            playback: dto::PlaybackSubscriptionV1::Subscriber,
+        }),
+        MessageBody::ConnectionRenameV1(dto::ConnectionRenameMsgBodyV1 {
+            name: "alice".to_string(),
+        }),
+        MessageBody::ConnectionRenameAckV1(dto::ConnectionRenameAckMsgBodyV1 {
+            name: "alice".to_string(),
+        }),
+        MessageBody::RoomCreateV1(dto::RoomCreateMsgBodyV1 {
+            name: "Movie night".to_string(),
+            password: Some("hunter2".to_string()),
+            public: true,
+            host_succession: vec![dto::RoomUserRoleV1::Guest],
+            default_role: Some(dto::RoomUserRoleV1::Spectator),
+            topic: Some("Classic horror, one film a week".to_string()),
+            tags: vec!["horror".to_string(), "classics".to_string()],
+            image_url: Some("https://example.com/cover.png".to_string()),
+            utc_offset: Some(60),
+            vanity_id: Some("movie-night".to_string()),
+            chat: Some(dto::RoomChatPolicyV1 {
+                max_length: Some(200),
+                format: dto::ChatFormatV1::PlainText,
+            }),
+            ready_quorum: Some(dto::PlaybackReadyQuorumV1::Percent(75)),
+        }),
+        MessageBody::RoomCreateFromTemplateV1(dto::RoomCreateFromTemplateMsgBodyV1 {
+            template: r#"{"settings":{"name":"Movie night","password":""}}"#.to_string(),
+        }),
+        MessageBody::RoomCreateAckV1,
+        MessageBody::RoomCloseV1,
+        MessageBody::RoomCloseAckV1,
+        MessageBody::RoomJoinV1(dto::RoomJoinMsgBodyV1 {
+            id: dto::RoomRefV1::Id(room_id()),
+            password: Some("hunter2".to_string()),
+            invite: None,
+            anonymous: true,
+        }),
+        MessageBody::RoomCreateInviteV1(dto::RoomCreateInviteMsgBodyV1 {
+            role: dto::RoomUserRoleV1::Spectator,
+        }),
+        MessageBody::RoomInviteV1(dto::RoomInviteMsgBodyV1 {
+            room_id: room_id(),
+            token: "3f9a1c7e5b2d4e6f8a0b1c2d3e4f5a6b".to_string(),
+            role: dto::RoomUserRoleV1::Spectator,
+        }),
+        MessageBody::RoomJoinAckV1,
+        MessageBody::RoomObserveV1(dto::RoomObserveMsgBodyV1 {
+            id: dto::RoomRefV1::Id(room_id()),
+        }),
+        MessageBody::RoomObserveAckV1,
+        MessageBody::RoomLeaveV1,
+        MessageBody::RoomLeaveAckV1,
+        MessageBody::RoomKickedV1(dto::RoomKickedMsgBodyV1 {
+            kicked_by: "alice".to_string(),
+        }),
+        MessageBody::RoomDisconnectedV1(dto::RoomDisconnectedMsgBodyV1 {
+            reason: dto::RoomDisconnectedReasonV1::ClosedByHost,
+        }),
+        MessageBody::RoomPendingApprovalV1,
+        MessageBody::RoomApprovedV1,
+        MessageBody::RoomRequestStateV1,
+        MessageBody::RoomStateV1(Arc::new(dto::RoomStateMsgBodyV1 {
+            id: room_id(),
+            name: "Movie night".to_string(),
+            password: "hunter2".to_string(),
+            open: false,
+            users: vec![dto::RoomUserV1 {
+                id: user_id(),
+                name: "alice".to_string(),
+                role: dto::RoomUserRoleV1::Host,
+            }],
+            playback_info: Some(playback_info()),
+            recent_links: vec![shared_link()],
+            intermission: Some(dto::RoomIntermissionV1 {
+                notice: "Back in ten minutes".to_string(),
+                ends_at: Some(TIMESTAMP + 600_000),
+                ends_at_iso: Some("2023-11-14T23:23:20+01:00".to_string()),
+            }),
+            spectator_count: 3,
+            topic: Some("Classic horror, one film a week".to_string()),
+            tags: vec!["horror".to_string(), "classics".to_string()],
+            image_url: Some("https://example.com/cover.png".to_string()),
+            utc_offset: Some(60),
+            vanity_id: Some("movie-night".to_string()),
+        })),
+        MessageBody::RoomRequestPermissionsV1,
+        MessageBody::RoomSetUserRole(dto::RoomSetUserRoleMsgBodyV1 {
+            user_id: user_id(),
+            role: dto::RoomUserRoleV1::Spectator,
+        }),
+        MessageBody::RoomKickUser(dto::RoomKickUserMsgBodyV1 { user_id: user_id() }),
+        MessageBody::RoomTransferHostV1(dto::RoomTransferHostMsgBodyV1 { user_id: user_id() }),
+        MessageBody::RoomIntermissionV1(dto::RoomIntermissionMsgBodyV1 {
+            notice: "Back in ten minutes".to_string(),
+            countdown: Some(600),
+        }),
+        MessageBody::RoomEndIntermissionV1,
+        MessageBody::RoomListV1,
+        MessageBody::RoomListAckV1(dto::RoomListAckMsgBodyV1 {
+            rooms: vec![dto::RoomListingV1 {
+                id: room_id(),
+                name: "Movie night".to_string(),
+                users: 3,
+                playback_active: true,
+                topic: Some("Classic horror, one film a week".to_string()),
+                tags: vec!["horror".to_string(), "classics".to_string()],
+                image_url: None,
+                vanity_id: Some("movie-night".to_string()),
+            }],
+            sync_quality: Some(dto::SyncQualityV1::Fair),
+        }),
+        MessageBody::RoomPeekV1(dto::RoomPeekMsgBodyV1 {
+            id: dto::RoomRefV1::Vanity("movie-night".to_string()),
+        }),
+        MessageBody::RoomPeekAckV1(dto::RoomPeekAckMsgBodyV1 {
+            room: dto::RoomListingV1 {
+                id: room_id(),
+                name: "Movie night".to_string(),
+                users: 3,
+                playback_active: true,
+                topic: None,
+                tags: Vec::new(),
+                image_url: None,
+                vanity_id: Some("movie-night".to_string()),
+            },
+            sync_quality: None,
+        }),
+        MessageBody::RoomBroadcastAckV1(dto::RoomBroadcastAckMsgBodyV1 {
+            event: dto::RoomBroadcastEventV1::State,
+        }),
+        MessageBody::RoomHostChangedV1(dto::RoomHostChangedMsgBodyV1 {
+            user_id: user_id(),
+            name: "alice".to_string(),
+        }),
+        MessageBody::RoomIdentityWarningV1(dto::RoomIdentityWarningMsgBodyV1 {
+            user_id: user_id(),
+            name: "alice".to_string(),
+        }),
+        MessageBody::RoomUserJoinedV1(dto::RoomUserJoinedMsgBodyV1 {
+            user_id: user_id(),
+            name: "alice".to_string(),
+            role: dto::RoomUserRoleV1::Guest,
+        }),
+        MessageBody::RoomUserLeftV1(dto::RoomUserLeftMsgBodyV1 {
+            user_id: user_id(),
+            name: "alice".to_string(),
+            reason: dto::RoomUserLeftReasonV1::ConnectionLost,
+        }),
+        MessageBody::RoomChatV1(dto::RoomChatMsgBodyV1 {
+            text: "Popcorn is ready".to_string(),
+        }),
+        MessageBody::RoomChatMessageV1(dto::RoomChatMessageMsgBodyV1 {
+            user_id: user_id(),
+            name: "alice".to_string(),
+            text: "Popcorn is ready".to_string(),
+            sent_at: 1_700_000_000_000,
+        }),
+        MessageBody::RoomShareLinkV1(dto::RoomShareLinkMsgBodyV1 {
+            url: "https://example.com/trailer".to_string(),
+        }),
+        MessageBody::RoomLinkSharedV1(shared_link()),
+        MessageBody::RoomRequestPlaybackHistoryV1,
+        MessageBody::RoomPlaybackHistoryV1(dto::RoomPlaybackHistoryMsgBodyV1 {
+            entries: vec![dto::RoomPlayedSourceV1 {
+                host_id: user_id(),
+                host_name: "alice".to_string(),
+                title: "Big Buck Bunny".to_string(),
+                page_href: "https://example.com/watch/bbb".to_string(),
+                started_at: 1_699_920_000_000,
+                ended_at: None,
+            }],
+        }),
+        MessageBody::RoomModerationLogV1(dto::RoomModerationLogMsgBodyV1 {
+            before: Some(20),
+            limit: Some(10),
+        }),
+        MessageBody::RoomModerationLogAckV1(dto::RoomModerationLogAckMsgBodyV1 {
+            entries: vec![dto::RoomModerationEntryV1 {
+                id: 19,
+                action: dto::RoomModerationActionV1::RoleChanged,
+                role: Some(dto::RoomUserRoleV1::Guest),
+                user_id: Uuid::from_u128(2).into(),
+                user_name: "bob".to_string(),
+                by_id: user_id(),
+                by_name: "alice".to_string(),
+                at: 1_699_920_000_000,
+            }],
+            more: true,
+        }),
+        MessageBody::RoomSetQuietHoursV1(dto::RoomSetQuietHoursMsgBodyV1 {
+            windows: vec![dto::RoomQuietWindowV1 {
+                start: 22 * 60,
+                end: 7 * 60,
+            }],
+        }),
+        MessageBody::RoomUpdateV1(dto::RoomUpdateMsgBodyV1 {
+            topic: Some("Classic horror, one film a week".to_string()),
+            tags: vec!["horror".to_string(), "classics".to_string()],
+            image_url: None,
+            utc_offset: Some(60),
+        }),
+        MessageBody::RoomSetPasswordV1(dto::RoomSetPasswordMsgBodyV1 {
+            password: Some("hunter3".to_string()),
+            open: false,
+        }),
+        MessageBody::RoomPeerProbeV1(dto::RoomPeerProbeMsgBodyV1 {
+            peer: user_id(),
+            probe_id: 7,
+            sent_at: TIMESTAMP,
+        }),
+        MessageBody::RoomPeerProbeReplyV1(dto::RoomPeerProbeReplyMsgBodyV1 {
+            peer: user_id(),
+            probe_id: 7,
+            sent_at: TIMESTAMP,
+            received_at: TIMESTAMP + 40,
+            replied_at: TIMESTAMP + 41,
+        }),
+        MessageBody::RoomPermissionsV1(dto::RoomPermissionsMsgBodyV1 {
+            role: dto::RoomUserRoleV1::Guest,
+            permissions: dto::RoomUserPermissionsV1 {
+                can_host: true,
+                can_watch: true,
+                can_close: false,
+                can_set_roles: false,
+                can_kick: false,
+            },
+        }),
+        MessageBody::PlaybackAvailableV1(dto::PlaybackAvailableMsgBodyV1 {
+            info: playback_info(),
+        }),
+        MessageBody::PlaybackRequestInfoV1,
+        MessageBody::PlaybackInfoV1(dto::PlaybackInfoMsgBodyV1 {
+            info: Some(playback_info()),
+            status: dto::PlaybackStatusV1::Playing,
+            position: Some(1337.5),
+            duration: Some(5400.0),
+        }),
+        MessageBody::PlaybackRequestHostV1,
+        MessageBody::PlaybackHosting,
+        MessageBody::PlaybackRequestStartV1(dto::PlaybackStartMsgBodyV1 {
+            source: playback_source(),
+        }),
+        MessageBody::PlaybackRequestStartV2(dto::PlaybackStartMsgBodyV2 { source: None }),
+        MessageBody::PlaybackStartedV1,
+        MessageBody::PlaybackStartAtV1(dto::PlaybackStartAtMsgBodyV1 {
+            timestamp: 1700000000300,
+            delay: 280,
+        }),
+        MessageBody::PlaybackLastPositionV1(dto::PlaybackLastPositionMsgBodyV1 {
+            position: 2467.5,
+        }),
+        MessageBody::PlaybackRequestConnectV1,
+        MessageBody::PlaybackConnectedV1,
+        MessageBody::PlaybackSourceChangedV1(dto::PlaybackSourceChangedMsgBodyV1 {
+            source: playback_source(),
+        }),
+        MessageBody::PlaybackSourceChangedV2(dto::PlaybackSourceChangedMsgBodyV2 { source: None }),
+        MessageBody::PlaybackSyncV1(dto::PlaybackSyncMsgBodyV1 {
+            state: playback_state(),
+        }),
+        MessageBody::PlaybackSeekHintsV1(dto::PlaybackSeekHintsMsgBodyV1 {
+            points: vec![0.0, 12.5, 300.0],
+        }),
+        MessageBody::PlaybackRequestStopV1,
+        MessageBody::PlaybackStoppedV1(dto::PlaybackStoppedMsgBodyV1 {
+            reason: dto::PlaybackStopReasonV1::StoppedByHost,
+        }),
+        MessageBody::PlaybackFinishedV1,
+        MessageBody::PlaybackEndedV1,
+        MessageBody::PlaybackQueueAddV1(dto::PlaybackQueueAddMsgBodyV1 {
+            source: playback_source(),
+            index: Some(0),
+        }),
+        MessageBody::PlaybackQueueRemoveV1(dto::PlaybackQueueRemoveMsgBodyV1 {
+            id: queue_entry_id(),
+        }),
+        MessageBody::PlaybackQueueMoveV1(dto::PlaybackQueueMoveMsgBodyV1 {
+            id: queue_entry_id(),
+            index: 2,
+        }),
+        MessageBody::PlaybackQueueStateV1(dto::PlaybackQueueStateMsgBodyV1 {
+            entries: vec![dto::PlaybackQueueEntryV1 {
+                id: queue_entry_id(),
+                source: playback_source(),
+            }],
+        }),
+        MessageBody::PlaybackRollCallV1(dto::PlaybackRollCallMsgBodyV1 { roll_call_id: 7 }),
+        MessageBody::PlaybackRollCallReplyV1(dto::PlaybackRollCallReplyMsgBodyV1 {
+            roll_call_id: 7,
+            state: playback_state(),
+        }),
+        MessageBody::PlaybackRollCallReportV1(dto::PlaybackRollCallReportMsgBodyV1 {
+            roll_call_id: 7,
+            entries: vec![dto::PlaybackRollCallEntryV1 {
+                user_id: user_id(),
+                name: "alice".to_string(),
+                state: Some(playback_state()),
+                drift: Some(-0.25),
+                buffered: Some(12.5),
+                dropped_frames: Some(3),
+            }],
+        }),
+        MessageBody::PlaybackRequestDisconnectV1,
+        MessageBody::PlaybackDisconnectedV1(dto::PlaybackDisconnectedMsgBodyV1 {
+            reason: dto::PlaybackDisconnectReasonV1::Stopped(dto::PlaybackStopReasonV1::Superseded),
+        }),
+        MessageBody::PlaybackQualityHintV1(dto::PlaybackQualityHintMsgBodyV1 {
+            hint: dto::PlaybackQualityHintV1::LowBuffer,
+        }),
+        MessageBody::PlaybackDelegateTimingV1(dto::PlaybackDelegateTimingMsgBodyV1 {
+            delegate: true,
+        }),
+        MessageBody::PlaybackLeaderV1(dto::PlaybackLeaderMsgBodyV1 {
+            user_id: Some(user_id()),
+        }),
+        MessageBody::PlaybackRequestStatsV1,
+        MessageBody::PlaybackStatsV1(dto::PlaybackStatsMsgBodyV1 {
+            subscribers: vec![dto::PlaybackSubscriberStatsV1 {
+                user_id: user_id(),
+                name: "bob".to_string(),
+                drift: Some(-0.25),
+                latency: 120,
+            }],
+        }),
+        MessageBody::PlaybackReadyV1,
+        MessageBody::PlaybackWaitingForV1(dto::PlaybackWaitingForMsgBodyV1 {
+            subscribers: vec![dto::PlaybackUnreadySubscriberV1 {
+                user_id: user_id(),
+                name: "bob".to_string(),
+            }],
+            ready: 2,
+            needed: 3,
+        }),
+        MessageBody::PlaybackRequestDeltaSyncV1(dto::PlaybackRequestDeltaSyncMsgBodyV1 {
+            enabled: true,
+        }),
+        MessageBody::PlaybackSyncDeltaV1(dto::PlaybackSyncDeltaMsgBodyV1 {
+            seq: 7,
+            base: Some(5),
+            delta: vec![0x03, 0xd0, 0x0f],
+        }),
+        MessageBody::PlaybackSyncAckV1(dto::PlaybackSyncAckMsgBodyV1 { seq: 7 }),
+        MessageBody::AdminListRoomsV1,
+        MessageBody::AdminListRoomsAckV1(dto::AdminListRoomsAckMsgBodyV1 {
+            rooms: vec![dto::AdminRoomV1 {
+                id: room_id(),
+                name: "Movie night".to_string(),
+                public: false,
+                pending_approval: false,
+                creator: Some("alice@example.com".to_string()),
+                users: 3,
+                now_playing: Some("Big Buck Bunny".to_string()),
+            }],
+        }),
+        MessageBody::AdminCloseRoomV1(dto::AdminCloseRoomMsgBodyV1 { room_id: room_id() }),
+        MessageBody::AdminCloseRoomAckV1,
+        MessageBody::AdminKickSessionV1(dto::AdminKickSessionMsgBodyV1 { user_id: user_id() }),
+        MessageBody::AdminKickSessionAckV1,
+        MessageBody::AdminStatsV1,
+        MessageBody::AdminStatsAckV1(dto::AdminStatsAckMsgBodyV1 {
+            sessions: 12,
+            rooms: 3,
+            pending_rooms: 1,
+            room_users: 9,
+        }),
+        MessageBody::AdminSetLogFilterV1(dto::AdminSetLogFilterMsgBodyV1 {
+            filter: Some("palantir_server::playback=debug".to_string()),
+        }),
+        MessageBody::AdminSetLogFilterAckV1,
+    ]
+}
+
+fn fixture_name(message: &serde_json::Value) -> String {
+    let tag = message["m"].as_str().expect("message should have a tag");
+    tag.replace("::", ".").replace('/', ".")
+}
+
+fn to_msgpack_hex(message: &Message) -> String {
+    let (tungstenite::Message::Binary(data), _) = serialize_msgpack(message.clone(), None).unwrap()
+    else {
+        panic!("MsgPack messages should be binary");
+    };
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("invalid hex in fixture"))
+        .collect()
+}
+
+#[test]
+fn should_match_golden_files() {
+    let bless = std::env::var_os("PALANTIR_BLESS_FIXTURES").is_some();
+    if bless {
+        fs::create_dir_all(fixtures_dir()).unwrap();
+    }
+
+    for body in cases() {
+        assert_covered(&body);
+
+        // given
+        let message = Message::new_with_timestamp(body, TIMESTAMP);
+
+        // when
+        let json = serde_json::to_value(&message).unwrap();
+        let msgpack = to_msgpack_hex(&message);
+
+        // then
+        let path = fixtures_dir().join(format!("{}.json", fixture_name(&json)));
+        if bless {
+            let golden = json!({ "json": json, "msgpack": msgpack });
+            fs::write(&path, serde_json::to_string_pretty(&golden).unwrap() + "\n").unwrap();
+            continue;
+        }
+
+        let golden: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(&path)
+                .unwrap_or_else(|err| panic!("Failed to read {}: {err}", path.display())),
+        )
+        .unwrap();
+        assert_eq!(json, golden["json"], "JSON mismatch in {}", path.display());
+        assert_eq!(
+            msgpack,
+            golden["msgpack"].as_str().unwrap(),
+            "MsgPack mismatch in {}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn should_round_trip_golden_files() {
+    for body in cases() {
+        // given
+        let message = Message::new_with_timestamp(body, TIMESTAMP);
+        let json = serde_json::to_value(&message).unwrap();
+        let path = fixtures_dir().join(format!("{}.json", fixture_name(&json)));
+        let golden: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        // when
+        let from_json: Message = serde_json::from_value(golden["json"].clone()).unwrap();
+        let from_msgpack: Message =
+            rmp_serde::from_slice(&from_hex(golden["msgpack"].as_str().unwrap())).unwrap();
+
+        // then
+        assert_eq!(
+            from_json,
+            message,
+            "JSON round trip failed for {}",
+            path.display()
+        );
+        assert_eq!(
+            from_msgpack,
+            message,
+            "MsgPack round trip failed for {}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn should_have_no_stale_golden_files() {
+    if std::env::var_os("PALANTIR_BLESS_FIXTURES").is_some() {
+        // the fixtures are being rewritten concurrently
+        return;
+    }
+
+    // given
+    let case_count = cases().len();
+
+    // when
+    let fixture_count = fs::read_dir(fixtures_dir()).unwrap().count();
+
+    // then
+    assert_eq!(fixture_count, case_count);
+}
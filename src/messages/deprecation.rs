@@ -0,0 +1,188 @@
+//! The version registry for messages that changed between protocol versions.
+//!
+//! When a message changes, its new form gets the suffix of the protocol version that introduced
+//! it, and the old form stays around for clients that haven't moved on yet. Each such change is
+//! described by a [`Deprecation`] in [`DEPRECATIONS`], which tells the server what to send to
+//! clients on older protocol versions.
+
+use super::{dto, Message, MessageBody};
+
+/// A message that has a newer form since some protocol version, while its older form is still
+/// supported.
+#[derive(Debug, Clone, Copy)]
+pub struct Deprecation {
+    /// The protocol version that introduced the newer form.
+    pub since: u32,
+
+    /// Whether clients on older protocol versions get the newer form along with the older one.
+    /// This opens the deprecation window, in which clients can start handling the newer form
+    /// before they negotiate the protocol version that introduced it.
+    pub dual_emit: bool,
+
+    /// Turns the newer form into the older one, or returns `None` for any other message.
+    pub downgrade: fn(&MessageBody) -> Option<MessageBody>,
+}
+
+/// Every message that currently has an older form.
+pub const DEPRECATIONS: &[Deprecation] = &[Deprecation {
+    since: 2,
+    dual_emit: false,
+    downgrade: source_changed_v2_to_v1,
+}];
+
+/// Switching to a stopwatch has no older form, so clients on protocol version 1 get the newer
+/// form for it.
+fn source_changed_v2_to_v1(body: &MessageBody) -> Option<MessageBody> {
+    let MessageBody::PlaybackSourceChangedV2(changed) = body else {
+        return None;
+    };
+    Some(MessageBody::PlaybackSourceChangedV1(
+        dto::PlaybackSourceChangedMsgBodyV1 {
+            source: changed.source.clone()?,
+        },
+    ))
+}
+
+impl Message {
+    /// What to send in place of this message to a client on the given protocol version. Messages
+    /// that changed several times are downgraded step by step; all forms that are sent share the
+    /// timestamp and sequence number of the original message, oldest form first.
+    pub fn for_protocol_version(
+        self,
+        protocol_version: u32,
+        deprecations: &[Deprecation],
+    ) -> Vec<Message> {
+        let mut forms = vec![self.body];
+        loop {
+            let newest = &forms[0];
+            let Some((deprecation, older)) = deprecations
+                .iter()
+                .filter(|deprecation| deprecation.since > protocol_version)
+                .find_map(|deprecation| Some((deprecation, (deprecation.downgrade)(newest)?)))
+            else {
+                break;
+            };
+            if !deprecation.dual_emit {
+                forms.clear();
+            }
+            forms.insert(0, older);
+        }
+        forms
+            .into_iter()
+            .map(|body| Message {
+                body,
+                timestamp: self.timestamp,
+                seq: self.seq,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pretends that `connection::stats/v1` is the newer form of `connection::probe/v1`.
+    fn stats_to_probe(body: &MessageBody) -> Option<MessageBody> {
+        let MessageBody::ConnectionStatsV1(stats) = body else {
+            return None;
+        };
+        Some(MessageBody::ConnectionProbeV1(
+            dto::ConnectionProbeMsgBodyV1 {
+                latency: stats.latency,
+                transport_latency: None,
+                time_offset: stats.time_offset,
+                sync_quality: stats.sync_quality,
+            },
+        ))
+    }
+
+    fn stats() -> Message {
+        Message::new_with_timestamp(
+            MessageBody::ConnectionStatsV1(dto::ConnectionStatsMsgBodyV1 {
+                latency: 120,
+                jitter: 15,
+                time_offset: -35,
+                sync_quality: dto::SyncQualityV1::Fair,
+            }),
+            69420,
+        )
+    }
+
+    fn tags(messages: &[Message]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| {
+                let json = serde_json::to_value(message).unwrap();
+                json["m"].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_send_both_forms_during_the_deprecation_window() {
+        // given
+        let deprecations = [Deprecation {
+            since: 2,
+            dual_emit: true,
+            downgrade: stats_to_probe,
+        }];
+
+        // when
+        let legacy = stats().for_protocol_version(1, &deprecations);
+        let current = stats().for_protocol_version(2, &deprecations);
+
+        // then
+        assert_eq!(
+            tags(&legacy),
+            ["connection::probe/v1", "connection::stats/v1"]
+        );
+        assert!(legacy.iter().all(|message| message.timestamp == 69420));
+        assert_eq!(tags(&current), ["connection::stats/v1"]);
+    }
+
+    #[test]
+    fn should_only_send_the_older_form_outside_the_deprecation_window() {
+        // given
+        let deprecations = [Deprecation {
+            since: 2,
+            dual_emit: false,
+            downgrade: stats_to_probe,
+        }];
+
+        // when
+        let legacy = stats().for_protocol_version(1, &deprecations);
+
+        // then
+        assert_eq!(tags(&legacy), ["connection::probe/v1"]);
+    }
+
+    #[test]
+    fn should_send_source_changes_to_older_clients_in_their_form() {
+        // given
+        let changed = |source| {
+            Message::new_with_timestamp(
+                MessageBody::PlaybackSourceChangedV2(dto::PlaybackSourceChangedMsgBodyV2 {
+                    source,
+                }),
+                69420,
+            )
+        };
+        let source = dto::PlaybackSourceV1 {
+            title: "Big Buck Bunny".to_string(),
+            page_href: "https://example.com/watch".to_string(),
+            frame_href: "https://player.example.com/embed".to_string(),
+            element_query: "video".to_string(),
+        };
+
+        // when
+        let legacy = changed(Some(source)).for_protocol_version(1, DEPRECATIONS);
+        let stopwatch = changed(None).for_protocol_version(1, DEPRECATIONS);
+        let current = changed(None).for_protocol_version(2, DEPRECATIONS);
+
+        // then
+        assert_eq!(tags(&legacy), ["playback::source_changed/v1"]);
+        assert_eq!(tags(&stopwatch), ["playback::source_changed/v2"]);
+        assert_eq!(tags(&current), ["playback::source_changed/v2"]);
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::{info, warn};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use tokio::{
+    sync::Notify,
+    time::{self, Instant},
+};
+
+use crate::{api_access::SessionPriority, session::SessionId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct OverloadConfig {
+    /// How many sessions may run at the same time. Beyond that, a new session is only admitted
+    /// if a session with a lower priority can be shed to make room for it.
+    pub max_sessions: Option<usize>,
+
+    /// The CPU usage of the server process above which sessions are shed, in percent of all the
+    /// CPUs it may run on. Other processes on the same host don't count. This is only supported
+    /// on Linux.
+    pub max_cpu_percent: Option<u32>,
+
+    /// How often the CPU usage is checked, in seconds.
+    pub check_interval: u64,
+
+    /// How many sessions are shed per check while the CPU usage is too high.
+    pub shed_per_check: usize,
+}
+
+impl Default for OverloadConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: None,
+            max_cpu_percent: None,
+            check_interval: 5,
+            shed_per_check: 10,
+        }
+    }
+}
+
+struct TrackedSession {
+    priority: SessionPriority,
+    started_at: Instant,
+    shed: Arc<Notify>,
+}
+
+/// Keeps track of running sessions, so that the least important ones can be shed when the
+/// server is overloaded.
+pub struct LoadShedder {
+    config: OverloadConfig,
+    sessions: Mutex<HashMap<SessionId, TrackedSession>>,
+    /// Sessions that were ended by an operator rather than shed, until they notice.
+    kicked: Mutex<HashSet<SessionId>>,
+    /// Once the server shuts down, every session is ended and no new ones are admitted.
+    shutting_down: AtomicBool,
+}
+
+impl LoadShedder {
+    pub fn new(config: OverloadConfig) -> Self {
+        Self {
+            config,
+            sessions: Mutex::new(HashMap::new()),
+            kicked: Mutex::new(HashSet::new()),
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.sessions.lock().len()
+    }
+
+    /// Registers a new session, which is notified through `shed` if it has to go. If the server
+    /// is full, a session with a lower priority is shed to make room; if there is none, the new
+    /// session is refused.
+    pub fn admit(&self, id: SessionId, priority: SessionPriority, shed: Arc<Notify>) -> bool {
+        if self.is_shutting_down() {
+            return false;
+        }
+        let mut sessions = self.sessions.lock();
+        if self
+            .config
+            .max_sessions
+            .is_some_and(|max| sessions.len() >= max)
+        {
+            let Some(victim) = Self::pick_victims(&sessions, 1, priority).pop() else {
+                return false;
+            };
+            info!(
+                "Shedding session {victim} to make room for a session with priority {priority:?}"
+            );
+            Self::shed(&mut sessions, victim);
+        }
+        sessions.insert(
+            id,
+            TrackedSession {
+                priority,
+                started_at: Instant::now(),
+                shed,
+            },
+        );
+        true
+    }
+
+    pub fn set_priority(&self, id: SessionId, priority: SessionPriority) {
+        if let Some(session) = self.sessions.lock().get_mut(&id) {
+            session.priority = priority;
+        }
+    }
+
+    pub fn release(&self, id: SessionId) {
+        self.sessions.lock().remove(&id);
+        self.kicked.lock().remove(&id);
+    }
+
+    /// Ends a session on an operator's request, the same way as if it was shed. Returns whether
+    /// the session was running.
+    pub fn kick(&self, id: SessionId) -> bool {
+        let mut sessions = self.sessions.lock();
+        if !sessions.contains_key(&id) {
+            return false;
+        }
+        self.kicked.lock().insert(id);
+        Self::shed(&mut sessions, id);
+        true
+    }
+
+    /// Whether the session was kicked by an operator, rather than shed.
+    pub fn was_kicked(&self, id: SessionId) -> bool {
+        self.kicked.lock().contains(&id)
+    }
+
+    /// Ends every session because the server is shutting down. Sessions stay tracked until they
+    /// are released, so that the server can wait for them.
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+        for session in self.sessions.lock().values() {
+            session.shed.notify_one();
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Sheds sessions while the CPU usage is too high. Owners are never shed for this, since
+    /// they are who the server is kept running for.
+    pub async fn watch_cpu(self: Arc<Self>) {
+        let Some(max_cpu_percent) = self.config.max_cpu_percent else {
+            return;
+        };
+        let mut interval = time::interval(Duration::from_secs(self.config.check_interval));
+        let cpus = thread::available_parallelism().map_or(1, usize::from);
+        let mut last_times: Option<CpuTimes> = None;
+        loop {
+            interval.tick().await;
+            let Some(times) = CpuTimes::read() else {
+                warn!("Failed to read the CPU usage; sessions won't be shed based on it");
+                return;
+            };
+            let usage = last_times.map(|last_times| times.usage_since(last_times, cpus));
+            last_times = Some(times);
+            let Some(usage) = usage.filter(|usage| *usage > max_cpu_percent) else {
+                continue;
+            };
+            let shed = self.shed_lowest(self.config.shed_per_check, SessionPriority::Owner);
+            if shed != 0 {
+                warn!("CPU usage is at {usage}%; shed {shed} sessions");
+            }
+        }
+    }
+
+    /// Sheds up to `count` sessions with a priority below `below`. Returns how many were shed.
+    fn shed_lowest(&self, count: usize, below: SessionPriority) -> usize {
+        let mut sessions = self.sessions.lock();
+        let victims = Self::pick_victims(&sessions, count, below);
+        for id in &victims {
+            Self::shed(&mut sessions, *id);
+        }
+        victims.len()
+    }
+
+    /// Picks the least important sessions, the most recent ones first, since they have the
+    /// least to lose.
+    fn pick_victims(
+        sessions: &HashMap<SessionId, TrackedSession>,
+        count: usize,
+        below: SessionPriority,
+    ) -> Vec<SessionId> {
+        let mut candidates: Vec<_> = sessions
+            .iter()
+            .filter(|(_, session)| session.priority < below)
+            .collect();
+        candidates.sort_by_key(|(_, session)| (session.priority, Reverse(session.started_at)));
+        candidates
+            .into_iter()
+            .take(count)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn shed(sessions: &mut HashMap<SessionId, TrackedSession>, id: SessionId) {
+        if let Some(session) = sessions.remove(&id) {
+            session.shed.notify_one();
+        }
+    }
+}
+
+/// The CPU time the server process has used so far, and when that was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CpuTimes {
+    used: Duration,
+    read_at: Instant,
+}
+
+impl CpuTimes {
+    /// The unit of the CPU times in `/proc`, which is the same on every Linux system.
+    const CLOCK_TICKS_PER_SECOND: u64 = 100;
+
+    fn read() -> Option<Self> {
+        Some(Self {
+            used: Self::parse(&fs::read_to_string("/proc/self/stat").ok()?)?,
+            read_at: Instant::now(),
+        })
+    }
+
+    /// Adds up the user and system time in a `/proc/<pid>/stat` line.
+    fn parse(stat: &str) -> Option<Duration> {
+        // the process name may contain spaces, but it's the only field in parentheses
+        let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+        // the user and system time are the 14th and 15th fields, counting from the pid
+        let user = fields.nth(11)?.parse::<u64>().ok()?;
+        let system = fields.next()?.parse::<u64>().ok()?;
+        Some(Duration::from_millis(
+            (user + system) * 1000 / Self::CLOCK_TICKS_PER_SECOND,
+        ))
+    }
+
+    /// The share of the CPUs the process may use that it used since the earlier reading, in
+    /// percent.
+    fn usage_since(self, earlier: Self, cpus: usize) -> u32 {
+        let available = self.read_at.duration_since(earlier.read_at) * cpus as u32;
+        if available.is_zero() {
+            return 0;
+        }
+        let used = self.used.saturating_sub(earlier.used);
+        (used.as_millis() * 100 / available.as_millis()) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn session_id(id: u128) -> SessionId {
+        SessionId::from(Uuid::from_u128(id))
+    }
+
+    fn shedder(max_sessions: usize) -> LoadShedder {
+        LoadShedder::new(OverloadConfig {
+            max_sessions: Some(max_sessions),
+            ..OverloadConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn should_shed_the_newest_session_with_the_lowest_priority() {
+        // given
+        let shedder = shedder(3);
+        let signals: Vec<_> = (0..3).map(|_| Arc::new(Notify::new())).collect();
+        let priorities = [
+            SessionPriority::Anonymous,
+            SessionPriority::Anonymous,
+            SessionPriority::Member,
+        ];
+        for (id, (signal, priority)) in (0..).zip(signals.iter().zip(priorities)) {
+            assert!(shedder.admit(session_id(id), priority, Arc::clone(signal)));
+            time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // when
+        let admitted = shedder.admit(
+            session_id(3),
+            SessionPriority::Member,
+            Arc::new(Notify::new()),
+        );
+
+        // then
+        assert!(admitted);
+        let shed =
+            |signal: &Arc<Notify>| futures::FutureExt::now_or_never(signal.notified()).is_some();
+        assert_eq!(
+            signals.iter().map(shed).collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+    }
+
+    #[test]
+    fn should_refuse_sessions_if_nobody_less_important_can_be_shed() {
+        // given
+        let shedder = shedder(1);
+        let member = Arc::new(Notify::new());
+        shedder.admit(session_id(4), SessionPriority::Member, Arc::clone(&member));
+
+        // when
+        let anonymous = shedder.admit(
+            session_id(5),
+            SessionPriority::Anonymous,
+            Arc::new(Notify::new()),
+        );
+        let other_member = shedder.admit(
+            session_id(6),
+            SessionPriority::Member,
+            Arc::new(Notify::new()),
+        );
+
+        // then
+        assert!(!anonymous);
+        assert!(!other_member);
+        assert!(futures::FutureExt::now_or_never(member.notified()).is_none());
+    }
+
+    #[test]
+    fn should_tell_kicked_sessions_from_shed_ones() {
+        // given
+        let shedder = shedder(2);
+        let kicked = Arc::new(Notify::new());
+        shedder.admit(session_id(7), SessionPriority::Owner, Arc::clone(&kicked));
+
+        // when
+        let found = shedder.kick(session_id(7));
+        let missing = shedder.kick(session_id(8));
+
+        // then
+        assert!(found);
+        assert!(!missing);
+        assert!(futures::FutureExt::now_or_never(kicked.notified()).is_some());
+        assert!(shedder.was_kicked(session_id(7)));
+        assert_eq!(shedder.session_count(), 0);
+    }
+
+    #[test]
+    fn should_end_every_session_when_shutting_down() {
+        // given
+        let shedder = shedder(2);
+        let owner = Arc::new(Notify::new());
+        shedder.admit(session_id(9), SessionPriority::Owner, Arc::clone(&owner));
+
+        // when
+        shedder.shut_down();
+        let admitted = shedder.admit(
+            session_id(10),
+            SessionPriority::Owner,
+            Arc::new(Notify::new()),
+        );
+
+        // then
+        assert!(futures::FutureExt::now_or_never(owner.notified()).is_some());
+        assert!(!admitted);
+        assert!(shedder.is_shutting_down());
+        assert_eq!(shedder.session_count(), 1);
+    }
+
+    #[test]
+    fn should_compute_cpu_usage_between_readings() {
+        // given
+        let stat = |user: u64, system: u64| {
+            format!("42 (palantir server) S 1 42 42 0 -1 4194560 1 0 0 0 {user} {system} 0 0 20")
+        };
+        let read_at = Instant::now();
+        let earlier = CpuTimes {
+            used: CpuTimes::parse(&stat(100, 50)).unwrap(),
+            read_at,
+        };
+        let later = CpuTimes {
+            used: CpuTimes::parse(&stat(300, 150)).unwrap(),
+            read_at: read_at + Duration::from_secs(2),
+        };
+
+        // when
+        let usage = later.usage_since(earlier, 4);
+
+        // then
+        assert_eq!(later.used, Duration::from_millis(4500));
+        assert_eq!(usage, 37);
+    }
+}
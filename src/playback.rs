@@ -1,28 +1,90 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    mem,
+    time::Duration,
+};
 
 use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use tokio::time::Instant;
 
 use crate::{
+    errors::ClientError,
+    id_type,
     messages::dto,
     session::{SessionHandle, SessionId, SessionMsg},
+    utils::{timestamp, YieldPoint},
 };
 
+mod delta;
+
+pub use delta::DeltaSync;
+
+/// Who decides where the playback is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncMode {
+    /// Syncs are relayed as they are sent by the host or a subscriber.
+    #[default]
+    Relay,
+
+    /// The server keeps its own playback clock, sends periodic corrections, and rejects changes
+    /// that conflict with one that was just made by someone else.
+    Authoritative,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct PlaybackConfig {
+    pub sync_mode: SyncMode,
+
+    /// How often corrections are sent in authoritative mode, in seconds.
+    pub correction_interval: u64,
+
+    /// How long after a seek or pause other users' changes count as conflicting, in milliseconds.
+    pub conflict_window: u64,
+
+    /// How far ahead a start is scheduled, on top of the slowest connection's latency, in
+    /// milliseconds. With 0, clients start as soon as they are told to.
+    pub start_delay: u64,
+
+    /// How far a sync may be off from where the playback should be and still only count as a
+    /// confirmation, which isn't relayed, in milliseconds. This keeps one client with a jittery
+    /// clock from making everyone seek. Only used in relay mode; with 0, every sync is relayed.
+    pub sync_tolerance: u64,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        Self {
+            sync_mode: SyncMode::Relay,
+            correction_interval: 5,
+            conflict_window: 500,
+            start_delay: 300,
+            sync_tolerance: 500,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PlaybackInfo {
     pub host: String,
     pub source: Option<PlaybackSource>,
+    /// Whether the host is only syncing a clock, for media that the extension can't control.
+    pub stopwatch: bool,
 }
 
 impl From<PlaybackInfo> for dto::RoomPlaybackInfoV1 {
     fn from(value: PlaybackInfo) -> Self {
         Self {
-            host: value.host.into(),
+            host: value.host,
             source: value.source.map(Into::into),
+            stopwatch: value.stopwatch,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PlaybackSource {
     pub title: String,
     pub page_href: String,
@@ -52,14 +114,179 @@ impl From<dto::PlaybackSourceV1> for PlaybackSource {
     }
 }
 
-#[derive(Debug, Clone)]
+/// When a client should begin playing a scheduled start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StartAt {
+    /// In the client's clock.
+    pub timestamp: u64,
+    /// From when the client receives it, in milliseconds.
+    pub delay: u64,
+}
+
+impl From<StartAt> for dto::PlaybackStartAtMsgBodyV1 {
+    fn from(value: StartAt) -> Self {
+        Self {
+            timestamp: value.timestamp,
+            delay: value.delay,
+        }
+    }
+}
+
+id_type!(QueueEntryId);
+
+impl From<dto::QueueEntryIdV1> for QueueEntryId {
+    fn from(value: dto::QueueEntryIdV1) -> Self {
+        Self::from(*value)
+    }
+}
+
+impl From<QueueEntryId> for dto::QueueEntryIdV1 {
+    fn from(value: QueueEntryId) -> Self {
+        Self::from(*value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueEntry {
+    pub id: QueueEntryId,
+    pub source: PlaybackSource,
+}
+
+impl From<QueueEntry> for dto::PlaybackQueueEntryV1 {
+    fn from(value: QueueEntry) -> Self {
+        Self {
+            id: value.id.into(),
+            source: value.source.into(),
+        }
+    }
+}
+
+/// How many sources can be queued up at once.
+pub const MAX_QUEUE_ENTRIES: usize = 100;
+
+/// The sources that are played after the current one, in order.
+#[derive(Debug, Clone, Default)]
+struct PlaybackQueue {
+    entries: VecDeque<QueueEntry>,
+}
+
+impl PlaybackQueue {
+    /// Adds a source at the given position, or at the end if there is none.
+    fn add(&mut self, source: PlaybackSource, index: Option<usize>) -> anyhow::Result<()> {
+        if self.entries.len() >= MAX_QUEUE_ENTRIES {
+            return Err(ClientError::invalid(format!(
+                "The queue can't hold more than {MAX_QUEUE_ENTRIES} entries"
+            ))
+            .into());
+        }
+        let entry = QueueEntry {
+            id: QueueEntryId::new(),
+            source,
+        };
+        let index = index.unwrap_or(self.entries.len()).min(self.entries.len());
+        self.entries.insert(index, entry);
+        Ok(())
+    }
+
+    fn position(&self, id: QueueEntryId) -> anyhow::Result<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.id == id)
+            .ok_or_else(|| ClientError::invalid(format!("There is no queue entry {id}")).into())
+    }
+
+    fn remove(&mut self, id: QueueEntryId) -> anyhow::Result<()> {
+        let index = self.position(id)?;
+        self.entries.remove(index);
+        Ok(())
+    }
+
+    fn move_to(&mut self, id: QueueEntryId, index: usize) -> anyhow::Result<()> {
+        let from = self.position(id)?;
+        if let Some(entry) = self.entries.remove(from) {
+            let index = index.min(self.entries.len());
+            self.entries.insert(index, entry);
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Option<PlaybackSource> {
+        self.entries.pop_front().map(|entry| entry.source)
+    }
+
+    fn entries(&self) -> Vec<QueueEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct PlaybackState {
     pub timestamp: u64,
     pub playing: bool,
     pub time: f32,
+    pub duration: Option<f32>,
 }
 
 impl PlaybackState {
+    /// How close to the end of the media the playback position has to be to count as finished,
+    /// in seconds. Players frequently stop slightly short of the reported duration.
+    const FINISHED_TOLERANCE: f32 = 0.5;
+
+    /// How far a position may be off from the expected one before it counts as a seek, in
+    /// seconds.
+    const SEEK_THRESHOLD: f32 = 1.0;
+
+    /// How far in the future a state's timestamp may be, in milliseconds. Clock offsets are
+    /// already taken into account, so anything beyond this comes from a broken clock.
+    const MAX_CLOCK_SKEW: u64 = 10_000;
+
+    fn is_finished(&self) -> bool {
+        self.duration
+            .is_some_and(|duration| self.time >= duration - Self::FINISHED_TOLERANCE)
+    }
+
+    /// Whether this state is a seek, a pause or a resume compared to the previous one, rather
+    /// than just a confirmation of it.
+    fn changes(&self, previous: &PlaybackState) -> bool {
+        self.playing != previous.playing
+            || (self.time - previous.position_at(self.timestamp)).abs() > Self::SEEK_THRESHOLD
+    }
+
+    /// Whether this state is close enough to the previous one to not be worth relaying. The
+    /// tolerance is in seconds.
+    fn confirms(&self, previous: &PlaybackState, tolerance: f32) -> bool {
+        self.playing == previous.playing
+            && self.duration == previous.duration
+            && !self.is_finished()
+            && (self.time - previous.position_at(self.timestamp)).abs() <= tolerance
+    }
+
+    /// Whether this state could have come from a working player, as seen at the given server
+    /// time.
+    fn is_plausible(&self, now: u64) -> bool {
+        self.time.is_finite()
+            && self.time >= 0.0
+            && self.duration.is_none_or(|duration| {
+                duration.is_finite()
+                    && duration > 0.0
+                    && self.time <= duration + Self::FINISHED_TOLERANCE
+            })
+            && self.timestamp <= now + Self::MAX_CLOCK_SKEW
+    }
+
+    /// Estimates the playback position at the given server time.
+    fn position_at(&self, now: u64) -> f32 {
+        if !self.playing {
+            return self.time;
+        }
+        let elapsed = now.saturating_sub(self.timestamp) as f32 / 1000.0;
+        let position = self.time + elapsed;
+        match self.duration {
+            Some(duration) => position.min(duration),
+            None => position,
+        }
+    }
+
     fn normalize_offset(&self, source_offset: i64) -> Self {
         Self {
             timestamp: self.timestamp.saturating_add_signed(-source_offset),
@@ -75,12 +302,276 @@ impl PlaybackState {
     }
 }
 
+/// Where a user's own player was in a source, as far as the server knows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchPosition {
+    pub page_href: String,
+    pub state: PlaybackState,
+}
+
+impl WatchPosition {
+    /// The position at the given server time, in seconds.
+    pub fn position_at(&self, now: u64) -> f32 {
+        self.state.position_at(now)
+    }
+
+    /// Stops the clock, for a user who isn't watching anymore.
+    pub fn frozen_at(&self, now: u64) -> Self {
+        Self {
+            page_href: self.page_href.clone(),
+            state: PlaybackState {
+                timestamp: now,
+                playing: false,
+                time: self.position_at(now),
+                duration: self.state.duration,
+            },
+        }
+    }
+}
+
+/// How one subscriber answered a roll call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollCallEntry {
+    pub user: SessionId,
+    pub name: String,
+    /// In the host's clock. Missing if the subscriber didn't answer in time.
+    pub state: Option<PlaybackState>,
+    /// How far ahead of the playback the subscriber is, in seconds; negative if behind.
+    pub drift: Option<f32>,
+    pub buffered: Option<f32>,
+    pub dropped_frames: Option<u32>,
+}
+
+impl From<RollCallEntry> for dto::PlaybackRollCallEntryV1 {
+    fn from(value: RollCallEntry) -> Self {
+        Self {
+            user_id: value.user.into(),
+            name: value.name,
+            state: value.state.map(From::from),
+            drift: value.drift,
+            buffered: value.buffered,
+            dropped_frames: value.dropped_frames,
+        }
+    }
+}
+
+/// What a subscriber's player reported in a heartbeat.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Telemetry {
+    pub state: PlaybackState,
+    /// How much of the media after the current position is buffered, in seconds.
+    pub buffered: Option<f32>,
+    /// How many frames the player dropped since the current source started.
+    pub dropped_frames: Option<u32>,
+}
+
+impl From<dto::PlaybackTelemetryV1> for Telemetry {
+    fn from(value: dto::PlaybackTelemetryV1) -> Self {
+        Self {
+            state: value.state.into(),
+            buffered: value.buffered,
+            dropped_frames: value.dropped_frames,
+        }
+    }
+}
+
+/// The last heartbeat of a subscriber, with its state in server time.
+#[derive(Debug, Clone)]
+struct ReceivedTelemetry {
+    telemetry: Telemetry,
+    received_at: Instant,
+    /// When the subscriber was last told to lower its quality.
+    hinted_at: Option<Instant>,
+    /// When the subscriber was last seeked back to the playback.
+    corrected_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityHint {
+    LowBuffer,
+    DroppingFrames,
+}
+
+impl From<QualityHint> for dto::PlaybackQualityHintV1 {
+    fn from(value: QualityHint) -> Self {
+        match value {
+            QualityHint::LowBuffer => Self::LowBuffer,
+            QualityHint::DroppingFrames => Self::DroppingFrames,
+        }
+    }
+}
+
+/// How many subscribers have to be ready before a start goes out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadyQuorum {
+    /// A number of subscribers, or all of them if there are fewer. Like with percentages, one
+    /// has to be ready unless the count is zero.
+    Count(u32),
+    /// A percentage of the subscribers, rounded up. Since subscribers can only connect once the
+    /// playback has started, at least one of them has to be ready.
+    Percent(u8),
+}
+
+impl ReadyQuorum {
+    fn needed(self, subscribers: usize) -> u32 {
+        match self {
+            Self::Count(count) => {
+                let subscribers = u32::try_from(subscribers).unwrap_or(u32::MAX);
+                count.min(subscribers.max(1))
+            }
+            Self::Percent(percent) => {
+                let subscribers = u32::try_from(subscribers).unwrap_or(u32::MAX);
+                (subscribers.saturating_mul(u32::from(percent.min(100))))
+                    .div_ceil(100)
+                    .max(1)
+            }
+        }
+    }
+}
+
+impl From<dto::PlaybackReadyQuorumV1> for ReadyQuorum {
+    fn from(value: dto::PlaybackReadyQuorumV1) -> Self {
+        match value {
+            dto::PlaybackReadyQuorumV1::Count(count) => Self::Count(count),
+            dto::PlaybackReadyQuorumV1::Percent(percent) => Self::Percent(percent),
+        }
+    }
+}
+
+impl From<ReadyQuorum> for dto::PlaybackReadyQuorumV1 {
+    fn from(value: ReadyQuorum) -> Self {
+        match value {
+            ReadyQuorum::Count(count) => Self::Count(count),
+            ReadyQuorum::Percent(percent) => Self::Percent(percent),
+        }
+    }
+}
+
+/// Who a held start is still waiting for, as reported to the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitingFor {
+    /// The subscribers who aren't ready yet, by id and name.
+    pub subscribers: Vec<(SessionId, String)>,
+    pub ready: u32,
+    pub needed: u32,
+}
+
+impl From<WaitingFor> for dto::PlaybackWaitingForMsgBodyV1 {
+    fn from(value: WaitingFor) -> Self {
+        Self {
+            subscribers: value
+                .subscribers
+                .into_iter()
+                .map(|(id, name)| dto::PlaybackUnreadySubscriberV1 {
+                    user_id: id.into(),
+                    name,
+                })
+                .collect(),
+            ready: value.ready,
+            needed: value.needed,
+        }
+    }
+}
+
+/// Everyone's answers to a roll call, as reported to the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollCallReport {
+    pub roll_call_id: u32,
+    pub entries: Vec<RollCallEntry>,
+}
+
+impl From<RollCallReport> for dto::PlaybackRollCallReportMsgBodyV1 {
+    fn from(value: RollCallReport) -> Self {
+        Self {
+            roll_call_id: value.roll_call_id,
+            entries: value.entries.into_iter().map(From::from).collect(),
+        }
+    }
+}
+
+/// How well a subscriber keeps up with the playback, as reported to the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriberStats {
+    pub user: SessionId,
+    pub name: String,
+    /// How far ahead of the playback the subscriber's player was when it last reported, in
+    /// seconds; negative if behind. Missing if it hasn't reported yet.
+    pub drift: Option<f32>,
+    /// In milliseconds.
+    pub latency: u64,
+}
+
+impl From<SubscriberStats> for dto::PlaybackSubscriberStatsV1 {
+    fn from(value: SubscriberStats) -> Self {
+        Self {
+            user_id: value.user.into(),
+            name: value.name,
+            drift: value.drift,
+            latency: value.latency,
+        }
+    }
+}
+
+/// A roll call that is still waiting for answers.
+#[derive(Debug, Clone)]
+struct RollCall {
+    id: u32,
+    deadline: Instant,
+    /// The state each asked subscriber answered with, in server time.
+    answers: HashMap<SessionId, Option<PlaybackState>>,
+}
+
+impl RollCall {
+    fn is_complete(&self) -> bool {
+        self.answers.values().all(Option::is_some)
+    }
+}
+
+/// Known good seek points of the media, in seconds, sorted in ascending order.
+#[derive(Debug, Clone, Default)]
+pub struct SeekHints {
+    points: Vec<f32>,
+}
+
+impl SeekHints {
+    /// Hosts can't make the server keep arbitrarily many points around.
+    const MAX_POINTS: usize = 4096;
+
+    /// How far a corrective seek may be moved to land on a hinted point, in seconds.
+    const SNAP_TOLERANCE: f32 = 2.0;
+
+    pub fn new(points: Vec<f32>) -> Self {
+        let mut points: Vec<f32> = points
+            .into_iter()
+            .filter(|point| point.is_finite() && *point >= 0.0)
+            .collect();
+        points.sort_by(f32::total_cmp);
+        points.dedup();
+        points.truncate(Self::MAX_POINTS);
+        Self { points }
+    }
+
+    /// Moves a position to the nearest hinted point, if there is one close enough.
+    fn snap(&self, position: f32) -> f32 {
+        let index = self.points.partition_point(|point| *point < position);
+        let before = index.checked_sub(1).map(|i| self.points[i]);
+        let after = self.points.get(index).copied();
+        [before, after]
+            .into_iter()
+            .flatten()
+            .filter(|point| (point - position).abs() <= Self::SNAP_TOLERANCE)
+            .min_by(|a, b| (a - position).abs().total_cmp(&(b - position).abs()))
+            .unwrap_or(position)
+    }
+}
+
 impl From<dto::PlaybackStateV1> for PlaybackState {
     fn from(value: dto::PlaybackStateV1) -> Self {
         Self {
             timestamp: value.timestamp,
             playing: value.playing,
             time: value.time,
+            duration: value.duration,
         }
     }
 }
@@ -91,6 +582,57 @@ impl From<PlaybackState> for dto::PlaybackStateV1 {
             timestamp: value.timestamp,
             playing: value.playing,
             time: value.time,
+            duration: value.duration,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackStatus {
+    Idle,
+    Loading,
+    Playing,
+    Paused,
+}
+
+impl From<PlaybackStatus> for dto::PlaybackStatusV1 {
+    fn from(value: PlaybackStatus) -> Self {
+        match value {
+            PlaybackStatus::Idle => Self::Idle,
+            PlaybackStatus::Loading => Self::Loading,
+            PlaybackStatus::Playing => Self::Playing,
+            PlaybackStatus::Paused => Self::Paused,
+        }
+    }
+}
+
+/// A snapshot of a playback for users who aren't subscribed to it.
+#[derive(Debug, Clone)]
+pub struct PlaybackOverview {
+    pub info: Option<PlaybackInfo>,
+    pub status: PlaybackStatus,
+    pub position: Option<f32>,
+    pub duration: Option<f32>,
+}
+
+impl PlaybackOverview {
+    pub fn idle() -> Self {
+        Self {
+            info: None,
+            status: PlaybackStatus::Idle,
+            position: None,
+            duration: None,
+        }
+    }
+}
+
+impl From<PlaybackOverview> for dto::PlaybackInfoMsgBodyV1 {
+    fn from(value: PlaybackOverview) -> Self {
+        Self {
+            info: value.info.map(From::from),
+            status: value.status.into(),
+            position: value.position,
+            duration: value.duration,
         }
     }
 }
@@ -100,6 +642,9 @@ pub enum StopReason {
     HostError,
     StoppedByHost,
     Superseded,
+    Finished,
+    /// The host left the room.
+    HostLeft,
 }
 
 impl From<StopReason> for dto::PlaybackStopReasonV1 {
@@ -108,6 +653,8 @@ impl From<StopReason> for dto::PlaybackStopReasonV1 {
             StopReason::HostError => Self::HostError,
             StopReason::StoppedByHost => Self::StoppedByHost,
             StopReason::Superseded => Self::Superseded,
+            StopReason::Finished => Self::Finished,
+            StopReason::HostLeft => Self::HostLeft,
         }
     }
 }
@@ -131,34 +678,208 @@ impl From<DisconnectReason> for dto::PlaybackDisconnectReasonV1 {
 
 #[derive(Debug, Clone)]
 pub enum PlaybackRequest {
-    Start(PlaybackSource),
+    /// Starts playing the given source, or a stopwatch if there is none.
+    Start(Option<PlaybackSource>),
     Disconnect(DisconnectReason),
     Stop(StopReason),
+    Finish,
+    /// The current source ended, so the next one in the queue should be played.
+    Ended,
     Sync(PlaybackState),
+    SeekHints(SeekHints),
+    QueueAdd(PlaybackSource, Option<usize>),
+    QueueRemove(QueueEntryId),
+    QueueMove(QueueEntryId, usize),
+    /// Asks every subscriber where their player is.
+    RollCall(u32),
+    RollCallReply(u32, PlaybackState),
+    Telemetry(Telemetry),
+    /// Lets the server pick a subscriber as the timing reference instead of the host, or stops
+    /// doing so.
+    DelegateTiming(bool),
+    /// Asks how far each subscriber is off the playback.
+    Stats,
+    /// A subscriber's player has loaded the source and can start playing.
+    Ready,
 }
 
 #[derive(Debug, Clone)]
 pub struct Playback {
     running: bool,
     source: Option<PlaybackSource>,
+    last_state: Option<PlaybackState>,
+    seek_hints: SeekHints,
+    queue: PlaybackQueue,
     host: SessionHandle,
     subscribers: HashMap<SessionId, SessionHandle>,
+    config: PlaybackConfig,
+    /// Who last seeked, paused or resumed, and when, in server time.
+    last_change: Option<(SessionId, u64)>,
+    correction_at: Option<Instant>,
+    /// When the current source is scheduled to start, in server time.
+    start_at: Option<u64>,
+    /// The last state each user's own player reported, in server time.
+    reports: HashMap<SessionId, PlaybackState>,
+    /// How far each subscriber's player was ahead of the playback when it last reported, in
+    /// seconds.
+    drift: HashMap<SessionId, f32>,
+    telemetry: HashMap<SessionId, ReceivedTelemetry>,
+    roll_call: Option<RollCall>,
+    /// While the room has an intermission, only the host's syncs are accepted.
+    intermission: bool,
+    /// Whether the host lets the server pick a subscriber as the timing reference.
+    delegated: bool,
+    /// The subscriber whose syncs everyone follows while the host delegates timing. The host is
+    /// the reference if there is none.
+    leader: Option<SessionId>,
+    ready_quorum: Option<ReadyQuorum>,
+    /// The subscribers who are ready to play the current source.
+    ready: HashSet<SessionId>,
+    /// Whether the start of the current source waits for enough subscribers to be ready.
+    start_held: bool,
 }
 
 impl Playback {
-    pub fn new(host: SessionHandle) -> Self {
+    /// How much a single slow connection may delay a scheduled start, in milliseconds.
+    const MAX_LATENCY_COMPENSATION: u64 = 2000;
+
+    /// How long subscribers have to answer a roll call before it is reported without them.
+    const ROLL_CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// How old a heartbeat may be to still answer a roll call in the subscriber's place.
+    const TELEMETRY_MAX_AGE: Duration = Duration::from_secs(3);
+
+    /// Below how many seconds of buffered media a playing subscriber is told to lower its
+    /// quality.
+    const LOW_BUFFER: f32 = 2.0;
+
+    /// How many frames a subscriber may drop between two heartbeats before it is told to lower
+    /// its quality.
+    const DROPPED_FRAMES_THRESHOLD: u32 = 30;
+
+    /// How often a subscriber is told to lower its quality at most.
+    const QUALITY_HINT_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// How often a subscriber that is off the playback is seeked back at most, which gives its
+    /// player time to get there.
+    const SEEK_CORRECTION_INTERVAL: Duration = Duration::from_secs(5);
+
+    /// How much more the leader's latency has to jitter than another subscriber's before that
+    /// subscriber takes over, in milliseconds. This keeps the leader from changing all the time.
+    const LEADER_JITTER_MARGIN: u64 = 20;
+
+    pub fn new(host: SessionHandle, config: PlaybackConfig) -> Self {
         Self {
             running: false,
             source: None,
+            last_state: None,
+            seek_hints: SeekHints::default(),
+            queue: PlaybackQueue::default(),
             host,
             subscribers: HashMap::new(),
+            config,
+            last_change: None,
+            correction_at: None,
+            start_at: None,
+            reports: HashMap::new(),
+            drift: HashMap::new(),
+            telemetry: HashMap::new(),
+            roll_call: None,
+            intermission: false,
+            delegated: false,
+            leader: None,
+            ready_quorum: None,
+            ready: HashSet::new(),
+            start_held: false,
+        }
+    }
+
+    /// Makes future starts wait for the given number of subscribers to be ready.
+    pub fn set_ready_quorum(&mut self, quorum: Option<ReadyQuorum>) {
+        self.ready_quorum = quorum;
+    }
+
+    /// When the next correction is due, if the server keeps the playback clock.
+    pub fn next_correction_at(&self) -> Option<Instant> {
+        self.correction_at
+    }
+
+    /// When the running roll call has to be reported, whether everyone answered or not.
+    pub fn roll_call_deadline(&self) -> Option<Instant> {
+        self.roll_call.as_ref().map(|roll_call| roll_call.deadline)
+    }
+
+    /// Where the given user is in the current source. That's the last state their player
+    /// reported, unless the playback has changed since.
+    pub fn watch_position(&self, id: SessionId) -> Option<WatchPosition> {
+        let page_href = self.source.as_ref()?.page_href.clone();
+        let watching = id == self.host.id || self.subscribers.contains_key(&id);
+        let state = match (self.reports.get(&id), &self.last_state) {
+            (Some(own), Some(current)) if current.timestamp > own.timestamp => current,
+            (Some(own), _) => own,
+            (None, Some(current)) if watching => current,
+            _ => return None,
+        };
+        Some(WatchPosition {
+            page_href,
+            state: state.clone(),
+        })
+    }
+
+    /// Keeps the names of the host and subscribers in step with the room.
+    pub fn rename(&mut self, id: SessionId, name: &str) {
+        let handles = self
+            .subscribers
+            .get_mut(&id)
+            .into_iter()
+            .chain((self.host.id == id).then_some(&mut self.host));
+        for handle in handles {
+            handle.name = name.to_string();
         }
     }
 
+    pub fn host_id(&self) -> SessionId {
+        self.host.id
+    }
+
+    /// Whether the host is playing something, be it a source or only a clock.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
     pub fn get_info(&self) -> PlaybackInfo {
         PlaybackInfo {
             source: self.source.clone(),
             host: self.host.name.clone(),
+            stopwatch: self.running && self.source.is_none(),
+        }
+    }
+
+    pub fn get_overview(&self) -> PlaybackOverview {
+        let info = Some(self.get_info());
+        if !self.running {
+            return PlaybackOverview {
+                info,
+                ..PlaybackOverview::idle()
+            };
+        }
+        let Some(state) = &self.last_state else {
+            return PlaybackOverview {
+                info,
+                status: PlaybackStatus::Loading,
+                position: None,
+                duration: None,
+            };
+        };
+        PlaybackOverview {
+            info,
+            status: if state.playing {
+                PlaybackStatus::Playing
+            } else {
+                PlaybackStatus::Paused
+            },
+            position: Some(state.position_at(timestamp())),
+            duration: state.duration,
         }
     }
 
@@ -169,42 +890,139 @@ impl Playback {
     ) -> anyhow::Result<()> {
         let is_host = session_id == self.host.id;
         if !is_host && !self.subscribers.contains_key(&session_id) {
-            return Err(anyhow!("Users who are neither the playback host nor a subscriber cannot send playback requests"));
+            return Err(ClientError::not_authorized("Users who are neither the playback host nor a subscriber cannot send playback requests").into());
         };
 
         match request {
             PlaybackRequest::Start(source) => {
                 if !is_host {
-                    return Err(anyhow!("Only the playback host can start playback"));
+                    return Err(ClientError::not_authorized(
+                        "Only the playback host can start playback",
+                    )
+                    .into());
                 }
                 self.start(source).await?;
             }
             PlaybackRequest::Disconnect(reason) => self.disconnect(session_id, reason).await?,
             PlaybackRequest::Stop(reason) => {
                 if !is_host {
-                    return Err(anyhow!("Only the playback host can stop playback"));
+                    return Err(ClientError::not_authorized(
+                        "Only the playback host can stop playback",
+                    )
+                    .into());
                 }
                 self.stop(reason).await?;
             }
+            PlaybackRequest::Finish => {
+                if !is_host {
+                    return Err(ClientError::not_authorized(
+                        "Only the playback host can finish playback",
+                    )
+                    .into());
+                }
+                self.stop(StopReason::Finished).await?;
+            }
+            PlaybackRequest::Ended => {
+                if !is_host {
+                    return Err(ClientError::not_authorized(
+                        "Only the playback host can end the current source",
+                    )
+                    .into());
+                }
+                self.advance().await?;
+            }
+            PlaybackRequest::Sync(..) if self.intermission && !is_host => {
+                return Err(
+                    ClientError::not_authorized("Playback is paused for an intermission").into(),
+                );
+            }
             PlaybackRequest::Sync(state) => self.sync(session_id, state).await?,
+            PlaybackRequest::SeekHints(hints) => {
+                if !is_host {
+                    return Err(ClientError::not_authorized(
+                        "Only the playback host can publish seek hints",
+                    )
+                    .into());
+                }
+                self.seek_hints = hints;
+            }
+            PlaybackRequest::QueueAdd(..)
+            | PlaybackRequest::QueueRemove(..)
+            | PlaybackRequest::QueueMove(..) => {
+                if !is_host {
+                    return Err(ClientError::not_authorized(
+                        "Only the playback host can change the queue",
+                    )
+                    .into());
+                }
+                self.change_queue(request).await?;
+            }
+            PlaybackRequest::RollCall(roll_call_id) => {
+                if !is_host {
+                    return Err(ClientError::not_authorized(
+                        "Only the playback host can start a roll call",
+                    )
+                    .into());
+                }
+                self.start_roll_call(roll_call_id).await?;
+            }
+            PlaybackRequest::RollCallReply(roll_call_id, state) => {
+                self.answer_roll_call(session_id, roll_call_id, state)
+                    .await?
+            }
+            PlaybackRequest::Telemetry(telemetry) => {
+                self.record_telemetry(session_id, telemetry).await?
+            }
+            PlaybackRequest::DelegateTiming(delegate) => {
+                if !is_host {
+                    return Err(ClientError::not_authorized(
+                        "Only the playback host can delegate timing",
+                    )
+                    .into());
+                }
+                self.delegated = delegate;
+            }
+            PlaybackRequest::Stats => {
+                if !is_host {
+                    return Err(ClientError::not_authorized(
+                        "Only the playback host can see how subscribers keep up",
+                    )
+                    .into());
+                }
+                self.send_stats().await?;
+            }
+            PlaybackRequest::Ready => self.report_ready(session_id).await?,
+        }
+
+        // connections change all the time, so the leader is checked whenever anything happens
+        if self.delegated || self.leader.is_some() {
+            self.elect_leader().await?;
         }
 
         Ok(())
     }
 
-    async fn start(&mut self, source: PlaybackSource) -> anyhow::Result<()> {
+    async fn start(&mut self, source: Option<PlaybackSource>) -> anyhow::Result<()> {
         if self.running {
+            if self.source != source {
+                self.change_source(source).await?;
+            } else if self.start_held {
+                // the host doesn't want to wait any longer
+                self.release_start().await?;
+            }
             return Ok(());
         }
         self.running = true;
-        self.source = Some(source);
+        self.source = source;
         if !self.host.send_message(SessionMsg::PlaybackStarted).await? {
             self.stop(StopReason::HostError)
                 .await
                 .context("Failed to stop playback after host error")?;
         }
 
+        let mut yield_point = YieldPoint::default();
         for (id, subscriber) in &self.subscribers {
+            yield_point.tick().await;
             if let Err(err) = subscriber
                 .send_message(SessionMsg::PlaybackAvailable(self.get_info()))
                 .await
@@ -212,15 +1030,233 @@ impl Playback {
                 log::error!("Failed to announce playback to user {id}: {err:?}");
             }
         }
+        if self.running {
+            self.schedule_start_when_ready().await?;
+        }
         Ok(())
     }
 
-    pub async fn stop(&mut self, reason: StopReason) -> anyhow::Result<()> {
-        if !self.running {
+    /// Restarts the running playback with a different source. Subscribers stay connected and are
+    /// told about the new source instead of being disconnected.
+    async fn change_source(&mut self, source: Option<PlaybackSource>) -> anyhow::Result<()> {
+        match &source {
+            Some(source) => {
+                log::debug!("Playback source changed to '{}'; restarting", source.title)
+            }
+            None => log::debug!("Playback switched to a stopwatch; restarting"),
+        }
+        let subscribers = mem::take(&mut self.subscribers);
+        self.stop(StopReason::Superseded).await?;
+        self.subscribers = subscribers;
+
+        self.running = true;
+        self.source = source.clone();
+        if !self.host.send_message(SessionMsg::PlaybackStarted).await? {
+            self.stop(StopReason::HostError)
+                .await
+                .context("Failed to stop playback after host error")?;
             return Ok(());
         }
+
+        let mut yield_point = YieldPoint::default();
+        for (id, subscriber) in &self.subscribers {
+            yield_point.tick().await;
+            if let Err(err) = subscriber
+                .send_message(SessionMsg::PlaybackSourceChanged(source.clone()))
+                .await
+            {
+                log::error!("Failed to announce source change to user {id}: {err:?}");
+            }
+        }
+        self.schedule_start_when_ready().await
+    }
+
+    /// Schedules the start, unless the room wants enough subscribers to be ready first.
+    async fn schedule_start_when_ready(&mut self) -> anyhow::Result<()> {
+        self.ready.clear();
+        if self.ready_quorum.is_none() || self.config.start_delay == 0 {
+            return self.schedule_start().await;
+        }
+        self.start_held = true;
+        self.check_readiness().await
+    }
+
+    fn waiting_for(&self) -> WaitingFor {
+        let needed = self
+            .ready_quorum
+            .map_or(0, |quorum| quorum.needed(self.subscribers.len()));
+        let mut subscribers: Vec<(SessionId, String)> = self
+            .subscribers
+            .values()
+            .filter(|subscriber| !self.ready.contains(&subscriber.id))
+            .map(|subscriber| (subscriber.id, subscriber.name.clone()))
+            .collect();
+        subscribers.sort_by(|a, b| a.1.cmp(&b.1));
+        WaitingFor {
+            subscribers,
+            ready: u32::try_from(self.ready.len()).unwrap_or(u32::MAX),
+            needed,
+        }
+    }
+
+    /// Lets a held start go out once enough subscribers are ready, or else tells the host who
+    /// it's still waiting for.
+    async fn check_readiness(&mut self) -> anyhow::Result<()> {
+        if !self.start_held {
+            return Ok(());
+        }
+        let waiting_for = self.waiting_for();
+        if waiting_for.ready >= waiting_for.needed {
+            return self.release_start().await;
+        }
+        self.host
+            .send_message(SessionMsg::PlaybackWaitingFor(waiting_for))
+            .await?;
+        Ok(())
+    }
+
+    async fn release_start(&mut self) -> anyhow::Result<()> {
+        self.start_held = false;
+        let waiting_for = WaitingFor {
+            subscribers: Vec::new(),
+            ..self.waiting_for()
+        };
+        self.host
+            .send_message(SessionMsg::PlaybackWaitingFor(waiting_for))
+            .await?;
+        self.schedule_start().await
+    }
+
+    async fn report_ready(&mut self, id: SessionId) -> anyhow::Result<()> {
+        if !self.subscribers.contains_key(&id) {
+            return Err(ClientError::not_authorized(
+                "Only subscribers report whether they are ready",
+            )
+            .into());
+        }
+        if self.ready.insert(id) {
+            self.check_readiness().await?;
+        }
+        Ok(())
+    }
+
+    /// Picks a moment shortly in the future for everyone to start playing at, late enough that
+    /// even the slowest connection hears about it in time, and tells everyone about it.
+    async fn schedule_start(&mut self) -> anyhow::Result<()> {
+        if self.config.start_delay == 0 {
+            return Ok(());
+        }
+        let slowest = self
+            .subscribers
+            .values()
+            .chain([&self.host])
+            .map(|session| session.latency() / 2)
+            .max()
+            .unwrap_or(0)
+            .min(Self::MAX_LATENCY_COMPENSATION);
+        self.start_at = Some(timestamp() + self.config.start_delay + slowest);
+
+        if let Some(start_at) = self.start_at_for(&self.host) {
+            self.host
+                .send_message(SessionMsg::PlaybackStartAt(start_at))
+                .await?;
+        }
+        let mut yield_point = YieldPoint::default();
+        for (id, subscriber) in &self.subscribers {
+            yield_point.tick().await;
+            let Some(start_at) = self.start_at_for(subscriber) else {
+                continue;
+            };
+            if let Err(err) = subscriber
+                .send_message(SessionMsg::PlaybackStartAt(start_at))
+                .await
+            {
+                log::error!("Failed to schedule playback start for user {id}: {err:?}");
+            }
+        }
+        Ok(())
+    }
+
+    /// The scheduled start as the given session should see it, if it hasn't passed yet.
+    fn start_at_for(&self, session: &SessionHandle) -> Option<StartAt> {
+        let start_at = self.start_at?;
+        let now = timestamp();
+        if start_at <= now {
+            return None;
+        }
+        Some(StartAt {
+            timestamp: start_at.saturating_add_signed(session.time_offset()),
+            delay: start_at.saturating_sub(now + session.latency() / 2),
+        })
+    }
+
+    /// Plays the next source in the queue, or finishes the playback if there is none.
+    async fn advance(&mut self) -> anyhow::Result<()> {
+        let Some(source) = self.queue.next() else {
+            return self.stop(StopReason::Finished).await;
+        };
+        log::debug!("Advancing the playback queue to '{}'", source.title);
+        self.start(Some(source)).await?;
+        self.send_queue().await
+    }
+
+    async fn change_queue(&mut self, request: PlaybackRequest) -> anyhow::Result<()> {
+        match request {
+            PlaybackRequest::QueueAdd(source, index) => self.queue.add(source, index)?,
+            PlaybackRequest::QueueRemove(id) => self.queue.remove(id)?,
+            PlaybackRequest::QueueMove(id, index) => self.queue.move_to(id, index)?,
+            _ => return Ok(()),
+        }
+        self.send_queue().await
+    }
+
+    /// Fills the queue with sources that were planned ahead, e.g. by a room template.
+    pub async fn seed_queue(&mut self, sources: Vec<PlaybackSource>) -> anyhow::Result<()> {
+        for source in sources {
+            self.queue.add(source, None)?;
+        }
+        self.send_queue().await
+    }
+
+    /// Lets the host and all subscribers know what's in the queue.
+    async fn send_queue(&self) -> anyhow::Result<()> {
+        let entries = self.queue.entries();
+        self.host
+            .send_message(SessionMsg::PlaybackQueue(entries.clone()))
+            .await?;
+        let mut yield_point = YieldPoint::default();
+        for (id, subscriber) in &self.subscribers {
+            yield_point.tick().await;
+            if let Err(err) = subscriber
+                .send_message(SessionMsg::PlaybackQueue(entries.clone()))
+                .await
+            {
+                log::error!("Failed to send playback queue to user {id}: {err:?}");
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn stop(&mut self, reason: StopReason) -> anyhow::Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+        self.running = false;
         self.source = None;
+        self.last_state = None;
+        self.last_change = None;
+        self.correction_at = None;
+        self.start_at = None;
+        self.reports.clear();
+        self.drift.clear();
+        self.telemetry.clear();
+        self.roll_call = None;
+        self.seek_hints = SeekHints::default();
+        self.ready.clear();
+        self.start_held = false;
+        let mut yield_point = YieldPoint::default();
         for subscriber in self.subscribers.values() {
+            yield_point.tick().await;
             subscriber
                 .send_message(SessionMsg::PlaybackDisconnected(DisconnectReason::Stopped(
                     reason,
@@ -246,17 +1282,102 @@ impl Playback {
             ));
         }
         user.send_message(SessionMsg::PlaybackConnected).await?;
+        if let Some(start_at) = self.start_at_for(&user) {
+            user.send_message(SessionMsg::PlaybackStartAt(start_at))
+                .await?;
+        }
+        if let Some(state) = self.catch_up_state() {
+            send_sync_msg(&user, &state).await?;
+        }
+        if !self.queue.entries.is_empty() {
+            user.send_message(SessionMsg::PlaybackQueue(self.queue.entries()))
+                .await?;
+        }
+        if let Some(leader) = self.leader {
+            user.send_message(SessionMsg::PlaybackLeader(Some(leader)))
+                .await?;
+        }
         self.subscribers.insert(user.id, user);
+        if self.delegated {
+            self.elect_leader().await?;
+        }
+        self.check_readiness().await
+    }
+
+    /// Picks the subscriber whose latency jitters the least as the leader while the host
+    /// delegates timing, and replaces the leader once its connection is clearly worse than
+    /// another subscriber's. Subscribers that haven't answered a ping yet can't lead.
+    async fn elect_leader(&mut self) -> anyhow::Result<()> {
+        let candidates = || {
+            self.subscribers
+                .values()
+                .filter(|subscriber| self.delegated && subscriber.latency() != 0)
+        };
+        let best = candidates()
+            .min_by_key(|subscriber| (subscriber.jitter(), subscriber.latency(), *subscriber.id));
+        let current = self
+            .leader
+            .and_then(|id| candidates().find(|subscriber| subscriber.id == id));
+        let leader = match (current, best) {
+            (Some(current), Some(best))
+                if current.jitter() <= best.jitter() + Self::LEADER_JITTER_MARGIN =>
+            {
+                Some(current.id)
+            }
+            (_, best) => best.map(|best| best.id),
+        };
+        if leader == self.leader {
+            return Ok(());
+        }
+        match leader {
+            Some(id) => log::debug!("Subscriber {id} is now the playback's timing reference"),
+            None => log::debug!("The playback host is the timing reference again"),
+        }
+        self.leader = leader;
+        self.host
+            .send_message(SessionMsg::PlaybackLeader(leader))
+            .await?;
+        let mut yield_point = YieldPoint::default();
+        for (id, subscriber) in &self.subscribers {
+            yield_point.tick().await;
+            if let Err(err) = subscriber
+                .send_message(SessionMsg::PlaybackLeader(leader))
+                .await
+            {
+                log::error!("Failed to tell user {id} about the playback leader: {err:?}");
+            }
+        }
         Ok(())
     }
 
+    /// The state that brings a newly connected or lagging subscriber up to speed, snapped to the
+    /// nearest seek hint so that they don't stall on a long decode.
+    fn catch_up_state(&self) -> Option<PlaybackState> {
+        let state = self.last_state.as_ref()?;
+        let now = timestamp();
+        Some(PlaybackState {
+            timestamp: now,
+            time: self.seek_hints.snap(state.position_at(now)),
+            ..state.clone()
+        })
+    }
+
     async fn disconnect(&mut self, id: SessionId, reason: DisconnectReason) -> anyhow::Result<()> {
         if let Some(handle) = self.subscribers.remove(&id) {
+            self.telemetry.remove(&id);
+            self.drift.remove(&id);
+            self.ready.remove(&id);
             handle
                 .send_message(SessionMsg::PlaybackDisconnected(reason))
                 .await?;
         }
-        Ok(())
+        if let Some(roll_call) = &mut self.roll_call {
+            roll_call.answers.remove(&id);
+            if roll_call.is_complete() {
+                self.finish_roll_call().await?;
+            }
+        }
+        self.check_readiness().await
     }
 
     async fn sync(&mut self, id: SessionId, state: PlaybackState) -> anyhow::Result<()> {
@@ -266,17 +1387,56 @@ impl Playback {
         } else if let Some(source) = self.subscribers.get(&id) {
             normalized_state = state.normalize_offset(source.time_offset());
         }
+        if !normalized_state.is_plausible(timestamp()) {
+            log::debug!("Ignoring implausible playback state from {id}: {normalized_state:?}");
+            return Err(ClientError::invalid("The playback state is implausible").into());
+        }
+        // even a rejected sync tells where that user's player is
+        self.record_report(id, normalized_state.clone());
+
+        // while there is a leader, only its syncs confirm where the playback is; anyone can
+        // still seek, pause and resume
+        let reference = self.leader.unwrap_or(self.host.id);
+        if id != reference
+            && self.leader.is_some()
+            && self
+                .last_state
+                .as_ref()
+                .is_some_and(|current| !normalized_state.changes(current))
+        {
+            return Ok(());
+        }
+        // the server's own clock already smooths syncs in authoritative mode
+        let tolerance = self.config.sync_tolerance as f32 / 1000.0;
+        if self.config.sync_mode == SyncMode::Relay
+            && self
+                .last_state
+                .as_ref()
+                .is_some_and(|current| normalized_state.confirms(current, tolerance))
+        {
+            return Ok(());
+        }
+
+        if self.config.sync_mode == SyncMode::Authoritative
+            && !self.accept_change(id, &normalized_state).await?
+        {
+            return Ok(());
+        }
+
+        self.last_state = Some(normalized_state.clone());
 
         if id != self.host.id && !send_sync_msg(&self.host, &normalized_state).await? {
             self.stop(StopReason::StoppedByHost).await?;
             return Ok(());
         }
         let mut errored_subscribers: Vec<SessionId> = vec![];
+        let mut yield_point = YieldPoint::default();
         for target in self.subscribers.values() {
+            yield_point.tick().await;
             if target.id == id {
                 continue;
             }
-            if !send_sync_msg(&target, &normalized_state).await? {
+            if !send_sync_msg(target, &normalized_state).await? {
                 errored_subscribers.push(target.id);
             }
         }
@@ -286,6 +1446,341 @@ impl Playback {
                 .await?;
         }
 
+        if id == reference && state.is_finished() {
+            log::debug!("Playback reached the end of the media; advancing");
+            self.advance().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Asks every subscriber where their player is. A roll call that is still running is
+    /// reported right away with the answers it has so far.
+    async fn start_roll_call(&mut self, roll_call_id: u32) -> anyhow::Result<()> {
+        self.finish_roll_call().await?;
+        let mut answers = HashMap::new();
+        let mut yield_point = YieldPoint::default();
+        for (id, subscriber) in &self.subscribers {
+            yield_point.tick().await;
+            // a recent heartbeat already tells where the player is
+            if let Some(received) = self
+                .telemetry
+                .get(id)
+                .filter(|received| received.received_at.elapsed() <= Self::TELEMETRY_MAX_AGE)
+            {
+                answers.insert(*id, Some(received.telemetry.state.clone()));
+                continue;
+            }
+            match subscriber
+                .send_message(SessionMsg::PlaybackRollCall(roll_call_id))
+                .await
+            {
+                Ok(true) => {
+                    answers.insert(*id, None);
+                }
+                Ok(false) => (),
+                Err(err) => log::error!("Failed to send roll call to user {id}: {err:?}"),
+            }
+        }
+        let roll_call = RollCall {
+            id: roll_call_id,
+            deadline: Instant::now() + Self::ROLL_CALL_TIMEOUT,
+            answers,
+        };
+        let complete = roll_call.is_complete();
+        self.roll_call = Some(roll_call);
+        if complete {
+            self.finish_roll_call().await?;
+        }
+        Ok(())
+    }
+
+    async fn answer_roll_call(
+        &mut self,
+        id: SessionId,
+        roll_call_id: u32,
+        state: PlaybackState,
+    ) -> anyhow::Result<()> {
+        let Some(subscriber) = self.subscribers.get(&id) else {
+            return Err(ClientError::invalid("Only subscribers can answer a roll call").into());
+        };
+        let state = state.normalize_offset(subscriber.time_offset());
+        self.record_report(id, state.clone());
+
+        let Some(roll_call) = &mut self.roll_call else {
+            return Ok(());
+        };
+        // answers to an earlier roll call still tell where the player is, but come too late
+        if roll_call.id != roll_call_id {
+            return Ok(());
+        }
+        if let Some(answer) = roll_call.answers.get_mut(&id) {
+            *answer = Some(state);
+        }
+        if roll_call.is_complete() {
+            self.finish_roll_call().await?;
+        }
+        Ok(())
+    }
+
+    /// Reports the running roll call to the host. Subscribers that haven't answered by now are
+    /// reported without a state.
+    pub async fn finish_roll_call(&mut self) -> anyhow::Result<()> {
+        let Some(roll_call) = self.roll_call.take() else {
+            return Ok(());
+        };
+        let now = timestamp();
+        let expected = self.last_state.as_ref().map(|state| state.position_at(now));
+        let mut entries: Vec<RollCallEntry> = roll_call
+            .answers
+            .into_iter()
+            .filter_map(|(id, state)| {
+                let subscriber = self.subscribers.get(&id)?;
+                let telemetry = self.telemetry.get(&id).map(|received| &received.telemetry);
+                Some(RollCallEntry {
+                    user: id,
+                    name: subscriber.name.clone(),
+                    drift: state
+                        .as_ref()
+                        .zip(expected)
+                        .map(|(state, expected)| state.position_at(now) - expected),
+                    state: state.map(|state| state.incorporate_offset(self.host.time_offset())),
+                    buffered: telemetry.and_then(|telemetry| telemetry.buffered),
+                    dropped_frames: telemetry.and_then(|telemetry| telemetry.dropped_frames),
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        self.host
+            .send_message(SessionMsg::PlaybackRollCallReport(RollCallReport {
+                roll_call_id: roll_call.id,
+                entries,
+            }))
+            .await?;
+        Ok(())
+    }
+
+    /// Keeps the state a user's own player reported, and how far off the playback a subscriber's
+    /// player is. Seeks, pauses and resumes are changes rather than drift, so they don't count.
+    fn record_report(&mut self, id: SessionId, state: PlaybackState) {
+        if let Some(current) = self
+            .last_state
+            .as_ref()
+            .filter(|current| self.subscribers.contains_key(&id) && !state.changes(current))
+        {
+            self.drift
+                .insert(id, state.time - current.position_at(state.timestamp));
+        }
+        self.reports.insert(id, state);
+    }
+
+    /// Tells the host how far each subscriber is off the playback, most lagging first.
+    async fn send_stats(&self) -> anyhow::Result<()> {
+        let mut stats: Vec<SubscriberStats> = self
+            .subscribers
+            .values()
+            .map(|subscriber| SubscriberStats {
+                user: subscriber.id,
+                name: subscriber.name.clone(),
+                drift: self.drift.get(&subscriber.id).copied(),
+                latency: subscriber.latency(),
+            })
+            .collect();
+        // subscribers that haven't reported yet come last
+        stats.sort_by(|a, b| {
+            let drift = |stats: &SubscriberStats| stats.drift.unwrap_or(f32::INFINITY);
+            drift(a)
+                .total_cmp(&drift(b))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        self.host
+            .send_message(SessionMsg::PlaybackStats(stats))
+            .await?;
+        Ok(())
+    }
+
+    /// Keeps what a subscriber's heartbeat says about its player for roll calls, tells the
+    /// subscriber to lower its quality if it struggles to keep up, and seeks it back to the
+    /// playback if it got too far off. Heartbeats from anyone else are ignored.
+    async fn record_telemetry(
+        &mut self,
+        id: SessionId,
+        telemetry: Telemetry,
+    ) -> anyhow::Result<()> {
+        let Some(subscriber) = self.subscribers.get(&id).cloned() else {
+            return Ok(());
+        };
+        let telemetry = Telemetry {
+            state: telemetry.state.normalize_offset(subscriber.time_offset()),
+            ..telemetry
+        };
+        self.record_report(id, telemetry.state.clone());
+        let previous = self.telemetry.remove(&id);
+        let dropped_since = telemetry.dropped_frames.map(|dropped| {
+            let before = previous
+                .as_ref()
+                .and_then(|previous| previous.telemetry.dropped_frames)
+                .unwrap_or(dropped);
+            // the count starts over with every source
+            dropped.saturating_sub(before)
+        });
+        let hint = if telemetry.state.playing
+            && telemetry
+                .buffered
+                .is_some_and(|buffered| buffered < Self::LOW_BUFFER)
+        {
+            Some(QualityHint::LowBuffer)
+        } else if dropped_since.is_some_and(|dropped| dropped > Self::DROPPED_FRAMES_THRESHOLD) {
+            Some(QualityHint::DroppingFrames)
+        } else {
+            None
+        };
+        let (mut hinted_at, mut corrected_at) = previous.map_or((None, None), |previous| {
+            (previous.hinted_at, previous.corrected_at)
+        });
+        if let Some(hint) = hint.filter(|_| {
+            hinted_at.is_none_or(|hinted_at| hinted_at.elapsed() >= Self::QUALITY_HINT_INTERVAL)
+        }) {
+            log::debug!("Suggesting lower quality to user {id}: {hint:?}");
+            hinted_at = Some(Instant::now());
+            subscriber
+                .send_message(SessionMsg::PlaybackQualityHint(hint))
+                .await?;
+        }
+        if self.is_off(id, &telemetry.state)
+            && corrected_at
+                .is_none_or(|corrected_at| corrected_at.elapsed() >= Self::SEEK_CORRECTION_INTERVAL)
+        {
+            if let Some(state) = self.catch_up_state() {
+                log::debug!(
+                    "User {id} is off the playback; seeking them to {}",
+                    state.time
+                );
+                corrected_at = Some(Instant::now());
+                send_sync_msg(&subscriber, &state).await?;
+            }
+        }
+        self.telemetry.insert(
+            id,
+            ReceivedTelemetry {
+                telemetry,
+                received_at: Instant::now(),
+                hinted_at,
+                corrected_at,
+            },
+        );
+        Ok(())
+    }
+
+    /// Whether a subscriber's player is further off the playback than a corrective seek would be
+    /// moved to land on a seek hint. The leader sets the pace, so it is never off.
+    fn is_off(&self, id: SessionId, reported: &PlaybackState) -> bool {
+        self.leader != Some(id)
+            && self.last_state.as_ref().is_some_and(|current| {
+                (reported.time - current.position_at(reported.timestamp)).abs()
+                    > SeekHints::SNAP_TOLERANCE
+            })
+    }
+
+    /// Decides whether a sync in authoritative mode may change the playback. If it conflicts with
+    /// a change someone else just made, the sender is corrected instead.
+    async fn accept_change(
+        &mut self,
+        id: SessionId,
+        state: &PlaybackState,
+    ) -> anyhow::Result<bool> {
+        let now = timestamp();
+        self.correction_at =
+            Some(Instant::now() + Duration::from_secs(self.config.correction_interval));
+        let Some(current) = &self.last_state else {
+            self.last_change = Some((id, now));
+            return Ok(true);
+        };
+        if !state.changes(current) {
+            return Ok(true);
+        }
+        let conflicts = self.last_change.is_some_and(|(changed_by, changed_at)| {
+            changed_by != id && now.saturating_sub(changed_at) < self.config.conflict_window
+        });
+        if !conflicts {
+            self.last_change = Some((id, now));
+            return Ok(true);
+        }
+        log::debug!("Rejecting playback change by {id} that conflicts with a recent one");
+        let authoritative = self.authoritative_state(now);
+        if id == self.host.id {
+            send_sync_msg(&self.host, &authoritative).await?;
+        } else if let Some(sender) = self.subscribers.get(&id) {
+            send_sync_msg(sender, &authoritative).await?;
+        }
+        Ok(false)
+    }
+
+    /// The state of the server's playback clock at the given time.
+    fn authoritative_state(&self, now: u64) -> PlaybackState {
+        match &self.last_state {
+            Some(state) => PlaybackState {
+                timestamp: now,
+                time: state.position_at(now),
+                ..state.clone()
+            },
+            None => PlaybackState {
+                timestamp: now,
+                playing: false,
+                time: 0.0,
+                duration: None,
+            },
+        }
+    }
+
+    /// Starts or ends an intermission. Starting one pauses everyone where they are.
+    pub async fn set_intermission(&mut self, active: bool) -> anyhow::Result<()> {
+        self.intermission = active;
+        let Some(current) = &self.last_state else {
+            return Ok(());
+        };
+        if !active || !self.running || !current.playing {
+            return Ok(());
+        }
+        let now = timestamp();
+        let paused = PlaybackState {
+            playing: false,
+            ..self.authoritative_state(now)
+        };
+        self.last_state = Some(paused.clone());
+        self.last_change = Some((self.host.id, now));
+        self.broadcast_state(&paused).await
+    }
+
+    /// Sends everyone the state of the server's playback clock, so that drift doesn't add up.
+    pub async fn send_correction(&mut self) -> anyhow::Result<()> {
+        if !self.running || self.last_state.is_none() {
+            self.correction_at = None;
+            return Ok(());
+        }
+        self.correction_at =
+            Some(Instant::now() + Duration::from_secs(self.config.correction_interval));
+        let state = self.authoritative_state(timestamp());
+        self.broadcast_state(&state).await
+    }
+
+    /// Sends a state that didn't come from anyone's player to the host and all subscribers.
+    async fn broadcast_state(&mut self, state: &PlaybackState) -> anyhow::Result<()> {
+        if !send_sync_msg(&self.host, state).await? {
+            return self.stop(StopReason::HostError).await;
+        }
+        let mut errored_subscribers = Vec::new();
+        let mut yield_point = YieldPoint::default();
+        for target in self.subscribers.values() {
+            yield_point.tick().await;
+            if !send_sync_msg(target, state).await? {
+                errored_subscribers.push(target.id);
+            }
+        }
+        for id in errored_subscribers {
+            self.disconnect(id, DisconnectReason::SubscriberError)
+                .await?;
+        }
         Ok(())
     }
 }
@@ -297,3 +1792,1095 @@ async fn send_sync_msg(session: &SessionHandle, state: &PlaybackState) -> anyhow
         ))
         .await
 }
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        errors::{error_code, ErrorCode},
+        testing::FakeSession,
+    };
+
+    fn state(playing: bool) -> PlaybackState {
+        PlaybackState {
+            timestamp: 1_700_000_000_000,
+            playing,
+            time: 60.0,
+            duration: Some(90.0),
+        }
+    }
+
+    fn source() -> PlaybackSource {
+        PlaybackSource {
+            title: "Big Buck Bunny".to_string(),
+            page_href: "https://example.com/bunny".to_string(),
+            frame_href: "https://example.com/bunny".to_string(),
+            element_query: "video".to_string(),
+        }
+    }
+
+    fn user(id: u128) -> SessionId {
+        SessionId::from(Uuid::from_u128(id))
+    }
+
+    fn synced_timestamps(session: &FakeSession) -> Vec<u64> {
+        session
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackSync(state) => Some(state.timestamp),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn should_fan_out_host_sync_to_subscribers() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(1_000);
+        let bob = FakeSession::new(-500);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback.connect(bob.handle(3, "bob")).await.unwrap();
+        host.take_messages();
+
+        // when
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(true)))
+            .await
+            .unwrap();
+
+        // then
+        let timestamp = state(true).timestamp;
+        assert_eq!(synced_timestamps(&alice), [timestamp + 1_000]);
+        assert_eq!(synced_timestamps(&bob), [timestamp - 500]);
+        assert!(host.take_messages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_not_relay_syncs_within_tolerance() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(true)))
+            .await
+            .unwrap();
+        alice.take_messages();
+
+        // when
+        let jittery = PlaybackState {
+            timestamp: state(true).timestamp + 2_000,
+            time: 62.3,
+            ..state(true)
+        };
+        let drifted = PlaybackState {
+            timestamp: state(true).timestamp + 4_000,
+            time: 65.0,
+            ..state(true)
+        };
+        for state in [jittery, drifted] {
+            playback
+                .handle_request(user(1), PlaybackRequest::Sync(state))
+                .await
+                .unwrap();
+        }
+
+        // then
+        assert_eq!(synced_timestamps(&alice), [state(true).timestamp + 4_000]);
+    }
+
+    #[tokio::test]
+    async fn should_reject_implausible_syncs() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        alice.take_messages();
+
+        // when
+        let mut results = Vec::new();
+        for state in [
+            PlaybackState {
+                time: f32::NAN,
+                ..state(true)
+            },
+            PlaybackState {
+                time: 300.0,
+                ..state(true)
+            },
+            PlaybackState {
+                timestamp: timestamp() + 60_000,
+                ..state(true)
+            },
+        ] {
+            results.push(
+                playback
+                    .handle_request(user(1), PlaybackRequest::Sync(state))
+                    .await,
+            );
+        }
+
+        // then
+        for result in results {
+            assert_eq!(error_code(&result.unwrap_err()), ErrorCode::InvalidRequest);
+        }
+        assert!(alice.take_messages().is_empty());
+        assert_eq!(playback.last_state, None);
+    }
+
+    #[tokio::test]
+    async fn should_fast_forward_late_joiners() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(1_000);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        let synced_at = timestamp() - 10_000;
+        playback
+            .handle_request(
+                user(1),
+                PlaybackRequest::Sync(PlaybackState {
+                    timestamp: synced_at,
+                    ..state(true)
+                }),
+            )
+            .await
+            .unwrap();
+
+        // when
+        let connected_at = timestamp();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+
+        // then
+        let synced: Vec<_> = alice
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackSync(state) => Some(state),
+                _ => None,
+            })
+            .collect();
+        let [state] = &synced[..] else {
+            panic!("Expected exactly one sync, got {synced:?}");
+        };
+        assert!(state.playing);
+        assert!((state.timestamp - 1_000).abs_diff(connected_at) < 1_000);
+        assert!((state.time - 70.0).abs() < 1.0);
+    }
+
+    #[tokio::test]
+    async fn should_pause_and_hold_subscribers_during_an_intermission() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(true)))
+            .await
+            .unwrap();
+        alice.take_messages();
+
+        // when
+        playback.set_intermission(true).await.unwrap();
+        let resumed_by_alice = playback
+            .handle_request(user(2), PlaybackRequest::Sync(state(true)))
+            .await;
+
+        // then
+        let paused = alice.take_messages().into_iter().any(|msg| {
+            matches!(
+                msg,
+                SessionMsg::PlaybackSync(PlaybackState { playing: false, .. })
+            )
+        });
+        assert!(paused);
+        assert_eq!(
+            error_code(&resumed_by_alice.unwrap_err()),
+            ErrorCode::NotAuthorized
+        );
+        assert!(!playback.last_state.as_ref().unwrap().playing);
+    }
+
+    #[tokio::test]
+    async fn should_disconnect_unreachable_subscribers() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let bob = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback.connect(bob.handle(3, "bob")).await.unwrap();
+        bob.disconnect();
+
+        // when
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(true)))
+            .await
+            .unwrap();
+
+        // then
+        assert!(playback.subscribers.contains_key(&user(2)));
+        assert!(!playback.subscribers.contains_key(&user(3)));
+        assert_eq!(synced_timestamps(&alice).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_track_positions_reported_by_each_user() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let bob = FakeSession::new(0);
+        let config = PlaybackConfig {
+            sync_mode: SyncMode::Authoritative,
+            ..PlaybackConfig::default()
+        };
+        let mut playback = Playback::new(host.handle(1, "host"), config);
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback.connect(bob.handle(3, "bob")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(false)))
+            .await
+            .unwrap();
+
+        // when
+        let behind = PlaybackState {
+            timestamp: state(false).timestamp + 1_000,
+            time: 10.0,
+            ..state(false)
+        };
+        playback
+            .handle_request(user(2), PlaybackRequest::Sync(behind))
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(playback.watch_position(user(2)).unwrap().state.time, 10.0);
+        assert_eq!(playback.watch_position(user(3)).unwrap().state.time, 60.0);
+        assert!(playback.watch_position(user(4)).is_none());
+    }
+
+    #[tokio::test]
+    async fn should_tell_only_the_host_how_far_subscribers_drift() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::with_latency(0, 80);
+        let bob = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback.connect(bob.handle(3, "bob")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(false)))
+            .await
+            .unwrap();
+        let behind = PlaybackState {
+            time: 59.5,
+            ..state(false)
+        };
+        playback
+            .handle_request(user(2), PlaybackRequest::Sync(behind))
+            .await
+            .unwrap();
+        host.take_messages();
+
+        // when
+        let by_subscriber = playback
+            .handle_request(user(2), PlaybackRequest::Stats)
+            .await;
+        playback
+            .handle_request(user(1), PlaybackRequest::Stats)
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(
+            error_code(&by_subscriber.unwrap_err()),
+            ErrorCode::NotAuthorized
+        );
+        let stats = host
+            .take_messages()
+            .into_iter()
+            .find_map(|msg| match msg {
+                SessionMsg::PlaybackStats(stats) => Some(stats),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            stats
+                .iter()
+                .map(|stats| (stats.name.as_str(), stats.drift, stats.latency))
+                .collect::<Vec<_>>(),
+            [("alice", Some(-0.5), 80), ("bob", None, 0)]
+        );
+    }
+
+    fn roll_call_reports(session: &FakeSession) -> Vec<RollCallReport> {
+        session
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackRollCallReport(report) => Some(report),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn should_report_roll_call_once_everyone_answered() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(1_000);
+        let bob = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback.connect(bob.handle(3, "bob")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(false)))
+            .await
+            .unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::RollCall(7))
+            .await
+            .unwrap();
+        host.take_messages();
+
+        // when
+        let alice_state = PlaybackState {
+            timestamp: state(false).timestamp + 1_000,
+            time: 58.0,
+            ..state(false)
+        };
+        playback
+            .handle_request(user(2), PlaybackRequest::RollCallReply(7, alice_state))
+            .await
+            .unwrap();
+        let early = roll_call_reports(&host);
+        playback
+            .handle_request(user(3), PlaybackRequest::RollCallReply(7, state(false)))
+            .await
+            .unwrap();
+
+        // then
+        assert!(early.is_empty());
+        let report = roll_call_reports(&host).pop().unwrap();
+        assert_eq!(report.roll_call_id, 7);
+        assert_eq!(
+            report
+                .entries
+                .iter()
+                .map(|entry| (entry.name.as_str(), entry.drift))
+                .collect::<Vec<_>>(),
+            [("alice", Some(-2.0)), ("bob", Some(0.0))]
+        );
+        assert_eq!(
+            report.entries[0].state.as_ref().unwrap().timestamp,
+            state(false).timestamp
+        );
+        assert!(playback.roll_call_deadline().is_none());
+    }
+
+    #[tokio::test]
+    async fn should_report_missing_answers_after_the_deadline() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::RollCall(1))
+            .await
+            .unwrap();
+        host.take_messages();
+
+        // when
+        playback.finish_roll_call().await.unwrap();
+
+        // then
+        assert!(alice
+            .take_messages()
+            .iter()
+            .any(|msg| matches!(msg, SessionMsg::PlaybackRollCall(1))));
+        let report = roll_call_reports(&host).pop().unwrap();
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!(report.entries[0].state, None);
+        assert_eq!(report.entries[0].drift, None);
+    }
+
+    #[tokio::test]
+    async fn should_answer_roll_calls_from_recent_heartbeats() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(false)))
+            .await
+            .unwrap();
+        playback
+            .handle_request(
+                user(2),
+                PlaybackRequest::Telemetry(Telemetry {
+                    state: state(false),
+                    buffered: Some(20.0),
+                    dropped_frames: Some(4),
+                }),
+            )
+            .await
+            .unwrap();
+        alice.take_messages();
+        host.take_messages();
+
+        // when
+        playback
+            .handle_request(user(1), PlaybackRequest::RollCall(3))
+            .await
+            .unwrap();
+
+        // then
+        assert!(alice.take_messages().is_empty());
+        let report = roll_call_reports(&host).pop().unwrap();
+        assert_eq!(report.entries[0].drift, Some(0.0));
+        assert_eq!(report.entries[0].buffered, Some(20.0));
+        assert_eq!(report.entries[0].dropped_frames, Some(4));
+    }
+
+    #[tokio::test]
+    async fn should_hint_struggling_subscribers_to_lower_their_quality() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        alice.take_messages();
+        let heartbeat = |buffered, dropped_frames| {
+            PlaybackRequest::Telemetry(Telemetry {
+                state: state(true),
+                buffered: Some(buffered),
+                dropped_frames: Some(dropped_frames),
+            })
+        };
+
+        // when
+        for request in [
+            heartbeat(10.0, 0),
+            heartbeat(10.0, 100),
+            heartbeat(0.5, 100),
+        ] {
+            playback.handle_request(user(2), request).await.unwrap();
+        }
+
+        // then
+        let hints: Vec<QualityHint> = alice
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackQualityHint(hint) => Some(hint),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hints, [QualityHint::DroppingFrames]);
+    }
+
+    #[tokio::test]
+    async fn should_seek_subscribers_that_fall_behind_to_the_nearest_hint() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback
+            .handle_request(
+                user(1),
+                PlaybackRequest::Sync(PlaybackState {
+                    timestamp: timestamp() - 10_000,
+                    ..state(true)
+                }),
+            )
+            .await
+            .unwrap();
+        playback
+            .handle_request(
+                user(1),
+                PlaybackRequest::SeekHints(SeekHints::new(vec![71.0])),
+            )
+            .await
+            .unwrap();
+        alice.take_messages();
+        let heartbeat = |time| {
+            PlaybackRequest::Telemetry(Telemetry {
+                state: PlaybackState {
+                    timestamp: timestamp(),
+                    playing: true,
+                    time,
+                    duration: Some(90.0),
+                },
+                buffered: None,
+                dropped_frames: None,
+            })
+        };
+
+        // when
+        for request in [heartbeat(70.5), heartbeat(50.0), heartbeat(51.0)] {
+            playback.handle_request(user(2), request).await.unwrap();
+        }
+
+        // then
+        let synced: Vec<f32> = alice
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackSync(state) => Some(state.time),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(synced, [71.0]);
+    }
+
+    fn leaders(session: &FakeSession) -> Vec<Option<SessionId>> {
+        session
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackLeader(leader) => Some(leader),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn should_elect_the_subscriber_with_the_steadiest_latency() {
+        // given
+        let host = FakeSession::with_latency(0, 400);
+        let alice = FakeSession::with_latency(0, 80);
+        let bob = FakeSession::with_latency(0, 120);
+        alice.set_jitter(40);
+        bob.set_jitter(5);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback.connect(bob.handle(3, "bob")).await.unwrap();
+
+        // when
+        playback
+            .handle_request(user(1), PlaybackRequest::DelegateTiming(true))
+            .await
+            .unwrap();
+        let elected = leaders(&host);
+        bob.set_jitter(50);
+        playback
+            .handle_request(user(2), PlaybackRequest::Sync(state(true)))
+            .await
+            .unwrap();
+        let within_margin = leaders(&host);
+        bob.set_jitter(90);
+        playback
+            .handle_request(user(2), PlaybackRequest::Sync(state(true)))
+            .await
+            .unwrap();
+        let degraded = leaders(&host);
+        playback
+            .handle_request(user(1), PlaybackRequest::DelegateTiming(false))
+            .await
+            .unwrap();
+        let taken_back = leaders(&host);
+
+        // then
+        assert_eq!(elected, vec![Some(user(3))]);
+        assert!(within_margin.is_empty());
+        assert_eq!(degraded, vec![Some(user(2))]);
+        assert_eq!(taken_back, vec![None]);
+        assert_eq!(leaders(&alice), vec![Some(user(3)), Some(user(2)), None]);
+    }
+
+    #[tokio::test]
+    async fn should_only_follow_the_leaders_confirmations() {
+        // given
+        let host = FakeSession::with_latency(0, 400);
+        let alice = FakeSession::with_latency(0, 80);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::DelegateTiming(true))
+            .await
+            .unwrap();
+        playback
+            .handle_request(user(2), PlaybackRequest::Sync(state(true)))
+            .await
+            .unwrap();
+        host.take_messages();
+        alice.take_messages();
+
+        // when
+        let confirmation = PlaybackState {
+            timestamp: state(true).timestamp + 1000,
+            time: 61.0,
+            ..state(true)
+        };
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(confirmation.clone()))
+            .await
+            .unwrap();
+        let after_host_confirmation = synced_timestamps(&alice);
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(false)))
+            .await
+            .unwrap();
+        let after_host_pause = synced_timestamps(&alice);
+        playback
+            .handle_request(user(2), PlaybackRequest::Sync(confirmation))
+            .await
+            .unwrap();
+
+        // then
+        assert!(after_host_confirmation.is_empty());
+        assert_eq!(after_host_pause, vec![state(false).timestamp]);
+        assert_eq!(synced_timestamps(&host), vec![state(true).timestamp + 1000]);
+    }
+
+    fn scheduled_starts(session: &FakeSession) -> Vec<StartAt> {
+        session
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackStartAt(start_at) => Some(start_at),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn should_schedule_start_for_everyone_at_the_same_moment() {
+        // given
+        let host = FakeSession::with_latency(0, 100);
+        let alice = FakeSession::with_latency(1_000, 40);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        host.take_messages();
+        alice.take_messages();
+        let before = timestamp();
+
+        // when
+        playback.start(Some(titled("other"))).await.unwrap();
+
+        // then
+        let host_start = scheduled_starts(&host)[0];
+        let alice_start = scheduled_starts(&alice)[0];
+        assert_eq!(alice_start.timestamp, host_start.timestamp + 1_000);
+        assert!(host_start.timestamp >= before + 300 + 50);
+        assert!(alice_start.delay > host_start.delay);
+    }
+
+    fn waiting_for(session: &FakeSession) -> Vec<WaitingFor> {
+        session
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackWaitingFor(waiting_for) => Some(waiting_for),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn should_hold_start_until_enough_subscribers_are_ready() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let bob = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.set_ready_quorum(Some(ReadyQuorum::Count(2)));
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback.connect(bob.handle(3, "bob")).await.unwrap();
+        playback.start(Some(titled("other"))).await.unwrap();
+        host.take_messages();
+        alice.take_messages();
+
+        // when
+        playback
+            .handle_request(user(2), PlaybackRequest::Ready)
+            .await
+            .unwrap();
+        let host_early = host.take_messages();
+        let alice_early = scheduled_starts(&alice);
+        playback
+            .handle_request(user(3), PlaybackRequest::Ready)
+            .await
+            .unwrap();
+
+        // then
+        assert!(alice_early.is_empty());
+        assert!(host_early.iter().any(|msg| matches!(
+            msg,
+            SessionMsg::PlaybackWaitingFor(WaitingFor { subscribers, ready: 1, needed: 2 })
+                if subscribers == &[(user(3), "bob".to_string())]
+        )));
+        assert_eq!(scheduled_starts(&alice).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_start_without_quorum_when_the_host_insists() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.set_ready_quorum(Some(ReadyQuorum::Percent(100)));
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        host.take_messages();
+
+        // when
+        playback.start(Some(source())).await.unwrap();
+
+        // then
+        let waiting_for = waiting_for(&host).pop().unwrap();
+        assert!(waiting_for.subscribers.is_empty());
+        assert_eq!(scheduled_starts(&alice).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_only_accept_ready_reports_from_subscribers() {
+        // given
+        let host = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.set_ready_quorum(Some(ReadyQuorum::Count(1)));
+        playback.start(Some(source())).await.unwrap();
+
+        // when
+        let result = playback
+            .handle_request(user(1), PlaybackRequest::Ready)
+            .await;
+
+        // then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_round_ready_quorum_percentages_up() {
+        // given
+        let quorum = ReadyQuorum::Percent(50);
+
+        // when
+        let needed = [0, 1, 3, 4].map(|subscribers| quorum.needed(subscribers));
+
+        // then
+        assert_eq!(needed, [1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn should_cap_ready_quorum_counts_at_the_subscribers() {
+        // given
+        let quorum = ReadyQuorum::Count(3);
+
+        // when
+        let needed = [0, 1, 2, 5].map(|subscribers| quorum.needed(subscribers));
+
+        // then
+        assert_eq!(needed, [1, 1, 2, 3]);
+        assert_eq!(ReadyQuorum::Count(0).needed(2), 0);
+    }
+
+    #[tokio::test]
+    async fn should_release_held_starts_when_too_few_subscribers_are_left() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let bob = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.set_ready_quorum(Some(ReadyQuorum::Count(2)));
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback.connect(bob.handle(3, "bob")).await.unwrap();
+        playback.start(Some(titled("other"))).await.unwrap();
+        playback
+            .handle_request(user(2), PlaybackRequest::Ready)
+            .await
+            .unwrap();
+        alice.take_messages();
+
+        // when
+        playback
+            .handle_request(user(3), PlaybackRequest::Disconnect(DisconnectReason::User))
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(scheduled_starts(&alice).len(), 1);
+    }
+
+    fn titled(title: &str) -> PlaybackSource {
+        PlaybackSource {
+            title: title.to_string(),
+            ..source()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_play_queued_sources_in_order() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(titled("first"))).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        for request in [
+            PlaybackRequest::QueueAdd(titled("third"), None),
+            PlaybackRequest::QueueAdd(titled("second"), Some(0)),
+        ] {
+            playback.handle_request(user(1), request).await.unwrap();
+        }
+        alice.take_messages();
+
+        // when
+        playback
+            .handle_request(user(1), PlaybackRequest::Ended)
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(playback.get_info().source, Some(titled("second")));
+        assert_eq!(
+            playback
+                .queue
+                .entries()
+                .into_iter()
+                .map(|entry| entry.source)
+                .collect::<Vec<_>>(),
+            [titled("third")]
+        );
+        assert!(alice.take_messages().iter().any(|msg| matches!(
+            msg,
+            SessionMsg::PlaybackSourceChanged(Some(source)) if source.title == "second"
+        )));
+    }
+
+    #[tokio::test]
+    async fn should_finish_when_queue_is_empty() {
+        // given
+        let host = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback.start(Some(source())).await.unwrap();
+
+        // when
+        playback
+            .handle_request(user(1), PlaybackRequest::Ended)
+            .await
+            .unwrap();
+
+        // then
+        assert!(!playback.running);
+        assert!(host
+            .take_messages()
+            .iter()
+            .any(|msg| matches!(msg, SessionMsg::PlaybackStopped(StopReason::Finished))));
+    }
+
+    #[tokio::test]
+    async fn should_sync_a_stopwatch_without_source() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), PlaybackConfig::default());
+        playback
+            .handle_request(user(1), PlaybackRequest::Start(None))
+            .await
+            .unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        let clock = PlaybackState {
+            duration: None,
+            ..state(true)
+        };
+
+        // when
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(clock.clone()))
+            .await
+            .unwrap();
+
+        // then
+        let info = playback.get_info();
+        assert!(info.stopwatch);
+        assert!(info.source.is_none());
+        assert_eq!(synced_timestamps(&alice), [clock.timestamp]);
+    }
+
+    fn authoritative() -> PlaybackConfig {
+        PlaybackConfig {
+            sync_mode: SyncMode::Authoritative,
+            ..PlaybackConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_seeks_that_conflict_with_a_recent_one() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), authoritative());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(true)))
+            .await
+            .unwrap();
+        host.take_messages();
+        alice.take_messages();
+
+        // when
+        let seek = PlaybackState {
+            time: 10.0,
+            ..state(true)
+        };
+        playback
+            .handle_request(user(2), PlaybackRequest::Sync(seek))
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(playback.last_state.as_ref().unwrap().time, 60.0);
+        assert!(host.take_messages().is_empty());
+        let corrections: Vec<f32> = alice
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackSync(state) => Some(state.time),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(corrections.len(), 1);
+        assert!(corrections[0] >= 60.0);
+    }
+
+    #[tokio::test]
+    async fn should_accept_syncs_that_confirm_the_clock() {
+        // given
+        let host = FakeSession::new(0);
+        let alice = FakeSession::new(0);
+        let mut playback = Playback::new(host.handle(1, "host"), authoritative());
+        playback.start(Some(source())).await.unwrap();
+        playback.connect(alice.handle(2, "alice")).await.unwrap();
+        playback
+            .handle_request(user(1), PlaybackRequest::Sync(state(true)))
+            .await
+            .unwrap();
+        host.take_messages();
+
+        // when
+        let confirmation = PlaybackState {
+            timestamp: state(true).timestamp + 2_000,
+            time: 62.2,
+            ..state(true)
+        };
+        playback
+            .handle_request(user(2), PlaybackRequest::Sync(confirmation))
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(playback.last_state.as_ref().unwrap().time, 62.2);
+        assert_eq!(synced_timestamps(&host).len(), 1);
+    }
+
+    #[test]
+    fn should_move_queue_entries() {
+        // given
+        let mut queue = PlaybackQueue::default();
+        for title in ["a", "b", "c"] {
+            queue.add(titled(title), None).unwrap();
+        }
+        let c = queue.entries()[2].id;
+
+        // when
+        queue.move_to(c, 0).unwrap();
+
+        // then
+        let titles: Vec<String> = queue
+            .entries()
+            .into_iter()
+            .map(|entry| entry.source.title)
+            .collect();
+        assert_eq!(titles, ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn should_snap_to_nearest_seek_hint() {
+        // given
+        let hints = SeekHints::new(vec![30.0, 10.0, 12.0, f32::NAN]);
+
+        // when
+        let snapped = [10.8, 11.2, 29.0].map(|position| hints.snap(position));
+
+        // then
+        assert_eq!(snapped, [10.0, 12.0, 30.0]);
+    }
+
+    #[test]
+    fn should_not_snap_to_distant_seek_hints() {
+        // given
+        let hints = SeekHints::new(vec![10.0, 30.0]);
+
+        // when
+        let snapped = hints.snap(20.0);
+
+        // then
+        assert_eq!(snapped, 20.0);
+    }
+
+    #[test]
+    fn should_advance_position_while_playing() {
+        // given
+        let state = state(true);
+
+        // when
+        let position = state.position_at(state.timestamp + 2_500);
+
+        // then
+        assert_eq!(position, 62.5);
+    }
+
+    #[test]
+    fn should_not_advance_position_while_paused() {
+        // given
+        let state = state(false);
+
+        // when
+        let position = state.position_at(state.timestamp + 2_500);
+
+        // then
+        assert_eq!(position, 60.0);
+    }
+
+    #[test]
+    fn should_not_estimate_position_past_duration() {
+        // given
+        let state = state(true);
+
+        // when
+        let position = state.position_at(state.timestamp + 60_000);
+
+        // then
+        assert_eq!(position, 90.0);
+    }
+}
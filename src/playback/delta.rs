@@ -0,0 +1,245 @@
+//! Playback syncs as compact deltas, for clients on metered connections. Each delta only has the
+//! fields that changed since the last state the client acknowledged, so that lost messages never
+//! leave it without a base to apply the next one to.
+
+use std::collections::VecDeque;
+
+use crate::messages::dto;
+
+use super::PlaybackState;
+
+const PLAYING: u8 = 0x01;
+const HAS_TIMESTAMP: u8 = 0x02;
+const HAS_TIME: u8 = 0x04;
+const HAS_DURATION: u8 = 0x08;
+const NO_DURATION: u8 = 0x10;
+
+/// A sync for one client, relative to a state it acknowledged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncDelta {
+    pub seq: u32,
+    /// The state the delta is relative to; a full state if there is none.
+    pub base: Option<u32>,
+    pub delta: Vec<u8>,
+}
+
+impl From<SyncDelta> for dto::PlaybackSyncDeltaMsgBodyV1 {
+    fn from(value: SyncDelta) -> Self {
+        Self {
+            seq: value.seq,
+            base: value.base,
+            delta: value.delta,
+        }
+    }
+}
+
+/// The states a session sent its client as deltas, and which of them it has acknowledged.
+#[derive(Debug, Default)]
+pub struct DeltaSync {
+    next_seq: u32,
+    /// The latest state the client acknowledged, which deltas are relative to.
+    base: Option<(u32, PlaybackState)>,
+    /// States that were sent since, oldest first.
+    unacknowledged: VecDeque<(u32, PlaybackState)>,
+}
+
+impl DeltaSync {
+    /// How many states may go unacknowledged before the client counts as having lost track,
+    /// and gets full states again.
+    const MAX_UNACKNOWLEDGED: usize = 16;
+
+    pub fn encode(&mut self, state: PlaybackState) -> SyncDelta {
+        if self.unacknowledged.len() >= Self::MAX_UNACKNOWLEDGED {
+            self.base = None;
+            self.unacknowledged.clear();
+        }
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let delta = SyncDelta {
+            seq,
+            base: self.base.as_ref().map(|(seq, _)| *seq),
+            delta: encode(self.base.as_ref().map(|(_, base)| base), &state),
+        };
+        self.unacknowledged.push_back((seq, state));
+        delta
+    }
+
+    /// Makes the acknowledged state the base of later deltas. A state the session doesn't know
+    /// about means the client lost track, so it gets a full state next.
+    pub fn acknowledge(&mut self, seq: u32) {
+        if self.base.as_ref().is_some_and(|(base, _)| *base == seq) {
+            return;
+        }
+        match self
+            .unacknowledged
+            .iter()
+            .position(|(sent, _)| *sent == seq)
+        {
+            Some(index) => {
+                self.base = self.unacknowledged.drain(..=index).next_back();
+            }
+            None => {
+                log::debug!("Client acknowledged unknown sync {seq}; sending full states again");
+                self.base = None;
+                self.unacknowledged.clear();
+            }
+        }
+    }
+}
+
+fn encode(base: Option<&PlaybackState>, state: &PlaybackState) -> Vec<u8> {
+    let mut delta = vec![0];
+    let mut flags = 0;
+    if state.playing {
+        flags |= PLAYING;
+    }
+    let base_timestamp = base.map_or(0, |base| base.timestamp);
+    if base.is_none() || state.timestamp != base_timestamp {
+        flags |= HAS_TIMESTAMP;
+        let difference = state.timestamp.wrapping_sub(base_timestamp) as i64;
+        write_varint(&mut delta, zigzag(difference));
+    }
+    let base_time = base.map_or(0, |base| base.time.to_bits());
+    if base.is_none() || state.time.to_bits() != base_time {
+        flags |= HAS_TIME;
+        write_varint(&mut delta, u64::from(state.time.to_bits() ^ base_time));
+    }
+    let base_duration = base.and_then(|base| base.duration);
+    match state.duration {
+        None => flags |= NO_DURATION,
+        Some(duration) if base.is_none() || Some(duration) != base_duration => {
+            flags |= HAS_DURATION;
+            let base_bits = base_duration.map_or(0, f32::to_bits);
+            write_varint(&mut delta, u64::from(duration.to_bits() ^ base_bits));
+        }
+        Some(_) => (),
+    }
+    delta[0] = flags;
+    delta
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies a delta the way a client would.
+    fn decode(base: Option<&PlaybackState>, delta: &[u8]) -> PlaybackState {
+        let mut bytes = delta.iter().copied();
+        let flags = bytes.next().unwrap();
+        let mut read_varint = || {
+            let mut value = 0u64;
+            for shift in (0..).step_by(7) {
+                let byte = bytes.next().unwrap();
+                value |= u64::from(byte & 0x7f) << shift;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            value
+        };
+        let mut state = base.cloned().unwrap_or(PlaybackState {
+            timestamp: 0,
+            playing: false,
+            time: 0.0,
+            duration: None,
+        });
+        state.playing = flags & PLAYING != 0;
+        if flags & HAS_TIMESTAMP != 0 {
+            let zigzagged = read_varint();
+            let difference = (zigzagged >> 1) as i64 ^ -((zigzagged & 1) as i64);
+            state.timestamp = state.timestamp.wrapping_add(difference as u64);
+        }
+        if flags & HAS_TIME != 0 {
+            state.time = f32::from_bits(state.time.to_bits() ^ read_varint() as u32);
+        }
+        if flags & NO_DURATION != 0 {
+            state.duration = None;
+        } else if flags & HAS_DURATION != 0 {
+            let base_bits = state.duration.map_or(0, f32::to_bits);
+            state.duration = Some(f32::from_bits(base_bits ^ read_varint() as u32));
+        }
+        state
+    }
+
+    fn state(timestamp: u64, time: f32) -> PlaybackState {
+        PlaybackState {
+            timestamp,
+            playing: true,
+            time,
+            duration: Some(5400.0),
+        }
+    }
+
+    #[test]
+    fn should_send_only_what_changed_since_the_acknowledged_state() {
+        // given
+        let mut sync = DeltaSync::default();
+        let first = sync.encode(state(1_700_000_000_000, 60.0));
+        sync.acknowledge(first.seq);
+
+        // when
+        let second = sync.encode(state(1_700_000_001_000, 61.0));
+
+        // then
+        assert_eq!(first.base, None);
+        assert_eq!(second.base, Some(first.seq));
+        assert!(second.delta.len() < first.delta.len());
+        let base = decode(None, &first.delta);
+        assert_eq!(base, state(1_700_000_000_000, 60.0));
+        assert_eq!(
+            decode(Some(&base), &second.delta),
+            state(1_700_000_001_000, 61.0)
+        );
+    }
+
+    #[test]
+    fn should_stay_relative_to_the_acknowledged_state_when_deltas_get_lost() {
+        // given
+        let mut sync = DeltaSync::default();
+        let first = sync.encode(state(1_000, 1.0));
+        sync.acknowledge(first.seq);
+        sync.encode(state(2_000, 2.0));
+
+        // when
+        let third = sync.encode(state(3_000, 3.0));
+
+        // then
+        assert_eq!(third.base, Some(first.seq));
+        let base = decode(None, &first.delta);
+        assert_eq!(decode(Some(&base), &third.delta), state(3_000, 3.0));
+    }
+
+    #[test]
+    fn should_fall_back_to_full_states_on_gaps() {
+        // given
+        let mut sync = DeltaSync::default();
+        let first = sync.encode(state(1_000, 1.0));
+        sync.acknowledge(first.seq);
+
+        // when
+        sync.acknowledge(42);
+        let after_unknown_ack = sync.encode(state(2_000, 2.0));
+        for i in 0..DeltaSync::MAX_UNACKNOWLEDGED {
+            sync.encode(state(3_000 + i as u64, 3.0));
+        }
+        sync.acknowledge(after_unknown_ack.seq);
+        let after_silence = sync.encode(state(4_000, 4.0));
+
+        // then
+        assert_eq!(after_unknown_ack.base, None);
+        assert_eq!(after_silence.base, None);
+        assert_eq!(decode(None, &after_silence.delta), state(4_000, 4.0));
+    }
+}
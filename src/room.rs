@@ -1,12 +1,53 @@
-use std::{collections::HashMap, fmt};
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    error::Error,
+    fmt, mem,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::{anyhow, Context};
+use futures::future;
 use log::error;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use tokio::{
-    sync::{mpsc, watch},
+    runtime::Handle,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        watch,
+    },
     task::JoinHandle,
+    time::{self, Instant},
 };
 
+mod approval;
+mod chat;
+mod event_log;
+mod events;
+mod heavy;
+mod history;
+mod intermission;
+mod links;
+mod metadata;
+mod moderation;
+mod send_queue;
+mod template;
+mod vanity;
+
+pub use approval::{PendingRoom, RoomApprovalConfig};
+pub use chat::{ChatConfig, ChatPolicy};
+pub use event_log::{EventLog, EventLogConfig};
+pub use events::{RoomEvent, RoomEventRecord};
+pub use heavy::HeavyRoomConfig;
+pub use history::PlayedSource;
+pub use intermission::Intermission;
+pub use links::{LinkSharingConfig, SharedLink};
+pub use metadata::RoomMetadata;
+pub use moderation::{ModerationAction, ModerationEntry, ModerationLogPage};
+pub use template::RoomTemplate;
+pub use vanity::RoomRef;
+
 id_type!(RoomId);
 
 impl From<dto::RoomIdV1> for RoomId {
@@ -21,14 +62,385 @@ impl From<RoomId> for dto::RoomIdV1 {
     }
 }
 
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
 use crate::{
+    content_filter::{ContentFilter, ContentFilterConfig},
+    errors::{error_code, ClientError, ErrorCode},
     id_type,
+    ids::{IdFormat, IdGenerator},
     messages::dto,
-    playback::{Playback, PlaybackInfo, PlaybackRequest, StopReason},
-    session::{SessionHandle, SessionId, SessionMsg},
+    playback::{
+        Playback, PlaybackConfig, PlaybackInfo, PlaybackOverview, PlaybackRequest, PlaybackSource,
+        PlaybackState, ReadyQuorum, StopReason, WatchPosition,
+    },
+    session::{PeerProbe, Session, SessionHandle, SessionId, SessionMsg},
+    storage::{MemberSnapshot, RoomSnapshot, Storage, WatchPositionSnapshot},
+    utils::{format_elapsed, timestamp, TokenBucket, YieldPoint},
 };
+use approval::ApprovalWebhook;
+use events::{Member, RoomModel};
+use heavy::HeavyRoomRuntime;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RoomLimits {
+    pub max_users: usize,
+    pub max_pending_requests: usize,
+
+    /// Observers don't count towards `max_users`.
+    pub max_observers: usize,
+
+    /// How long a room may go without playback activity or chat before it is closed, in
+    /// minutes. With 0, rooms never expire.
+    pub idle_timeout: u64,
+}
+
+impl RoomLimits {
+    /// When a room that was last active now expires. Timeouts too long to represent never
+    /// expire, just like 0.
+    fn idle_deadline(&self) -> Option<Instant> {
+        if self.idle_timeout == 0 {
+            return None;
+        }
+        let timeout = Duration::from_secs(self.idle_timeout.saturating_mul(60));
+        Instant::now().checked_add(timeout)
+    }
+}
+
+impl Default for RoomLimits {
+    fn default() -> Self {
+        Self {
+            max_users: 64,
+            max_pending_requests: 32,
+            max_observers: 8,
+            idle_timeout: 0,
+        }
+    }
+}
+
+/// What the user who caused a broadcast receives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EchoPolicy {
+    /// The full broadcast, like everyone else.
+    #[default]
+    Full,
+
+    /// Only a slim acknowledgement, since they already know what changed.
+    Ack,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct BroadcastEchoConfig {
+    pub room_state: EchoPolicy,
+    pub chat: EchoPolicy,
+}
+
+/// What happens when a user joins a room in which someone already goes by their name. Names are
+/// compared regardless of case, and anonymous spectators don't count, since nobody sees their
+/// names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateNamePolicy {
+    /// Both users keep the same name.
+    Allow,
+
+    /// The user who came later gets a number after their name, like "alice (2)".
+    #[default]
+    Discriminate,
+
+    /// The user who came later can't join under that name.
+    Reject,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RoomConfig {
+    pub room_limits: RoomLimits,
+    pub duplicate_names: DuplicateNamePolicy,
+    pub broadcast_echo: BroadcastEchoConfig,
+    pub chat: ChatConfig,
+    pub link_sharing: LinkSharingConfig,
+    pub playback: PlaybackConfig,
+    pub heavy_rooms: HeavyRoomConfig,
+    pub content_filter: ContentFilterConfig,
+    pub room_approval: RoomApprovalConfig,
+    /// The kind of ids that new rooms get.
+    pub room_ids: IdFormat,
+    pub event_log: EventLogConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastEvent {
+    RoomState,
+    Chat,
+}
+
+impl From<BroadcastEvent> for dto::RoomBroadcastEventV1 {
+    fn from(value: BroadcastEvent) -> Self {
+        match value {
+            BroadcastEvent::RoomState => Self::State,
+            BroadcastEvent::Chat => Self::Chat,
+        }
+    }
+}
+
+/// Who caused the changes that a broadcast is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChangeOrigin {
+    User(SessionId),
+
+    /// The change wasn't caused by a single user who already knows about it, so everyone needs
+    /// the full broadcast.
+    Everyone,
+}
 
+impl ChangeOrigin {
+    fn merge(self, other: Self) -> Self {
+        if self == other {
+            self
+        } else {
+            Self::Everyone
+        }
+    }
+}
+
+/// Errors caused by a room running into one of its resource limits, or being gone.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomError {
+    Full,
+    Busy,
+    Closed(RoomCloseReason),
+}
+
+impl fmt::Display for RoomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => write!(f, "The room is full"),
+            Self::Busy => write!(f, "The room is too busy right now; try again later"),
+            Self::Closed(reason) => write!(f, "The room was closed: {reason}"),
+        }
+    }
+}
+
+impl Error for RoomError {}
+
+impl<T> From<TrySendError<T>> for RoomError {
+    fn from(_: TrySendError<T>) -> Self {
+        Self::Busy
+    }
+}
+
+/// A snapshot of the resources a room is currently using.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoomUsage {
+    pub users: usize,
+    pub pending_requests: usize,
+}
+
+/// Information about a running room that is published by the room task.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoomStatus {
+    pub usage: RoomUsage,
+    pub now_playing: Option<String>,
+    /// Whether a playback is running, which may have no title to show as `now_playing`.
+    pub playback_active: bool,
+    /// The roles of all users with an account subject, sorted by subject.
+    pub members: Vec<(String, UserRole)>,
+    /// Where users with an account subject are or were in the source, sorted by subject.
+    pub watch_positions: Vec<(String, WatchPosition)>,
+    pub metadata: RoomMetadata,
+}
+
+/// What a room that is restored from a snapshot starts out with.
+struct RestoredRoom {
+    /// When to give up on the room if nobody has rejoined.
+    abandon_at: Instant,
+    watch_positions: HashMap<String, WatchPosition>,
+    pending_approval: bool,
+    /// What the room's event log still holds from before the restart, oldest first.
+    history: Vec<RoomEventRecord>,
+}
+
+/// Decides who becomes the new host when the host leaves. Users with a role earlier in the
+/// priority list are preferred, then everyone else; ties go to whoever joined the room first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostSuccession {
+    priority: Vec<UserRole>,
+}
+
+impl Default for HostSuccession {
+    fn default() -> Self {
+        Self {
+            priority: vec![UserRole::Guest, UserRole::Spectator],
+        }
+    }
+}
+
+impl HostSuccession {
+    fn rank(&self, role: UserRole) -> usize {
+        self.priority
+            .iter()
+            .position(|r| *r == role)
+            .unwrap_or(self.priority.len())
+    }
+
+    /// Anonymous spectators never become host, since nobody else knows they are there.
+    fn choose(&self, members: &HashMap<SessionId, Member>) -> Option<SessionId> {
+        members
+            .iter()
+            .filter(|(_, member)| !member.anonymous)
+            .min_by_key(|(id, member)| (self.rank(member.role), member.joined, ***id))
+            .map(|(id, _)| *id)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoomSettings {
+    pub name: String,
+    /// Open rooms don't have a password.
+    pub password: Option<String>,
+    pub public: bool,
+    pub host_succession: HostSuccession,
+    /// The role of users who join without an invite.
+    pub default_role: UserRole,
+    pub metadata: RoomMetadata,
+    pub vanity_id: Option<String>,
+    /// Restrictions on chat on top of the server's chat config.
+    pub chat: ChatPolicy,
+    /// How many subscribers have to be ready before a playback start goes out.
+    pub ready_quorum: Option<ReadyQuorum>,
+}
+
+impl From<dto::RoomCreateMsgBodyV1> for RoomSettings {
+    fn from(value: dto::RoomCreateMsgBodyV1) -> Self {
+        Self {
+            name: value.name,
+            password: value.password,
+            public: value.public,
+            host_succession: if value.host_succession.is_empty() {
+                HostSuccession::default()
+            } else {
+                HostSuccession {
+                    priority: value.host_succession.into_iter().map(From::from).collect(),
+                }
+            },
+            default_role: value.default_role.map_or(UserRole::Guest, From::from),
+            metadata: RoomMetadata::new(value.topic, value.tags, value.image_url, value.utc_offset),
+            vanity_id: value.vanity_id,
+            chat: value.chat.map(From::from).unwrap_or_default(),
+            ready_quorum: value.ready_quorum.map(From::from),
+        }
+    }
+}
+
+/// A daily window in UTC during which the room is quiet, in minutes since midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietWindow {
+    start: u16,
+    end: u16,
+}
+
+impl QuietWindow {
+    const MINUTES_PER_DAY: u64 = 24 * 60;
+
+    fn contains(&self, timestamp: u64) -> bool {
+        let minute = ((timestamp / 60_000) % Self::MINUTES_PER_DAY) as u16;
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl TryFrom<dto::RoomQuietWindowV1> for QuietWindow {
+    type Error = anyhow::Error;
+
+    fn try_from(value: dto::RoomQuietWindowV1) -> Result<Self, Self::Error> {
+        let max = Self::MINUTES_PER_DAY as u16;
+        if value.start >= max || value.end >= max {
+            return Err(anyhow!(
+                "Quiet hours must be given in minutes since midnight"
+            ));
+        }
+        Ok(Self {
+            start: value.start,
+            end: value.end,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PublicRoomInfo {
+    pub id: RoomId,
+    pub name: String,
+    pub users: usize,
+    pub now_playing: Option<String>,
+    pub playback_active: bool,
+    pub metadata: RoomMetadata,
+    pub vanity_id: Option<String>,
+}
+
+/// What operators see of a room, whether it is public or not.
+#[derive(Debug, Clone)]
+pub struct RoomInfo {
+    pub id: RoomId,
+    pub name: String,
+    pub public: bool,
+    pub pending_approval: bool,
+    pub creator: Option<String>,
+    pub users: usize,
+    pub now_playing: Option<String>,
+}
+
+impl From<RoomInfo> for dto::AdminRoomV1 {
+    fn from(value: RoomInfo) -> Self {
+        Self {
+            id: value.id.into(),
+            name: value.name,
+            public: value.public,
+            pending_approval: value.pending_approval,
+            creator: value.creator,
+            users: value.users.try_into().unwrap_or(u32::MAX),
+            now_playing: value.now_playing,
+        }
+    }
+}
+
+impl dto::AdminStatsAckMsgBodyV1 {
+    /// Sums up the given rooms for operators, along with how many sessions are running.
+    pub fn new(sessions: usize, rooms: &[RoomInfo]) -> Self {
+        let count = |n: usize| n.try_into().unwrap_or(u32::MAX);
+        Self {
+            sessions: count(sessions),
+            rooms: count(rooms.len()),
+            pending_rooms: count(rooms.iter().filter(|room| room.pending_approval).count()),
+            room_users: count(rooms.iter().map(|room| room.users).sum()),
+        }
+    }
+}
+
+impl From<PublicRoomInfo> for dto::RoomListingV1 {
+    fn from(value: PublicRoomInfo) -> Self {
+        Self {
+            id: value.id.into(),
+            name: value.name,
+            users: value.users.try_into().unwrap_or(u32::MAX),
+            playback_active: value.playback_active,
+            topic: value.metadata.topic,
+            tags: value.metadata.tags,
+            image_url: value.metadata.image_url,
+            vanity_id: value.vanity_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum UserRole {
     Host,
     Guest,
@@ -74,6 +486,8 @@ impl From<UserRole> for dto::RoomUserRoleV1 {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UserPermissions {
     pub can_host: bool,
+    /// Whether the user may connect to synced playback, which hosting doesn't depend on.
+    pub can_watch: bool,
     pub can_set_roles: bool,
     pub can_kick: bool,
     pub can_close: bool,
@@ -84,18 +498,21 @@ impl From<UserRole> for UserPermissions {
         match value {
             UserRole::Host => Self {
                 can_host: true,
+                can_watch: true,
                 can_set_roles: true,
                 can_kick: true,
                 can_close: true,
             },
             UserRole::Guest => Self {
                 can_host: true,
+                can_watch: true,
                 can_set_roles: false,
                 can_kick: false,
                 can_close: false,
             },
             UserRole::Spectator => Self {
                 can_host: false,
+                can_watch: true,
                 can_set_roles: false,
                 can_kick: false,
                 can_close: false,
@@ -109,6 +526,7 @@ impl From<UserPermissions> for dto::RoomUserPermissionsV1 {
         Self {
             can_close: value.can_close,
             can_host: value.can_host,
+            can_watch: value.can_watch,
             can_set_roles: value.can_set_roles,
             can_kick: value.can_kick,
         }
@@ -121,9 +539,17 @@ impl Default for UserPermissions {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RoomCloseReason {
     ClosedByHost,
+    Empty,
+    /// Nobody did anything in the room for too long.
+    Expired,
+    /// A moderator turned the room down while it was waiting for approval.
+    Denied,
+    /// A server operator closed the room through the admin messages.
+    ClosedByOperator,
     ServerError,
 }
 
@@ -131,74 +557,348 @@ impl fmt::Display for RoomCloseReason {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::ClosedByHost => write!(f, "Closed by host"),
+            Self::Empty => write!(f, "All users left"),
+            Self::Expired => write!(f, "Idle for too long"),
+            Self::Denied => write!(f, "Denied by a moderator"),
+            Self::ClosedByOperator => write!(f, "Closed by an operator"),
             Self::ServerError => write!(f, "Internal server error"),
         }
     }
 }
 
+/// Why a user is no longer in a room, so that the others can tell a kick from a network drop.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaveReason {
+    #[default]
+    Left,
+    Kicked,
+    /// The client stopped answering pings and didn't come back in time.
+    Timeout,
+    /// The connection dropped and the session wasn't resumed in time.
+    ConnectionLost,
+}
+
+impl From<LeaveReason> for dto::RoomUserLeftReasonV1 {
+    fn from(value: LeaveReason) -> Self {
+        match value {
+            LeaveReason::Left => Self::Left,
+            LeaveReason::Kicked => Self::Kicked,
+            LeaveReason::Timeout => Self::Timeout,
+            LeaveReason::ConnectionLost => Self::ConnectionLost,
+        }
+    }
+}
+
 #[derive(Debug)]
 enum RoomCmd {
-    Join(UserRole, SessionHandle),
+    /// Joins with a role, or anonymously as a spectator if the flag is set.
+    Join(UserRole, SessionHandle, bool),
+    Observe(SessionHandle),
+    /// A moderator approved the room, which was waiting for approval until now.
+    Approve,
+    /// Sets up a new room from a template, before anyone has joined.
+    ApplyTemplate(Vec<QuietWindow>, Vec<PlaybackSource>),
     Close(RoomCloseReason),
 }
 
+/// The live connection of a room member, which isn't part of the room's events.
 #[derive(Debug, Clone)]
-pub struct User {
-    pub role: UserRole,
-    pub session: SessionHandle,
-}
-
-impl User {
-    fn get_user_data(&self) -> UserData {
-        UserData {
-            id: self.session.id,
-            name: self.session.name.clone(),
-            role: self.role,
-        }
-    }
+struct Participant {
+    session: SessionHandle,
+    chat_limit: TokenBucket,
 }
 
 #[derive(Debug, Clone)]
 pub enum RoomRequest {
     GetState,
-    SetRole(SessionId, UserRole),
-    Leave(SessionId),
+    /// Sets the role of the second user; the first one is the user who requested it.
+    SetRole(SessionId, SessionId, UserRole),
+    Leave(SessionId, LeaveReason),
+    /// Kicks the second user out of the room; the first one is the user who requested it.
+    Kick(SessionId, SessionId),
+    /// Makes the second user the host, and the first one, who is the host, a guest.
+    TransferHost(SessionId, SessionId),
+    /// The user lost their connection for now, so someone else hosts until they are back.
+    StepAway(SessionId),
+    /// Changes the name of a user. The flag tells whether the new name was first used from a
+    /// different client.
+    Rename(SessionId, String, bool),
     PlaybackHost(SessionId),
     PlaybackConnect(SessionId),
     Playback(SessionId, PlaybackRequest),
+    RelayPeerProbe(SessionId, SessionId, PeerProbe),
+    SetQuietHours(SessionId, Vec<QuietWindow>),
+    UpdateMetadata(SessionId, RoomMetadata),
+    /// Replaces the password, or opens the room if there is none.
+    SetPassword(SessionId, Option<String>),
+    /// Starts an intermission, or ends the current one if there is none.
+    SetIntermission(SessionId, Option<Intermission>),
+    PlaybackInfo(SessionId),
+    PlaybackHistory(SessionId),
+    /// Asks for the moderation log entries before the given id, at most as many as given.
+    ModerationLog(SessionId, Option<u64>, Option<usize>),
+    Chat(SessionId, String),
+    ShareLink(SessionId, String),
+}
+
+impl RoomRequest {
+    /// Whether an observer may make this request, since observers shouldn't affect the room.
+    pub fn is_allowed_for_observers(&self) -> bool {
+        matches!(
+            self,
+            Self::GetState | Self::Leave(..) | Self::PlaybackInfo(..) | Self::PlaybackHistory(..)
+        )
+    }
+
+    /// Whether the request takes a user out of the room, for good or for now, which a busy room
+    /// still has to handle.
+    pub fn is_teardown(&self) -> bool {
+        matches!(self, Self::Leave(..) | Self::StepAway(..))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Invite {
+    role: UserRole,
+    expires_at: Instant,
 }
 
 #[derive(Debug)]
 struct RoomController {
     id: RoomId,
-    name: String,
-    password: String,
+    settings: RoomSettings,
+    creator: Option<String>,
+    /// Roles from before a restart, for users who haven't rejoined yet.
+    restored_roles: HashMap<String, UserRole>,
+    /// Invite tokens and what they grant. They are revoked when the password changes, since
+    /// they let people in without it.
+    invites: HashMap<String, Invite>,
+    /// Rooms waiting for approval aren't listed, and only their creator can join them.
+    pending_approval: bool,
+    limits: RoomLimits,
     command_tx: mpsc::Sender<RoomCmd>,
     request_tx: mpsc::Sender<RoomRequest>,
     result_rx: watch::Receiver<anyhow::Result<()>>,
-    join_handle: JoinHandle<()>,
+    status_rx: watch::Receiver<RoomStatus>,
+    liveness: RoomLiveness,
+    join_handle: JoinHandle<ClosedRoom>,
+    #[cfg(feature = "chaos")]
+    chaos: Option<Arc<Chaos>>,
 }
 
 impl RoomController {
+    const INVITE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    const MAX_INVITES: usize = 32;
+
     fn handle(&self, role: UserRole) -> RoomHandle {
         RoomHandle {
             id: self.id,
-            name: self.name.clone(),
+            name: self.settings.name.clone(),
             role,
+            observer: false,
             request_tx: self.request_tx.clone().downgrade(),
             result_rx: self.result_rx.clone(),
+            liveness: self.liveness.clone(),
+            #[cfg(feature = "chaos")]
+            chaos: self.chaos.clone(),
+        }
+    }
+
+    /// Lets messages from the room to the session get lost, if chaos is turned on for the room.
+    #[cfg(feature = "chaos")]
+    fn disturb(&self, session: SessionHandle) -> SessionHandle {
+        match &self.chaos {
+            Some(chaos) => chaos.wrap_session(session),
+            None => session,
         }
     }
 
-    async fn join(&mut self, role: UserRole, session: SessionHandle) -> anyhow::Result<RoomHandle> {
-        self.command_tx.send(RoomCmd::Join(role, session)).await?;
+    fn join(
+        &mut self,
+        role: UserRole,
+        session: SessionHandle,
+        anonymous: bool,
+    ) -> anyhow::Result<RoomHandle> {
+        let users = self.status_rx.borrow().usage.users;
+        if users >= self.limits.max_users {
+            return Err(RoomError::Full.into());
+        }
+        #[cfg(feature = "chaos")]
+        let session = self.disturb(session);
+        let restored_role = session
+            .subject
+            .as_ref()
+            .and_then(|subject| self.restored_roles.remove(subject));
+        let role = match restored_role {
+            // anonymous spectators can't do anything that would give them away
+            _ if anonymous => UserRole::Spectator,
+            Some(restored_role) => restored_role,
+            // the first user to come back to a restored room takes over until the host returns
+            None if users == 0 => UserRole::Host,
+            None => role,
+        };
+        // Never wait for a busy room here; its shard is locked while joining.
+        self.command_tx
+            .try_send(RoomCmd::Join(role, session, anonymous))
+            .map_err(RoomError::from)?;
         Ok(self.handle(role))
     }
 
-    async fn close(self, reason: RoomCloseReason) -> anyhow::Result<()> {
+    fn observe(&mut self, session: SessionHandle) -> anyhow::Result<RoomHandle> {
+        #[cfg(feature = "chaos")]
+        let session = self.disturb(session);
+        self.command_tx
+            .try_send(RoomCmd::Observe(session))
+            .map_err(RoomError::from)?;
+        Ok(RoomHandle {
+            observer: true,
+            ..self.handle(UserRole::Spectator)
+        })
+    }
+
+    fn create_invite(&mut self, role: UserRole) -> anyhow::Result<String> {
+        if role == UserRole::Host {
+            return Err(anyhow!("Invites can't make someone the host"));
+        }
+        let now = Instant::now();
+        self.invites.retain(|_, invite| invite.expires_at > now);
+        // invites are handed out in order, so the one that expires first is the oldest
+        if self.invites.len() >= Self::MAX_INVITES {
+            if let Some(oldest) = self
+                .invites
+                .iter()
+                .min_by_key(|(_, invite)| invite.expires_at)
+                .map(|(token, _)| token.clone())
+            {
+                self.invites.remove(&oldest);
+            }
+        }
+        let token = Uuid::new_v4().simple().to_string();
+        self.invites.insert(
+            token.clone(),
+            Invite {
+                role,
+                expires_at: now + Self::INVITE_TTL,
+            },
+        );
+        Ok(token)
+    }
+
+    /// The role an invite grants, if it is still valid.
+    fn invited_role(&self, token: &str) -> Option<UserRole> {
+        self.invites
+            .get(token)
+            .filter(|invite| invite.expires_at > Instant::now())
+            .map(|invite| invite.role)
+    }
+
+    fn pending_info(&self) -> PendingRoom {
+        PendingRoom {
+            id: self.id.to_string(),
+            name: self.settings.name.clone(),
+            creator: self.creator.clone(),
+        }
+    }
+
+    fn info(&self) -> RoomInfo {
+        let status = self.status_rx.borrow();
+        RoomInfo {
+            id: self.id,
+            name: self.settings.name.clone(),
+            public: self.settings.public,
+            pending_approval: self.pending_approval,
+            creator: self.creator.clone(),
+            users: status.usage.users,
+            now_playing: status.now_playing.clone(),
+        }
+    }
+
+    fn public_info(&self) -> PublicRoomInfo {
+        let status = self.status_rx.borrow();
+        PublicRoomInfo {
+            id: self.id,
+            name: self.settings.name.clone(),
+            users: status.usage.users,
+            now_playing: status.now_playing.clone(),
+            playback_active: status.playback_active,
+            metadata: status.metadata.clone(),
+            vanity_id: self.settings.vanity_id.clone(),
+        }
+    }
+
+    fn snapshot(&self) -> RoomSnapshot {
+        let status = self.status_rx.borrow();
+        let now = timestamp();
+        let mut members: HashMap<String, UserRole> = self.restored_roles.clone();
+        members.extend(status.members.iter().cloned());
+        RoomSnapshot {
+            id: *self.id,
+            name: self.settings.name.clone(),
+            password: self.settings.password.clone(),
+            public: self.settings.public,
+            creator: self.creator.clone(),
+            default_role: Some(self.settings.default_role.into()),
+            host_succession: self
+                .settings
+                .host_succession
+                .priority
+                .iter()
+                .map(|role| (*role).into())
+                .collect(),
+            members: members
+                .into_iter()
+                .map(|(subject, role)| MemberSnapshot {
+                    subject,
+                    role: role.into(),
+                })
+                .collect(),
+            watch_positions: status
+                .watch_positions
+                .iter()
+                .map(|(subject, position)| WatchPositionSnapshot {
+                    subject: subject.clone(),
+                    page_href: position.page_href.clone(),
+                    position: position.position_at(now),
+                })
+                .collect(),
+            pending_approval: self.pending_approval,
+            topic: status.metadata.topic.clone(),
+            tags: status.metadata.tags.clone(),
+            image_url: status.metadata.image_url.clone(),
+            utc_offset: status.metadata.utc_offset,
+            vanity_id: self.settings.vanity_id.clone(),
+            chat: Some(self.settings.chat.into()),
+            ready_quorum: self.settings.ready_quorum.map(From::from),
+        }
+    }
+
+    async fn close(self, reason: RoomCloseReason) -> anyhow::Result<ClosedRoom> {
         self.command_tx.send(RoomCmd::Close(reason)).await?;
-        self.join_handle.await?;
-        Ok(())
+        Ok(self.join_handle.await?)
+    }
+}
+
+/// Lets room handles tell whether their room is still open, and why it closed if not. Each
+/// update bumps the version of the underlying watch channel, so handles notice a closed room
+/// before they try to talk to it.
+#[derive(Debug, Clone)]
+struct RoomLiveness(watch::Receiver<Option<RoomCloseReason>>);
+
+impl RoomLiveness {
+    fn close_reason(&self) -> Option<RoomCloseReason> {
+        if let Some(reason) = *self.0.borrow() {
+            return Some(reason);
+        }
+        // the room is gone without having closed properly, so it must have crashed
+        self.0
+            .has_changed()
+            .is_err()
+            .then_some(RoomCloseReason::ServerError)
+    }
+
+    fn closed(&self) -> RoomError {
+        RoomError::Closed(self.close_reason().unwrap_or(RoomCloseReason::ServerError))
     }
 }
 
@@ -207,23 +907,50 @@ pub struct RoomHandle {
     pub id: RoomId,
     pub name: String,
     pub role: UserRole,
+    /// Observers are invisible to everyone else in the room.
+    pub observer: bool,
     request_tx: mpsc::WeakSender<RoomRequest>,
     result_rx: watch::Receiver<anyhow::Result<()>>,
+    liveness: RoomLiveness,
+    #[cfg(feature = "chaos")]
+    chaos: Option<Arc<Chaos>>,
 }
 
 impl RoomHandle {
-    pub async fn send_request(&mut self, req: RoomRequest) -> anyhow::Result<bool> {
+    /// Fails with [`RoomError::Closed`] if the room has closed, so that callers can tell that
+    /// apart from the request itself failing.
+    pub async fn send_request(&mut self, req: RoomRequest) -> anyhow::Result<()> {
+        if let Some(reason) = self.liveness.close_reason() {
+            return Err(RoomError::Closed(reason).into());
+        }
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            chaos.disturb_request().await?;
+        }
         let Some(request_tx) = self.request_tx.upgrade() else {
-            return Ok(false);
+            return Err(self.liveness.closed().into());
         };
-        request_tx.send(req).await?;
-        self.result_rx.changed().await?;
+        if req.is_teardown() {
+            // turning these away would leave the user in the room as a ghost
+            if request_tx.send(req).await.is_err() {
+                return Err(self.liveness.closed().into());
+            }
+        } else {
+            match request_tx.try_send(req) {
+                Ok(()) => (),
+                Err(TrySendError::Full(..)) => return Err(RoomError::Busy.into()),
+                Err(TrySendError::Closed(..)) => return Err(self.liveness.closed().into()),
+            }
+        }
+        if self.result_rx.changed().await.is_err() {
+            return Err(self.liveness.closed().into());
+        }
         if let Err(err) = &*self.result_rx.borrow_and_update() {
             // anyhow's errors aren't clonable... not ideal, but works
-            return Err(anyhow!("{err:?}"));
+            return Err(ClientError::new(error_code(err), format!("{err:#}")).into());
         }
 
-        Ok(true)
+        Ok(())
     }
 }
 
@@ -244,13 +971,36 @@ impl From<UserData> for dto::RoomUserV1 {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ChatMessage {
+    pub sender: UserData,
+    pub text: String,
+    pub sent_at: u64,
+}
+
+impl From<ChatMessage> for dto::RoomChatMessageMsgBodyV1 {
+    fn from(value: ChatMessage) -> Self {
+        Self {
+            user_id: value.sender.id.into(),
+            name: value.sender.name,
+            text: value.text,
+            sent_at: value.sent_at,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RoomState {
     pub id: RoomId,
     pub name: String,
-    pub password: String,
+    pub password: Option<String>,
     pub playback_info: Option<PlaybackInfo>,
     pub users: Vec<UserData>,
+    pub spectator_count: usize,
+    pub recent_links: Vec<SharedLink>,
+    pub intermission: Option<Intermission>,
+    pub metadata: RoomMetadata,
+    pub vanity_id: Option<String>,
 }
 
 impl From<RoomState> for dto::RoomStateMsgBodyV1 {
@@ -258,9 +1008,20 @@ impl From<RoomState> for dto::RoomStateMsgBodyV1 {
         Self {
             id: value.id.into(),
             name: value.name,
-            password: value.password,
+            open: value.password.is_none(),
+            password: value.password.unwrap_or_default(),
             users: value.users.into_iter().map(From::from).collect(),
             playback_info: value.playback_info.map(From::from),
+            recent_links: value.recent_links.into_iter().map(From::from).collect(),
+            intermission: value
+                .intermission
+                .map(|intermission| intermission.into_dto(&value.metadata)),
+            spectator_count: value.spectator_count.try_into().unwrap_or(u32::MAX),
+            topic: value.metadata.topic,
+            tags: value.metadata.tags,
+            image_url: value.metadata.image_url,
+            utc_offset: value.metadata.utc_offset,
+            vanity_id: value.vanity_id,
         }
     }
 }
@@ -268,92 +1029,313 @@ impl From<RoomState> for dto::RoomStateMsgBodyV1 {
 struct Room {
     id: RoomId,
     running: bool,
-    name: String,
-    password: String,
-    users: HashMap<SessionId, User>,
+    close_reason: RoomCloseReason,
+    settings: RoomSettings,
+    model: RoomModel,
+    participants: HashMap<SessionId, Participant>,
+    observers: HashMap<SessionId, SessionHandle>,
+    /// Events that haven't been handed to the storage yet.
+    events: Vec<RoomEventRecord>,
+    next_seq: u64,
+    /// How many broadcasts went out, which numbers them for the clients.
+    broadcast_seq: u64,
+    /// Where events are persisted, if anywhere.
+    event_log: Option<EventLog>,
     playback: Option<Playback>,
+    state_broadcast_at: Option<Instant>,
+    state_change_origin: Option<ChangeOrigin>,
+    /// When to give up on a room that was restored but that nobody has rejoined.
+    abandon_at: Option<Instant>,
+    /// When to close the room if nobody plays anything or chats until then.
+    idle_at: Option<Instant>,
+    pending_approval: bool,
+    /// Where users with an account subject are or were in the source, so that they can pick up
+    /// where they left off when they rejoin. Only the latest source is kept.
+    watch_positions: HashMap<String, WatchPosition>,
+    /// Sources from the room's template, which go into the queue of the first playback.
+    planned_queue: Vec<PlaybackSource>,
+    /// Keeps playback paused until the host ends it.
+    intermission: Option<Intermission>,
+    /// Where the room moves once it has enough users, if it hasn't moved yet.
+    heavy_runtime: Option<Handle>,
+    heavy_min_users: usize,
+    limits: RoomLimits,
+    broadcast_echo: BroadcastEchoConfig,
+    chat: ChatConfig,
+    duplicate_names: DuplicateNamePolicy,
+    link_sharing: LinkSharingConfig,
+    playback_config: PlaybackConfig,
+    content_filter: Arc<ContentFilter>,
     command_rx: mpsc::Receiver<RoomCmd>,
     request_rx: mpsc::Receiver<RoomRequest>,
     result_tx: watch::Sender<anyhow::Result<()>>,
+    status_tx: watch::Sender<RoomStatus>,
+    /// Tells room handles why the room closed, once it has.
+    liveness_tx: watch::Sender<Option<RoomCloseReason>>,
 }
 
 impl Room {
+    /// State changes within this window are coalesced into a single broadcast.
+    const STATE_BROADCAST_DELAY: Duration = Duration::from_millis(50);
+
+    /// During quiet hours, join/leave chatter is batched over a much longer window.
+    const QUIET_STATE_BROADCAST_DELAY: Duration = Duration::from_secs(30);
+
     fn new(
-        name: String,
-        password: String,
+        id: RoomId,
+        settings: RoomSettings,
+        config: RoomConfig,
         command_rx: mpsc::Receiver<RoomCmd>,
         request_rx: mpsc::Receiver<RoomRequest>,
         result_tx: watch::Sender<anyhow::Result<()>>,
+        status_tx: watch::Sender<RoomStatus>,
     ) -> Self {
+        let idle_at = config.room_limits.idle_deadline();
         Self {
-            id: RoomId::new(),
+            id,
             running: true,
-            name,
-            password,
+            close_reason: RoomCloseReason::ServerError,
+            chat: config.chat.restricted_by(settings.chat),
+            settings,
+            limits: config.room_limits,
+            broadcast_echo: config.broadcast_echo,
+            duplicate_names: config.duplicate_names,
+            link_sharing: config.link_sharing,
+            playback_config: config.playback,
+            content_filter: Arc::default(),
             command_rx,
             request_rx,
             result_tx,
+            status_tx,
+            liveness_tx: watch::Sender::new(None),
             playback: None,
-            users: HashMap::new(),
+            state_broadcast_at: None,
+            state_change_origin: None,
+            abandon_at: None,
+            idle_at,
+            pending_approval: false,
+            watch_positions: HashMap::new(),
+            heavy_runtime: None,
+            planned_queue: Vec::new(),
+            intermission: None,
+            heavy_min_users: config.heavy_rooms.min_users,
+            model: RoomModel::default(),
+            participants: HashMap::new(),
+            observers: HashMap::new(),
+            events: Vec::new(),
+            next_seq: 0,
+            broadcast_seq: 0,
+            event_log: None,
+        }
+    }
+
+    /// Pushes back the expiry of the room after someone did something in it.
+    fn touch(&mut self) {
+        self.idle_at = self.limits.idle_deadline();
+    }
+
+    fn usage(&self) -> RoomUsage {
+        RoomUsage {
+            users: self.model.members.len(),
+            pending_requests: self.request_rx.len(),
+        }
+    }
+
+    fn update_status(&self) {
+        self.status_tx.send_if_modified(|status| {
+            let new_status = RoomStatus {
+                usage: self.usage(),
+                now_playing: self
+                    .playback
+                    .as_ref()
+                    .and_then(|playback| playback.get_info().source)
+                    .map(|source| source.title),
+                playback_active: self.playback.as_ref().is_some_and(Playback::is_running),
+                members: self.members(),
+                watch_positions: self.sorted_watch_positions(),
+                metadata: self.settings.metadata.clone(),
+            };
+            if new_status == *status {
+                return false;
+            }
+            if new_status.usage != status.usage {
+                log::debug!("Room {} is now using {:?}", self.id, new_status.usage);
+            }
+            *status = new_status;
+            true
+        });
+    }
+
+    fn sorted_watch_positions(&self) -> Vec<(String, WatchPosition)> {
+        let mut positions: Vec<(String, WatchPosition)> = self
+            .watch_positions
+            .iter()
+            .map(|(subject, position)| (subject.clone(), position.clone()))
+            .collect();
+        positions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        positions
+    }
+
+    /// Remembers where a user is in the current source. Once they leave, the position stops
+    /// advancing.
+    fn remember_watch_position(&mut self, session_id: SessionId, left: bool) {
+        let Some(subject) = self
+            .model
+            .members
+            .get(&session_id)
+            .and_then(|member| member.subject.clone())
+        else {
+            return;
+        };
+        if let Some(position) = self
+            .playback
+            .as_ref()
+            .and_then(|playback| playback.watch_position(session_id))
+        {
+            self.watch_positions
+                .retain(|_, other| other.page_href == position.page_href);
+            self.watch_positions.insert(subject.clone(), position);
         }
+        if left {
+            if let Some(position) = self.watch_positions.get_mut(&subject) {
+                *position = position.frozen_at(timestamp());
+            }
+        }
+    }
+
+    /// Where a rejoining user left off in the current source, if they had watched it before.
+    fn last_watch_position(&self, session: &SessionHandle) -> Option<f32> {
+        let position = self.watch_positions.get(session.subject.as_ref()?)?;
+        let source = self.playback.as_ref()?.get_info().source?;
+        (source.page_href == position.page_href).then(|| position.position_at(timestamp()))
+    }
+
+    fn members(&self) -> Vec<(String, UserRole)> {
+        let mut members: Vec<(String, UserRole)> = self
+            .model
+            .members
+            .values()
+            .filter_map(|member| Some((member.subject.clone()?, member.role)))
+            .collect();
+        members.sort_by(|(a, _), (b, _)| a.cmp(b));
+        members
     }
 
     fn get_state(&self) -> RoomState {
         RoomState {
             id: self.id,
-            name: self.name.clone(),
-            password: self.password.clone(),
+            name: self.settings.name.clone(),
+            password: self.settings.password.clone(),
             playback_info: self.playback.as_ref().map(Playback::get_info),
-            users: self.users.values().map(User::get_user_data).collect(),
+            users: self
+                .model
+                .members
+                .iter()
+                .filter(|(_, member)| !member.anonymous)
+                .filter_map(|(id, _)| self.model.user_data(*id))
+                .collect(),
+            spectator_count: self
+                .model
+                .members
+                .values()
+                .filter(|member| member.anonymous)
+                .count(),
+            recent_links: self.model.recent_links.iter().cloned().collect(),
+            intermission: self.intermission.clone(),
+            metadata: self.settings.metadata.clone(),
+            vanity_id: self.settings.vanity_id.clone(),
         }
     }
 
-    fn create(name: String, password: String) -> RoomController {
+    fn create(
+        id: RoomId,
+        settings: RoomSettings,
+        config: RoomConfig,
+        content_filter: Arc<ContentFilter>,
+        restored: Option<RestoredRoom>,
+        event_log: Option<EventLog>,
+        heavy_runtime: Option<Handle>,
+    ) -> RoomController {
+        let limits = config.room_limits.clone();
+        let pending_approval = restored.as_ref().map_or(
+            config.room_approval.required && settings.public,
+            |restored| restored.pending_approval,
+        );
         let (command_tx, command_rx) = mpsc::channel::<RoomCmd>(8);
-        let (request_tx, request_rx) = mpsc::channel::<RoomRequest>(32);
+        let (request_tx, request_rx) =
+            mpsc::channel::<RoomRequest>(limits.max_pending_requests.max(1));
         let (result_tx, result_rx) = watch::channel::<anyhow::Result<()>>(Ok(()));
+        let (status_tx, status_rx) = watch::channel(RoomStatus::default());
 
         let mut room = Room::new(
-            name.clone(),
-            password.clone(),
+            id,
+            settings.clone(),
+            config,
             command_rx,
             request_rx,
             result_tx,
+            status_tx,
         );
-        let room_id = room.id;
-
-        let join_handle = tokio::spawn(async move { room.run().await });
+        room.content_filter = content_filter;
+        room.pending_approval = pending_approval;
+        if let Some(restored) = restored {
+            room.abandon_at = Some(restored.abandon_at);
+            room.watch_positions = restored.watch_positions;
+            room.restore_history(restored.history);
+        }
+        // listings read the status, so it has to be there before the room runs
+        room.update_status();
+        room.event_log = event_log;
+        room.heavy_runtime = heavy_runtime;
+        log::info!("Room '{}' created", room.settings.name);
+        let liveness = RoomLiveness(room.liveness_tx.subscribe());
+        let join_handle = tokio::spawn(room.run());
 
-        let controller = RoomController {
-            id: room_id,
-            name,
-            password,
+        RoomController {
+            id,
+            settings,
+            creator: None,
+            restored_roles: HashMap::new(),
+            invites: HashMap::new(),
+            pending_approval,
+            limits,
             command_tx,
             request_tx,
             result_rx,
+            status_rx,
+            liveness,
             join_handle,
-        };
-
-        controller
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        }
     }
 
     async fn send_user_msg(&mut self, id: SessionId, msg: SessionMsg) -> anyhow::Result<()> {
-        let Some(user) = self.users.get(&id) else {
+        let Some(participant) = self.participants.get(&id) else {
             return Ok(());
         };
-        if !user.session.send_message(msg).await? {
-            Box::pin(self.leave(id)).await;
+        if !participant.session.send_message(msg).await? {
+            Box::pin(self.leave(id, LeaveReason::ConnectionLost)).await;
         };
         Ok(())
     }
 
     fn user_ids(&self) -> Vec<SessionId> {
-        self.users.keys().copied().collect()
+        self.participants.keys().copied().collect()
+    }
+
+    /// Numbers the next broadcast, so that clients can tell when they missed one.
+    fn sequence(&mut self, msg: SessionMsg) -> SessionMsg {
+        self.broadcast_seq += 1;
+        SessionMsg::Broadcast(self.broadcast_seq, Box::new(msg))
     }
 
     async fn broadcast_msg(&mut self, msg: SessionMsg) -> anyhow::Result<()> {
+        let msg = self.sequence(msg);
         let mut result = Ok(());
+        let mut yield_point = YieldPoint::default();
         for id in self.user_ids() {
+            yield_point.tick().await;
             if let Err(err) = self.send_user_msg(id, msg.clone()).await {
                 error!("Failed to broadcast message to user {id}: {err:?}");
                 if result.is_ok() {
@@ -361,63 +1343,444 @@ impl Room {
                 }
             }
         }
+        self.broadcast_to_observers(msg).await;
         result
     }
 
-    async fn broadcast_state(&mut self) -> anyhow::Result<()> {
-        self.broadcast_msg(SessionMsg::RoomState(self.get_state()))
-            .await
-    }
-
-    async fn leave(&mut self, session_id: SessionId) {
-        let Some(user) = self.users.remove(&session_id) else {
+    /// Observers get every broadcast, but failing to reach one of them is no reason to fail the
+    /// broadcast for everyone else.
+    async fn broadcast_to_observers(&mut self, msg: SessionMsg) {
+        if self.observers.is_empty() {
             return;
-        };
-        log::info!("User '{}' left room '{}'", user.session.name, self.name);
-        if self.users.is_empty() {
-            log::info!("Room '{}' is empty and will be closed", self.name);
+        }
+        let msg = Self::observer_view(msg);
+        let mut gone = Vec::new();
+        let mut yield_point = YieldPoint::default();
+        for observer in self.observers.values() {
+            yield_point.tick().await;
+            match observer.send_message(msg.clone()).await {
+                Ok(true) => (),
+                Ok(false) => gone.push(observer.id),
+                Err(err) => error!(
+                    "Failed to broadcast message to observer {}: {err:?}",
+                    observer.id
+                ),
+            }
+        }
+        for id in gone {
+            self.observers.remove(&id);
+        }
+    }
+
+    /// Observers aren't members of the room, so they don't learn its password from the state.
+    fn observer_view(msg: SessionMsg) -> SessionMsg {
+        match msg {
+            SessionMsg::Broadcast(seq, msg) => {
+                SessionMsg::Broadcast(seq, Box::new(Self::observer_view(*msg)))
+            }
+            SessionMsg::RoomState(state) => {
+                SessionMsg::RoomState(Arc::new(dto::RoomStateMsgBodyV1 {
+                    password: String::new(),
+                    ..(*state).clone()
+                }))
+            }
+            msg => msg,
+        }
+    }
+
+    fn echo_policy(&self, event: BroadcastEvent) -> EchoPolicy {
+        match event {
+            BroadcastEvent::RoomState => self.broadcast_echo.room_state,
+            BroadcastEvent::Chat => self.broadcast_echo.chat,
+        }
+    }
+
+    /// Broadcasts a message to all users, except that the user who caused it may only get an
+    /// acknowledgement, depending on the configured echo policy for the event.
+    async fn broadcast_from(
+        &mut self,
+        origin: ChangeOrigin,
+        event: BroadcastEvent,
+        msg: SessionMsg,
+    ) -> anyhow::Result<()> {
+        let ack_to = match (origin, self.echo_policy(event)) {
+            (ChangeOrigin::User(id), EchoPolicy::Ack) => Some(id),
+            _ => None,
+        };
+        let msg = self.sequence(msg);
+        // the acknowledgement takes the place of the broadcast, so it has the same number
+        let ack = SessionMsg::Broadcast(
+            self.broadcast_seq,
+            Box::new(SessionMsg::BroadcastAck(event)),
+        );
+        let mut result = Ok(());
+        let mut yield_point = YieldPoint::default();
+        for id in self.user_ids() {
+            yield_point.tick().await;
+            let msg = if Some(id) == ack_to {
+                ack.clone()
+            } else {
+                msg.clone()
+            };
+            if let Err(err) = self.send_user_msg(id, msg).await {
+                error!("Failed to broadcast message to user {id}: {err:?}");
+                if result.is_ok() {
+                    result = Err(anyhow!("Failed to broadcast message to one or more users"))
+                }
+            }
+        }
+        self.broadcast_to_observers(msg).await;
+        result
+    }
+
+    fn is_quiet(&self) -> bool {
+        let now = timestamp();
+        self.model
+            .quiet_hours
+            .iter()
+            .any(|window| window.contains(now))
+    }
+
+    fn schedule_state_broadcast(&mut self, origin: ChangeOrigin) {
+        let delay = if self.is_quiet() {
+            Self::QUIET_STATE_BROADCAST_DELAY
+        } else {
+            Self::STATE_BROADCAST_DELAY
+        };
+        self.schedule_state_broadcast_within(origin, delay);
+    }
+
+    fn schedule_state_broadcast_within(&mut self, origin: ChangeOrigin, delay: Duration) {
+        self.state_change_origin = Some(match self.state_change_origin {
+            Some(pending) => pending.merge(origin),
+            None => origin,
+        });
+        let deadline = Instant::now() + delay;
+        match &mut self.state_broadcast_at {
+            Some(scheduled) if *scheduled <= deadline => (),
+            scheduled => *scheduled = Some(deadline),
+        }
+    }
+
+    async fn set_quiet_hours(
+        &mut self,
+        session_id: SessionId,
+        windows: Vec<QuietWindow>,
+    ) -> anyhow::Result<()> {
+        let Some(member) = self.model.members.get(&session_id) else {
+            return Ok(());
+        };
+        if member.role != UserRole::Host {
+            return Err(ClientError::not_authorized("Only the host can set quiet hours").into());
+        }
+        log::info!(
+            "Setting quiet hours of room '{}' to {windows:?}",
+            self.settings.name
+        );
+        self.emit(RoomEvent::QuietHoursSet { windows }).await
+    }
+
+    async fn update_metadata(
+        &mut self,
+        session_id: SessionId,
+        metadata: RoomMetadata,
+    ) -> anyhow::Result<()> {
+        let Some(member) = self.model.members.get(&session_id) else {
+            return Ok(());
+        };
+        if member.role != UserRole::Host {
+            return Err(ClientError::not_authorized("Only the host can update the room").into());
+        }
+        let metadata = metadata.checked(&self.content_filter)?;
+        log::info!(
+            "Updating metadata of room '{}' to {metadata:?}",
+            self.settings.name
+        );
+        self.settings.metadata = metadata.clone();
+        self.emit(RoomEvent::MetadataUpdated { metadata }).await
+    }
+
+    /// Only changes the password the room hands out in its state. Joins are checked against the
+    /// room manager's copy, which the session updates once the room has accepted the change.
+    async fn set_password(
+        &mut self,
+        session_id: SessionId,
+        password: Option<String>,
+    ) -> anyhow::Result<()> {
+        // the session may think it's in the room when its join failed, so this has to fail too
+        let Some(member) = self.model.members.get(&session_id) else {
+            return Err(ClientError::not_authorized("Only members can change the password").into());
+        };
+        if member.role != UserRole::Host {
+            return Err(
+                ClientError::not_authorized("Only the host can change the password").into(),
+            );
+        }
+        match password {
+            Some(_) => log::info!("Changing the password of room '{}'", self.settings.name),
+            None => log::info!("Room '{}' is now open to anyone", self.settings.name),
+        }
+        self.settings.password = password;
+        self.emit(RoomEvent::PasswordChanged).await
+    }
+
+    async fn set_intermission(
+        &mut self,
+        session_id: SessionId,
+        intermission: Option<Intermission>,
+    ) -> anyhow::Result<()> {
+        let Some(member) = self.model.members.get(&session_id) else {
+            return Ok(());
+        };
+        if member.role != UserRole::Host {
+            return Err(
+                ClientError::not_authorized("Only the host can call an intermission").into(),
+            );
+        }
+        let intermission = match intermission {
+            Some(intermission) => {
+                if intermission.notice.chars().count() > self.chat.max_length {
+                    return Err(ClientError::invalid(format!(
+                        "Intermission notices can be at most {} characters long",
+                        self.chat.max_length
+                    ))
+                    .into());
+                }
+                Some(Intermission {
+                    notice: self
+                        .content_filter
+                        .apply(&intermission.notice, "Intermission notices")?
+                        .into_owned(),
+                    ..intermission
+                })
+            }
+            None => None,
+        };
+        log::info!(
+            "Setting intermission of room '{}' to {intermission:?}",
+            self.settings.name
+        );
+        if let Some(playback) = &mut self.playback {
+            playback.set_intermission(intermission.is_some()).await?;
+        }
+        self.intermission = intermission;
+        self.touch();
+        self.schedule_state_broadcast(ChangeOrigin::Everyone);
+        Ok(())
+    }
+
+    async fn flush_state_broadcast(&mut self) {
+        if self.state_broadcast_at.take().is_none() {
+            return;
+        }
+        let origin = self
+            .state_change_origin
+            .take()
+            .unwrap_or(ChangeOrigin::Everyone);
+        let state = SessionMsg::RoomState(Arc::new(self.get_state().into()));
+        if let Err(err) = self
+            .broadcast_from(origin, BroadcastEvent::RoomState, state)
+            .await
+        {
+            log::error!("Failed to broadcast room state: {err:?}");
+        }
+    }
+
+    async fn leave(&mut self, session_id: SessionId, reason: LeaveReason) {
+        if let Some(observer) = self.observers.remove(&session_id) {
+            log::info!(
+                "Observer '{}' stopped observing room '{}'",
+                observer.name,
+                self.settings.name
+            );
+            return;
+        }
+        self.remove_member(
+            session_id,
+            RoomEvent::Left {
+                user: session_id,
+                reason,
+            },
+        )
+        .await;
+    }
+
+    async fn kick(&mut self, by: SessionId, session_id: SessionId) -> anyhow::Result<()> {
+        if by == session_id {
+            return Err(ClientError::invalid("You can't kick yourself").into());
+        }
+        let Some(kicker) = self.model.user_data(by) else {
+            return Ok(());
+        };
+        // the role may have changed since the kicker joined
+        if !kicker.role.permissions().can_kick {
+            return Err(ClientError::not_authorized("Not authorized to kick users").into());
+        }
+        if !self.model.members.contains_key(&session_id) {
+            return Err(
+                ClientError::invalid(format!("User {session_id} is not in this room")).into(),
+            );
+        }
+        log::info!("User '{}' is kicking {session_id}", kicker.name);
+        self.send_user_msg(session_id, SessionMsg::Kicked(kicker.name))
+            .await?;
+        self.remove_member(
+            session_id,
+            RoomEvent::Kicked {
+                user: session_id,
+                by,
+                kicked_at: timestamp(),
+            },
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Hands the room over to another member in one go, so that there is always exactly one host.
+    async fn transfer_host(&mut self, from: SessionId, to: SessionId) -> anyhow::Result<()> {
+        if from == to {
+            return Err(ClientError::invalid("You are already the host").into());
+        }
+        let Some(host) = self.model.user_data(from) else {
+            return Ok(());
+        };
+        // the role may have changed since the host joined
+        if host.role != UserRole::Host {
+            return Err(ClientError::not_authorized("Only the host can hand over the room").into());
+        }
+        let Some(new_host) = self.model.user_data(to) else {
+            return Err(ClientError::invalid(format!("User {to} is not in this room")).into());
+        };
+        log::info!(
+            "User '{}' is handing room '{}' over to '{}'",
+            host.name,
+            self.settings.name,
+            new_host.name
+        );
+        self.emit(RoomEvent::RoleChanged {
+            user: to,
+            role: UserRole::Host,
+            by: from,
+            changed_at: timestamp(),
+        })
+        .await?;
+        self.emit(RoomEvent::RoleChanged {
+            user: from,
+            role: UserRole::Guest,
+            by: from,
+            changed_at: timestamp(),
+        })
+        .await?;
+        self.broadcast_msg(SessionMsg::HostChanged(new_host)).await
+    }
+
+    /// Hands the room over to the successor of a host who lost their connection for now. They
+    /// are a guest when they come back.
+    async fn step_away(&mut self, session_id: SessionId) -> anyhow::Result<()> {
+        if self.model.host() != Some(session_id) {
+            return Ok(());
+        }
+        let Some(successor) = self
+            .model
+            .choose_successor(&self.settings.host_succession, session_id)
+        else {
+            return Ok(());
+        };
+        self.transfer_host(session_id, successor).await
+    }
+
+    /// Removes a member who left or was kicked, choosing a new host or closing the room if
+    /// necessary.
+    async fn remove_member(&mut self, session_id: SessionId, event: RoomEvent) {
+        let Some(participant) = self.participants.remove(&session_id) else {
+            return;
+        };
+        self.remember_watch_position(session_id, true);
+        log::info!(
+            "User '{}' left room '{}'",
+            participant.session.name,
+            self.settings.name
+        );
+        let reason = match &event {
+            RoomEvent::Left { reason, .. } => *reason,
+            _ => LeaveReason::Kicked,
+        };
+        // the member is gone once the event is applied, so this has to be looked up first
+        let user = self.model.user_data(session_id);
+        let anonymous = self
+            .model
+            .members
+            .get(&session_id)
+            .is_some_and(|member| member.anonymous);
+        if let Err(err) = self.emit(event).await {
+            log::error!("Failed to announce that user {session_id} left: {err:?}");
+        }
+        if let Some(user) = user.filter(|_| !anonymous) {
+            self.announce_occupancy(SessionMsg::UserLeft(user, reason))
+                .await;
+        }
+        if let Some(mut playback) = self
+            .playback
+            .take_if(|playback| playback.host_id() == session_id)
+        {
+            if let Err(err) = playback.stop(StopReason::HostLeft).await {
+                log::error!("Failed to stop the playback of a host who left: {err:?}");
+            }
+        }
+        // anonymous spectators can't keep a room going on their own, since none of them can host
+        if self.model.members.values().all(|member| member.anonymous) {
+            log::info!("Room '{}' is empty and will be closed", self.settings.name);
             // Close the room if it has no users
-            if let Err(err) = self.close(RoomCloseReason::ClosedByHost).await {
+            if let Err(err) = self.close(RoomCloseReason::Empty).await {
                 log::error!("Error while closing empty room: {err:?}");
             }
             return;
         }
-        if self
-            .users
-            .iter()
-            .all(|(_, user)| user.role != UserRole::Host)
-        {
-            let Some(new_host) = self.choose_new_host() else {
+        if !self.model.has_host() {
+            let Some(new_host) = self.model.choose_new_host(&self.settings.host_succession) else {
                 log::error!(
                     "Failed to choose a new host id in session {session_id}! closing the room!"
                 );
                 let _ = self.close(RoomCloseReason::ServerError).await;
                 return;
             };
-            if let Err(err) = self.set_role(UserRole::Host, new_host.id).await {
-                log::error!("Failed to set new room host: {err:?}");
-                let _ = self.close(RoomCloseReason::ServerError).await;
+            if let Err(err) = self.emit(RoomEvent::HostSucceeded { user: new_host }).await {
+                log::error!("Failed to announce new room host: {err:?}");
             }
-            log::info!(
-                "User '{}' is the new host of room '{}'",
-                new_host.name,
-                self.name
-            );
         }
-        if let Err(err) = self.broadcast_state().await {
-            log::error!("Failed to broadcast state after leaving the room: {err}");
+    }
+
+    async fn correct_playback(&mut self) {
+        let Some(playback) = &mut self.playback else {
+            return;
+        };
+        if let Err(err) = playback.send_correction().await {
+            log::error!("Failed to send playback correction: {err:?}");
+        }
+    }
+
+    async fn finish_roll_call(&mut self) {
+        let Some(playback) = &mut self.playback else {
+            return;
+        };
+        if let Err(err) = playback.finish_roll_call().await {
+            log::error!("Failed to report roll call: {err:?}");
         }
     }
 
-    fn choose_new_host(&mut self) -> Option<UserData> {
-        let mut new_host: Option<UserData> = None;
-        for (id, user) in &self.users {
-            if matches!(user.role, UserRole::Host | UserRole::Guest) {
-                return Some(user.get_user_data());
-            }
-            new_host.get_or_insert(user.get_user_data());
+    async fn apply_template(
+        &mut self,
+        quiet_hours: Vec<QuietWindow>,
+        queue: Vec<PlaybackSource>,
+    ) -> anyhow::Result<()> {
+        self.planned_queue = queue;
+        if quiet_hours.is_empty() {
+            return Ok(());
         }
-        new_host
+        self.emit(RoomEvent::QuietHoursSet {
+            windows: quiet_hours,
+        })
+        .await
     }
 
     async fn host_playback(&mut self, session_id: SessionId) -> anyhow::Result<()> {
@@ -427,21 +1790,35 @@ impl Room {
             }
         }
 
-        let Some(host) = self.users.get(&session_id) else {
+        let Some(host) = self.participants.get(&session_id) else {
             return Err(anyhow!("Unknown user"));
         };
 
-        self.playback = Some(Playback::new(host.session.clone()));
+        self.playback = Some(Playback::new(
+            host.session.clone(),
+            self.playback_config.clone(),
+        ));
 
         log::info!(
             "User '{}' is hosting playback in room '{}'",
             host.session.name,
-            self.name
+            self.settings.name
         );
 
         self.send_user_msg(host.session.id, SessionMsg::PlaybackHosting)
             .await?;
 
+        let planned_queue = mem::take(&mut self.planned_queue);
+        if let Some(playback) = &mut self.playback {
+            playback.set_ready_quorum(self.settings.ready_quorum);
+        }
+        if let (Some(playback), Some(..)) = (&mut self.playback, &self.intermission) {
+            playback.set_intermission(true).await?;
+        }
+        if let (Some(playback), false) = (&mut self.playback, planned_queue.is_empty()) {
+            playback.seed_queue(planned_queue).await?;
+        }
+
         Ok(())
     }
 
@@ -450,15 +1827,93 @@ impl Room {
             return Err(anyhow!("No active playback"));
         };
 
-        let Some(subscriber) = self.users.get(&session_id) else {
+        let Some(subscriber) = self.participants.get(&session_id) else {
             return Err(anyhow!("Unkown user"));
         };
+        // the role may have changed since the session checked it
+        let can_watch = self
+            .model
+            .members
+            .get(&session_id)
+            .is_some_and(|member| member.role.permissions().can_watch);
+        if !can_watch {
+            return Err(ClientError::not_authorized("Not authorized to watch playback").into());
+        }
 
         playback.connect(subscriber.session.clone()).await?;
 
         Ok(())
     }
 
+    async fn send_playback_overview(&mut self, session_id: SessionId) -> anyhow::Result<()> {
+        let overview = self
+            .playback
+            .as_ref()
+            .map_or_else(PlaybackOverview::idle, Playback::get_overview);
+        self.send_user_msg(session_id, SessionMsg::PlaybackOverview(overview))
+            .await
+    }
+
+    async fn send_playback_history(&mut self, session_id: SessionId) -> anyhow::Result<()> {
+        let history = self.model.playback_history.iter().cloned().collect();
+        self.send_user_msg(session_id, SessionMsg::PlaybackHistory(history))
+            .await
+    }
+
+    async fn send_moderation_log(
+        &mut self,
+        session_id: SessionId,
+        before: Option<u64>,
+        len: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let Some(member) = self.model.members.get(&session_id) else {
+            return Ok(());
+        };
+        let permissions = member.role.permissions();
+        if !permissions.can_kick && !permissions.can_set_roles {
+            return Err(
+                ClientError::not_authorized("Only moderators can see the moderation log").into(),
+            );
+        }
+        let page = ModerationLogPage::of(&self.model.moderation_log, before, len);
+        self.send_user_msg(session_id, SessionMsg::ModerationLog(page))
+            .await
+    }
+
+    /// Records in the playback history when the host starts playing something else, or playback
+    /// ends. Playback can change in many places, so this just compares after the fact.
+    async fn track_playback_history(&mut self) {
+        let now_playing = self
+            .playback
+            .as_ref()
+            .filter(|_| self.running)
+            .and_then(|playback| Some((playback.host_id(), playback.get_info().source?)));
+        let event = match (now_playing, self.model.now_playing()) {
+            (Some((host, source)), played)
+                if played.is_some_and(|played| {
+                    played.host == host
+                        && played.title == source.title
+                        && played.page_href == source.page_href
+                }) =>
+            {
+                return;
+            }
+            (Some((host, source)), _) => RoomEvent::PlaybackStarted {
+                host,
+                title: source.title,
+                page_href: source.page_href,
+                started_at: timestamp(),
+            },
+            (None, Some(_)) => RoomEvent::PlaybackEnded {
+                ended_at: timestamp(),
+            },
+            (None, None) => return,
+        };
+        if let Err(err) = self.emit(event).await {
+            log::error!("Failed to record playback history: {err:?}");
+        }
+    }
+
     async fn playback_request(
         &mut self,
         session_id: SessionId,
@@ -467,154 +1922,3048 @@ impl Room {
         let Some(playback) = &mut self.playback else {
             return Err(anyhow!("No active playback"));
         };
+        let is_report = matches!(
+            request,
+            PlaybackRequest::Sync(..) | PlaybackRequest::RollCallReply(..)
+        );
+        // telemetry and the like come in on their own as long as anyone is subscribed, so only
+        // controlling the playback counts as activity
+        let is_activity = matches!(
+            request,
+            PlaybackRequest::Start(..)
+                | PlaybackRequest::Stop(..)
+                | PlaybackRequest::Finish
+                | PlaybackRequest::Ended
+                | PlaybackRequest::Sync(..)
+                | PlaybackRequest::SeekHints(..)
+                | PlaybackRequest::QueueAdd(..)
+                | PlaybackRequest::QueueRemove(..)
+                | PlaybackRequest::QueueMove(..)
+        );
+
+        let result = playback.handle_request(session_id, request).await;
+        if is_report {
+            self.remember_watch_position(session_id, false);
+        }
+        if is_activity && result.is_ok() {
+            self.touch();
+        }
+        result
+    }
+
+    async fn chat(&mut self, session_id: SessionId, text: String) -> anyhow::Result<()> {
+        let Some(participant) = self.participants.get_mut(&session_id) else {
+            return Ok(());
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ClientError::invalid("Chat messages can't be empty").into());
+        }
+        self.chat.check(text)?;
+        let text = self.content_filter.apply(text, "Chat messages")?;
+        if !participant.chat_limit.try_take() {
+            return Err(ClientError::new(
+                ErrorCode::RateLimited,
+                "You are sending chat messages too quickly",
+            )
+            .into());
+        }
+        let event = RoomEvent::ChatSent {
+            user: session_id,
+            text: text.into_owned(),
+            sent_at: timestamp(),
+        };
+        self.touch();
+        self.emit(event).await
+    }
+
+    /// Shared links count towards the chat rate limit, since they show up in the same place.
+    async fn share_link(&mut self, session_id: SessionId, url: String) -> anyhow::Result<()> {
+        let Some(participant) = self.participants.get_mut(&session_id) else {
+            return Ok(());
+        };
+        let url = self.link_sharing.check(&url)?;
+        if !participant.chat_limit.try_take() {
+            return Err(ClientError::new(
+                ErrorCode::RateLimited,
+                "You are sharing links too quickly",
+            )
+            .into());
+        }
+        let event = RoomEvent::LinkShared {
+            user: session_id,
+            url,
+            shared_at: timestamp(),
+        };
+        self.touch();
+        self.emit(event).await
+    }
 
-        playback.handle_request(session_id, request).await
+    async fn relay_peer_probe(
+        &mut self,
+        from: SessionId,
+        to: SessionId,
+        probe: PeerProbe,
+    ) -> anyhow::Result<()> {
+        if from == to {
+            return Err(anyhow!("Cannot send a peer probe to yourself"));
+        }
+        if !self.model.members.contains_key(&to) {
+            return Err(ClientError::invalid(format!("User {to} is not in this room")).into());
+        }
+        self.send_user_msg(to, SessionMsg::PeerProbe(from, probe))
+            .await
     }
 
     async fn handle_request(&mut self, request: RoomRequest) {
         let result = match request {
-            RoomRequest::GetState => self.broadcast_state().await,
-            RoomRequest::SetRole(session_id, role) => self.set_role(role, session_id).await,
-            RoomRequest::Leave(session_id) => {
-                self.leave(session_id).await;
+            RoomRequest::GetState => {
+                // someone is explicitly waiting for this one, so don't hold it back for quiet hours
+                self.schedule_state_broadcast_within(
+                    ChangeOrigin::Everyone,
+                    Self::STATE_BROADCAST_DELAY,
+                );
+                Ok(())
+            }
+            RoomRequest::SetRole(origin, session_id, role) => {
+                self.set_role(role, session_id, origin).await
+            }
+            RoomRequest::Leave(session_id, reason) => {
+                self.leave(session_id, reason).await;
                 Ok(())
             }
+            RoomRequest::Kick(by, session_id) => self.kick(by, session_id).await,
+            RoomRequest::TransferHost(from, to) => self.transfer_host(from, to).await,
+            RoomRequest::StepAway(session_id) => self.step_away(session_id).await,
+            RoomRequest::Rename(session_id, name, unverified) => {
+                self.rename(session_id, name, unverified).await
+            }
             RoomRequest::PlaybackHost(session_id) => self.host_playback(session_id).await,
             RoomRequest::PlaybackConnect(session_id) => self.connect_playback(session_id).await,
             RoomRequest::Playback(session_id, request) => {
                 self.playback_request(session_id, request).await
             }
+            RoomRequest::RelayPeerProbe(from, to, probe) => {
+                self.relay_peer_probe(from, to, probe).await
+            }
+            RoomRequest::SetQuietHours(session_id, windows) => {
+                self.set_quiet_hours(session_id, windows).await
+            }
+            RoomRequest::UpdateMetadata(session_id, metadata) => {
+                self.update_metadata(session_id, metadata).await
+            }
+            RoomRequest::SetPassword(session_id, password) => {
+                self.set_password(session_id, password).await
+            }
+            RoomRequest::SetIntermission(session_id, intermission) => {
+                self.set_intermission(session_id, intermission).await
+            }
+            RoomRequest::PlaybackInfo(session_id) => self.send_playback_overview(session_id).await,
+            RoomRequest::PlaybackHistory(session_id) => {
+                self.send_playback_history(session_id).await
+            }
+            RoomRequest::ModerationLog(session_id, before, len) => {
+                self.send_moderation_log(session_id, before, len).await
+            }
+            RoomRequest::Chat(session_id, text) => self.chat(session_id, text).await,
+            RoomRequest::ShareLink(session_id, url) => self.share_link(session_id, url).await,
         };
         if let Err(err) = self.result_tx.send(result) {
             log::error!("Failed to send room request result: {err:?}");
         }
     }
 
-    async fn join(&mut self, role: UserRole, session: SessionHandle) -> anyhow::Result<()> {
-        if self.users.contains_key(&session.id) {
+    async fn join(
+        &mut self,
+        role: UserRole,
+        mut session: SessionHandle,
+        anonymous: bool,
+    ) -> anyhow::Result<()> {
+        if self.model.members.contains_key(&session.id) {
             return Err(anyhow!("Already joined this room"));
         }
-        log::info!("User '{}' has joined room '{}'", session.name, self.name);
-        self.users.insert(session.id, User { role, session });
-        self.broadcast_state().await
-    }
-
-    async fn set_role(&mut self, role: UserRole, session_id: SessionId) -> anyhow::Result<()> {
-        let Some(user) = self.users.get_mut(&session_id) else {
-            return Ok(());
+        if self.model.members.len() >= self.limits.max_users {
+            return Err(RoomError::Full.into());
+        }
+        if !anonymous {
+            match self.member_name(session.id, &session.name) {
+                Ok(name) => session.name = name,
+                Err(err) => {
+                    session
+                        .send_message(SessionMsg::JoinRejected(self.id, err.clone()))
+                        .await?;
+                    return Err(err.into());
+                }
+            }
+        }
+        log::info!(
+            "User '{}' has joined room '{}'",
+            session.name,
+            self.settings.name
+        );
+        // a returning host takes the room back from whoever stood in for them
+        let stand_in = self.model.host().filter(|_| role == UserRole::Host);
+        // a restored room has no host until someone comes back, whatever role they had before
+        let promoted = !anonymous && role != UserRole::Host && self.model.host().is_none();
+        let event = RoomEvent::Joined {
+            user: session.id,
+            name: session.name.clone(),
+            subject: session.subject.clone(),
+            role: if stand_in.is_some() {
+                UserRole::Guest
+            } else if promoted {
+                UserRole::Host
+            } else {
+                role
+            },
+            anonymous,
         };
-        user.role = role;
-        log::info!("Setting rome of user '{}' to {role}", user.session.name);
-        self.broadcast_state().await
+        let last_position = self.last_watch_position(&session);
+        let session = send_queue::wrap_session(session);
+        // anonymous spectators don't show their name, so there's nobody to impersonate
+        let unverified = session.unverified && !anonymous;
+        let session_id = session.id;
+        self.participants.insert(
+            session.id,
+            Participant {
+                session,
+                chat_limit: self.chat.rate_limit(),
+            },
+        );
+        self.abandon_at = None;
+        self.emit(event).await?;
+        if let Some(stand_in) = stand_in {
+            self.transfer_host(stand_in, session_id).await?;
+        }
+        if promoted {
+            self.send_user_msg(session_id, SessionMsg::RoleChanged(UserRole::Host))
+                .await?;
+        }
+        if unverified {
+            self.warn_hosts(session_id).await?;
+        }
+        if self.pending_approval {
+            self.send_user_msg(session_id, SessionMsg::RoomPendingApproval)
+                .await?;
+        }
+        if let Some(position) = last_position {
+            self.send_user_msg(session_id, SessionMsg::PlaybackLastPosition(position))
+                .await?;
+        }
+        Ok(())
     }
 
-    async fn close(&mut self, reason: RoomCloseReason) -> anyhow::Result<()> {
-        log::debug!("Closing room {} ('{}'): {reason}", self.id, self.name);
-        self.running = false;
-        log::info!("Room '{}' has been closed", self.name);
-        self.broadcast_msg(SessionMsg::RoomClosed(reason)).await
+    /// Checks a name that a user wants to go by in the room, whether it came with their login or
+    /// they changed it, and makes it unique.
+    fn member_name(&self, user: SessionId, name: &str) -> Result<String, ClientError> {
+        if name.chars().count() > Session::MAX_NAME_LEN {
+            return Err(ClientError::invalid(format!(
+                "Names can't be longer than {} characters",
+                Session::MAX_NAME_LEN
+            )));
+        }
+        let name = self
+            .content_filter
+            .apply(name, "Names")
+            .map_err(|err| ClientError::invalid(err.to_string()))?;
+        self.unique_name(user, &name)
     }
 
-    async fn handle_cmd(&mut self, cmd: RoomCmd) {
-        let result = match cmd {
-            RoomCmd::Join(user_role, session_info) => self.join(user_role, session_info).await,
-            RoomCmd::Close(reason) => self.close(reason).await,
+    /// The name a user goes by in the room, given that other members may already go by the name
+    /// they asked for.
+    fn unique_name(&self, user: SessionId, name: &str) -> Result<String, ClientError> {
+        let taken = |name: &str| {
+            self.model.members.iter().any(|(id, member)| {
+                *id != user
+                    && !member.anonymous
+                    && member.name.to_lowercase() == name.to_lowercase()
+            })
         };
-        if let Err(err) = self.result_tx.send(result) {
-            error!("Failed to send room command result: {err:?}");
+        if !taken(name) {
+            return Ok(name.to_string());
         }
-    }
-
-    async fn run(&mut self) {
-        log::info!("Room '{}' created", self.name);
-        while self.running {
-            tokio::select! {
-                cmd = self.command_rx.recv() => {
-                    if let Some(cmd) = cmd {
-                        self.handle_cmd(cmd).await
-                    } else {
-                        error!("Room command receiver was unexpectedly closed");
-                        let _ = self.close(RoomCloseReason::ServerError).await;
+        match self.duplicate_names {
+            DuplicateNamePolicy::Allow => Ok(name.to_string()),
+            DuplicateNamePolicy::Discriminate => {
+                let mut discriminator = 2;
+                loop {
+                    let candidate = format!("{name} ({discriminator})");
+                    if !taken(&candidate) {
+                        return Ok(candidate);
+                    }
+                    discriminator += 1;
+                }
+            }
+            DuplicateNamePolicy::Reject => Err(ClientError::new(
+                ErrorCode::NameTaken,
+                format!("Someone in this room already goes by '{name}'"),
+            )),
+        }
+    }
+
+    async fn rename(
+        &mut self,
+        session_id: SessionId,
+        name: String,
+        unverified: bool,
+    ) -> anyhow::Result<()> {
+        let anonymous = self
+            .model
+            .members
+            .get(&session_id)
+            .is_some_and(|member| member.anonymous);
+        let name = if anonymous {
+            name
+        } else {
+            self.member_name(session_id, &name)?
+        };
+        let Some(participant) = self.participants.get_mut(&session_id) else {
+            return Err(ClientError::invalid("You aren't in this room").into());
+        };
+        log::info!(
+            "User '{}' is now called '{name}' in room '{}'",
+            participant.session.name,
+            self.settings.name
+        );
+        participant.session.name = name.clone();
+        if let Some(playback) = &mut self.playback {
+            playback.rename(session_id, &name);
+        }
+        self.emit(RoomEvent::Renamed {
+            user: session_id,
+            name: name.clone(),
+        })
+        .await?;
+        self.send_user_msg(session_id, SessionMsg::Renamed(name))
+            .await?;
+        if unverified && !anonymous {
+            self.warn_hosts(session_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Lets the hosts know that a user joined under a name that was first used from another
+    /// client.
+    async fn warn_hosts(&mut self, user: SessionId) -> anyhow::Result<()> {
+        let Some(user_data) = self.model.user_data(user) else {
+            return Ok(());
+        };
+        log::info!(
+            "User '{}' joined room '{}' from an unknown client",
+            user_data.name,
+            self.settings.name
+        );
+        let hosts: Vec<SessionId> = self
+            .model
+            .members
+            .iter()
+            .filter(|(id, member)| member.role == UserRole::Host && **id != user)
+            .map(|(id, _)| *id)
+            .collect();
+        for host in hosts {
+            self.send_user_msg(host, SessionMsg::IdentityWarning(user_data.clone()))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn approve(&mut self) -> anyhow::Result<()> {
+        if !mem::take(&mut self.pending_approval) {
+            return Ok(());
+        }
+        log::info!("Room '{}' has been approved", self.settings.name);
+        self.broadcast_msg(SessionMsg::RoomApproved).await
+    }
+
+    async fn observe(&mut self, session: SessionHandle) -> anyhow::Result<()> {
+        if self.model.members.contains_key(&session.id) || self.observers.contains_key(&session.id)
+        {
+            return Err(anyhow!("Already joined this room"));
+        }
+        if self.observers.len() >= self.limits.max_observers {
+            return Err(ClientError::new(
+                ErrorCode::RoomFull,
+                "This room can't take any more observers",
+            )
+            .into());
+        }
+        log::info!(
+            "User '{}' is observing room '{}'",
+            session.name,
+            self.settings.name
+        );
+        let session = send_queue::wrap_session(session);
+        // Nobody else learns about the observer, so it gets its first room state on its own. That
+        // is the state as of the last broadcast, so it has the same number.
+        let state = Self::observer_view(SessionMsg::Broadcast(
+            self.broadcast_seq,
+            Box::new(SessionMsg::RoomState(Arc::new(self.get_state().into()))),
+        ));
+        if session.send_message(state).await? {
+            self.observers.insert(session.id, session);
+        }
+        Ok(())
+    }
+
+    async fn set_role(
+        &mut self,
+        role: UserRole,
+        session_id: SessionId,
+        origin: SessionId,
+    ) -> anyhow::Result<()> {
+        let Some(setter) = self.model.members.get(&origin) else {
+            return Ok(());
+        };
+        // the role may have changed since the setter joined
+        if !setter.role.permissions().can_set_roles {
+            return Err(ClientError::not_authorized("Not authorized to set user roles").into());
+        }
+        let Some(member) = self.model.members.get(&session_id) else {
+            return Ok(());
+        };
+        // a room always has exactly one host, so that role can only be handed over
+        if role == UserRole::Host {
+            return self.transfer_host(origin, session_id).await;
+        }
+        if member.role == UserRole::Host {
+            return Err(ClientError::invalid(
+                "The host has to hand the room over to someone else first",
+            )
+            .into());
+        }
+        log::info!("Setting role of user '{}' to {role}", member.name);
+        self.emit(RoomEvent::RoleChanged {
+            user: session_id,
+            role,
+            by: origin,
+            changed_at: timestamp(),
+        })
+        .await
+    }
+
+    async fn close(&mut self, reason: RoomCloseReason) -> anyhow::Result<()> {
+        log::debug!(
+            "Closing room {} ('{}'): {reason}",
+            self.id,
+            self.settings.name
+        );
+        log::info!("Room '{}' has been closed", self.settings.name);
+        self.emit(RoomEvent::Closed { reason }).await
+    }
+
+    /// Records an event and applies it to the room. Everything that changes the room goes
+    /// through here, so that its event log is enough to replay it.
+    async fn emit(&mut self, event: RoomEvent) -> anyhow::Result<()> {
+        self.model.apply(&event);
+        self.events.push(RoomEventRecord {
+            seq: self.next_seq,
+            at: timestamp(),
+            event: event.clone(),
+        });
+        self.next_seq += 1;
+        self.react(event).await
+    }
+
+    /// Tells everyone that someone joined or left, on top of the state broadcast. Quiet hours are
+    /// meant to keep this kind of chatter down, so nothing is announced during them.
+    async fn announce_occupancy(&mut self, msg: SessionMsg) {
+        if self.is_quiet() {
+            return;
+        }
+        if let Err(err) = self.broadcast_msg(msg).await {
+            log::error!("Failed to announce a change in occupancy: {err:?}");
+        }
+    }
+
+    /// Lets everyone know about an event that was just applied.
+    async fn react(&mut self, event: RoomEvent) -> anyhow::Result<()> {
+        match event {
+            RoomEvent::Joined {
+                user, anonymous, ..
+            } => {
+                self.schedule_state_broadcast(ChangeOrigin::Everyone);
+                // anonymous spectators aren't listed, so their coming and going isn't either
+                if let Some(user) = self.model.user_data(user).filter(|_| !anonymous) {
+                    self.announce_occupancy(SessionMsg::UserJoined(user)).await;
+                }
+                Ok(())
+            }
+            RoomEvent::Left { .. } | RoomEvent::Kicked { .. } => {
+                self.schedule_state_broadcast(ChangeOrigin::Everyone);
+                Ok(())
+            }
+            RoomEvent::RoleChanged { user, role, by, .. } => {
+                self.schedule_state_broadcast(ChangeOrigin::User(by));
+                self.send_user_msg(user, SessionMsg::RoleChanged(role))
+                    .await
+            }
+            RoomEvent::Renamed { user, .. } => {
+                self.schedule_state_broadcast(ChangeOrigin::User(user));
+                Ok(())
+            }
+            RoomEvent::HostSucceeded { user } => {
+                self.schedule_state_broadcast(ChangeOrigin::Everyone);
+                self.send_user_msg(user, SessionMsg::RoleChanged(UserRole::Host))
+                    .await?;
+                let Some(host) = self.model.user_data(user) else {
+                    return Ok(());
+                };
+                log::info!(
+                    "User '{}' is the new host of room '{}'",
+                    host.name,
+                    self.settings.name
+                );
+                self.broadcast_msg(SessionMsg::HostChanged(host)).await
+            }
+            RoomEvent::QuietHoursSet { .. } => Ok(()),
+            RoomEvent::MetadataUpdated { .. } | RoomEvent::PasswordChanged => {
+                self.schedule_state_broadcast(ChangeOrigin::Everyone);
+                Ok(())
+            }
+            RoomEvent::ChatSent {
+                user,
+                text,
+                sent_at,
+            } => {
+                let Some(sender) = self.model.user_data(user) else {
+                    return Ok(());
+                };
+                let message = ChatMessage {
+                    sender,
+                    text,
+                    sent_at,
+                };
+                self.broadcast_from(
+                    ChangeOrigin::User(user),
+                    BroadcastEvent::Chat,
+                    SessionMsg::Chat(message),
+                )
+                .await
+            }
+            RoomEvent::LinkShared { user, .. } => {
+                let Some(link) = self.model.recent_links.back().cloned() else {
+                    return Ok(());
+                };
+                self.broadcast_from(
+                    ChangeOrigin::User(user),
+                    BroadcastEvent::Chat,
+                    SessionMsg::LinkShared(link),
+                )
+                .await
+            }
+            RoomEvent::PlaybackStarted { .. } | RoomEvent::PlaybackEnded { .. } => Ok(()),
+            RoomEvent::Closed { reason } => {
+                self.running = false;
+                self.close_reason = reason;
+                self.state_broadcast_at = None;
+                self.liveness_tx.send_replace(Some(reason));
+                self.broadcast_msg(SessionMsg::RoomClosed(self.id, reason))
+                    .await
+            }
+        }
+    }
+
+    /// Hands the recorded events to the event log, without waiting for them to be written.
+    fn persist_events(&mut self) {
+        let events = mem::take(&mut self.events);
+        match &self.event_log {
+            Some(event_log) if !events.is_empty() => event_log.append(*self.id, events),
+            _ => (),
+        }
+    }
+
+    /// Picks up where the room left off before the restart. Nobody is in the room anymore, and
+    /// whatever was playing stopped with the server.
+    fn restore_history(&mut self, history: Vec<RoomEventRecord>) {
+        let Some(last) = history.last() else {
+            return;
+        };
+        self.next_seq = last.seq + 1;
+        self.model = RoomModel::replay(history.iter().map(|record| &record.event));
+        self.model.members.clear();
+        if self.model.now_playing().is_some() {
+            let event = RoomEvent::PlaybackEnded { ended_at: last.at };
+            self.model.apply(&event);
+            self.events.push(RoomEventRecord {
+                seq: self.next_seq,
+                at: timestamp(),
+                event,
+            });
+            self.next_seq += 1;
+        }
+    }
+
+    async fn handle_cmd(&mut self, cmd: RoomCmd) {
+        let result = match cmd {
+            RoomCmd::Join(user_role, session_info, anonymous) => {
+                self.join(user_role, session_info, anonymous).await
+            }
+            RoomCmd::Observe(session_info) => self.observe(session_info).await,
+            RoomCmd::Approve => self.approve().await,
+            RoomCmd::ApplyTemplate(quiet_hours, queue) => {
+                self.apply_template(quiet_hours, queue).await
+            }
+            RoomCmd::Close(reason) => self.close(reason).await,
+        };
+        if let Err(err) = self.result_tx.send(result) {
+            error!("Failed to send room command result: {err:?}");
+        }
+    }
+
+    async fn run(mut self) -> ClosedRoom {
+        let Some(runtime) = self.serve().await else {
+            return self.closed();
+        };
+        log::info!(
+            "Room '{}' has {} users; moving it to the threads for large rooms",
+            self.settings.name,
+            self.participants.len()
+        );
+        let name = self.settings.name.clone();
+        let moved = runtime.spawn(async move {
+            self.serve().await;
+            self.closed()
+        });
+        match moved.await {
+            Ok(closed) => closed,
+            Err(err) => {
+                error!("Room '{name}' crashed: {err:?}");
+                ClosedRoom {
+                    name,
+                    reason: RoomCloseReason::ServerError,
+                    closed_at: Instant::now(),
+                }
+            }
+        }
+    }
+
+    /// Handles everything sent to the room until it closes, or until it has grown large enough
+    /// to move to the given runtime.
+    async fn serve(&mut self) -> Option<Handle> {
+        while self.running {
+            tokio::select! {
+                cmd = self.command_rx.recv() => {
+                    if let Some(cmd) = cmd {
+                        self.handle_cmd(cmd).await;
+                        self.update_status();
+                    } else {
+                        error!("Room command receiver was unexpectedly closed");
+                        let _ = self.close(RoomCloseReason::ServerError).await;
                     }
                 }
                 req = self.request_rx.recv() => {
                     if let Some(req) = req {
-                        self.handle_request(req).await
+                        self.handle_request(req).await;
+                        self.update_status();
                     } else {
                         error!("Room request receiver was unexpectedly closed");
                         let _ = self.close(RoomCloseReason::ServerError).await;
                     }
                 }
+                _ = wait_until(self.state_broadcast_at) => self.flush_state_broadcast().await,
+                _ = wait_until(self.playback.as_ref().and_then(Playback::next_correction_at)) => {
+                    self.correct_playback().await
+                }
+                _ = wait_until(self.playback.as_ref().and_then(Playback::roll_call_deadline)) => {
+                    self.finish_roll_call().await
+                }
+                _ = wait_until(self.idle_at) => {
+                    log::info!("Room '{}' has been idle for too long", self.settings.name);
+                    let _ = self.close(RoomCloseReason::Expired).await;
+                }
+                _ = wait_until(self.abandon_at) => {
+                    log::info!("Nobody came back to restored room '{}'", self.settings.name);
+                    let _ = self.close(RoomCloseReason::Empty).await;
+                }
+            }
+            self.track_playback_history().await;
+            self.persist_events();
+            if self.participants.len() >= self.heavy_min_users && self.heavy_runtime.is_some() {
+                return self.heavy_runtime.take();
+            }
+        }
+        if let Some(event_log) = &self.event_log {
+            event_log.delete(*self.id);
+        }
+        None
+    }
+
+    fn closed(&self) -> ClosedRoom {
+        ClosedRoom {
+            name: self.settings.name.clone(),
+            reason: self.close_reason,
+            closed_at: Instant::now(),
+        }
+    }
+}
+
+async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
+
+/// A room that was closed recently, kept around to explain to late joiners what happened to it.
+#[derive(Debug, Clone)]
+struct ClosedRoom {
+    name: String,
+    reason: RoomCloseReason,
+    closed_at: Instant,
+}
+
+impl fmt::Display for ClosedRoom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ago = format_elapsed(self.closed_at.elapsed());
+        match self.reason {
+            RoomCloseReason::ClosedByHost => {
+                write!(f, "Room '{}' was closed {ago} by the host", self.name)
+            }
+            RoomCloseReason::Empty => {
+                write!(
+                    f,
+                    "Room '{}' was closed {ago} after everyone left",
+                    self.name
+                )
+            }
+            RoomCloseReason::Expired => {
+                write!(
+                    f,
+                    "Room '{}' was closed {ago} after being idle for too long",
+                    self.name
+                )
+            }
+            RoomCloseReason::Denied => {
+                write!(
+                    f,
+                    "Room '{}' was closed {ago} because a moderator turned it down",
+                    self.name
+                )
+            }
+            RoomCloseReason::ClosedByOperator => {
+                write!(f, "Room '{}' was closed {ago} by an operator", self.name)
+            }
+            RoomCloseReason::ServerError => {
+                write!(
+                    f,
+                    "Room '{}' was closed {ago} due to a server error",
+                    self.name
+                )
             }
         }
     }
 }
 
+/// The rooms in one shard of the room manager.
+type RoomShard = HashMap<RoomId, RoomController>;
+
 pub struct RoomManager {
-    room_controllers: HashMap<RoomId, RoomController>,
+    config: RoomConfig,
+    content_filter: Arc<ContentFilter>,
+    event_log: EventLog,
+    heavy_runtime: Option<HeavyRoomRuntime>,
+    approval_webhook: Option<Arc<ApprovalWebhook>>,
+    room_ids: Box<dyn IdGenerator>,
+    /// Rooms are spread over the shards by their id, so that sessions only wait for each other
+    /// if their rooms happen to share a shard. Shards are never locked across an await.
+    shards: Box<[Mutex<RoomShard>]>,
+    vanity_ids: Mutex<HashMap<String, RoomId>>,
+    closed_rooms: Mutex<VecDeque<(RoomId, ClosedRoom)>>,
+    #[cfg(feature = "chaos")]
+    chaos: Arc<Chaos>,
 }
 
 impl RoomManager {
-    pub fn new() -> Self {
-        Self {
-            room_controllers: HashMap::new(),
+    const MAX_CLOSED_ROOMS: usize = 64;
+    const SHARDS: usize = 16;
+
+    pub fn new(
+        config: RoomConfig,
+        content_filter: Arc<ContentFilter>,
+        storage: Arc<dyn Storage>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            heavy_runtime: HeavyRoomRuntime::new(&config.heavy_rooms)?,
+            approval_webhook: config
+                .room_approval
+                .webhook
+                .as_ref()
+                .map(ApprovalWebhook::new)
+                .transpose()?
+                .map(Arc::new),
+            room_ids: Box::new(config.room_ids),
+            event_log: EventLog::new(storage, &config.event_log),
+            config,
+            content_filter,
+            shards: (0..Self::SHARDS).map(|_| Mutex::default()).collect(),
+            vanity_ids: Mutex::default(),
+            closed_rooms: Mutex::default(),
+            #[cfg(feature = "chaos")]
+            chaos: Arc::default(),
+        })
+    }
+
+    fn shard(&self, id: RoomId) -> &Mutex<RoomShard> {
+        // the last bits of the id are random in every id format
+        let index = id.as_u128() % self.shards.len() as u128;
+        &self.shards[index as usize]
+    }
+
+    /// Runs `f` on a room while its shard is locked.
+    fn with_room<T>(
+        &self,
+        id: RoomId,
+        f: impl FnOnce(&mut RoomController) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut shard = self.shard(id).lock();
+        let controller = shard.get_mut(&id).ok_or_else(|| {
+            ClientError::new(ErrorCode::RoomNotFound, format!("Room {id} does not exist"))
+        })?;
+        f(controller)
+    }
+
+    /// Whether every shard can be locked within `timeout`. It can't if something got stuck while
+    /// holding one, which would leave every session in its rooms waiting.
+    pub fn is_responsive(&self, timeout: Duration) -> bool {
+        let deadline = std::time::Instant::now() + timeout;
+        self.shards
+            .iter()
+            .all(|shard| shard.try_lock_until(deadline).is_some())
+    }
+
+    /// Collects something from every room that `f` returns it for. Shards are locked one after
+    /// the other, so the result isn't a consistent snapshot of all rooms at once.
+    fn filter_map_rooms<T>(&self, mut f: impl FnMut(&RoomController) -> Option<T>) -> Vec<T> {
+        let mut rooms = Vec::new();
+        for shard in self.shards.iter() {
+            rooms.extend(shard.lock().values().filter_map(&mut f));
+        }
+        rooms
+    }
+
+    fn remember_closed_room(&self, id: RoomId, closed: ClosedRoom) {
+        let mut closed_rooms = self.closed_rooms.lock();
+        if closed_rooms.len() >= Self::MAX_CLOSED_ROOMS {
+            closed_rooms.pop_front();
+        }
+        closed_rooms.push_back((id, closed));
+    }
+
+    /// Frees up the vanity id of a room that is gone, so that a new room can take it.
+    fn release_vanity_id(&self, id: RoomId, vanity_id: Option<&String>) {
+        let Some(vanity_id) = vanity_id else {
+            return;
+        };
+        let vanity_id = vanity::normalize_vanity_id(vanity_id);
+        let mut vanity_ids = self.vanity_ids.lock();
+        if vanity_ids.get(&vanity_id) == Some(&id) {
+            vanity_ids.remove(&vanity_id);
+        }
+    }
+
+    /// Finds the id of the room that a client refers to. Ids are returned as they are, even if
+    /// there is no such room.
+    pub fn resolve_room(&self, room: &RoomRef) -> Option<RoomId> {
+        match room {
+            RoomRef::Id(id) => Some(*id),
+            RoomRef::Vanity(vanity_id) => self
+                .vanity_ids
+                .lock()
+                .get(&vanity::normalize_vanity_id(vanity_id))
+                .copied(),
+        }
+    }
+
+    /// Explains why a room can't be found, which is nicer than a bare "does not exist" if the
+    /// room was closed recently.
+    pub fn describe_missing_room(&self, id: RoomId) -> String {
+        self.closed_rooms
+            .lock()
+            .iter()
+            .rev()
+            .find(|(closed_id, _)| *closed_id == id)
+            .map(|(_, closed)| closed.to_string())
+            .unwrap_or_else(|| format!("Room {id} does not exist"))
+    }
+
+    /// Forgets about rooms whose task has finished, either because they were closed or because
+    /// they crashed. Dropping the controller invalidates all remaining handles to the room.
+    async fn prune_rooms(&self) {
+        let mut finished = Vec::new();
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock();
+            let ids: Vec<RoomId> = shard
+                .values()
+                .filter(|controller| controller.join_handle.is_finished())
+                .map(|controller| controller.id)
+                .collect();
+            finished.extend(ids.iter().filter_map(|id| shard.remove(id)));
+        }
+        for controller in finished {
+            let id = controller.id;
+            self.release_vanity_id(id, controller.settings.vanity_id.as_ref());
+            let closed = match controller.join_handle.await {
+                Ok(closed) => closed,
+                Err(err) => {
+                    log::error!("Room '{}' crashed: {err:?}", controller.settings.name);
+                    ClosedRoom {
+                        name: controller.settings.name,
+                        reason: RoomCloseReason::ServerError,
+                        closed_at: Instant::now(),
+                    }
+                }
+            };
+            self.remember_closed_room(id, closed);
         }
     }
 
     pub async fn create_room(
-        &mut self,
-        name: String,
-        password: String,
+        &self,
+        template: RoomTemplate,
+        creator: Option<&str>,
         session: SessionHandle,
     ) -> anyhow::Result<RoomHandle> {
+        let RoomTemplate {
+            settings,
+            quiet_hours,
+            queue,
+        } = template;
         log::debug!(
-            "Creating room with name {name} for session {}...",
+            "Creating room with name {} for session {}...",
+            settings.name,
             session.id
         );
+        self.prune_rooms().await;
+        if settings.default_role == UserRole::Host {
+            return Err(anyhow!("New users can't join as the host"));
+        }
         let role = UserRole::Host;
+        let settings = RoomSettings {
+            name: self
+                .content_filter
+                .apply(&settings.name, "Room names")?
+                .into_owned(),
+            metadata: settings.metadata.checked(&self.content_filter)?,
+            vanity_id: settings
+                .vanity_id
+                .as_deref()
+                .map(|id| vanity::check_vanity_id(id, &self.content_filter))
+                .transpose()?,
+            ..settings
+        };
+        let id = RoomId::generate(self.room_ids.as_ref());
+        if let Some(vanity_id) = &settings.vanity_id {
+            match self.vanity_ids.lock().entry(vanity_id.clone()) {
+                Entry::Occupied(_) => {
+                    return Err(ClientError::invalid(format!(
+                        "The room id '{vanity_id}' is already taken"
+                    ))
+                    .into());
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(id);
+                }
+            }
+        }
+        let vanity_id = settings.vanity_id.clone();
+
+        let mut controller = Room::create(
+            id,
+            settings,
+            self.config.clone(),
+            Arc::clone(&self.content_filter),
+            None,
+            Some(self.event_log.clone()),
+            self.heavy_runtime
+                .as_ref()
+                .and_then(HeavyRoomRuntime::handle),
+        );
+        controller.creator = creator.map(str::to_string);
+        #[cfg(feature = "chaos")]
+        {
+            controller.chaos = Some(Arc::clone(&self.chaos));
+        }
+        let handle = Self::open_room(&mut controller, role, session, quiet_hours, queue)
+            .inspect_err(|_| self.release_vanity_id(id, vanity_id.as_ref()))?;
+        if controller.pending_approval {
+            log::info!(
+                "Room '{}' is waiting for approval",
+                controller.settings.name
+            );
+            self.announce_pending_room(controller.pending_info());
+        }
+        self.shard(id).lock().insert(id, controller);
+        Ok(handle)
+    }
 
-        let mut controller = Room::create(name, password);
+    /// Sets up a new room from its template and lets its creator in.
+    fn open_room(
+        controller: &mut RoomController,
+        role: UserRole,
+        session: SessionHandle,
+        quiet_hours: Vec<QuietWindow>,
+        queue: Vec<PlaybackSource>,
+    ) -> anyhow::Result<RoomHandle> {
+        if !quiet_hours.is_empty() || !queue.is_empty() {
+            controller
+                .command_tx
+                .try_send(RoomCmd::ApplyTemplate(quiet_hours, queue))
+                .map_err(RoomError::from)
+                .context("Failed to set up new room")?;
+        }
         controller
-            .join(role, session)
-            .await
+            .join(role, session, false)
             .context("Failed to create new room")?;
-        let handle = controller.handle(role);
-        self.room_controllers.insert(controller.id, controller);
-        Ok(handle)
+        Ok(controller.handle(role))
+    }
+
+    /// Lets the moderation webhook know about a new room that needs approval. The room stays
+    /// pending even if the webhook can't be reached, since moderators can still find it through
+    /// the admin API.
+    fn announce_pending_room(&self, room: PendingRoom) {
+        let Some(webhook) = self.approval_webhook.clone() else {
+            return;
+        };
+        tokio::spawn(async move {
+            if let Err(err) = webhook.announce(&room).await {
+                log::error!(
+                    "Failed to announce room '{}' for approval: {err:?}",
+                    room.name
+                );
+            }
+        });
+    }
+
+    /// Recreates rooms from snapshots taken before a restart, with the same ids as before. Rooms
+    /// that nobody rejoins within the timeout are closed again.
+    pub async fn restore_rooms(&self, snapshots: Vec<RoomSnapshot>, timeout: Duration) {
+        let abandon_at = Instant::now() + timeout;
+        let now = timestamp();
+        for snapshot in snapshots {
+            let id = RoomId::from(snapshot.id);
+            let settings = RoomSettings {
+                name: snapshot.name,
+                password: snapshot.password,
+                public: snapshot.public,
+                host_succession: if snapshot.host_succession.is_empty() {
+                    HostSuccession::default()
+                } else {
+                    HostSuccession {
+                        priority: snapshot
+                            .host_succession
+                            .into_iter()
+                            .map(From::from)
+                            .collect(),
+                    }
+                },
+                default_role: snapshot.default_role.map_or(UserRole::Guest, From::from),
+                metadata: RoomMetadata::new(
+                    snapshot.topic,
+                    snapshot.tags,
+                    snapshot.image_url,
+                    snapshot.utc_offset,
+                ),
+                vanity_id: snapshot
+                    .vanity_id
+                    .as_deref()
+                    .map(vanity::normalize_vanity_id),
+                chat: snapshot.chat.map(From::from).unwrap_or_default(),
+                ready_quorum: snapshot.ready_quorum.map(From::from),
+            };
+            log::info!("Restoring room '{}' ({id})", settings.name);
+            let history = self.event_log.load(*id).await.unwrap_or_else(|err| {
+                log::error!("Failed to load the event log of room {id}: {err:?}");
+                Vec::new()
+            });
+            let mut controller = Room::create(
+                id,
+                settings,
+                self.config.clone(),
+                Arc::clone(&self.content_filter),
+                Some(RestoredRoom {
+                    abandon_at,
+                    watch_positions: snapshot
+                        .watch_positions
+                        .into_iter()
+                        .map(|snapshot| {
+                            let position = WatchPosition {
+                                page_href: snapshot.page_href,
+                                state: PlaybackState {
+                                    timestamp: now,
+                                    playing: false,
+                                    time: snapshot.position,
+                                    duration: None,
+                                },
+                            };
+                            (snapshot.subject, position)
+                        })
+                        .collect(),
+                    pending_approval: snapshot.pending_approval,
+                    history,
+                }),
+                Some(self.event_log.clone()),
+                self.heavy_runtime
+                    .as_ref()
+                    .and_then(HeavyRoomRuntime::handle),
+            );
+            controller.creator = snapshot.creator;
+            #[cfg(feature = "chaos")]
+            {
+                controller.chaos = Some(Arc::clone(&self.chaos));
+            }
+            controller.restored_roles = snapshot
+                .members
+                .into_iter()
+                .map(|member| (member.subject, member.role.into()))
+                .collect();
+            if let Some(vanity_id) = &controller.settings.vanity_id {
+                self.vanity_ids.lock().insert(vanity_id.clone(), id);
+            }
+            self.shard(id).lock().insert(id, controller);
+        }
+    }
+
+    pub async fn snapshot_rooms(&self) -> Vec<RoomSnapshot> {
+        self.prune_rooms().await;
+        self.filter_map_rooms(|controller| Some(controller.snapshot()))
+    }
+
+    pub async fn list_public_rooms(&self) -> Vec<PublicRoomInfo> {
+        self.prune_rooms().await;
+        self.filter_map_rooms(|controller| {
+            (controller.settings.public && !controller.pending_approval)
+                .then(|| controller.public_info())
+        })
+    }
+
+    pub async fn list_all_rooms(&self) -> Vec<RoomInfo> {
+        self.prune_rooms().await;
+        self.filter_map_rooms(|controller| Some(controller.info()))
     }
 
-    pub fn get_room_password(&self, id: RoomId) -> Option<String> {
-        let controller = self.room_controllers.get(&id)?;
-        Some(controller.password.clone())
+    pub async fn list_pending_rooms(&self) -> Vec<PendingRoom> {
+        self.prune_rooms().await;
+        self.filter_map_rooms(|controller| {
+            controller
+                .pending_approval
+                .then(|| controller.pending_info())
+        })
     }
 
+    /// Runs `f` on a room that is waiting for approval while its shard is locked.
+    fn with_pending_room<T>(
+        &self,
+        id: RoomId,
+        f: impl FnOnce(&mut RoomController) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        self.with_room(id, |controller| {
+            if !controller.pending_approval {
+                return Err(
+                    ClientError::invalid(format!("Room {id} is not waiting for approval")).into(),
+                );
+            }
+            f(controller)
+        })
+    }
+
+    /// Lists a room that was waiting for approval and opens it to everyone.
+    pub async fn approve_room(&self, id: RoomId) -> anyhow::Result<()> {
+        self.prune_rooms().await;
+        let command_tx = self.with_pending_room(id, |controller| {
+            controller.pending_approval = false;
+            Ok(controller.command_tx.clone())
+        })?;
+        command_tx
+            .send(RoomCmd::Approve)
+            .await
+            .context(format!("Failed to approve room {id}"))
+    }
+
+    /// Closes a room that was waiting for approval.
+    pub async fn deny_room(&self, id: RoomId) -> anyhow::Result<()> {
+        self.prune_rooms().await;
+        self.with_pending_room(id, |_| Ok(()))?;
+        self.close_room(id, RoomCloseReason::Denied).await
+    }
+
+    /// What a room looks like from the outside, to anyone who knows its id. Rooms that are
+    /// waiting for approval can't be seen yet.
+    pub fn peek_room(&self, id: RoomId) -> Option<PublicRoomInfo> {
+        let shard = self.shard(id).lock();
+        let controller = shard.get(&id)?;
+        (!controller.pending_approval).then(|| controller.public_info())
+    }
+
+    pub fn get_room_name(&self, id: RoomId) -> Option<String> {
+        let shard = self.shard(id).lock();
+        let controller = shard.get(&id)?;
+        Some(controller.settings.name.clone())
+    }
+
+    /// Joins a room. The password is not required for open rooms, if the joining account is the
+    /// one that created the room, so that creators can easily join from their other devices, or
+    /// if the user has an invite, which also decides their role.
     pub async fn join_room(
-        &mut self,
+        &self,
         id: RoomId,
+        password: Option<&str>,
+        invite: Option<&str>,
+        subject: Option<&str>,
         session: SessionHandle,
+        anonymous: bool,
     ) -> anyhow::Result<Option<RoomHandle>> {
-        // TODO: it's probably not the best idea to assume we trust anyone who joins the room, but
-        // there isn't a system for assigning permissions yet (1.4.2025)
-        let role = UserRole::Guest;
-
-        let Some(controller) = self.room_controllers.get_mut(&id) else {
+        self.prune_rooms().await;
+        let mut shard = self.shard(id).lock();
+        let Some(controller) = shard.get_mut(&id) else {
             return Ok(None);
         };
+        let invited_role = match invite {
+            Some(token) => Some(
+                controller
+                    .invited_role(token)
+                    .ok_or_else(|| ClientError::invalid("This invite isn't valid"))?,
+            ),
+            None => None,
+        };
+        let is_creator = subject.is_some() && subject == controller.creator.as_deref();
+        if controller.pending_approval && !is_creator {
+            return Err(ClientError::not_authorized(
+                "This room is waiting for a moderator's approval",
+            )
+            .into());
+        }
+        let wrong_password = controller
+            .settings
+            .password
+            .as_deref()
+            .is_some_and(|required| password != Some(required));
+        if invited_role.is_none() && !is_creator && wrong_password {
+            return Err(ClientError::new(ErrorCode::WrongPassword, "Incorrect password").into());
+        }
+        let role = invited_role.unwrap_or(controller.settings.default_role);
         let handle = controller
-            .join(role, session)
-            .await
+            .join(role, session, anonymous)
             .context(format!("Failed to join room {id}"))?;
         Ok(Some(handle))
     }
 
-    pub async fn close_room(&mut self, id: RoomId, reason: RoomCloseReason) -> anyhow::Result<()> {
-        let Some(controller) = self.room_controllers.remove(&id) else {
-            return Ok(());
+    /// Replaces the password that users need to join a room, or opens the room to anyone if
+    /// there is no new password. Invites from before are revoked, so that whoever the old
+    /// password was meant to keep out can't use them instead.
+    pub fn set_password(&self, id: RoomId, password: Option<String>) -> anyhow::Result<()> {
+        self.with_room(id, |controller| {
+            controller.settings.password = password;
+            controller.invites.clear();
+            Ok(())
+        })
+    }
+
+    /// Creates an invite token that lets users join a room with the given role.
+    pub fn create_invite(&self, id: RoomId, role: UserRole) -> anyhow::Result<String> {
+        self.with_room(id, |controller| controller.create_invite(role))
+    }
+
+    /// Joins a room as an invisible observer. Observers don't need the password, since only
+    /// trusted API keys may observe rooms.
+    pub async fn observe_room(
+        &self,
+        id: RoomId,
+        session: SessionHandle,
+    ) -> anyhow::Result<Option<RoomHandle>> {
+        self.prune_rooms().await;
+        let mut shard = self.shard(id).lock();
+        let Some(controller) = shard.get_mut(&id) else {
+            return Ok(None);
         };
-        controller
-            .close(reason)
-            .await
+        let handle = controller
+            .observe(session)
+            .context(format!("Failed to observe room {id}"))?;
+        Ok(Some(handle))
+    }
+
+    #[cfg(feature = "chaos")]
+    pub fn chaos(&self) -> Arc<Chaos> {
+        Arc::clone(&self.chaos)
+    }
+
+    /// Aborts the task of a room, as if it had crashed.
+    #[cfg(feature = "chaos")]
+    pub fn kill_room(&self, id: RoomId) -> anyhow::Result<()> {
+        self.with_room(id, |controller| {
+            log::warn!("Killing room '{}' for chaos", controller.settings.name);
+            controller.join_handle.abort();
+            Ok(())
+        })
+    }
+
+    pub async fn close_room(&self, id: RoomId, reason: RoomCloseReason) -> anyhow::Result<()> {
+        let Some(controller) = self.shard(id).lock().remove(&id) else {
+            return Ok(());
+        };
+        self.release_vanity_id(id, controller.settings.vanity_id.as_ref());
+        let closed = controller
+            .close(reason)
+            .await
             .context(format!("Failed to close room {id}"))?;
+        self.remember_closed_room(id, closed);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod invariants;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        content_filter::FilterMode,
+        playback::{DisconnectReason, Telemetry},
+        storage::{self, FileStorageConfig, StorageConfig},
+        testing::FakeSession,
+    };
+
+    /// The settings of a private room without a password, for tests to adjust as they need.
+    pub(super) fn test_settings() -> dto::RoomCreateMsgBodyV1 {
+        dto::RoomCreateMsgBodyV1 {
+            name: "Movie night".to_string(),
+            password: None,
+            public: false,
+            host_succession: Vec::new(),
+            default_role: None,
+            topic: None,
+            tags: Vec::new(),
+            image_url: None,
+            utc_offset: None,
+            vanity_id: None,
+            chat: None,
+            ready_quorum: None,
+        }
+    }
+
+    fn test_room() -> RoomController {
+        test_room_with(test_settings(), RoomConfig::default())
+    }
+
+    fn test_room_with(settings: dto::RoomCreateMsgBodyV1, config: RoomConfig) -> RoomController {
+        Room::create(
+            RoomId::new(),
+            settings.into(),
+            config,
+            Arc::default(),
+            None,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn should_rank_roles_by_host_succession_priority() {
+        // given
+        let succession = RoomSettings::from(dto::RoomCreateMsgBodyV1 {
+            host_succession: vec![dto::RoomUserRoleV1::Spectator],
+            ..test_settings()
+        })
+        .host_succession;
+
+        // when
+        let ranks = [UserRole::Spectator, UserRole::Guest].map(|role| succession.rank(role));
+
+        // then
+        assert_eq!(ranks, [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn should_only_invite_guests_and_spectators() {
+        // given
+        let mut controller = test_room();
+
+        // when
+        let spectator_invite = controller.create_invite(UserRole::Spectator);
+        let host_invite = controller.create_invite(UserRole::Host);
+
+        // then
+        assert_eq!(
+            controller.invited_role(&spectator_invite.unwrap()),
+            Some(UserRole::Spectator)
+        );
+        assert!(host_invite.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_expire_invites_and_keep_only_the_newest() {
+        // given
+        let mut controller = test_room();
+        let expiring = controller.create_invite(UserRole::Guest).unwrap();
+        time::sleep(RoomController::INVITE_TTL).await;
+
+        // when
+        let mut invites = Vec::new();
+        for _ in 0..=RoomController::MAX_INVITES {
+            invites.push(controller.create_invite(UserRole::Guest).unwrap());
+            time::sleep(Duration::from_secs(1)).await;
+        }
+
+        // then
+        assert_eq!(controller.invited_role(&expiring), None);
+        assert_eq!(controller.invites.len(), RoomController::MAX_INVITES);
+        assert_eq!(controller.invited_role(&invites[0]), None);
+        assert_eq!(
+            controller.invited_role(&invites[RoomController::MAX_INVITES]),
+            Some(UserRole::Guest)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_revoke_invites_when_the_password_changes() {
+        // given
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr = RoomManager::new(RoomConfig::default(), Arc::default(), storage).unwrap();
+        let settings = RoomSettings::from(dto::RoomCreateMsgBodyV1 {
+            password: Some("popcorn".to_string()),
+            ..test_settings()
+        });
+        let room = room_mgr
+            .create_room(
+                settings.into(),
+                None,
+                FakeSession::new(0).handle(1, "alice"),
+            )
+            .await
+            .unwrap();
+        let invite = room_mgr.create_invite(room.id, UserRole::Guest).unwrap();
+
+        // when
+        room_mgr
+            .set_password(room.id, Some("nachos".to_string()))
+            .unwrap();
+        let result = room_mgr
+            .join_room(
+                room.id,
+                None,
+                Some(&invite),
+                None,
+                FakeSession::new(0).handle(2, "bob"),
+                false,
+            )
+            .await;
+
+        // then
+        assert_eq!(error_code(&result.unwrap_err()), ErrorCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn should_tell_kicked_users_who_kicked_them() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        // so that the handle doesn't mistake the join results for the result of its request
+        host.result_rx.borrow_and_update();
+
+        // when
+        host.send_request(RoomRequest::Kick(alice.id, bob.id))
+            .await
+            .unwrap();
+
+        // then
+        let kicked_by = bob_session
+            .take_messages()
+            .into_iter()
+            .find_map(|msg| match msg {
+                SessionMsg::Kicked(kicked_by) => Some(kicked_by),
+                _ => None,
+            });
+        assert_eq!(kicked_by.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn should_tell_members_why_someone_left() {
+        // given
+        let mut controller = test_room();
+        let sessions = [
+            FakeSession::new(0),
+            FakeSession::new(0),
+            FakeSession::new(0),
+        ];
+        let (alice, bob, carol) = (
+            sessions[0].handle(1, "alice"),
+            sessions[1].handle(2, "bob"),
+            sessions[2].handle(3, "carol"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, carol.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+        guest.result_rx.borrow_and_update();
+
+        // when
+        host.send_request(RoomRequest::Kick(alice.id, bob.id))
+            .await
+            .unwrap();
+        guest.result_rx.borrow_and_update();
+        guest
+            .send_request(RoomRequest::Leave(carol.id, LeaveReason::ConnectionLost))
+            .await
+            .unwrap();
+
+        // then
+        let occupancy: Vec<(String, Option<LeaveReason>)> = sessions[0]
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::UserJoined(user) => Some((user.name, None)),
+                SessionMsg::UserLeft(user, reason) => Some((user.name, Some(reason))),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            occupancy,
+            vec![
+                ("alice".to_string(), None),
+                ("bob".to_string(), None),
+                ("carol".to_string(), None),
+                ("bob".to_string(), Some(LeaveReason::Kicked)),
+                ("carol".to_string(), Some(LeaveReason::ConnectionLost)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_let_users_leave_busy_rooms() {
+        // given
+        let mut controller = test_room_with(
+            test_settings(),
+            RoomConfig {
+                room_limits: RoomLimits {
+                    max_pending_requests: 1,
+                    ..RoomLimits::default()
+                },
+                ..RoomConfig::default()
+            },
+        );
+        let (alice, bob) = (
+            FakeSession::new(0).handle(1, "alice"),
+            FakeSession::new(0).handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        controller.join(UserRole::Host, alice, false).unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        guest.result_rx.borrow_and_update();
+        let request_tx = guest.request_tx.upgrade().unwrap();
+        request_tx.try_send(RoomRequest::GetState).unwrap();
+
+        // when
+        let busy = guest.send_request(RoomRequest::GetState).await;
+        let left = guest
+            .send_request(RoomRequest::Leave(bob.id, LeaveReason::Left))
+            .await;
+
+        // then
+        assert_eq!(error_code(&busy.unwrap_err()), ErrorCode::RoomBusy);
+        assert!(left.is_ok());
+        let mut status = controller.status_rx.clone();
+        status
+            .wait_for(|status| status.usage.users == 1)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_number_broadcasts_without_gaps() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        guest.result_rx.borrow_and_update();
+
+        // when
+        guest
+            .send_request(RoomRequest::Chat(bob.id, "Popcorn is ready".to_string()))
+            .await
+            .unwrap();
+
+        // then
+        let seqs = |session: &FakeSession| {
+            session
+                .take_sequenced_messages()
+                .into_iter()
+                .filter_map(|msg| match msg {
+                    SessionMsg::Broadcast(seq, _) => Some(seq),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let (alice_seqs, bob_seqs) = (seqs(&alice_session), seqs(&bob_session));
+        assert!(alice_seqs.len() >= 3);
+        assert!(alice_seqs.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        assert_eq!(bob_seqs.last(), alice_seqs.last());
+    }
+
+    #[tokio::test]
+    async fn should_transfer_host_to_another_member() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+        guest.result_rx.borrow_and_update();
+
+        // when
+        let by_guest = guest
+            .send_request(RoomRequest::TransferHost(bob.id, alice.id))
+            .await;
+        host.result_rx.borrow_and_update();
+        host.send_request(RoomRequest::TransferHost(alice.id, bob.id))
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(error_code(&by_guest.unwrap_err()), ErrorCode::NotAuthorized);
+        let roles = |session: &FakeSession| {
+            session
+                .take_messages()
+                .into_iter()
+                .filter_map(|msg| match msg {
+                    SessionMsg::RoleChanged(role) => Some(role),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(roles(&bob_session), vec![UserRole::Host]);
+        assert_eq!(roles(&alice_session), vec![UserRole::Guest]);
+    }
+
+    #[tokio::test]
+    async fn should_hand_the_host_role_over_when_it_is_set() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+        guest.result_rx.borrow_and_update();
+
+        // when
+        host.send_request(RoomRequest::SetRole(alice.id, bob.id, UserRole::Host))
+            .await
+            .unwrap();
+
+        // then
+        let roles = |session: &FakeSession| {
+            session
+                .take_messages()
+                .into_iter()
+                .filter_map(|msg| match msg {
+                    SessionMsg::RoleChanged(role) => Some(role),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(roles(&bob_session), vec![UserRole::Host]);
+        assert_eq!(roles(&alice_session), vec![UserRole::Guest]);
+    }
+
+    #[tokio::test]
+    async fn should_only_let_members_who_may_set_roles_set_them() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+        guest.result_rx.borrow_and_update();
+
+        // when
+        let by_guest = guest
+            .send_request(RoomRequest::SetRole(bob.id, alice.id, UserRole::Guest))
+            .await;
+        host.result_rx.borrow_and_update();
+        let by_host = host
+            .send_request(RoomRequest::SetRole(alice.id, alice.id, UserRole::Guest))
+            .await;
+
+        // then
+        assert_eq!(error_code(&by_guest.unwrap_err()), ErrorCode::NotAuthorized);
+        assert_eq!(error_code(&by_host.unwrap_err()), ErrorCode::InvalidRequest);
+        let roles = |session: &FakeSession| {
+            session
+                .take_messages()
+                .into_iter()
+                .filter_map(|msg| match msg {
+                    SessionMsg::RoleChanged(role) => Some(role),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(roles(&alice_session).is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_stop_the_playback_when_its_host_leaves() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+        guest.result_rx.borrow_and_update();
+        host.send_request(RoomRequest::PlaybackHost(alice.id))
+            .await
+            .unwrap();
+        host.send_request(RoomRequest::Playback(
+            alice.id,
+            PlaybackRequest::Start(None),
+        ))
+        .await
+        .unwrap();
+        guest.result_rx.borrow_and_update();
+        guest
+            .send_request(RoomRequest::PlaybackConnect(bob.id))
+            .await
+            .unwrap();
+        bob_session.take_messages();
+
+        // when
+        host.result_rx.borrow_and_update();
+        host.send_request(RoomRequest::Leave(alice.id, LeaveReason::Left))
+            .await
+            .unwrap();
+
+        // then
+        let stopped = bob_session.take_messages().into_iter().any(|msg| {
+            matches!(
+                msg,
+                SessionMsg::PlaybackDisconnected(DisconnectReason::Stopped(StopReason::HostLeft))
+            )
+        });
+        assert!(stopped);
+    }
+
+    #[tokio::test]
+    async fn should_let_spectators_watch_playback() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut spectator = controller
+            .join(UserRole::Spectator, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+        host.send_request(RoomRequest::PlaybackHost(alice.id))
+            .await
+            .unwrap();
+        host.send_request(RoomRequest::Playback(
+            alice.id,
+            PlaybackRequest::Start(None),
+        ))
+        .await
+        .unwrap();
+        spectator.result_rx.borrow_and_update();
+
+        // when
+        let result = spectator
+            .send_request(RoomRequest::PlaybackConnect(bob.id))
+            .await;
+
+        // then
+        result.unwrap();
+        assert!(!UserRole::Spectator.permissions().can_host);
+        let connected = bob_session
+            .take_messages()
+            .into_iter()
+            .any(|msg| matches!(msg, SessionMsg::PlaybackConnected));
+        assert!(connected);
+    }
+
+    #[tokio::test]
+    async fn should_let_only_the_host_update_metadata() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+        guest.result_rx.borrow_and_update();
+        let metadata = RoomMetadata::new(
+            Some("Classic horror".to_string()),
+            vec!["horror".to_string()],
+            None,
+            Some(60),
+        );
+
+        // when
+        let by_guest = guest
+            .send_request(RoomRequest::UpdateMetadata(
+                bob.id,
+                RoomMetadata::new(Some("Mine now".to_string()), Vec::new(), None, None),
+            ))
+            .await;
+        host.result_rx.borrow_and_update();
+        host.send_request(RoomRequest::UpdateMetadata(alice.id, metadata.clone()))
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(error_code(&by_guest.unwrap_err()), ErrorCode::NotAuthorized);
+        let mut status = controller.status_rx.clone();
+        let status = status
+            .wait_for(|status| status.metadata != RoomMetadata::default())
+            .await
+            .unwrap();
+        assert_eq!(status.metadata, metadata);
+    }
+
+    #[tokio::test]
+    async fn should_show_new_names_to_everyone() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        guest.result_rx.borrow_and_update();
+
+        // when
+        guest
+            .send_request(RoomRequest::Rename(bob.id, "robert".to_string(), false))
+            .await
+            .unwrap();
+        time::sleep(Room::STATE_BROADCAST_DELAY * 4).await;
+
+        // then
+        let names = alice_session
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::RoomState(state) => Some(
+                    state
+                        .users
+                        .iter()
+                        .map(|user| user.name.clone())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            })
+            .last()
+            .unwrap();
+        assert!(names.contains(&"robert".to_string()));
+        assert!(!names.contains(&"bob".to_string()));
+    }
+
+    #[tokio::test]
+    async fn should_filter_new_names_like_login_names() {
+        // given
+        let mut controller = Room::create(
+            RoomId::new(),
+            RoomSettings::from(test_settings()),
+            RoomConfig::default(),
+            Arc::new(ContentFilter::new(["heck"], FilterMode::Reject)),
+            None,
+            None,
+            None,
+        );
+        let alice = FakeSession::new(0).handle(1, "alice");
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+
+        // when
+        let renamed = host
+            .send_request(RoomRequest::Rename(alice.id, "heck yes".to_string(), false))
+            .await;
+        controller
+            .join(
+                UserRole::Guest,
+                FakeSession::new(0).handle(2, "heck"),
+                false,
+            )
+            .unwrap();
+        results.changed().await.unwrap();
+
+        // then
+        assert_eq!(error_code(&renamed.unwrap_err()), ErrorCode::InvalidRequest);
+        assert_eq!(
+            error_code(results.borrow_and_update().as_ref().unwrap_err()),
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(controller.status_rx.borrow().usage.users, 1);
+    }
+
+    async fn room_with_duplicate_names(policy: DuplicateNamePolicy) -> RoomController {
+        test_room_with(
+            test_settings(),
+            RoomConfig {
+                duplicate_names: policy,
+                ..RoomConfig::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn should_number_duplicate_names() {
+        // given
+        let mut controller = room_with_duplicate_names(DuplicateNamePolicy::Discriminate).await;
+        let sessions = [
+            FakeSession::new(0),
+            FakeSession::new(0),
+            FakeSession::new(0),
+        ];
+        let mut results = controller.result_rx.clone();
+        controller
+            .join(UserRole::Host, sessions[0].handle(1, "alice"), false)
+            .unwrap();
+        results.changed().await.unwrap();
+
+        // when
+        controller
+            .join(UserRole::Guest, sessions[1].handle(2, "Alice"), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        controller
+            .join(UserRole::Guest, sessions[2].handle(3, "alice"), false)
+            .unwrap();
+        results.changed().await.unwrap();
+
+        // then
+        let joined: Vec<String> = sessions[0]
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::UserJoined(user) => Some(user.name),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(joined, ["alice", "Alice (2)", "alice (3)"]);
+    }
+
+    #[tokio::test]
+    async fn should_tell_renamed_users_which_name_they_got() {
+        // given
+        let mut controller = room_with_duplicate_names(DuplicateNamePolicy::Discriminate).await;
+        let bob_session = FakeSession::new(0);
+        let bob = bob_session.handle(2, "bob");
+        let mut results = controller.result_rx.clone();
+        controller
+            .join(
+                UserRole::Host,
+                FakeSession::new(0).handle(1, "alice"),
+                false,
+            )
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        guest.result_rx.borrow_and_update();
+
+        // when
+        guest
+            .send_request(RoomRequest::Rename(bob.id, "Alice".to_string(), false))
+            .await
+            .unwrap();
+
+        // then
+        let renamed: Vec<String> = bob_session
+            .take_messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::Renamed(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(renamed, ["Alice (2)"]);
+    }
+
+    #[tokio::test]
+    async fn should_reject_duplicate_names_if_configured() {
+        // given
+        let mut controller = room_with_duplicate_names(DuplicateNamePolicy::Reject).await;
+        let (alice_session, impostor_session) = (FakeSession::new(0), FakeSession::new(0));
+        let mut results = controller.result_rx.clone();
+        controller
+            .join(UserRole::Host, alice_session.handle(1, "alice"), false)
+            .unwrap();
+        results.changed().await.unwrap();
+
+        // when
+        controller
+            .join(UserRole::Guest, impostor_session.handle(2, "alice"), false)
+            .unwrap();
+        results.changed().await.unwrap();
+
+        // then
+        let result = results.borrow_and_update();
+        assert_eq!(
+            error_code(result.as_ref().unwrap_err()),
+            ErrorCode::NameTaken
+        );
+        let rejected = impostor_session
+            .take_messages()
+            .into_iter()
+            .any(|msg| matches!(msg, SessionMsg::JoinRejected(..)));
+        assert!(rejected);
+        assert_eq!(controller.status_rx.borrow().usage.users, 1);
+    }
+
+    #[tokio::test]
+    async fn should_enforce_the_chat_policy_of_the_room() {
+        // given
+        let mut controller = test_room_with(
+            dto::RoomCreateMsgBodyV1 {
+                chat: Some(dto::RoomChatPolicyV1 {
+                    max_length: Some(4),
+                    format: dto::ChatFormatV1::EmojiOnly,
+                }),
+                ..test_settings()
+            },
+            RoomConfig::default(),
+        );
+        let alice = FakeSession::new(0).handle(1, "alice");
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+
+        // when
+        let mut chat = async |text: &str| {
+            host.result_rx.borrow_and_update();
+            host.send_request(RoomRequest::Chat(alice.id, text.to_string()))
+                .await
+                .map_err(|err| error_code(&err))
+        };
+        let results = [
+            chat("🍿🎬").await,
+            chat("🍿🍿🍿🍿🍿").await,
+            chat("pop").await,
+        ];
+
+        // then
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Err(ErrorCode::ChatTooLong),
+                Err(ErrorCode::ChatFormatNotAllowed)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_warn_hosts_about_users_from_unknown_clients() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let alice = alice_session.handle(1, "alice");
+        let mut bob = bob_session.handle(2, "alice");
+        bob.unverified = true;
+        let mut results = controller.result_rx.clone();
+        controller.join(UserRole::Host, alice, false).unwrap();
+        results.changed().await.unwrap();
+
+        // when
+        controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+
+        // then
+        let warned_about = alice_session
+            .take_messages()
+            .into_iter()
+            .find_map(|msg| match msg {
+                SessionMsg::IdentityWarning(user) => Some(user.id),
+                _ => None,
+            });
+        assert_eq!(warned_about, Some(bob.id));
+    }
+
+    #[tokio::test]
+    async fn should_tell_handles_why_their_room_closed() {
+        // given
+        let controller = test_room();
+        let session = FakeSession::new(0).handle(1, "alice");
+        let mut handle = controller.handle(UserRole::Host);
+        controller
+            .close(RoomCloseReason::ClosedByHost)
+            .await
+            .unwrap();
+
+        // when
+        let err = handle
+            .send_request(RoomRequest::Kick(session.id, session.id))
+            .await
+            .unwrap_err();
+
+        // then
+        assert_eq!(
+            err.downcast_ref::<RoomError>(),
+            Some(&RoomError::Closed(RoomCloseReason::ClosedByHost))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_close_rooms_that_only_anonymous_spectators_are_left_in() {
+        // given
+        let mut controller = test_room();
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let alice = alice_session.handle(1, "alice");
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        controller
+            .join(UserRole::Guest, bob_session.handle(2, "bob"), true)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+
+        // when
+        host.send_request(RoomRequest::Leave(alice.id, LeaveReason::Left))
+            .await
+            .unwrap();
+
+        // then
+        let closed = bob_session
+            .take_messages()
+            .into_iter()
+            .any(|msg| matches!(msg, SessionMsg::RoomClosed(_, RoomCloseReason::Empty)));
+        assert!(closed);
+    }
+
+    #[tokio::test]
+    async fn should_keep_the_password_from_observers() {
+        // given
+        let mut controller = test_room_with(
+            dto::RoomCreateMsgBodyV1 {
+                password: Some("hunter2".to_string()),
+                ..test_settings()
+            },
+            RoomConfig::default(),
+        );
+        let (alice_session, bot_session) = (FakeSession::new(0), FakeSession::new(0));
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice_session.handle(1, "alice"), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        controller.observe(bot_session.handle(2, "bot")).unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+
+        // when
+        host.send_request(RoomRequest::GetState).await.unwrap();
+        time::sleep(Room::STATE_BROADCAST_DELAY * 4).await;
+
+        // then
+        let states = |session: &FakeSession| {
+            session
+                .take_messages()
+                .into_iter()
+                .filter_map(|msg| match msg {
+                    SessionMsg::Broadcast(_, msg) => match *msg {
+                        SessionMsg::RoomState(state) => Some(state),
+                        _ => None,
+                    },
+                    SessionMsg::RoomState(state) => Some(state),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let observed = states(&bot_session);
+        assert_eq!(observed.len(), 2);
+        assert!(observed
+            .iter()
+            .all(|state| state.password.is_empty() && !state.open));
+        assert_eq!(states(&alice_session).last().unwrap().password, "hunter2");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_keep_rooms_open_while_links_are_shared() {
+        // given
+        let config = RoomConfig {
+            room_limits: RoomLimits {
+                idle_timeout: 1,
+                ..RoomLimits::default()
+            },
+            ..RoomConfig::default()
+        };
+        let mut controller = test_room_with(test_settings(), config);
+        let alice_session = FakeSession::new(0);
+        let alice = alice_session.handle(1, "alice");
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+        let expired = |session: &FakeSession| {
+            session
+                .take_messages()
+                .into_iter()
+                .any(|msg| matches!(msg, SessionMsg::RoomClosed(_, RoomCloseReason::Expired)))
+        };
+
+        // when
+        time::sleep(Duration::from_secs(50)).await;
+        host.send_request(RoomRequest::ShareLink(
+            alice.id,
+            "https://example.com/trailer".to_string(),
+        ))
+        .await
+        .unwrap();
+        time::sleep(Duration::from_secs(50)).await;
+        let after_sharing = expired(&alice_session);
+        time::sleep(Duration::from_secs(20)).await;
+
+        // then
+        assert!(!after_sharing);
+        assert!(expired(&alice_session));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_not_keep_rooms_open_for_telemetry_or_failed_requests() {
+        // given
+        let config = RoomConfig {
+            room_limits: RoomLimits {
+                idle_timeout: 1,
+                ..RoomLimits::default()
+            },
+            ..RoomConfig::default()
+        };
+        let mut controller = test_room_with(test_settings(), config);
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let (alice, bob) = (
+            alice_session.handle(1, "alice"),
+            bob_session.handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        host.result_rx.borrow_and_update();
+        guest.result_rx.borrow_and_update();
+        host.send_request(RoomRequest::PlaybackHost(alice.id))
+            .await
+            .unwrap();
+        host.send_request(RoomRequest::Playback(
+            alice.id,
+            PlaybackRequest::Start(None),
+        ))
+        .await
+        .unwrap();
+        guest.result_rx.borrow_and_update();
+        guest
+            .send_request(RoomRequest::PlaybackConnect(bob.id))
+            .await
+            .unwrap();
+        let telemetry = Telemetry {
+            state: PlaybackState {
+                timestamp: timestamp(),
+                playing: true,
+                time: 0.0,
+                duration: None,
+            },
+            buffered: None,
+            dropped_frames: None,
+        };
+
+        // when
+        for _ in 0..7 {
+            time::sleep(Duration::from_secs(10)).await;
+            guest.result_rx.borrow_and_update();
+            let _ = guest
+                .send_request(RoomRequest::Playback(
+                    bob.id,
+                    PlaybackRequest::Telemetry(telemetry.clone()),
+                ))
+                .await;
+            guest.result_rx.borrow_and_update();
+            let _ = guest
+                .send_request(RoomRequest::Playback(
+                    bob.id,
+                    PlaybackRequest::Stop(StopReason::StoppedByHost),
+                ))
+                .await;
+        }
+
+        // then
+        let expired = alice_session
+            .take_messages()
+            .into_iter()
+            .any(|msg| matches!(msg, SessionMsg::RoomClosed(_, RoomCloseReason::Expired)));
+        assert!(expired);
+    }
+
+    #[test]
+    fn should_never_expire_rooms_with_idle_timeouts_too_long_to_represent() {
+        // given
+        let limits = RoomLimits {
+            idle_timeout: u64::MAX,
+            ..RoomLimits::default()
+        };
+
+        // when
+        let deadline = limits.idle_deadline();
+
+        // then
+        assert_eq!(deadline, None);
+    }
+
+    #[tokio::test]
+    async fn should_hold_public_rooms_until_approved() {
+        // given
+        let config = RoomConfig {
+            room_approval: RoomApprovalConfig {
+                required: true,
+                webhook: None,
+            },
+            ..RoomConfig::default()
+        };
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr = RoomManager::new(config, Arc::default(), storage).unwrap();
+        let settings = RoomSettings::from(dto::RoomCreateMsgBodyV1 {
+            public: true,
+            ..test_settings()
+        });
+        let room = room_mgr
+            .create_room(
+                settings.into(),
+                Some("alice"),
+                FakeSession::new(0).handle(1, "alice"),
+            )
+            .await
+            .unwrap();
+
+        // when
+        let listed = room_mgr.list_public_rooms().await.len();
+        let stranger = room_mgr
+            .join_room(
+                room.id,
+                None,
+                None,
+                None,
+                FakeSession::new(0).handle(2, "bob"),
+                false,
+            )
+            .await;
+        let creator = room_mgr
+            .join_room(
+                room.id,
+                None,
+                None,
+                Some("alice"),
+                FakeSession::new(0).handle(3, "alice"),
+                false,
+            )
+            .await;
+
+        // then
+        assert_eq!(listed, 0);
+        assert_eq!(error_code(&stranger.unwrap_err()), ErrorCode::NotAuthorized);
+        assert!(creator.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn should_only_let_hosts_change_the_password() {
+        // given
+        let mut controller = test_room_with(
+            dto::RoomCreateMsgBodyV1 {
+                password: Some("popcorn".to_string()),
+                ..test_settings()
+            },
+            RoomConfig::default(),
+        );
+        let (alice, bob) = (
+            FakeSession::new(0).handle(1, "alice"),
+            FakeSession::new(0).handle(2, "bob"),
+        );
+        let mut results = controller.result_rx.clone();
+        let mut host = controller
+            .join(UserRole::Host, alice.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+        let mut guest = controller
+            .join(UserRole::Guest, bob.clone(), false)
+            .unwrap();
+        results.changed().await.unwrap();
+
+        // when
+        guest.result_rx.borrow_and_update();
+        let by_guest = guest
+            .send_request(RoomRequest::SetPassword(bob.id, Some("nachos".to_string())))
+            .await;
+        guest.result_rx.borrow_and_update();
+        let by_stranger = guest
+            .send_request(RoomRequest::SetPassword(
+                SessionId::from(Uuid::from_u128(3)),
+                None,
+            ))
+            .await;
+        host.result_rx.borrow_and_update();
+        let by_host = host
+            .send_request(RoomRequest::SetPassword(
+                alice.id,
+                Some("nachos".to_string()),
+            ))
+            .await;
+
+        // then
+        assert_eq!(error_code(&by_guest.unwrap_err()), ErrorCode::NotAuthorized);
+        assert_eq!(
+            error_code(&by_stranger.unwrap_err()),
+            ErrorCode::NotAuthorized
+        );
+        assert!(by_host.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_check_joins_against_the_new_password() {
+        // given
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr = RoomManager::new(RoomConfig::default(), Arc::default(), storage).unwrap();
+        let settings = RoomSettings::from(dto::RoomCreateMsgBodyV1 {
+            password: Some("popcorn".to_string()),
+            ..test_settings()
+        });
+        let room = room_mgr
+            .create_room(
+                settings.into(),
+                None,
+                FakeSession::new(0).handle(1, "alice"),
+            )
+            .await
+            .unwrap();
+
+        // when
+        room_mgr
+            .set_password(room.id, Some("nachos".to_string()))
+            .unwrap();
+        let with_old = room_mgr
+            .join_room(
+                room.id,
+                Some("popcorn"),
+                None,
+                None,
+                FakeSession::new(0).handle(2, "bob"),
+                false,
+            )
+            .await;
+        let with_new = room_mgr
+            .join_room(
+                room.id,
+                Some("nachos"),
+                None,
+                None,
+                FakeSession::new(0).handle(3, "carol"),
+                false,
+            )
+            .await;
+
+        // then
+        assert_eq!(error_code(&with_old.unwrap_err()), ErrorCode::WrongPassword);
+        assert!(with_new.unwrap().is_some());
+    }
+
+    fn snapshot(id: RoomId, members: Vec<MemberSnapshot>) -> RoomSnapshot {
+        RoomSnapshot {
+            id: *id,
+            name: "Movie night".to_string(),
+            password: None,
+            public: false,
+            creator: None,
+            default_role: None,
+            host_succession: Vec::new(),
+            members,
+            watch_positions: Vec::new(),
+            pending_approval: false,
+            topic: None,
+            tags: Vec::new(),
+            image_url: None,
+            utc_offset: None,
+            vanity_id: None,
+            chat: None,
+            ready_quorum: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_let_the_first_to_return_stand_in_for_the_host() {
+        // given
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr = RoomManager::new(RoomConfig::default(), Arc::default(), storage).unwrap();
+        let id = RoomId::new();
+        room_mgr
+            .restore_rooms(
+                vec![snapshot(
+                    id,
+                    ["alice", "bob"]
+                        .into_iter()
+                        .zip([dto::RoomUserRoleV1::Host, dto::RoomUserRoleV1::Guest])
+                        .map(|(subject, role)| MemberSnapshot {
+                            subject: subject.to_string(),
+                            role,
+                        })
+                        .collect(),
+                )],
+                Duration::from_secs(60),
+            )
+            .await;
+        let (alice_session, bob_session) = (FakeSession::new(0), FakeSession::new(0));
+        let with_subject = |session: &Arc<FakeSession>, id, name: &str| {
+            let mut handle = session.handle(id, name);
+            handle.subject = Some(name.to_string());
+            handle
+        };
+        let roles = |messages: &[SessionMsg]| {
+            messages
+                .iter()
+                .filter_map(|msg| match msg {
+                    SessionMsg::RoleChanged(role) => Some(*role),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // when
+        let bob = with_subject(&bob_session, 2, "bob");
+        room_mgr
+            .join_room(id, None, None, Some("bob"), bob, false)
+            .await
+            .unwrap();
+        time::sleep(Room::STATE_BROADCAST_DELAY * 4).await;
+        let while_away = roles(&bob_session.take_messages());
+        let alice = with_subject(&alice_session, 1, "alice");
+        room_mgr
+            .join_room(id, None, None, Some("alice"), alice, false)
+            .await
+            .unwrap();
+        time::sleep(Room::STATE_BROADCAST_DELAY * 4).await;
+
+        // then
+        let alice_messages = alice_session.take_messages();
+        let hosts = alice_messages
+            .iter()
+            .rev()
+            .filter_map(|msg| match msg {
+                SessionMsg::RoomState(state) => Some(
+                    state
+                        .users
+                        .iter()
+                        .filter(|user| user.role == dto::RoomUserRoleV1::Host)
+                        .map(|user| user.name.clone())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            })
+            .next()
+            .unwrap();
+        assert_eq!(while_away, vec![UserRole::Host]);
+        assert_eq!(roles(&bob_session.take_messages()), vec![UserRole::Guest]);
+        assert_eq!(roles(&alice_messages), vec![UserRole::Host]);
+        assert_eq!(hosts, vec!["alice".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn should_pick_up_the_event_log_of_restored_rooms() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage::create_storage(StorageConfig::File(FileStorageConfig {
+            path: dir.path().join("rooms.json"),
+        }))
+        .await
+        .unwrap();
+        let id = RoomId::new();
+        let alice_id = SessionId::from(Uuid::from_u128(1));
+        let record = |seq, event| RoomEventRecord {
+            seq,
+            at: 1_699_920_000_000,
+            event,
+        };
+        storage
+            .append_room_events(
+                *id,
+                vec![
+                    record(
+                        0,
+                        RoomEvent::Joined {
+                            user: alice_id,
+                            name: "alice".to_string(),
+                            subject: None,
+                            role: UserRole::Host,
+                            anonymous: false,
+                        },
+                    ),
+                    record(
+                        1,
+                        RoomEvent::PlaybackStarted {
+                            host: alice_id,
+                            title: "Nosferatu".to_string(),
+                            page_href: "https://example.com/nosferatu".to_string(),
+                            started_at: 1_699_920_000_000,
+                        },
+                    ),
+                ],
+            )
+            .await
+            .unwrap();
+        let room_mgr =
+            RoomManager::new(RoomConfig::default(), Arc::default(), Arc::clone(&storage)).unwrap();
+        room_mgr
+            .restore_rooms(vec![snapshot(id, Vec::new())], Duration::from_secs(60))
+            .await;
+        let session = FakeSession::new(0);
+
+        // when
+        let mut alice = room_mgr
+            .join_room(id, None, None, None, session.handle(2, "alice"), false)
+            .await
+            .unwrap()
+            .unwrap();
+        time::sleep(Room::STATE_BROADCAST_DELAY * 4).await;
+        alice
+            .send_request(RoomRequest::PlaybackHistory(SessionId::from(
+                Uuid::from_u128(2),
+            )))
+            .await
+            .unwrap();
+        time::sleep(Room::STATE_BROADCAST_DELAY * 4).await;
+
+        // then
+        let history = session
+            .take_messages()
+            .into_iter()
+            .find_map(|msg| match msg {
+                SessionMsg::PlaybackHistory(history) => Some(history),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].title, "Nosferatu");
+        assert_eq!(history[0].ended_at, Some(1_699_920_000_000));
+        let seqs: Vec<u64> = storage
+            .load_room_events(*id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.seq)
+            .collect();
+        assert_eq!(seqs[..4], [0, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn should_let_anyone_join_open_rooms() {
+        // given
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr = RoomManager::new(RoomConfig::default(), Arc::default(), storage).unwrap();
+        let settings = RoomSettings::from(test_settings());
+        let room = room_mgr
+            .create_room(
+                settings.into(),
+                None,
+                FakeSession::new(0).handle(1, "alice"),
+            )
+            .await
+            .unwrap();
+
+        // when
+        let without_password = room_mgr
+            .join_room(
+                room.id,
+                None,
+                None,
+                None,
+                FakeSession::new(0).handle(2, "bob"),
+                false,
+            )
+            .await;
+        let with_any_password = room_mgr
+            .join_room(
+                room.id,
+                Some("popcorn"),
+                None,
+                None,
+                FakeSession::new(0).handle(3, "carol"),
+                false,
+            )
+            .await;
+
+        // then
+        assert!(without_password.unwrap().is_some());
+        assert!(with_any_password.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn should_find_rooms_by_vanity_id() {
+        // given
+        let config = RoomConfig {
+            room_ids: IdFormat::TimeOrdered,
+            ..RoomConfig::default()
+        };
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr = RoomManager::new(config, Arc::default(), storage).unwrap();
+        let settings = RoomSettings::from(dto::RoomCreateMsgBodyV1 {
+            vanity_id: Some("Movie-Night".to_string()),
+            ..test_settings()
+        });
+        let room = room_mgr
+            .create_room(
+                settings.clone().into(),
+                None,
+                FakeSession::new(0).handle(1, "alice"),
+            )
+            .await
+            .unwrap();
+
+        // when
+        let by_vanity_id: dto::RoomRefV1 = serde_json::from_str(r#""movie-night""#).unwrap();
+        let by_id: dto::RoomRefV1 = serde_json::from_str(&format!(r#""{}""#, room.id)).unwrap();
+        let duplicate = room_mgr
+            .create_room(
+                RoomSettings {
+                    vanity_id: Some("MOVIE-NIGHT".to_string()),
+                    ..settings
+                }
+                .into(),
+                None,
+                FakeSession::new(0).handle(2, "bob"),
+            )
+            .await;
+
+        // then
+        assert_eq!(room.id.get_version_num(), 7);
+        assert_eq!(room_mgr.resolve_room(&by_vanity_id.into()), Some(room.id));
+        assert_eq!(
+            room_mgr.resolve_room(&RoomRef::Vanity("Movie-NIGHT".to_string())),
+            Some(room.id)
+        );
+        assert_eq!(room_mgr.resolve_room(&by_id.into()), Some(room.id));
+        assert_eq!(
+            room_mgr.resolve_room(&RoomRef::Vanity("book-club".to_string())),
+            None
+        );
+        assert_eq!(
+            error_code(&duplicate.unwrap_err()),
+            ErrorCode::InvalidRequest
+        );
+    }
+
+    #[tokio::test]
+    async fn should_free_vanity_ids_of_closed_rooms() {
+        // given
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr = RoomManager::new(RoomConfig::default(), Arc::default(), storage).unwrap();
+        let settings = RoomSettings::from(dto::RoomCreateMsgBodyV1 {
+            vanity_id: Some("movie-night".to_string()),
+            ..test_settings()
+        });
+        let first = room_mgr
+            .create_room(
+                settings.clone().into(),
+                None,
+                FakeSession::new(0).handle(1, "alice"),
+            )
+            .await
+            .unwrap();
+
+        // when
+        room_mgr
+            .close_room(first.id, RoomCloseReason::ClosedByHost)
+            .await
+            .unwrap();
+        let second = room_mgr
+            .create_room(settings.into(), None, FakeSession::new(0).handle(2, "bob"))
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(
+            room_mgr.resolve_room(&RoomRef::Vanity("movie-night".to_string())),
+            Some(second.id)
+        );
+        assert!(room_mgr.describe_missing_room(first.id).contains("closed"));
+    }
+
+    #[tokio::test]
+    async fn should_not_respond_while_a_shard_is_stuck() {
+        // given
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr = RoomManager::new(RoomConfig::default(), Arc::default(), storage).unwrap();
+        let timeout = Duration::from_millis(10);
+        let responsive = room_mgr.is_responsive(timeout);
+
+        // when
+        let _stuck = room_mgr.shards[3].lock();
+
+        // then
+        assert!(responsive);
+        assert!(!room_mgr.is_responsive(timeout));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn should_create_rooms_concurrently() {
+        // given
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr =
+            Arc::new(RoomManager::new(RoomConfig::default(), Arc::default(), storage).unwrap());
+
+        // when
+        let tasks: Vec<_> = (0..64)
+            .map(|i| {
+                let room_mgr = Arc::clone(&room_mgr);
+                tokio::spawn(async move {
+                    let settings = RoomSettings::from(dto::RoomCreateMsgBodyV1 {
+                        name: format!("Room {i}"),
+                        public: true,
+                        ..test_settings()
+                    });
+                    room_mgr
+                        .create_room(
+                            settings.into(),
+                            None,
+                            FakeSession::new(0).handle(i, "alice"),
+                        )
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        // then
+        assert_eq!(room_mgr.list_public_rooms().await.len(), 64);
+    }
+
+    #[test]
+    fn should_prefer_guests_by_default() {
+        // given
+        let succession = HostSuccession::default();
+
+        // when
+        let ranks = [UserRole::Guest, UserRole::Spectator].map(|role| succession.rank(role));
+
+        // then
+        assert!(ranks[0] < ranks[1]);
+    }
+
+    #[test]
+    fn should_allow_chat_bursts_up_to_limit() {
+        // given
+        let mut limit = ChatConfig::default().rate_limit();
+
+        // when
+        let allowed = (0..6).filter(|_| limit.try_take()).count();
+
+        // then
+        assert_eq!(allowed, 5);
+    }
+
+    fn at(hour: u64, minute: u64) -> u64 {
+        // some day in 2023, plus the time of day
+        1_699_920_000_000 + (hour * 60 + minute) * 60_000
+    }
+
+    #[test]
+    fn should_merge_change_origins() {
+        // given
+        let alice = SessionId::from(uuid::Uuid::from_u128(1));
+        let bob = SessionId::from(uuid::Uuid::from_u128(2));
+
+        // when
+        let same = ChangeOrigin::User(alice).merge(ChangeOrigin::User(alice));
+        let different = ChangeOrigin::User(alice).merge(ChangeOrigin::User(bob));
+        let everyone = ChangeOrigin::User(alice).merge(ChangeOrigin::Everyone);
+
+        // then
+        assert_eq!(same, ChangeOrigin::User(alice));
+        assert_eq!(different, ChangeOrigin::Everyone);
+        assert_eq!(everyone, ChangeOrigin::Everyone);
+    }
+
+    #[test]
+    fn should_contain_times_within_quiet_window() {
+        // given
+        let window = QuietWindow::try_from(dto::RoomQuietWindowV1 {
+            start: 13 * 60,
+            end: 15 * 60,
+        })
+        .unwrap();
+
+        // when
+        let contained = [at(12, 59), at(13, 0), at(14, 30), at(15, 0)].map(|t| window.contains(t));
+
+        // then
+        assert_eq!(contained, [false, true, true, false]);
+    }
+
+    #[test]
+    fn should_wrap_quiet_window_around_midnight() {
+        // given
+        let window = QuietWindow::try_from(dto::RoomQuietWindowV1 {
+            start: 22 * 60,
+            end: 7 * 60,
+        })
+        .unwrap();
+
+        // when
+        let contained = [at(21, 59), at(23, 0), at(3, 0), at(7, 0)].map(|t| window.contains(t));
+
+        // then
+        assert_eq!(contained, [false, true, true, false]);
+    }
+
+    #[test]
+    fn should_reject_quiet_window_outside_of_day() {
+        // when
+        let result = QuietWindow::try_from(dto::RoomQuietWindowV1 {
+            start: 0,
+            end: 24 * 60,
+        });
+
+        // then
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::WebhookConfig;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RoomApprovalConfig {
+    /// Holds new public rooms until a moderator approves them through the admin API. Until
+    /// then, they aren't listed and only their creator can join them.
+    pub required: bool,
+
+    /// Where rooms that need approval are announced, if anywhere.
+    pub webhook: Option<WebhookConfig>,
+}
+
+/// A room that is waiting for a moderator's approval, as the moderation webhook and the admin
+/// API describe it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PendingRoom {
+    pub id: String,
+    pub name: String,
+    /// The account subject of the creator, if they have one.
+    pub creator: Option<String>,
+}
+
+/// Announces rooms that need approval to the moderation webhook.
+pub struct ApprovalWebhook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl ApprovalWebhook {
+    pub fn new(config: &WebhookConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .context("Failed to create moderation webhook client")?;
+        Ok(Self {
+            url: config.url.clone(),
+            client,
+        })
+    }
+
+    pub async fn announce(&self, room: &PendingRoom) -> anyhow::Result<()> {
+        self.client
+            .post(&self.url)
+            .json(room)
+            .send()
+            .await
+            .context("Moderation webhook request failed")?
+            .error_for_status()
+            .context("Moderation webhook returned an error")?;
+        Ok(())
+    }
+}
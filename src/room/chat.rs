@@ -0,0 +1,281 @@
+use serde::Deserialize;
+
+use crate::{
+    errors::{ClientError, ErrorCode},
+    messages::dto,
+    utils::TokenBucket,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    /// The maximum length of a chat message, in characters.
+    pub max_length: usize,
+
+    /// What chat messages may contain. Rooms can restrict this further, but not loosen it.
+    pub format: ChatFormat,
+
+    /// How many chat messages a user can send in quick succession.
+    pub burst: u32,
+
+    /// How many chat messages a user can send per minute in the long run.
+    pub messages_per_minute: u32,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 500,
+            format: ChatFormat::default(),
+            burst: 5,
+            messages_per_minute: 20,
+        }
+    }
+}
+
+impl ChatConfig {
+    pub(super) fn rate_limit(&self) -> TokenBucket {
+        TokenBucket::new(self.burst, f64::from(self.messages_per_minute) / 60.0)
+    }
+
+    /// The config that applies in a room with the given policy, which is whichever is stricter
+    /// for each setting.
+    pub fn restricted_by(&self, policy: ChatPolicy) -> Self {
+        Self {
+            max_length: policy.max_length.map_or(self.max_length, |max_length| {
+                max_length.min(self.max_length)
+            }),
+            format: self.format.max(policy.format),
+            ..self.clone()
+        }
+    }
+
+    /// Checks whether a chat message may be sent. The length is checked first, so that huge
+    /// messages are turned away before anything else looks at them.
+    pub fn check(&self, text: &str) -> Result<(), ClientError> {
+        if text.chars().count() > self.max_length {
+            return Err(ClientError::new(
+                ErrorCode::ChatTooLong,
+                format!(
+                    "Chat messages can't be longer than {} characters",
+                    self.max_length
+                ),
+            ));
+        }
+        self.format.check(text)
+    }
+}
+
+/// What chat messages may contain, from least to most restrictive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatFormat {
+    /// Anything goes, including markdown formatting.
+    #[default]
+    Markdown,
+
+    /// Text without markdown formatting.
+    PlainText,
+
+    /// Nothing but emoji.
+    EmojiOnly,
+}
+
+impl ChatFormat {
+    fn check(self, text: &str) -> Result<(), ClientError> {
+        match self {
+            Self::Markdown => Ok(()),
+            Self::PlainText if has_markdown(text) => Err(ClientError::new(
+                ErrorCode::ChatFormatNotAllowed,
+                "Chat messages can't use markdown formatting in this room",
+            )),
+            Self::PlainText => Ok(()),
+            Self::EmojiOnly if !is_emoji_only(text) => Err(ClientError::new(
+                ErrorCode::ChatFormatNotAllowed,
+                "Chat messages can only contain emoji in this room",
+            )),
+            Self::EmojiOnly => Ok(()),
+        }
+    }
+}
+
+impl From<dto::ChatFormatV1> for ChatFormat {
+    fn from(value: dto::ChatFormatV1) -> Self {
+        match value {
+            dto::ChatFormatV1::Markdown => Self::Markdown,
+            dto::ChatFormatV1::PlainText => Self::PlainText,
+            dto::ChatFormatV1::EmojiOnly => Self::EmojiOnly,
+        }
+    }
+}
+
+impl From<ChatFormat> for dto::ChatFormatV1 {
+    fn from(value: ChatFormat) -> Self {
+        match value {
+            ChatFormat::Markdown => Self::Markdown,
+            ChatFormat::PlainText => Self::PlainText,
+            ChatFormat::EmojiOnly => Self::EmojiOnly,
+        }
+    }
+}
+
+/// Restrictions on chat that the creator of a room chose for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChatPolicy {
+    pub max_length: Option<usize>,
+    pub format: ChatFormat,
+}
+
+impl From<dto::RoomChatPolicyV1> for ChatPolicy {
+    fn from(value: dto::RoomChatPolicyV1) -> Self {
+        Self {
+            max_length: value.max_length,
+            format: value.format.into(),
+        }
+    }
+}
+
+impl From<ChatPolicy> for dto::RoomChatPolicyV1 {
+    fn from(value: ChatPolicy) -> Self {
+        Self {
+            max_length: value.max_length,
+            format: value.format.into(),
+        }
+    }
+}
+
+/// Markers that clients would render as formatting anywhere in a message.
+const INLINE_MARKDOWN: [&str; 5] = ["**", "__", "~~", "`", "]("];
+
+/// Whether the text uses markdown formatting. Single asterisks and underscores are left alone,
+/// since they show up in plain text all the time.
+fn has_markdown(text: &str) -> bool {
+    INLINE_MARKDOWN.iter().any(|marker| text.contains(marker))
+        || text.lines().any(|line| {
+            let line = line.trim_start();
+            line.starts_with('#') || line.starts_with('>')
+        })
+}
+
+/// Whether the text consists of emoji only. Digits and the like only count as part of a keycap,
+/// so that plain numbers don't pass.
+fn is_emoji_only(text: &str) -> bool {
+    text.chars().any(|c| is_emoji(c) || c == '\u{20e3}')
+        && text
+            .chars()
+            .all(|c| c.is_whitespace() || is_emoji(c) || is_emoji_component(c))
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00a9}'
+            | '\u{00ae}'
+            | '\u{203c}'
+            | '\u{2049}'
+            | '\u{2122}'
+            | '\u{2139}'
+            | '\u{2194}'..='\u{21aa}'
+            | '\u{2300}'..='\u{23ff}'
+            | '\u{24c2}'
+            | '\u{25aa}'..='\u{25fe}'
+            | '\u{2600}'..='\u{27bf}'
+            | '\u{2934}'..='\u{2935}'
+            | '\u{2b00}'..='\u{2bff}'
+            | '\u{3030}'
+            | '\u{303d}'
+            | '\u{3297}'
+            | '\u{3299}'
+            | '\u{1f000}'..='\u{1faff}'
+    )
+}
+
+/// Characters that only appear as part of an emoji sequence, like keycaps, skin tone variants
+/// or families.
+fn is_emoji_component(c: char) -> bool {
+    matches!(
+        c,
+        '0'..='9'
+            | '#'
+            | '*'
+            | '\u{200d}'
+            | '\u{20e3}'
+            | '\u{fe0e}'
+            | '\u{fe0f}'
+            | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_tighten_the_server_config() {
+        // given
+        let config = ChatConfig {
+            max_length: 200,
+            format: ChatFormat::PlainText,
+            ..ChatConfig::default()
+        };
+
+        // when
+        let restricted = config.restricted_by(ChatPolicy {
+            max_length: Some(1000),
+            format: ChatFormat::Markdown,
+        });
+
+        // then
+        assert_eq!(restricted.max_length, 200);
+        assert_eq!(restricted.format, ChatFormat::PlainText);
+    }
+
+    #[test]
+    fn should_tell_markdown_from_plain_text() {
+        // given
+        let messages = [
+            "**spoilers**",
+            "see [here](https://example.com)",
+            "> quoted",
+            "use `unwrap`",
+            "that was a 5*",
+            "snake_case_names",
+        ];
+
+        // when
+        let markdown = messages.map(has_markdown);
+
+        // then
+        assert_eq!(markdown, [true, true, true, true, false, false]);
+    }
+
+    #[test]
+    fn should_tell_emoji_from_other_text() {
+        // given
+        let messages = ["🍿🎬", "👍🏽 ❤️", "👨‍👩‍👧", "1️⃣", "123", "nice 🍿", ""];
+
+        // when
+        let emoji_only = messages.map(is_emoji_only);
+
+        // then
+        assert_eq!(emoji_only, [true, true, true, true, false, false, false]);
+    }
+
+    #[test]
+    fn should_report_structured_errors() {
+        // given
+        let config = ChatConfig {
+            max_length: 10,
+            format: ChatFormat::EmojiOnly,
+            ..ChatConfig::default()
+        };
+
+        // when
+        let too_long = config.check("🍿🍿🍿🍿🍿🍿🍿🍿🍿🍿🍿").unwrap_err();
+        let not_emoji = config.check("popcorn").unwrap_err();
+
+        // then
+        assert_eq!(too_long.code, ErrorCode::ChatTooLong);
+        assert_eq!(not_emoji.code, ErrorCode::ChatFormatNotAllowed);
+    }
+}
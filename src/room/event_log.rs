@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::storage::Storage;
+
+use super::RoomEventRecord;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct EventLogConfig {
+    /// How many of its latest events are kept for each room. Older ones are trimmed off every so
+    /// often, so a log may hold up to twice as many.
+    pub retention: u64,
+}
+
+impl Default for EventLogConfig {
+    fn default() -> Self {
+        Self { retention: 1000 }
+    }
+}
+
+#[derive(Debug)]
+enum LogOp {
+    Append(Uuid, Vec<RoomEventRecord>),
+    Delete(Uuid),
+}
+
+/// Writes room events to the storage from a task of its own, so that rooms never wait for it.
+#[derive(Clone)]
+pub struct EventLog {
+    storage: Arc<dyn Storage>,
+    op_tx: mpsc::Sender<LogOp>,
+}
+
+impl EventLog {
+    /// How many writes may be waiting for the storage before new events are dropped.
+    const CAPACITY: usize = 256;
+
+    pub fn new(storage: Arc<dyn Storage>, config: &EventLogConfig) -> Self {
+        let (op_tx, op_rx) = mpsc::channel(Self::CAPACITY);
+        tokio::spawn(write(Arc::clone(&storage), config.retention.max(1), op_rx));
+        Self { storage, op_tx }
+    }
+
+    /// Events that can't be stored are lost, but that is no reason to disturb the room.
+    pub fn append(&self, room: Uuid, events: Vec<RoomEventRecord>) {
+        if self.op_tx.try_send(LogOp::Append(room, events)).is_err() {
+            log::warn!("The event log can't keep up; dropping events of room {room}");
+        }
+    }
+
+    /// Deletes the log of a closed room once everything before has been written.
+    pub fn delete(&self, room: Uuid) {
+        let op_tx = self.op_tx.clone();
+        tokio::spawn(async move {
+            let _ = op_tx.send(LogOp::Delete(room)).await;
+        });
+    }
+
+    pub async fn load(&self, room: Uuid) -> anyhow::Result<Vec<RoomEventRecord>> {
+        self.storage.load_room_events(room).await
+    }
+}
+
+async fn write(storage: Arc<dyn Storage>, retention: u64, mut op_rx: mpsc::Receiver<LogOp>) {
+    while let Some(op) = op_rx.recv().await {
+        let result = match op {
+            LogOp::Append(room, events) => {
+                let seqs = events
+                    .first()
+                    .zip(events.last())
+                    .map(|(first, last)| (first.seq, last.seq));
+                let result = storage.append_room_events(room, events).await;
+                match seqs {
+                    // trimming whenever another `retention` events have come in keeps the cost
+                    // of rewriting logs down
+                    Some((first, last))
+                        if result.is_ok()
+                            && last >= retention
+                            && first / retention != (last + 1) / retention =>
+                    {
+                        storage.trim_room_events(room, last + 1 - retention).await
+                    }
+                    _ => result,
+                }
+            }
+            LogOp::Delete(room) => storage.delete_room_events(room).await,
+        };
+        if let Err(err) = result {
+            log::error!("Failed to write to the room event log: {err:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{
+        room::{RoomCloseReason, RoomEvent},
+        storage::{self, FileStorageConfig, StorageConfig},
+    };
+
+    fn event(seq: u64) -> RoomEventRecord {
+        RoomEventRecord {
+            seq,
+            at: 1_699_920_000_000,
+            event: RoomEvent::Closed {
+                reason: RoomCloseReason::Empty,
+            },
+        }
+    }
+
+    async fn seqs_eventually(event_log: &EventLog, room: Uuid, expected: &[u64]) -> Vec<u64> {
+        let load = || async {
+            let events = event_log.load(room).await.unwrap();
+            events
+                .into_iter()
+                .map(|record| record.seq)
+                .collect::<Vec<_>>()
+        };
+        let _ = timeout(Duration::from_secs(1), async {
+            while load().await != expected {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await;
+        load().await
+    }
+
+    #[tokio::test]
+    async fn should_trim_old_events_and_delete_the_log_of_closed_rooms() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage::create_storage(StorageConfig::File(FileStorageConfig {
+            path: dir.path().join("rooms.json"),
+        }))
+        .await
+        .unwrap();
+        let event_log = EventLog::new(storage, &EventLogConfig { retention: 2 });
+        let room = Uuid::from_u128(1);
+
+        // when
+        event_log.append(room, vec![event(0), event(1)]);
+        event_log.append(room, vec![event(2)]);
+        let before_trim = seqs_eventually(&event_log, room, &[0, 1, 2]).await;
+        event_log.append(room, vec![event(3)]);
+        let trimmed = seqs_eventually(&event_log, room, &[2, 3]).await;
+        event_log.delete(room);
+        let deleted = seqs_eventually(&event_log, room, &[]).await;
+
+        // then
+        assert_eq!(before_trim, vec![0, 1, 2]);
+        assert_eq!(trimmed, vec![2, 3]);
+        assert!(deleted.is_empty());
+    }
+}
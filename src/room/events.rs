@@ -0,0 +1,612 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::session::SessionId;
+
+use super::{
+    HostSuccession, LeaveReason, ModerationAction, ModerationEntry, PlayedSource, QuietWindow,
+    RoomCloseReason, RoomMetadata, SharedLink, UserData, UserRole,
+};
+
+/// Something that happened in a room. Events are the only thing that changes a [`RoomModel`], so
+/// applying the same events in the same order always results in the same room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomEvent {
+    Joined {
+        user: SessionId,
+        name: String,
+        subject: Option<String>,
+        role: UserRole,
+        /// Anonymous spectators are counted, but not listed by name.
+        #[serde(default)]
+        anonymous: bool,
+    },
+    Left {
+        user: SessionId,
+        #[serde(default)]
+        reason: LeaveReason,
+    },
+    Kicked {
+        user: SessionId,
+        by: SessionId,
+        #[serde(default)]
+        kicked_at: u64,
+    },
+    RoleChanged {
+        user: SessionId,
+        role: UserRole,
+        /// The user who changed the role.
+        by: SessionId,
+        #[serde(default)]
+        changed_at: u64,
+    },
+    /// A user changed the name they go by.
+    Renamed {
+        user: SessionId,
+        name: String,
+    },
+    /// The host left and someone else took over.
+    HostSucceeded {
+        user: SessionId,
+    },
+    QuietHoursSet {
+        windows: Vec<QuietWindow>,
+    },
+    /// The host changed what the room is about.
+    MetadataUpdated {
+        metadata: RoomMetadata,
+    },
+    /// The host changed the password. The password itself is left out of the log.
+    PasswordChanged,
+    ChatSent {
+        user: SessionId,
+        text: String,
+        sent_at: u64,
+    },
+    LinkShared {
+        user: SessionId,
+        url: String,
+        shared_at: u64,
+    },
+    /// The host started playing something new. Whatever played before has ended.
+    PlaybackStarted {
+        host: SessionId,
+        title: String,
+        page_href: String,
+        started_at: u64,
+    },
+    PlaybackEnded {
+        ended_at: u64,
+    },
+    Closed {
+        reason: RoomCloseReason,
+    },
+}
+
+/// An event as it is kept in a room's event log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomEventRecord {
+    /// Counts the events of a room, carrying on where its log left off when the room is restored
+    /// after a restart.
+    pub seq: u64,
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: RoomEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub name: String,
+    pub subject: Option<String>,
+    pub role: UserRole,
+    pub anonymous: bool,
+    /// The order in which members joined, which decides ties in host succession.
+    pub joined: u64,
+}
+
+/// The state of a room that is built from its events.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoomModel {
+    pub members: HashMap<SessionId, Member>,
+    pub quiet_hours: Vec<QuietWindow>,
+    pub closed: Option<RoomCloseReason>,
+    /// The last few links that were shared, oldest first.
+    pub recent_links: VecDeque<SharedLink>,
+    /// The last things that were played in the room, oldest first.
+    pub playback_history: VecDeque<PlayedSource>,
+    /// Who was last kicked or given another role by whom, oldest first.
+    pub moderation_log: VecDeque<ModerationEntry>,
+    joins: u64,
+    moderations: u64,
+}
+
+impl RoomModel {
+    const RECENT_LINKS: usize = 20;
+    const PLAYBACK_HISTORY: usize = 100;
+    const MODERATION_LOG: usize = 1000;
+
+    pub fn replay<'a>(events: impl IntoIterator<Item = &'a RoomEvent>) -> Self {
+        let mut model = Self::default();
+        for event in events {
+            model.apply(event);
+        }
+        model
+    }
+
+    pub fn apply(&mut self, event: &RoomEvent) {
+        match event {
+            RoomEvent::Joined {
+                user,
+                name,
+                subject,
+                role,
+                anonymous,
+            } => {
+                self.members.insert(
+                    *user,
+                    Member {
+                        name: name.clone(),
+                        subject: subject.clone(),
+                        role: *role,
+                        anonymous: *anonymous,
+                        joined: self.joins,
+                    },
+                );
+                self.joins += 1;
+            }
+            RoomEvent::Left { user, .. } => {
+                self.members.remove(user);
+            }
+            RoomEvent::Kicked {
+                user,
+                by,
+                kicked_at,
+            } => {
+                self.log_moderation(ModerationAction::Kicked, *user, *by, *kicked_at);
+                self.members.remove(user);
+            }
+            RoomEvent::RoleChanged {
+                user,
+                role,
+                by,
+                changed_at,
+            } => {
+                // hosts stepping down when they hand the room over is no news
+                if by != user {
+                    self.log_moderation(
+                        ModerationAction::RoleChanged(*role),
+                        *user,
+                        *by,
+                        *changed_at,
+                    );
+                }
+                if let Some(member) = self.members.get_mut(user) {
+                    member.role = *role;
+                }
+            }
+            RoomEvent::Renamed { user, name } => {
+                if let Some(member) = self.members.get_mut(user) {
+                    member.name = name.clone();
+                }
+            }
+            RoomEvent::HostSucceeded { user } => {
+                if let Some(member) = self.members.get_mut(user) {
+                    member.role = UserRole::Host;
+                }
+            }
+            RoomEvent::QuietHoursSet { windows } => self.quiet_hours = windows.clone(),
+            RoomEvent::MetadataUpdated { .. }
+            | RoomEvent::PasswordChanged
+            | RoomEvent::ChatSent { .. } => (),
+            RoomEvent::LinkShared {
+                user,
+                url,
+                shared_at,
+            } => {
+                let Some(member) = self.members.get(user) else {
+                    return;
+                };
+                if self.recent_links.len() == Self::RECENT_LINKS {
+                    self.recent_links.pop_front();
+                }
+                self.recent_links.push_back(SharedLink {
+                    user: *user,
+                    name: member.name.clone(),
+                    url: url.clone(),
+                    shared_at: *shared_at,
+                });
+            }
+            RoomEvent::PlaybackStarted {
+                host,
+                title,
+                page_href,
+                started_at,
+            } => {
+                self.end_playback(*started_at);
+                let host_name = self
+                    .members
+                    .get(host)
+                    .map(|member| member.name.clone())
+                    .unwrap_or_default();
+                if self.playback_history.len() == Self::PLAYBACK_HISTORY {
+                    self.playback_history.pop_front();
+                }
+                self.playback_history.push_back(PlayedSource {
+                    host: *host,
+                    host_name,
+                    title: title.clone(),
+                    page_href: page_href.clone(),
+                    started_at: *started_at,
+                    ended_at: None,
+                });
+            }
+            RoomEvent::PlaybackEnded { ended_at } => self.end_playback(*ended_at),
+            RoomEvent::Closed { reason } => self.closed = Some(*reason),
+        }
+    }
+
+    /// What is playing right now, according to the history.
+    pub fn now_playing(&self) -> Option<&PlayedSource> {
+        self.playback_history
+            .back()
+            .filter(|played| played.ended_at.is_none())
+    }
+
+    fn log_moderation(
+        &mut self,
+        action: ModerationAction,
+        user: SessionId,
+        by: SessionId,
+        at: u64,
+    ) {
+        let name = |id: SessionId| {
+            self.members
+                .get(&id)
+                .map(|member| member.name.clone())
+                .unwrap_or_default()
+        };
+        let entry = ModerationEntry {
+            id: self.moderations,
+            action,
+            user,
+            user_name: name(user),
+            by,
+            by_name: name(by),
+            at,
+        };
+        if self.moderation_log.len() == Self::MODERATION_LOG {
+            self.moderation_log.pop_front();
+        }
+        self.moderation_log.push_back(entry);
+        self.moderations += 1;
+    }
+
+    fn end_playback(&mut self, ended_at: u64) {
+        if let Some(played) = self.playback_history.back_mut() {
+            played.ended_at.get_or_insert(ended_at);
+        }
+    }
+
+    pub fn user_data(&self, id: SessionId) -> Option<UserData> {
+        let member = self.members.get(&id)?;
+        Some(UserData {
+            id,
+            name: member.name.clone(),
+            role: member.role,
+        })
+    }
+
+    pub fn host(&self) -> Option<SessionId> {
+        self.members
+            .iter()
+            .find(|(_, member)| member.role == UserRole::Host)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn has_host(&self) -> bool {
+        self.members
+            .values()
+            .any(|member| member.role == UserRole::Host)
+    }
+
+    pub fn choose_new_host(&self, succession: &HostSuccession) -> Option<SessionId> {
+        succession.choose(&self.members)
+    }
+
+    /// Who would become host if the given host left.
+    pub fn choose_successor(
+        &self,
+        succession: &HostSuccession,
+        host: SessionId,
+    ) -> Option<SessionId> {
+        let others = self
+            .members
+            .iter()
+            .filter(|(id, _)| **id != host)
+            .map(|(id, member)| (*id, member.clone()))
+            .collect();
+        succession.choose(&others)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn user(id: u128) -> SessionId {
+        SessionId::from(Uuid::from_u128(id))
+    }
+
+    fn joined(id: u128, name: &str, role: UserRole) -> RoomEvent {
+        RoomEvent::Joined {
+            user: user(id),
+            name: name.to_string(),
+            subject: None,
+            role,
+            anonymous: false,
+        }
+    }
+
+    fn movie_night() -> Vec<RoomEvent> {
+        vec![
+            joined(1, "alice", UserRole::Host),
+            joined(3, "carol", UserRole::Spectator),
+            joined(2, "bob", UserRole::Spectator),
+            RoomEvent::RoleChanged {
+                user: user(2),
+                role: UserRole::Guest,
+                by: user(1),
+                changed_at: 1_699_920_000_000,
+            },
+            RoomEvent::ChatSent {
+                user: user(3),
+                text: "hi".to_string(),
+                sent_at: 1_699_920_000_000,
+            },
+            RoomEvent::Left {
+                user: user(1),
+                reason: LeaveReason::Left,
+            },
+        ]
+    }
+
+    #[test]
+    fn should_replay_events_into_the_same_room() {
+        // given
+        let records: Vec<RoomEventRecord> = movie_night()
+            .into_iter()
+            .enumerate()
+            .map(|(seq, event)| RoomEventRecord {
+                seq: seq as u64,
+                at: 1_699_920_000_000,
+                event,
+            })
+            .collect();
+        let stored = serde_json::to_string(&records).unwrap();
+
+        // when
+        let restored: Vec<RoomEventRecord> = serde_json::from_str(&stored).unwrap();
+        let replayed = RoomModel::replay(restored.iter().map(|record| &record.event));
+
+        // then
+        assert_eq!(replayed, RoomModel::replay(&movie_night()));
+        assert_eq!(replayed.members.len(), 2);
+        assert_eq!(replayed.members[&user(2)].role, UserRole::Guest);
+        assert!(!replayed.has_host());
+    }
+
+    #[test]
+    fn should_remove_kicked_members() {
+        // given
+        let mut model = RoomModel::replay(&movie_night());
+
+        // when
+        model.apply(&RoomEvent::Kicked {
+            user: user(3),
+            by: user(2),
+            kicked_at: 1_699_920_060_000,
+        });
+
+        // then
+        assert!(model.user_data(user(3)).is_none());
+        assert!(model.user_data(user(2)).is_some());
+    }
+
+    #[test]
+    fn should_log_what_moderators_did() {
+        // given
+        let mut model = RoomModel::replay(&movie_night());
+
+        // when
+        model.apply(&RoomEvent::Kicked {
+            user: user(3),
+            by: user(2),
+            kicked_at: 1_699_920_060_000,
+        });
+
+        // then
+        let log: Vec<_> = model
+            .moderation_log
+            .iter()
+            .map(|entry| {
+                (
+                    entry.action,
+                    entry.user_name.as_str(),
+                    entry.by_name.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            log,
+            vec![
+                (
+                    ModerationAction::RoleChanged(UserRole::Guest),
+                    "bob",
+                    "alice"
+                ),
+                (ModerationAction::Kicked, "carol", "bob"),
+            ]
+        );
+        assert_eq!(model.moderation_log[1].id, 1);
+    }
+
+    #[test]
+    fn should_keep_only_the_most_recent_links() {
+        // given
+        let mut model = RoomModel::replay(&movie_night());
+
+        // when
+        for i in 0..=RoomModel::RECENT_LINKS {
+            model.apply(&RoomEvent::LinkShared {
+                user: user(2),
+                url: format!("https://example.com/{i}"),
+                shared_at: 1_699_920_000_000,
+            });
+        }
+
+        // then
+        assert_eq!(model.recent_links.len(), RoomModel::RECENT_LINKS);
+        assert_eq!(model.recent_links[0].url, "https://example.com/1");
+        assert_eq!(model.recent_links[0].name, "bob");
+    }
+
+    #[test]
+    fn should_choose_the_same_host_on_every_replay() {
+        // given
+        let mut events = vec![
+            joined(1, "alice", UserRole::Host),
+            joined(3, "carol", UserRole::Guest),
+            joined(2, "bob", UserRole::Guest),
+            RoomEvent::Left {
+                user: user(1),
+                reason: LeaveReason::Left,
+            },
+        ];
+        let new_host = RoomModel::replay(&events)
+            .choose_new_host(&HostSuccession::default())
+            .unwrap();
+        events.push(RoomEvent::HostSucceeded { user: new_host });
+
+        // when
+        let replayed = (0..10)
+            .map(|_| RoomModel::replay(&events))
+            .collect::<Vec<_>>();
+
+        // then
+        assert_eq!(new_host, user(3));
+        assert!(replayed
+            .iter()
+            .all(|model| model.user_data(user(3)).unwrap().role == UserRole::Host));
+    }
+
+    #[test]
+    fn should_never_choose_anonymous_spectators_as_host() {
+        // given
+        let events = vec![
+            joined(1, "alice", UserRole::Host),
+            RoomEvent::Joined {
+                user: user(2),
+                name: "bob".to_string(),
+                subject: None,
+                role: UserRole::Spectator,
+                anonymous: true,
+            },
+            joined(3, "carol", UserRole::Spectator),
+            RoomEvent::Left {
+                user: user(1),
+                reason: LeaveReason::Left,
+            },
+        ];
+
+        // when
+        let new_host = RoomModel::replay(&events).choose_new_host(&HostSuccession::default());
+
+        // then
+        assert_eq!(new_host, Some(user(3)));
+    }
+
+    fn started(host: u128, title: &str, started_at: u64) -> RoomEvent {
+        RoomEvent::PlaybackStarted {
+            host: user(host),
+            title: title.to_string(),
+            page_href: format!("https://example.com/{title}"),
+            started_at,
+        }
+    }
+
+    #[test]
+    fn should_end_playback_when_something_else_starts() {
+        // given
+        let mut model = RoomModel::replay(&movie_night());
+
+        // when
+        model.apply(&started(2, "first", 1000));
+        model.apply(&started(3, "second", 2000));
+        model.apply(&RoomEvent::PlaybackEnded { ended_at: 3000 });
+
+        // then
+        let history = &model.playback_history;
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            (history[0].host_name.as_str(), history[0].ended_at),
+            ("bob", Some(2000))
+        );
+        assert_eq!(
+            (history[1].host_name.as_str(), history[1].ended_at),
+            ("carol", Some(3000))
+        );
+        assert!(model.now_playing().is_none());
+    }
+
+    #[test]
+    fn should_keep_only_the_latest_playback_history() {
+        // given
+        let mut model = RoomModel::replay(&movie_night());
+
+        // when
+        for i in 0..=RoomModel::PLAYBACK_HISTORY as u64 {
+            model.apply(&started(2, &format!("episode{i}"), 1000 * i));
+        }
+
+        // then
+        let history = &model.playback_history;
+        assert_eq!(history.len(), RoomModel::PLAYBACK_HISTORY);
+        assert_eq!(
+            (history[0].title.as_str(), history[0].ended_at),
+            ("episode1", Some(2000))
+        );
+        assert_eq!(
+            model.now_playing().map(|played| played.title.clone()),
+            Some(format!("episode{}", RoomModel::PLAYBACK_HISTORY))
+        );
+    }
+
+    #[test]
+    fn should_keep_counting_moderation_entries_past_the_oldest_kept() {
+        // given
+        let mut model = RoomModel::replay(&movie_night());
+
+        // when
+        for i in 0..RoomModel::MODERATION_LOG as u64 {
+            model.apply(&RoomEvent::RoleChanged {
+                user: user(3),
+                role: UserRole::Guest,
+                by: user(2),
+                changed_at: 1_699_920_000_000 + i,
+            });
+        }
+
+        // then
+        let log = &model.moderation_log;
+        assert_eq!(log.len(), RoomModel::MODERATION_LOG);
+        assert_eq!(log.front().map(|entry| entry.id), Some(1));
+        assert_eq!(
+            log.back().map(|entry| entry.id),
+            Some(RoomModel::MODERATION_LOG as u64)
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::Context;
+use serde::Deserialize;
+use tokio::runtime::{self, Handle, Runtime};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HeavyRoomConfig {
+    /// How many threads are reserved for large rooms. With none, all rooms share the main
+    /// runtime.
+    pub threads: usize,
+
+    /// How many users a room needs to be moved to the reserved threads.
+    pub min_users: usize,
+}
+
+impl Default for HeavyRoomConfig {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            min_users: 50,
+        }
+    }
+}
+
+/// A runtime that is reserved for large rooms, so that their fan-out can't starve small rooms
+/// and everything else on the main runtime.
+pub struct HeavyRoomRuntime {
+    runtime: Option<Runtime>,
+}
+
+impl HeavyRoomRuntime {
+    pub fn new(config: &HeavyRoomConfig) -> anyhow::Result<Option<Self>> {
+        if config.threads == 0 {
+            return Ok(None);
+        }
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(config.threads)
+            .thread_name("palantir-heavy-rooms")
+            .enable_all()
+            .build()
+            .context("Failed to start the runtime for large rooms")?;
+        Ok(Some(Self {
+            runtime: Some(runtime),
+        }))
+    }
+
+    pub fn handle(&self) -> Option<Handle> {
+        self.runtime
+            .as_ref()
+            .map(|runtime| runtime.handle().clone())
+    }
+}
+
+impl Drop for HeavyRoomRuntime {
+    /// Runtimes can't be dropped normally from within another runtime.
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_start_a_runtime_if_threads_are_reserved() {
+        // given
+        let disabled = HeavyRoomConfig::default();
+        let enabled = HeavyRoomConfig {
+            threads: 1,
+            ..HeavyRoomConfig::default()
+        };
+
+        // when
+        let disabled = HeavyRoomRuntime::new(&disabled).unwrap();
+        let enabled = HeavyRoomRuntime::new(&enabled).unwrap();
+
+        // then
+        assert!(disabled.is_none());
+        assert!(enabled.and_then(|runtime| runtime.handle()).is_some());
+    }
+}
@@ -0,0 +1,26 @@
+use crate::{messages::dto, session::SessionId};
+
+/// Something that was played in a room, and who hosted it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayedSource {
+    pub host: SessionId,
+    pub host_name: String,
+    pub title: String,
+    pub page_href: String,
+    pub started_at: u64,
+    /// Not set while it's still playing.
+    pub ended_at: Option<u64>,
+}
+
+impl From<PlayedSource> for dto::RoomPlayedSourceV1 {
+    fn from(value: PlayedSource) -> Self {
+        Self {
+            host_id: value.host.into(),
+            host_name: value.host_name,
+            title: value.title,
+            page_href: value.page_href,
+            started_at: value.started_at,
+            ended_at: value.ended_at,
+        }
+    }
+}
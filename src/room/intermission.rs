@@ -0,0 +1,65 @@
+use crate::{errors::ClientError, messages::dto, utils::timestamp};
+
+use super::RoomMetadata;
+
+/// A break called by the host, during which playback stays paused and a notice is pinned in
+/// the room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Intermission {
+    pub notice: String,
+    /// When the host expects to resume, if they said so.
+    pub ends_at: Option<u64>,
+}
+
+impl Intermission {
+    /// The longest countdown that can be set, in seconds.
+    const MAX_COUNTDOWN: u64 = 24 * 60 * 60;
+
+    pub fn new(notice: String, countdown: Option<u64>) -> Result<Self, ClientError> {
+        if countdown.is_some_and(|countdown| countdown > Self::MAX_COUNTDOWN) {
+            return Err(ClientError::invalid(
+                "Intermissions can't be longer than a day",
+            ));
+        }
+        Ok(Self {
+            notice: notice.trim().to_string(),
+            ends_at: countdown.map(|countdown| timestamp() + countdown * 1000),
+        })
+    }
+
+    /// The intermission as the room's users see it, with times in the room's time zone.
+    pub fn into_dto(self, metadata: &RoomMetadata) -> dto::RoomIntermissionV1 {
+        dto::RoomIntermissionV1 {
+            notice: self.notice,
+            ends_at: self.ends_at,
+            ends_at_iso: self.ends_at.map(|ends_at| metadata.local_time(ends_at)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_show_the_end_in_the_rooms_time_zone() {
+        // given
+        let intermission = Intermission {
+            notice: "Back in ten minutes".to_string(),
+            ends_at: Some(1_700_000_000_000),
+        };
+        let metadata = RoomMetadata::new(None, Vec::new(), None, Some(60));
+
+        // when
+        let local = intermission.clone().into_dto(&metadata);
+        let utc = intermission.into_dto(&RoomMetadata::default());
+
+        // then
+        assert_eq!(local.ends_at, Some(1_700_000_000_000));
+        assert_eq!(
+            local.ends_at_iso.as_deref(),
+            Some("2023-11-14T23:13:20+01:00")
+        );
+        assert_eq!(utc.ends_at_iso.as_deref(), Some("2023-11-14T22:13:20Z"));
+    }
+}
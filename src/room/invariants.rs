@@ -0,0 +1,250 @@
+//! Property-based checks of what a room guarantees, whatever its users do. Every case runs a
+//! generated sequence of joins and requests against a room, and checks its invariants after each
+//! step. Failing sequences are shrunk to the fewest steps that still break an invariant.
+
+use std::collections::HashSet;
+
+use proptest::{collection::vec, prelude::*};
+use uuid::Uuid;
+
+use super::{tests::test_settings, *};
+use crate::testing::FakeSession;
+
+const CASES: u32 = 200;
+const STEPS: usize = 40;
+const USERS: u128 = 5;
+
+/// Something a user does in a room.
+#[derive(Debug, Clone, Copy)]
+enum UserEvent {
+    Join {
+        user: u128,
+        role: UserRole,
+        anonymous: bool,
+    },
+    Leave(u128),
+    Kick(u128, u128),
+    SetRole(u128, u128, UserRole),
+    TransferHost(u128, u128),
+    HostPlayback(u128),
+    ConnectPlayback(u128),
+}
+
+fn user() -> impl Strategy<Value = u128> {
+    1..=USERS
+}
+
+fn role() -> impl Strategy<Value = UserRole> {
+    prop_oneof![
+        Just(UserRole::Host),
+        Just(UserRole::Guest),
+        Just(UserRole::Spectator)
+    ]
+}
+
+fn user_event() -> impl Strategy<Value = UserEvent> {
+    prop_oneof![
+        2 => (user(), role(), prop::bool::weighted(0.25)).prop_map(|(user, role, anonymous)| {
+            UserEvent::Join {
+                user,
+                role,
+                anonymous,
+            }
+        }),
+        1 => user().prop_map(UserEvent::Leave),
+        1 => (user(), user()).prop_map(|(by, user)| UserEvent::Kick(by, user)),
+        1 => (user(), user(), role()).prop_map(|(by, user, role)| UserEvent::SetRole(by, user, role)),
+        1 => (user(), user()).prop_map(|(from, to)| UserEvent::TransferHost(from, to)),
+        1 => user().prop_map(UserEvent::HostPlayback),
+        1 => user().prop_map(UserEvent::ConnectPlayback),
+    ]
+}
+
+struct Harness {
+    room: Room,
+    sessions: Vec<Arc<FakeSession>>,
+    // the room's channels close when these are dropped
+    _command_tx: mpsc::Sender<RoomCmd>,
+    _request_tx: mpsc::Sender<RoomRequest>,
+    _status_rx: watch::Receiver<RoomStatus>,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let (command_tx, command_rx) = mpsc::channel(8);
+        let (request_tx, request_rx) = mpsc::channel(8);
+        let (result_tx, _) = watch::channel(Ok(()));
+        let (status_tx, status_rx) = watch::channel(RoomStatus::default());
+        Self {
+            room: Room::new(
+                RoomId::new(),
+                RoomSettings::from(test_settings()),
+                RoomConfig::default(),
+                command_rx,
+                request_rx,
+                result_tx,
+                status_tx,
+            ),
+            sessions: (0..=USERS).map(|_| FakeSession::new(0)).collect(),
+            _command_tx: command_tx,
+            _request_tx: request_tx,
+            _status_rx: status_rx,
+        }
+    }
+
+    fn id(user: u128) -> SessionId {
+        SessionId::from(Uuid::from_u128(user))
+    }
+
+    /// Makes the room handle the event, the way a session would ask for it.
+    async fn run(&mut self, event: UserEvent) {
+        let request = match event {
+            UserEvent::Join {
+                user,
+                role,
+                anonymous,
+            } => {
+                // the first user creates the room, so they are its host
+                let (role, anonymous) = if self.room.model.members.is_empty() {
+                    (UserRole::Host, false)
+                } else if anonymous {
+                    (UserRole::Spectator, true)
+                } else {
+                    (role, false)
+                };
+                let session = self.sessions[user as usize].handle(user, &format!("user{user}"));
+                self.room
+                    .handle_cmd(RoomCmd::Join(role, session, anonymous))
+                    .await;
+                return;
+            }
+            UserEvent::Leave(user) => RoomRequest::Leave(Self::id(user), LeaveReason::Left),
+            UserEvent::Kick(by, user) => RoomRequest::Kick(Self::id(by), Self::id(user)),
+            UserEvent::SetRole(by, user, role) => {
+                RoomRequest::SetRole(Self::id(by), Self::id(user), role)
+            }
+            UserEvent::TransferHost(from, to) => {
+                RoomRequest::TransferHost(Self::id(from), Self::id(to))
+            }
+            UserEvent::HostPlayback(user) => {
+                // sessions only ask if their role allows it
+                let can_host = self
+                    .room
+                    .model
+                    .members
+                    .get(&Self::id(user))
+                    .is_some_and(|member| member.role.permissions().can_host);
+                if !can_host {
+                    return;
+                }
+                RoomRequest::PlaybackHost(Self::id(user))
+            }
+            UserEvent::ConnectPlayback(user) => RoomRequest::PlaybackConnect(Self::id(user)),
+        };
+        // sessions can only make requests while they are in the room
+        if !self
+            .room
+            .participants
+            .contains_key(&request_sender(&request))
+        {
+            return;
+        }
+        self.room.handle_request(request).await;
+    }
+
+    /// Checks the invariants, and returns what is wrong if any of them don't hold.
+    async fn check(&mut self) -> Result<(), String> {
+        let room = &self.room;
+        let members: HashSet<SessionId> = room.model.members.keys().copied().collect();
+        let participants: HashSet<SessionId> = room.participants.keys().copied().collect();
+        if members != participants {
+            return Err(format!(
+                "members {members:?} don't match participants {participants:?}"
+            ));
+        }
+        let hosts = room
+            .model
+            .members
+            .values()
+            .filter(|member| member.role == UserRole::Host)
+            .count();
+        if room.running && !members.is_empty() && hosts != 1 {
+            return Err(format!("the room has {hosts} hosts"));
+        }
+        if let Some(playback) = &room.playback {
+            if !members.contains(&playback.host_id()) {
+                return Err(format!(
+                    "playback host {} is not a member",
+                    playback.host_id()
+                ));
+            }
+        }
+
+        self.room.flush_state_broadcast().await;
+        let expected = dto::RoomStateMsgBodyV1::from(self.room.get_state());
+        let unique: HashSet<_> = expected.users.iter().map(|user| user.id).collect();
+        if unique.len() != expected.users.len() {
+            return Err(format!(
+                "the user list has duplicates: {:?}",
+                expected.users
+            ));
+        }
+        for user in 1..=USERS {
+            let id = Self::id(user);
+            for msg in self.sessions[user as usize].take_messages() {
+                let SessionMsg::RoomState(state) = msg else {
+                    continue;
+                };
+                if !members.contains(&id) {
+                    return Err(format!("user {user} got a room state after leaving"));
+                }
+                if *state != expected {
+                    return Err(format!("user {user} got an outdated room state"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn request_sender(request: &RoomRequest) -> SessionId {
+    match request {
+        RoomRequest::Leave(id, _)
+        | RoomRequest::Kick(id, _)
+        | RoomRequest::SetRole(id, _, _)
+        | RoomRequest::TransferHost(id, _)
+        | RoomRequest::PlaybackHost(id)
+        | RoomRequest::PlaybackConnect(id) => *id,
+        _ => unreachable!("user events don't make other requests"),
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn should_keep_invariants_whatever_users_do(events in vec(user_event(), 1..=STEPS)) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            // given
+            let mut harness = Harness::new();
+
+            for (step, event) in events.into_iter().enumerate() {
+                // when
+                harness.run(event).await;
+
+                // then
+                if let Err(violation) = harness.check().await {
+                    return Err(TestCaseError::fail(format!("{violation} after step {step}")));
+                }
+                if !harness.room.running {
+                    break;
+                }
+            }
+            Ok(())
+        })?;
+    }
+}
@@ -0,0 +1,141 @@
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::{errors::ClientError, messages::dto, session::SessionId};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct LinkSharingConfig {
+    /// If not empty, only links to these domains and their subdomains can be shared.
+    pub allowed_domains: Vec<String>,
+
+    /// Links to these domains and their subdomains can never be shared.
+    pub blocked_domains: Vec<String>,
+}
+
+impl LinkSharingConfig {
+    /// Checks whether a link may be shared, and returns it in normalized form if so.
+    pub fn check(&self, link: &str) -> Result<String, ClientError> {
+        let url = Url::parse(link.trim())
+            .map_err(|_| ClientError::invalid(format!("'{link}' is not a valid link")))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(ClientError::invalid(
+                "Only http and https links can be shared",
+            ));
+        }
+        let Some(host) = url.host_str() else {
+            return Err(ClientError::invalid("Links need to point to a host"));
+        };
+        if self
+            .blocked_domains
+            .iter()
+            .any(|domain| is_within(host, domain))
+        {
+            return Err(ClientError::not_authorized(format!(
+                "Links to {host} can't be shared"
+            )));
+        }
+        if !self.allowed_domains.is_empty()
+            && !self
+                .allowed_domains
+                .iter()
+                .any(|domain| is_within(host, domain))
+        {
+            return Err(ClientError::not_authorized(format!(
+                "Links to {host} can't be shared"
+            )));
+        }
+        Ok(url.into())
+    }
+}
+
+/// Whether the host is the domain itself or one of its subdomains.
+fn is_within(host: &str, domain: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+    host == domain
+        || host
+            .strip_suffix(&domain)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// A link that was shared in a room, along with who shared it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedLink {
+    pub user: SessionId,
+    pub name: String,
+    pub url: String,
+    pub shared_at: u64,
+}
+
+impl From<SharedLink> for dto::RoomSharedLinkV1 {
+    fn from(value: SharedLink) -> Self {
+        Self {
+            user_id: value.user.into(),
+            name: value.name,
+            url: value.url,
+            shared_at: value.shared_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::ErrorCode;
+
+    use super::*;
+
+    fn config() -> LinkSharingConfig {
+        LinkSharingConfig {
+            allowed_domains: Vec::new(),
+            blocked_domains: vec!["evil.example".to_string()],
+        }
+    }
+
+    #[test]
+    fn should_only_accept_web_links() {
+        // given
+        let config = config();
+
+        // when
+        let web = config.check("https://example.com/watch?v=1");
+        let file = config.check("file:///etc/passwd");
+        let script = config.check("javascript:alert(1)");
+
+        // then
+        assert_eq!(web.unwrap(), "https://example.com/watch?v=1");
+        assert_eq!(file.unwrap_err().code, ErrorCode::InvalidRequest);
+        assert_eq!(script.unwrap_err().code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn should_block_subdomains_of_blocked_domains() {
+        // given
+        let config = config();
+
+        // when
+        let blocked = config.check("http://www.EVIL.example/");
+        let lookalike = config.check("http://notevil.example/");
+
+        // then
+        assert_eq!(blocked.unwrap_err().code, ErrorCode::NotAuthorized);
+        assert!(lookalike.is_ok());
+    }
+
+    #[test]
+    fn should_only_accept_allowed_domains_if_any_are_given() {
+        // given
+        let config = LinkSharingConfig {
+            allowed_domains: vec!["youtube.com".to_string()],
+            ..config()
+        };
+
+        // when
+        let allowed = config.check("https://m.youtube.com/watch?v=1");
+        let other = config.check("https://example.com/");
+
+        // then
+        assert!(allowed.is_ok());
+        assert_eq!(other.unwrap_err().code, ErrorCode::NotAuthorized);
+    }
+}
@@ -0,0 +1,217 @@
+use std::ops::RangeInclusive;
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    content_filter::ContentFilter, errors::ClientError, messages::dto, utils::format_iso8601,
+};
+
+/// What a room is about, for clients to show alongside its name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomMetadata {
+    pub topic: Option<String>,
+    pub tags: Vec<String>,
+    pub image_url: Option<String>,
+    /// The room's offset from UTC in minutes, which times are shown in for its users.
+    #[serde(default)]
+    pub utc_offset: Option<i16>,
+}
+
+impl RoomMetadata {
+    const MAX_TOPIC_LEN: usize = 200;
+    const MAX_TAGS: usize = 10;
+    const MAX_TAG_LEN: usize = 32;
+    const MAX_IMAGE_URL_LEN: usize = 2048;
+    /// From UTC-12:00 to UTC+14:00, the range of the time zones in use.
+    const UTC_OFFSETS: RangeInclusive<i16> = -12 * 60..=14 * 60;
+
+    pub fn new(
+        topic: Option<String>,
+        tags: Vec<String>,
+        image_url: Option<String>,
+        utc_offset: Option<i16>,
+    ) -> Self {
+        Self {
+            topic,
+            tags,
+            image_url,
+            utc_offset,
+        }
+    }
+
+    /// Checks the metadata against the limits and the content filter, and returns it trimmed,
+    /// with duplicate tags removed.
+    pub fn checked(self, content_filter: &ContentFilter) -> anyhow::Result<Self> {
+        let topic = match self.topic.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(topic) if topic.chars().count() > Self::MAX_TOPIC_LEN => {
+                return Err(ClientError::invalid(format!(
+                    "Room topics can't be longer than {} characters",
+                    Self::MAX_TOPIC_LEN
+                ))
+                .into());
+            }
+            Some(topic) => Some(content_filter.apply(topic, "Room topics")?.into_owned()),
+        };
+
+        // checking the limit as tags are added keeps looking for duplicates cheap
+        let mut tags: Vec<String> = Vec::new();
+        for tag in self.tags.iter().map(|tag| tag.trim()) {
+            if tag.is_empty() || tags.iter().any(|other| other.eq_ignore_ascii_case(tag)) {
+                continue;
+            }
+            if tags.len() == Self::MAX_TAGS {
+                return Err(ClientError::invalid(format!(
+                    "Rooms can't have more than {} tags",
+                    Self::MAX_TAGS
+                ))
+                .into());
+            }
+            if tag.chars().count() > Self::MAX_TAG_LEN {
+                return Err(ClientError::invalid(format!(
+                    "Room tags can't be longer than {} characters",
+                    Self::MAX_TAG_LEN
+                ))
+                .into());
+            }
+            tags.push(content_filter.apply(tag, "Room tags")?.into_owned());
+        }
+
+        let image_url = match self.image_url.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(url) if url.len() > Self::MAX_IMAGE_URL_LEN => {
+                return Err(ClientError::invalid("Room image links are too long").into());
+            }
+            Some(url) => Some(check_image_url(url)?),
+        };
+
+        if self
+            .utc_offset
+            .is_some_and(|offset| !Self::UTC_OFFSETS.contains(&offset))
+        {
+            return Err(ClientError::invalid("That is not a valid offset from UTC").into());
+        }
+
+        Ok(Self {
+            topic,
+            tags,
+            image_url,
+            utc_offset: self.utc_offset,
+        })
+    }
+
+    /// Formats a timestamp as an ISO 8601 time in the room's time zone, or in UTC if it has
+    /// none.
+    pub fn local_time(&self, timestamp: u64) -> String {
+        format_iso8601(timestamp, self.utc_offset.unwrap_or(0))
+    }
+}
+
+impl From<dto::RoomUpdateMsgBodyV1> for RoomMetadata {
+    fn from(value: dto::RoomUpdateMsgBodyV1) -> Self {
+        Self::new(value.topic, value.tags, value.image_url, value.utc_offset)
+    }
+}
+
+fn check_image_url(url: &str) -> Result<String, ClientError> {
+    let parsed = Url::parse(url)
+        .map_err(|_| ClientError::invalid(format!("'{url}' is not a valid link")))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+        return Err(ClientError::invalid(
+            "Room images need to be http or https links",
+        ));
+    }
+    Ok(parsed.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::{error_code, ErrorCode};
+
+    use super::*;
+
+    #[test]
+    fn should_tidy_up_metadata() {
+        // given
+        let metadata = RoomMetadata::new(
+            Some("  Friday horror night ".to_string()),
+            vec![
+                "horror".to_string(),
+                " ".to_string(),
+                "Horror".to_string(),
+                " classics ".to_string(),
+            ],
+            Some(" https://example.com/cover.png ".to_string()),
+            Some(60),
+        );
+
+        // when
+        let checked = metadata.checked(&ContentFilter::default());
+
+        // then
+        assert_eq!(
+            checked.unwrap(),
+            RoomMetadata::new(
+                Some("Friday horror night".to_string()),
+                vec!["horror".to_string(), "classics".to_string()],
+                Some("https://example.com/cover.png".to_string()),
+                Some(60),
+            )
+        );
+    }
+
+    #[test]
+    fn should_reject_images_that_arent_web_links() {
+        // given
+        let metadata = RoomMetadata::new(
+            None,
+            Vec::new(),
+            Some("javascript:alert(1)".to_string()),
+            None,
+        );
+
+        // when
+        let result = metadata.checked(&ContentFilter::default());
+
+        // then
+        assert_eq!(error_code(&result.unwrap_err()), ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn should_reject_offsets_that_no_time_zone_has() {
+        // given
+        let metadata = RoomMetadata::new(None, Vec::new(), None, Some(15 * 60));
+
+        // when
+        let result = metadata.checked(&ContentFilter::default());
+
+        // then
+        assert_eq!(error_code(&result.unwrap_err()), ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn should_limit_tags_but_not_their_repetitions() {
+        // given
+        let too_many = RoomMetadata::new(
+            None,
+            (0..=RoomMetadata::MAX_TAGS)
+                .map(|i| format!("tag {i}"))
+                .collect(),
+            None,
+            None,
+        );
+        let repeated = RoomMetadata::new(None, vec!["horror".to_string(); 10_000], None, None);
+
+        // when
+        let too_many = too_many.checked(&ContentFilter::default());
+        let repeated = repeated.checked(&ContentFilter::default());
+
+        // then
+        assert_eq!(
+            error_code(&too_many.unwrap_err()),
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(repeated.unwrap().tags, ["horror"]);
+    }
+}
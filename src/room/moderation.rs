@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+
+use crate::{messages::dto, session::SessionId};
+
+use super::UserRole;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationAction {
+    Kicked,
+    RoleChanged(UserRole),
+}
+
+/// Something a moderator did to another user in a room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModerationEntry {
+    /// Counts the entries of a room, so that clients can page through them.
+    pub id: u64,
+    pub action: ModerationAction,
+    pub user: SessionId,
+    pub user_name: String,
+    pub by: SessionId,
+    pub by_name: String,
+    pub at: u64,
+}
+
+impl From<ModerationEntry> for dto::RoomModerationEntryV1 {
+    fn from(value: ModerationEntry) -> Self {
+        let (action, role) = match value.action {
+            ModerationAction::Kicked => (dto::RoomModerationActionV1::Kicked, None),
+            ModerationAction::RoleChanged(role) => {
+                (dto::RoomModerationActionV1::RoleChanged, Some(role.into()))
+            }
+        };
+        Self {
+            id: value.id,
+            action,
+            role,
+            user_id: value.user.into(),
+            user_name: value.user_name,
+            by_id: value.by.into(),
+            by_name: value.by_name,
+            at: value.at,
+        }
+    }
+}
+
+/// Part of a room's moderation log, newest entries first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModerationLogPage {
+    pub entries: Vec<ModerationEntry>,
+    /// Whether there are older entries than the ones on this page.
+    pub more: bool,
+}
+
+impl ModerationLogPage {
+    const DEFAULT_LEN: usize = 50;
+    const MAX_LEN: usize = 200;
+
+    /// Takes the entries that come before the one with the given id, or the newest ones.
+    pub fn of(log: &VecDeque<ModerationEntry>, before: Option<u64>, len: Option<usize>) -> Self {
+        let end = before.map_or(log.len(), |before| {
+            log.partition_point(|entry| entry.id < before)
+        });
+        let len = len.unwrap_or(Self::DEFAULT_LEN).min(Self::MAX_LEN);
+        let start = end.saturating_sub(len);
+        Self {
+            entries: log.range(start..end).rev().cloned().collect(),
+            more: start != 0,
+        }
+    }
+}
+
+impl From<ModerationLogPage> for dto::RoomModerationLogAckMsgBodyV1 {
+    fn from(value: ModerationLogPage) -> Self {
+        Self {
+            entries: value.entries.into_iter().map(From::from).collect(),
+            more: value.more,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn entry(id: u64) -> ModerationEntry {
+        ModerationEntry {
+            id,
+            action: ModerationAction::Kicked,
+            user: SessionId::from(Uuid::from_u128(1)),
+            user_name: "bob".to_string(),
+            by: SessionId::from(Uuid::from_u128(2)),
+            by_name: "alice".to_string(),
+            at: 1000 + id,
+        }
+    }
+
+    #[test]
+    fn should_page_backwards_from_the_newest_entry() {
+        // given
+        let log: VecDeque<_> = (0..5).map(entry).collect();
+
+        // when
+        let first = ModerationLogPage::of(&log, None, Some(2));
+        let second = ModerationLogPage::of(&log, Some(3), Some(2));
+        let last = ModerationLogPage::of(&log, Some(1), Some(2));
+
+        // then
+        let ids = |page: &ModerationLogPage| page.entries.iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!((ids(&first), first.more), (vec![4, 3], true));
+        assert_eq!((ids(&second), second.more), (vec![2, 1], true));
+        assert_eq!((ids(&last), last.more), (vec![0], false));
+    }
+}
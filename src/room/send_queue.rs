@@ -0,0 +1,312 @@
+//! A send queue for each room member, so that broadcasting never waits on a slow client. Messages
+//! go straight to the session while it keeps up, and queue up for a task of its own once it
+//! doesn't.
+
+use std::{collections::VecDeque, fmt, sync::Arc};
+
+use futures::future::{self, BoxFuture};
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+use crate::session::{SessionHandle, SessionMsg, SessionSink};
+
+/// How many messages may queue up before syncs get dropped to make room.
+const CAPACITY: usize = 64;
+
+/// How many messages may queue up at most. Only messages that can't be dropped get past
+/// [`CAPACITY`], and a session that falls this far behind counts as gone, so that the room lets
+/// go of it.
+const MAX_BACKLOG: usize = 4 * CAPACITY;
+
+#[derive(Debug, Default)]
+struct QueueState {
+    messages: VecDeque<SessionMsg>,
+    /// Whether the delivery task is handing a message to the session right now.
+    sending: bool,
+    /// The session turned out to be gone.
+    gone: bool,
+    /// Nobody can add to the queue anymore, so the delivery task stops once it's empty.
+    closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+/// Makes messages to the session go through a queue of its own.
+pub fn wrap_session(session: SessionHandle) -> SessionHandle {
+    session.wrap_sink(|inner| {
+        let shared = Arc::new(Shared::default());
+        tokio::spawn(deliver(Arc::clone(&shared), Arc::clone(&inner)));
+        Arc::new(QueuedSink { inner, shared })
+    })
+}
+
+async fn deliver(shared: Arc<Shared>, inner: Arc<dyn SessionSink>) {
+    loop {
+        let next = {
+            let mut state = shared.state.lock();
+            let next = state.messages.pop_front();
+            state.sending = next.is_some();
+            if next.is_none() && state.closed {
+                return;
+            }
+            next
+        };
+        let Some(msg) = next else {
+            shared.notify.notified().await;
+            continue;
+        };
+        match inner.deliver_message(msg).await {
+            Ok(true) => (),
+            Ok(false) => {
+                let mut state = shared.state.lock();
+                state.gone = true;
+                state.messages.clear();
+                return;
+            }
+            Err(err) => log::error!("Failed to deliver a queued message: {err:?}"),
+        }
+    }
+}
+
+struct QueuedSink {
+    inner: Arc<dyn SessionSink>,
+    shared: Arc<Shared>,
+}
+
+impl QueuedSink {
+    fn push(&self, mut msg: SessionMsg) -> bool {
+        let mut state = self.shared.state.lock();
+        if state.gone {
+            return false;
+        }
+        if state.messages.is_empty() && !state.sending {
+            match self.inner.try_send_message(msg) {
+                Ok(delivered) => {
+                    state.gone = !delivered;
+                    return delivered;
+                }
+                Err(returned) => msg = *returned,
+            }
+        }
+        if state.messages.len() >= CAPACITY {
+            if let Some(index) = state.messages.iter().position(SessionMsg::is_droppable) {
+                state.messages.remove(index);
+            } else if state.messages.len() >= MAX_BACKLOG {
+                log::warn!("A session fell too far behind on its messages; letting go of it");
+                state.gone = true;
+                return false;
+            }
+        }
+        state.messages.push_back(msg);
+        self.shared.notify.notify_one();
+        true
+    }
+}
+
+impl fmt::Debug for QueuedSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueuedSink")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for QueuedSink {
+    fn drop(&mut self) {
+        self.shared.state.lock().closed = true;
+        self.shared.notify.notify_one();
+    }
+}
+
+impl SessionSink for QueuedSink {
+    /// Never waits for the session. Queued messages count as delivered, like ones that are still
+    /// on the way to the client.
+    fn send_message(&self, msg: SessionMsg) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(future::ready(Ok(self.push(msg))))
+    }
+
+    fn try_send_message(&self, msg: SessionMsg) -> Result<bool, Box<SessionMsg>> {
+        Ok(self.push(msg))
+    }
+
+    fn time_offset(&self) -> i64 {
+        self.inner.time_offset()
+    }
+
+    fn latency(&self) -> u64 {
+        self.inner.latency()
+    }
+
+    fn jitter(&self) -> u64 {
+        self.inner.jitter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{sync::Semaphore, time::timeout};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{playback::PlaybackState, session::SessionId};
+
+    /// A session that only takes messages while it's let through.
+    #[derive(Debug)]
+    struct SlowSession {
+        messages: Mutex<Vec<SessionMsg>>,
+        permits: Semaphore,
+    }
+
+    impl SessionSink for SlowSession {
+        fn send_message(&self, msg: SessionMsg) -> BoxFuture<'_, anyhow::Result<bool>> {
+            Box::pin(async move {
+                self.permits.acquire().await?.forget();
+                self.messages.lock().push(msg);
+                Ok(true)
+            })
+        }
+
+        fn try_send_message(&self, msg: SessionMsg) -> Result<bool, Box<SessionMsg>> {
+            Err(Box::new(msg))
+        }
+
+        fn time_offset(&self) -> i64 {
+            0
+        }
+
+        fn latency(&self) -> u64 {
+            0
+        }
+
+        fn jitter(&self) -> u64 {
+            0
+        }
+    }
+
+    fn slow_session() -> (Arc<SlowSession>, SessionHandle) {
+        let slow = Arc::new(SlowSession {
+            messages: Mutex::default(),
+            permits: Semaphore::new(0),
+        });
+        let handle = SessionHandle::new(
+            SessionId::from(Uuid::from_u128(1)),
+            "alice".to_string(),
+            None,
+            Arc::clone(&slow) as Arc<dyn SessionSink>,
+        );
+        (slow, wrap_session(handle))
+    }
+
+    fn sync(time: f32) -> SessionMsg {
+        SessionMsg::PlaybackSync(PlaybackState {
+            timestamp: 1_700_000_000_000,
+            playing: true,
+            time,
+            duration: None,
+        })
+    }
+
+    async fn received(slow: &SlowSession, count: usize) -> Vec<SessionMsg> {
+        slow.permits.add_permits(count);
+        timeout(Duration::from_secs(1), async {
+            while slow.messages.lock().len() < count {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        std::mem::take(&mut slow.messages.lock())
+    }
+
+    #[tokio::test]
+    async fn should_not_wait_for_slow_sessions() {
+        // given
+        let (slow, session) = slow_session();
+
+        // when
+        let sent = timeout(Duration::from_millis(100), async {
+            for i in 0..10 {
+                session.send_message(sync(i as f32)).await.unwrap();
+            }
+        })
+        .await;
+
+        // then
+        assert!(sent.is_ok());
+        let times: Vec<f32> = received(&slow, 10)
+            .await
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SessionMsg::PlaybackSync(state) => Some(state.time),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(times, (0..10).map(|i| i as f32).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn should_drop_oldest_syncs_but_keep_room_state_when_full() {
+        // given
+        let (slow, session) = slow_session();
+        session.send_message(sync(0.0)).await.unwrap();
+        session
+            .send_message(SessionMsg::RoomApproved)
+            .await
+            .unwrap();
+        for i in 1..CAPACITY - 1 {
+            session.send_message(sync(i as f32)).await.unwrap();
+        }
+
+        // when
+        session.send_message(sync(CAPACITY as f32)).await.unwrap();
+
+        // then
+        let messages = received(&slow, CAPACITY).await;
+        assert!(matches!(messages[0], SessionMsg::RoomApproved));
+        assert!(matches!(
+            messages.last(),
+            Some(SessionMsg::PlaybackSync(state)) if state.time == CAPACITY as f32
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_give_up_on_sessions_that_never_catch_up() {
+        // given
+        let (_slow, session) = slow_session();
+        for _ in 0..MAX_BACKLOG {
+            let sent = session.send_message(SessionMsg::RoomApproved).await;
+            assert!(sent.unwrap());
+        }
+
+        // when
+        let overflowing = session.send_message(SessionMsg::RoomApproved).await;
+        let after = session.send_message(sync(0.0)).await;
+
+        // then
+        assert!(!overflowing.unwrap());
+        assert!(!after.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_deliver_queued_messages_after_the_room_lets_go() {
+        // given
+        let (slow, session) = slow_session();
+        session
+            .send_message(SessionMsg::RoomApproved)
+            .await
+            .unwrap();
+
+        // when
+        drop(session);
+
+        // then
+        let messages = received(&slow, 1).await;
+        assert!(matches!(messages[..], [SessionMsg::RoomApproved]));
+    }
+}
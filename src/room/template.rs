@@ -0,0 +1,108 @@
+use crate::{
+    errors::ClientError,
+    messages::dto,
+    playback::{PlaybackSource, MAX_QUEUE_ENTRIES},
+};
+
+use super::{QuietWindow, RoomSettings};
+
+/// Everything a room is set up with when it is created, so that recurring events can be set up
+/// again in one step.
+#[derive(Debug, Clone)]
+pub struct RoomTemplate {
+    pub settings: RoomSettings,
+    pub quiet_hours: Vec<QuietWindow>,
+    /// The queue of the first playback in the room.
+    pub queue: Vec<PlaybackSource>,
+}
+
+impl RoomTemplate {
+    /// Reads a template file, as clients submit it with `room::create_from_template/v1`.
+    pub fn parse(template: &str) -> Result<Self, ClientError> {
+        let template: dto::RoomTemplateV1 = serde_json::from_str(template)
+            .map_err(|err| ClientError::invalid(format!("Invalid room template: {err}")))?;
+        Self::try_from(template)
+    }
+}
+
+impl From<RoomSettings> for RoomTemplate {
+    fn from(settings: RoomSettings) -> Self {
+        Self {
+            settings,
+            quiet_hours: Vec::new(),
+            queue: Vec::new(),
+        }
+    }
+}
+
+impl TryFrom<dto::RoomTemplateV1> for RoomTemplate {
+    type Error = ClientError;
+
+    fn try_from(value: dto::RoomTemplateV1) -> Result<Self, Self::Error> {
+        let quiet_hours = value
+            .quiet_hours
+            .into_iter()
+            .map(QuietWindow::try_from)
+            .collect::<anyhow::Result<_>>()
+            .map_err(|err| ClientError::invalid(format!("Invalid room template: {err}")))?;
+        if value.queue.len() > MAX_QUEUE_ENTRIES {
+            return Err(ClientError::invalid(format!(
+                "Invalid room template: the queue can't hold more than {MAX_QUEUE_ENTRIES} entries"
+            )));
+        }
+        Ok(Self {
+            settings: value.settings.into(),
+            quiet_hours,
+            queue: value.queue.into_iter().map(From::from).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::ErrorCode;
+
+    use super::*;
+
+    #[test]
+    fn should_read_template_files() {
+        // given
+        let file = r#"{
+            "settings": { "name": "Movie night", "password": "", "public": true },
+            "quiet_hours": [{ "start": 1320, "end": 420 }],
+            "queue": [{
+                "title": "Big Buck Bunny",
+                "page_href": "https://example.com/bbb",
+                "frame_href": "https://example.com/bbb",
+                "element_query": "video"
+            }]
+        }"#;
+
+        // when
+        let template = RoomTemplate::parse(file).unwrap();
+
+        // then
+        assert_eq!(template.settings.name, "Movie night");
+        assert!(template.settings.public);
+        assert_eq!(template.quiet_hours.len(), 1);
+        assert_eq!(template.queue[0].title, "Big Buck Bunny");
+    }
+
+    #[test]
+    fn should_reject_invalid_templates() {
+        // given
+        let malformed = r#"{ "settings": { "password": "popcorn" } }"#;
+        let bad_quiet_hours = r#"{
+            "settings": { "name": "Movie night", "password": "" },
+            "quiet_hours": [{ "start": 9000, "end": 0 }]
+        }"#;
+
+        // when
+        let malformed = RoomTemplate::parse(malformed);
+        let bad_quiet_hours = RoomTemplate::parse(bad_quiet_hours);
+
+        // then
+        assert_eq!(malformed.unwrap_err().code, ErrorCode::InvalidRequest);
+        assert_eq!(bad_quiet_hours.unwrap_err().code, ErrorCode::InvalidRequest);
+    }
+}
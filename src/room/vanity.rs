@@ -0,0 +1,107 @@
+use std::fmt;
+
+use uuid::Uuid;
+
+use crate::{content_filter::ContentFilter, errors::ClientError, messages::dto};
+
+use super::RoomId;
+
+const MIN_LEN: usize = 3;
+const MAX_LEN: usize = 32;
+
+/// Vanity ids don't depend on case or surrounding whitespace, so they are stored and looked up
+/// in this form.
+pub fn normalize_vanity_id(id: &str) -> String {
+    id.trim().to_ascii_lowercase()
+}
+
+/// Checks a vanity id that a room should be reachable by, like `movie-night`, and returns it
+/// in lowercase. Vanity ids consist of letters, digits and dashes, and can't look like UUIDs,
+/// so that they are never mistaken for a room's real id.
+pub fn check_vanity_id(id: &str, content_filter: &ContentFilter) -> anyhow::Result<String> {
+    let id = normalize_vanity_id(id);
+    if !(MIN_LEN..=MAX_LEN).contains(&id.len()) {
+        return Err(ClientError::invalid(format!(
+            "Room ids need to be between {MIN_LEN} and {MAX_LEN} characters long"
+        ))
+        .into());
+    }
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        || id.starts_with('-')
+        || id.ends_with('-')
+    {
+        return Err(ClientError::invalid(
+            "Room ids can only contain letters, digits and dashes between them",
+        )
+        .into());
+    }
+    if Uuid::parse_str(&id).is_ok() {
+        return Err(ClientError::invalid("Room ids can't be UUIDs").into());
+    }
+    if content_filter.apply(&id, "Room ids")? != id {
+        return Err(ClientError::invalid("Room ids can't contain filtered words").into());
+    }
+    Ok(id)
+}
+
+/// How a client refers to a room: by its id, or by its vanity id if it has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomRef {
+    Id(RoomId),
+    Vanity(String),
+}
+
+impl From<dto::RoomRefV1> for RoomRef {
+    fn from(value: dto::RoomRefV1) -> Self {
+        match value {
+            dto::RoomRefV1::Id(id) => Self::Id(id.into()),
+            dto::RoomRefV1::Vanity(id) => Self::Vanity(normalize_vanity_id(&id)),
+        }
+    }
+}
+
+impl fmt::Display for RoomRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "{id}"),
+            Self::Vanity(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::{error_code, ErrorCode};
+
+    use super::*;
+
+    #[test]
+    fn should_accept_vanity_ids_in_lowercase() {
+        // when
+        let id = check_vanity_id(" Movie-Night-42 ", &ContentFilter::default());
+
+        // then
+        assert_eq!(id.unwrap(), "movie-night-42");
+    }
+
+    #[test]
+    fn should_reject_malformed_vanity_ids() {
+        // given
+        let filter = ContentFilter::default();
+
+        // then
+        for id in [
+            "ab",
+            "movie night",
+            "-movie",
+            "movie-",
+            "kino/abend",
+            "00000000-0000-0000-0000-000000000001",
+        ] {
+            let result = check_vanity_id(id, &filter);
+            assert_eq!(error_code(&result.unwrap_err()), ErrorCode::InvalidRequest);
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use std::{path::PathBuf, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{messages::dto, overload::LoadShedder, room::RoomManager};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RpcConfig {
+    /// A unix socket on which read-only admin queries are answered over JSON-RPC, for scripts
+    /// and status bar modules on the same machine. It is only created if this is set.
+    pub rpc_socket: Option<PathBuf>,
+}
+
+/// A JSON-RPC 2.0 request. Requests without an id are notifications, which get no response.
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    id: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl RpcError {
+    const PARSE_ERROR: i32 = -32700;
+    const INVALID_REQUEST: i32 = -32600;
+    const METHOD_NOT_FOUND: i32 = -32601;
+    const INTERNAL_ERROR: i32 = -32603;
+
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Answers the queries. All of them only read what the server is doing, so that the socket
+/// can't be used to change anything.
+struct RpcHandler {
+    room_mgr: Arc<RoomManager>,
+    load_shedder: Arc<LoadShedder>,
+}
+
+impl RpcHandler {
+    /// Handles one line of input, and returns the response to it, if there is one.
+    async fn handle(&self, line: &str) -> Option<String> {
+        let response = match serde_json::from_str::<Value>(line) {
+            Err(err) => Self::error_response(
+                Value::Null,
+                RpcError::new(RpcError::PARSE_ERROR, err.to_string()),
+            ),
+            Ok(value) => {
+                let id = value.get("id").cloned().unwrap_or(Value::Null);
+                match serde_json::from_value::<RpcRequest>(value) {
+                    Ok(request) if request.jsonrpc == "2.0" => {
+                        let id = request.id?;
+                        match self.call(&request.method).await {
+                            Ok(result) => RpcResponse {
+                                jsonrpc: "2.0",
+                                result: Some(result),
+                                error: None,
+                                id,
+                            },
+                            Err(err) => Self::error_response(id, err),
+                        }
+                    }
+                    Ok(..) => Self::error_response(
+                        id,
+                        RpcError::new(RpcError::INVALID_REQUEST, "Only JSON-RPC 2.0 is supported"),
+                    ),
+                    Err(err) => Self::error_response(
+                        id,
+                        RpcError::new(RpcError::INVALID_REQUEST, err.to_string()),
+                    ),
+                }
+            }
+        };
+        match serde_json::to_string(&response) {
+            Ok(response) => Some(response),
+            Err(err) => {
+                log::error!("Failed to serialize RPC response: {err:?}");
+                None
+            }
+        }
+    }
+
+    fn error_response(id: Value, error: RpcError) -> RpcResponse {
+        RpcResponse {
+            jsonrpc: "2.0",
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+
+    async fn call(&self, method: &str) -> Result<Value, RpcError> {
+        let result = match method {
+            "stats" => {
+                let rooms = self.room_mgr.list_all_rooms().await;
+                serde_json::to_value(dto::AdminStatsAckMsgBodyV1::new(
+                    self.load_shedder.session_count(),
+                    &rooms,
+                ))
+            }
+            "rooms.list" => {
+                let rooms = self.room_mgr.list_all_rooms().await;
+                serde_json::to_value(
+                    rooms
+                        .into_iter()
+                        .map(dto::AdminRoomV1::from)
+                        .collect::<Vec<_>>(),
+                )
+            }
+            "rooms.pending" => serde_json::to_value(self.room_mgr.list_pending_rooms().await),
+            _ => {
+                return Err(RpcError::new(
+                    RpcError::METHOD_NOT_FOUND,
+                    format!("Unknown method '{method}'"),
+                ))
+            }
+        };
+        result.map_err(|err| {
+            log::error!("Failed to serialize result of RPC method {method}: {err:?}");
+            RpcError::new(RpcError::INTERNAL_ERROR, "Internal error")
+        })
+    }
+}
+
+#[cfg(unix)]
+pub use server::RpcServer;
+
+#[cfg(unix)]
+mod server {
+    use std::{
+        fs,
+        os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use anyhow::{bail, Context};
+    use log::{debug, error, info};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+    };
+    use uuid::Uuid;
+
+    use super::{RpcConfig, RpcHandler};
+    use crate::{overload::LoadShedder, room::RoomManager};
+
+    /// Serves the JSON-RPC queries on a unix socket, with one request per line.
+    pub struct RpcServer {
+        listener: UnixListener,
+        path: PathBuf,
+        handler: Arc<RpcHandler>,
+    }
+
+    impl RpcServer {
+        const MAX_LINE_SIZE: usize = 64 * 1024;
+
+        pub async fn bind(
+            config: RpcConfig,
+            room_mgr: Arc<RoomManager>,
+            load_shedder: Arc<LoadShedder>,
+        ) -> anyhow::Result<Option<Self>> {
+            let Some(path) = config.rpc_socket else {
+                return Ok(None);
+            };
+            if let Ok(metadata) = fs::symlink_metadata(&path) {
+                if !metadata.file_type().is_socket() {
+                    bail!("{} is in the way of the RPC socket", path.display());
+                }
+                // a socket that nobody answers on was left behind by a server that didn't exit
+                // cleanly
+                if UnixStream::connect(&path).await.is_ok() {
+                    bail!("The RPC socket {} is already in use", path.display());
+                }
+                fs::remove_file(&path).context("Failed to remove stale RPC socket")?;
+            }
+            let listener = Self::bind_private(&path)?;
+            Ok(Some(Self {
+                listener,
+                path,
+                handler: Arc::new(RpcHandler {
+                    room_mgr,
+                    load_shedder,
+                }),
+            }))
+        }
+
+        /// Only the user the server runs as may connect. The socket is created in a directory that
+        /// nobody else can enter, and only moved into place once its permissions are restricted.
+        fn bind_private(path: &Path) -> anyhow::Result<UnixListener> {
+            let parent = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            let private_dir = parent.join(format!(".rpc-{}", Uuid::new_v4().simple()));
+            fs::DirBuilder::new()
+                .mode(0o700)
+                .create(&private_dir)
+                .context("Failed to create a private directory for the RPC socket")?;
+            let bind = || {
+                let private_path = private_dir.join("rpc.sock");
+                let listener =
+                    UnixListener::bind(&private_path).context("Failed to create RPC socket")?;
+                fs::set_permissions(&private_path, fs::Permissions::from_mode(0o600))
+                    .context("Failed to restrict access to RPC socket")?;
+                fs::rename(&private_path, path).context("Failed to move RPC socket into place")?;
+                anyhow::Ok(listener)
+            };
+            let result = bind();
+            if let Err(err) = fs::remove_dir_all(&private_dir) {
+                debug!("Failed to clean up after creating the RPC socket: {err:?}");
+            }
+            result
+        }
+
+        pub async fn serve(self) {
+            info!("RPC socket listening at {}...", self.path.display());
+            loop {
+                let stream = match self.listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        error!("RPC connection failed: {err:?}");
+                        continue;
+                    }
+                };
+                let handler = Arc::clone(&self.handler);
+                tokio::spawn(async move {
+                    if let Err(err) = Self::handle_connection(stream, &handler).await {
+                        debug!("Error on RPC connection: {err:?}");
+                    }
+                });
+            }
+        }
+
+        async fn handle_connection(stream: UnixStream, handler: &RpcHandler) -> anyhow::Result<()> {
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            let mut line = String::new();
+            loop {
+                line.clear();
+                let read = (&mut reader)
+                    .take(Self::MAX_LINE_SIZE as u64)
+                    .read_line(&mut line)
+                    .await?;
+                if read == 0 {
+                    return Ok(());
+                }
+                if !line.ends_with('\n') && read == Self::MAX_LINE_SIZE {
+                    bail!("Oversized RPC request");
+                }
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if let Some(mut response) = handler.handle(line.trim()).await {
+                    response.push('\n');
+                    writer.write_all(response.as_bytes()).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::{
+        fs,
+        os::unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            net::UnixListener,
+        },
+        path::Path,
+    };
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        overload::OverloadConfig,
+        room::RoomConfig,
+        storage::{self, StorageConfig},
+    };
+
+    async fn handler() -> RpcHandler {
+        let storage = storage::create_storage(StorageConfig::Memory)
+            .await
+            .unwrap();
+        let room_mgr = RoomManager::new(RoomConfig::default(), Arc::default(), storage).unwrap();
+        RpcHandler {
+            room_mgr: Arc::new(room_mgr),
+            load_shedder: Arc::new(LoadShedder::new(OverloadConfig::default())),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_answer_queries() {
+        // given
+        let handler = handler().await;
+
+        // when
+        let stats = handler
+            .handle(r#"{"jsonrpc":"2.0","method":"stats","id":1}"#)
+            .await;
+        let rooms = handler
+            .handle(r#"{"jsonrpc":"2.0","method":"rooms.list","id":"a"}"#)
+            .await;
+
+        // then
+        let parse =
+            |response: Option<String>| serde_json::from_str::<Value>(&response.unwrap()).unwrap();
+        assert_eq!(
+            parse(stats),
+            json!({
+                "jsonrpc": "2.0",
+                "result": { "sessions": 0, "rooms": 0, "pending_rooms": 0, "room_users": 0 },
+                "id": 1
+            })
+        );
+        assert_eq!(
+            parse(rooms),
+            json!({ "jsonrpc": "2.0", "result": [], "id": "a" })
+        );
+    }
+
+    #[tokio::test]
+    async fn should_report_bad_requests() {
+        // given
+        let handler = handler().await;
+
+        // when
+        let garbage = handler.handle("{").await;
+        let unknown = handler
+            .handle(r#"{"jsonrpc":"2.0","method":"rooms.close","id":2}"#)
+            .await;
+        let notification = handler
+            .handle(r#"{"jsonrpc":"2.0","method":"stats"}"#)
+            .await;
+
+        // then
+        let code = |response: Option<String>| {
+            serde_json::from_str::<Value>(&response.unwrap()).unwrap()["error"]["code"].clone()
+        };
+        assert_eq!(code(garbage), RpcError::PARSE_ERROR);
+        assert_eq!(code(unknown), RpcError::METHOD_NOT_FOUND);
+        assert_eq!(notification, None);
+    }
+
+    #[cfg(unix)]
+    async fn bind(path: &Path) -> anyhow::Result<Option<RpcServer>> {
+        let handler = handler().await;
+        RpcServer::bind(
+            RpcConfig {
+                rpc_socket: Some(path.to_path_buf()),
+            },
+            handler.room_mgr,
+            handler.load_shedder,
+        )
+        .await
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn should_only_let_the_server_user_connect() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("palantir.sock");
+
+        // when
+        let server = bind(&path).await.unwrap();
+
+        // then
+        assert!(server.is_some());
+        let metadata = fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn should_only_replace_stale_sockets() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let stale = dir.path().join("stale.sock");
+        drop(UnixListener::bind(&stale).unwrap());
+        let file = dir.path().join("notes.txt");
+        fs::write(&file, "keep me").unwrap();
+
+        // when
+        let replaced = bind(&stale).await;
+        let in_the_way = bind(&file).await;
+
+        // then
+        assert!(replaced.unwrap().is_some());
+        assert!(in_the_way.is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep me");
+    }
+}
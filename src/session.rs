@@ -1,18 +1,27 @@
 use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
     sync::{
-        atomic::{AtomicI64, Ordering},
+        atomic::{AtomicI64, AtomicU64, Ordering},
         Arc, Weak,
     },
     time::Duration,
 };
 
 use anyhow::{anyhow, Context};
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use tokio::{
-    sync::{self, mpsc},
-    time,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, Notify,
+    },
+    time::{self, Instant},
 };
+use uuid::Uuid;
 
-id_type!(SessionId);
+id_type!(SessionId, Serialize, Deserialize);
 
 impl From<dto::UserIdV1> for SessionId {
     fn from(value: dto::UserIdV1) -> Self {
@@ -27,88 +36,553 @@ impl From<SessionId> for dto::UserIdV1 {
 }
 
 use crate::{
-    connection::{CloseReason, Connection},
+    app::SessionServices,
+    auth::AuthProvider,
+    connection::{CloseReason, Connection, PingResult, SyncQuality},
+    errors::{error_code, ClientError, ErrorCode},
+    features::{Feature, FeatureFlags},
+    guest_names::GuestNameConfig,
     id_type,
+    logging::LogController,
     messages::{dto, Message, MessageBody},
-    playback::{DisconnectReason, PlaybackInfo, PlaybackRequest, PlaybackState, StopReason},
-    room::{RoomCloseReason, RoomHandle, RoomId, RoomManager, RoomRequest, RoomState, UserRole},
+    overload::{LoadShedder, OverloadConfig},
+    playback::{
+        DeltaSync, DisconnectReason, PlaybackInfo, PlaybackOverview, PlaybackRequest,
+        PlaybackSource, PlaybackState, QualityHint, QueueEntry, RollCallReport, SeekHints, StartAt,
+        StopReason, SubscriberStats, WaitingFor,
+    },
+    room::{
+        BroadcastEvent, ChatMessage, Intermission, LeaveReason, ModerationLogPage, PlayedSource,
+        QuietWindow, RoomCloseReason, RoomError, RoomHandle, RoomId, RoomManager, RoomRef,
+        RoomRequest, RoomSettings, RoomTemplate, SharedLink, UserData, UserRole,
+    },
+    storage::Storage,
 };
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ResumeConfig {
+    pub enabled: bool,
+
+    /// How long a session waits for its client to come back after losing the connection, in
+    /// seconds.
+    pub grace_period: u64,
+}
+
+impl Default for ResumeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            grace_period: 60,
+        }
+    }
+}
+
+/// Clients are pinged to make sure they're still there and to keep track of their clock offset.
+/// Clients that keep sending messages anyway are pinged less often.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct KeepaliveConfig {
+    /// How long a client may be silent before it is pinged, in seconds.
+    pub silent_interval: u64,
+
+    /// The longest time between two pings even if the client is busy, in seconds.
+    pub max_interval: u64,
+
+    /// How many pings in a row a client may leave unanswered before its connection is closed.
+    /// With 0, clients are never disconnected for not answering.
+    pub max_missed_pings: u32,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            silent_interval: 5,
+            max_interval: 30,
+            max_missed_pings: 3,
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    fn next_ping_at(&self, last_ping_at: Instant, last_message_at: Instant) -> Instant {
+        let silent_deadline =
+            last_ping_at.max(last_message_at) + Duration::from_secs(self.silent_interval);
+        let max_deadline = last_ping_at + Duration::from_secs(self.max_interval);
+        silent_deadline.min(max_deadline)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    pub resume: ResumeConfig,
+    pub keepalive: KeepaliveConfig,
+    pub overload: OverloadConfig,
+    pub guest_names: GuestNameConfig,
+}
+
+/// Keeps track of sessions that lost their connection and are waiting to be resumed.
+pub struct SuspendedSessions {
+    config: ResumeConfig,
+    sessions: Mutex<HashMap<String, oneshot::Sender<Connection>>>,
+}
+
+impl SuspendedSessions {
+    pub fn new(config: ResumeConfig) -> Self {
+        Self {
+            config,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates a new resume token, unless resuming sessions is disabled.
+    pub fn issue_token(&self) -> Option<String> {
+        self.config
+            .enabled
+            .then(|| Uuid::new_v4().simple().to_string())
+    }
+
+    /// Hands the connection over to the suspended session with the given token. The connection
+    /// is returned if there is no such session.
+    pub fn resume(&self, token: &str, connection: Connection) -> Result<(), Box<Connection>> {
+        match self.sessions.lock().remove(token) {
+            Some(session) => session.send(connection).map_err(Box::new),
+            None => Err(Box::new(connection)),
+        }
+    }
+
+    fn suspend(&self, token: String) -> oneshot::Receiver<Connection> {
+        let (connection_tx, connection_rx) = oneshot::channel();
+        self.sessions.lock().insert(token, connection_tx);
+        connection_rx
+    }
+
+    fn forget(&self, token: &str) {
+        self.sessions.lock().remove(token);
+    }
+}
+
+/// A timestamped probe that two clients exchange through the server to estimate the offset
+/// between their clocks directly.
+#[derive(Debug, Clone, Copy)]
+pub enum PeerProbe {
+    Request {
+        probe_id: u32,
+        sent_at: u64,
+    },
+    Reply {
+        probe_id: u32,
+        sent_at: u64,
+        received_at: u64,
+        replied_at: u64,
+    },
+}
+
+impl PeerProbe {
+    fn into_message(self, peer: SessionId) -> MessageBody {
+        let peer = peer.into();
+        match self {
+            Self::Request { probe_id, sent_at } => {
+                MessageBody::RoomPeerProbeV1(dto::RoomPeerProbeMsgBodyV1 {
+                    peer,
+                    probe_id,
+                    sent_at,
+                })
+            }
+            Self::Reply {
+                probe_id,
+                sent_at,
+                received_at,
+                replied_at,
+            } => MessageBody::RoomPeerProbeReplyV1(dto::RoomPeerProbeReplyMsgBodyV1 {
+                peer,
+                probe_id,
+                sent_at,
+                received_at,
+                replied_at,
+            }),
+        }
+    }
+}
+
+/// How a session takes part in the playback of its room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlaybackRole {
+    Host,
+    Subscriber,
+}
+
+impl From<PlaybackRole> for dto::PlaybackSubscriptionV1 {
+    fn from(value: PlaybackRole) -> Self {
+        match value {
+            PlaybackRole::Host => Self::Host,
+            PlaybackRole::Subscriber => Self::Subscriber,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum SessionMsg {
-    RoomState(RoomState),
-    RoomClosed(RoomCloseReason),
+    /// A message that went to the whole room, numbered so that clients can tell when they missed
+    /// one.
+    Broadcast(u64, Box<SessionMsg>),
+    /// Shared between everyone the state is broadcast to, so that it is only built once.
+    RoomState(Arc<dto::RoomStateMsgBodyV1>),
+    RoomClosed(RoomId, RoomCloseReason),
+    /// This session was kicked from its room by the user with the given name.
+    Kicked(String),
+    /// This session couldn't join the room after all.
+    JoinRejected(RoomId, ClientError),
+    HostChanged(UserData),
+    /// Someone joined the room under a name that was first used from a different client.
+    IdentityWarning(UserData),
+    /// The role of this session's user in its room changed.
+    RoleChanged(UserRole),
+    UserJoined(UserData),
+    UserLeft(UserData, LeaveReason),
+    Chat(ChatMessage),
+    LinkShared(SharedLink),
+    RoomPendingApproval,
+    RoomApproved,
+    BroadcastAck(BroadcastEvent),
     PlaybackHosting,
     PlaybackAvailable(PlaybackInfo),
+    PlaybackOverview(PlaybackOverview),
+    PlaybackHistory(Vec<PlayedSource>),
+    ModerationLog(ModerationLogPage),
     PlaybackStarted,
+    PlaybackStartAt(StartAt),
+    /// Where the user was in the current source when they last left the room, in seconds.
+    PlaybackLastPosition(f32),
     PlaybackConnected,
+    PlaybackSourceChanged(Option<PlaybackSource>),
+    PlaybackQueue(Vec<QueueEntry>),
+    PlaybackRollCall(u32),
+    PlaybackRollCallReport(RollCallReport),
+    /// How far each subscriber is off the playback, for its host.
+    PlaybackStats(Vec<SubscriberStats>),
+    /// Who a held start is still waiting for, for the playback host.
+    PlaybackWaitingFor(WaitingFor),
     PlaybackSync(PlaybackState),
     PlaybackStopped(StopReason),
     PlaybackDisconnected(DisconnectReason),
+    /// This session's player struggles to keep up with the playback.
+    PlaybackQualityHint(QualityHint),
+    /// Someone else than the host became the playback's timing reference, or the host is again.
+    PlaybackLeader(Option<SessionId>),
+    PeerProbe(SessionId, PeerProbe),
+    /// The name this session's user goes by in its room after renaming themselves, which the
+    /// room may have changed to tell them apart from other members.
+    Renamed(String),
 }
 
-#[derive(Debug, Clone)]
-pub struct SessionHandle {
-    pub id: SessionId,
-    pub name: String,
+impl SessionMsg {
+    /// The message itself, without the number of the broadcast it may have been part of.
+    pub fn unsequenced(self) -> Self {
+        match self {
+            Self::Broadcast(_, msg) => msg.unsequenced(),
+            msg => msg,
+        }
+    }
+
+    /// Whether the message makes an earlier one pointless, because it is a newer version of the
+    /// same state.
+    pub fn supersedes(&self, earlier: &SessionMsg) -> bool {
+        matches!(
+            (self, earlier),
+            (Self::RoomState(..), Self::RoomState(..))
+                | (Self::RoleChanged(..), Self::RoleChanged(..))
+                | (Self::PlaybackSync(..), Self::PlaybackSync(..))
+                | (Self::PlaybackOverview(..), Self::PlaybackOverview(..))
+        )
+    }
+
+    /// Whether the message can be left out when the session falls behind, because a later one
+    /// makes up for it.
+    pub fn is_droppable(&self) -> bool {
+        match self {
+            Self::Broadcast(_, msg) => msg.is_droppable(),
+            msg => matches!(msg, Self::PlaybackSync(..)),
+        }
+    }
+}
+
+/// Where the messages for a session go. This is the message channel of a running session
+/// everywhere but in tests.
+pub trait SessionSink: fmt::Debug + Send + Sync {
+    /// Returns whether the session was still there to receive the message. Fails if the session
+    /// couldn't take the message right away.
+    fn send_message(&self, msg: SessionMsg) -> BoxFuture<'_, anyhow::Result<bool>>;
+
+    /// Like [`SessionSink::send_message`], but waits for the session to make room for the
+    /// message. Rooms never do this; only tasks that work for a single session may.
+    fn deliver_message(&self, msg: SessionMsg) -> BoxFuture<'_, anyhow::Result<bool>> {
+        self.send_message(msg)
+    }
+
+    /// Like [`SessionSink::send_message`], but without waiting. The message is handed back if the
+    /// session can't take it right away.
+    fn try_send_message(&self, msg: SessionMsg) -> Result<bool, Box<SessionMsg>>;
+
+    /// The offset of the client's clock from the server's, in milliseconds.
+    fn time_offset(&self) -> i64;
+
+    /// The round trip time of the client's last ping in milliseconds, or 0 if it hasn't answered
+    /// one yet.
+    fn latency(&self) -> u64;
+
+    /// How much the round trip time varies from ping to ping, in milliseconds.
+    fn jitter(&self) -> u64;
+}
+
+#[derive(Debug)]
+struct SessionChannel {
     time_offset: Weak<AtomicI64>,
+    latency: Weak<AtomicU64>,
+    jitter: Weak<AtomicU64>,
     message_tx: mpsc::WeakSender<SessionMsg>,
 }
 
-impl SessionHandle {
-    pub async fn send_message(&self, msg: SessionMsg) -> anyhow::Result<bool> {
+impl SessionSink for SessionChannel {
+    /// Never waits, since whoever sends would hold up everyone else's messages meanwhile. While
+    /// the session's queue is full, messages that a later one makes up for are dropped.
+    fn send_message(&self, msg: SessionMsg) -> BoxFuture<'_, anyhow::Result<bool>> {
+        let result = match self.try_send_message(msg) {
+            Ok(delivered) => Ok(delivered),
+            Err(msg) if msg.is_droppable() => {
+                log::debug!("Dropping a message for a session that is behind: {msg:?}");
+                Ok(true)
+            }
+            Err(..) => Err(anyhow!("The session didn't keep up with its messages")),
+        };
+        Box::pin(async move { result })
+    }
+
+    fn deliver_message(&self, msg: SessionMsg) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(async move {
+            let Some(message_tx) = self.message_tx.upgrade() else {
+                return Ok(false);
+            };
+            Ok(message_tx.send(msg).await.is_ok())
+        })
+    }
+
+    fn try_send_message(&self, msg: SessionMsg) -> Result<bool, Box<SessionMsg>> {
         let Some(message_tx) = self.message_tx.upgrade() else {
             return Ok(false);
         };
-        message_tx.send(msg).await?;
-        Ok(true)
+        match message_tx.try_send(msg) {
+            Ok(()) => Ok(true),
+            Err(TrySendError::Closed(..)) => Ok(false),
+            Err(TrySendError::Full(msg)) => Err(Box::new(msg)),
+        }
     }
 
-    pub fn time_offset(&self) -> i64 {
+    fn time_offset(&self) -> i64 {
         self.time_offset
             .upgrade()
             .map(|t| t.load(Ordering::Relaxed))
             .unwrap_or(0)
     }
+
+    fn latency(&self) -> u64 {
+        self.latency
+            .upgrade()
+            .map(|l| l.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    fn jitter(&self) -> u64 {
+        self.jitter
+            .upgrade()
+            .map(|j| j.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionHandle {
+    pub id: SessionId,
+    pub name: String,
+    pub subject: Option<String>,
+    /// The client's fingerprint doesn't match the one that first used the name.
+    pub unverified: bool,
+    sink: Arc<dyn SessionSink>,
+}
+
+impl SessionHandle {
+    pub fn new(
+        id: SessionId,
+        name: String,
+        subject: Option<String>,
+        sink: Arc<dyn SessionSink>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            subject,
+            unverified: false,
+            sink,
+        }
+    }
+
+    /// The same session, with its messages passed through another sink first.
+    pub fn wrap_sink(
+        self,
+        wrap: impl FnOnce(Arc<dyn SessionSink>) -> Arc<dyn SessionSink>,
+    ) -> Self {
+        Self {
+            sink: wrap(self.sink),
+            ..self
+        }
+    }
+
+    pub async fn send_message(&self, msg: SessionMsg) -> anyhow::Result<bool> {
+        self.sink.send_message(msg).await
+    }
+
+    pub fn time_offset(&self) -> i64 {
+        self.sink.time_offset()
+    }
+
+    pub fn latency(&self) -> u64 {
+        self.sink.latency()
+    }
+
+    pub fn jitter(&self) -> u64 {
+        self.sink.jitter()
+    }
 }
 
 pub struct Session {
     id: SessionId,
     running: bool,
-    room_manager: Arc<sync::Mutex<RoomManager>>,
+    auth_provider: Arc<dyn AuthProvider>,
+    room_manager: Arc<RoomManager>,
+    suspended_sessions: Arc<SuspendedSessions>,
+    load_shedder: Arc<LoadShedder>,
+    storage: Arc<dyn Storage>,
+    features: Arc<FeatureFlags>,
+    log_controller: LogController,
+    unverified: bool,
+    /// Notified when the session is shed because the server is overloaded, or kicked by an
+    /// operator.
+    shed_signal: Arc<Notify>,
+    shed: bool,
     room: Option<RoomHandle>,
+    playback_role: Option<PlaybackRole>,
     message_tx: mpsc::Sender<SessionMsg>,
     message_rx: mpsc::Receiver<SessionMsg>,
     connection: Connection,
-    ping_interval: time::Interval,
+    keepalive: KeepaliveConfig,
+    last_ping_at: Instant,
+    last_message_at: Instant,
+    /// How many pings in a row the client hasn't answered.
+    missed_pings: u32,
+    /// The number of the room broadcast that is being handled, which goes along with whatever
+    /// is sent to the client for it.
+    broadcast_seq: Option<u64>,
+    /// Whether the client wants to hear about its connection's quality after every ping.
+    periodic_stats: bool,
+    /// Where the client is with delta syncs, if it turned them on.
+    delta_sync: Option<DeltaSync>,
     time_offset: Arc<AtomicI64>,
+    latency: Arc<AtomicU64>,
+    jitter: Arc<AtomicU64>,
 }
 
 impl Session {
-    const PING_INTERVAL: Duration = Duration::from_secs(5);
+    const MAX_BATCH_LEN: usize = 32;
+    /// How many messages a suspended session keeps for its client at most.
+    const MAX_MISSED_MESSAGES: usize = 64;
+    const MAX_FINGERPRINT_LEN: usize = 128;
+    pub const MAX_NAME_LEN: usize = 64;
 
-    pub fn new(connection: Connection, room_manager: Arc<sync::Mutex<RoomManager>>) -> Self {
+    pub fn new(connection: Connection, services: &SessionServices) -> Self {
         let (message_tx, message_rx) = mpsc::channel::<SessionMsg>(32);
+        let keepalive = services.keepalive;
         Self {
             id: SessionId::new(),
             running: true,
+            auth_provider: Arc::clone(&services.auth_provider),
+            suspended_sessions: Arc::clone(&services.suspended_sessions),
+            load_shedder: Arc::clone(&services.load_shedder),
+            storage: Arc::clone(&services.storage),
+            features: Arc::clone(&services.features),
+            log_controller: services.log_controller.clone(),
+            unverified: false,
+            shed_signal: Arc::new(Notify::new()),
+            shed: false,
             room: None,
+            playback_role: None,
             message_rx,
             message_tx,
             connection,
-            room_manager,
+            room_manager: Arc::clone(&services.room_mgr),
             time_offset: Arc::new(0.into()),
-            ping_interval: time::interval(Self::PING_INTERVAL),
+            latency: Arc::new(0.into()),
+            jitter: Arc::new(0.into()),
+            keepalive,
+            // ping right away to learn the clock offset
+            last_ping_at: Instant::now()
+                .checked_sub(Duration::from_secs(keepalive.max_interval))
+                .unwrap_or_else(Instant::now),
+            last_message_at: Instant::now(),
+            missed_pings: 0,
+            broadcast_seq: None,
+            periodic_stats: false,
+            delta_sync: None,
         }
     }
 
     pub async fn run(&mut self) {
         log::debug!("Starting session for user '{}'", self.connection.username());
+        let priority = self.connection.permissions().priority;
+        if !self
+            .load_shedder
+            .admit(self.id, priority, Arc::clone(&self.shed_signal))
+        {
+            let (reason, message) = if self.load_shedder.is_shutting_down() {
+                (CloseReason::Restart, "The server is restarting")
+            } else {
+                (CloseReason::Overloaded, "The server is overloaded")
+            };
+            log::info!(
+                "Refusing session of user '{}': {message}",
+                self.connection.username()
+            );
+            if let Err(err) = self.connection.close(reason, message).await {
+                log::error!("Failed to close connection: {err:?}");
+            }
+            return;
+        }
         log::info!("User '{}' connected.", self.connection.username());
+        let username = self.connection.username().to_string();
+        self.unverified = self.check_fingerprint(&username).await;
+        loop {
+            self.serve().await;
+            if !self.suspend().await {
+                break;
+            }
+        }
+        // rooms are kept as they are for when the server is back, and their users rejoin them
+        if !self.load_shedder.is_shutting_down() {
+            if let Err(error) = self.leave_room(self.departure_reason()).await {
+                log::error!("Failed to leave room after session termination: {error:?}");
+            }
+        }
+        self.load_shedder.release(self.id);
+    }
+
+    async fn serve(&mut self) {
+        self.running = true;
         while self.running {
+            let next_ping_at = self.next_ping_at();
             tokio::select! {
                 client_msg = self.connection.recv() => {
                     if let Some(msg) = client_msg {
+                        self.last_message_at = Instant::now();
                         self.handle_client_msg(msg).await
                     } else {
                         // the connection was closed
@@ -126,47 +600,391 @@ impl Session {
                         }
                     }
                 },
-                _ = self.ping_interval.tick() => self.ping().await
+                _ = time::sleep_until(next_ping_at) => self.ping().await,
+                _ = self.shed_signal.notified() => self.shed().await,
+            }
+        }
+    }
+
+    /// Keeps the session around for a while after its client lost the connection, so that the
+    /// client can resume it. Returns whether it was resumed.
+    async fn suspend(&mut self) -> bool {
+        if self.shed || self.connection.is_open() || self.connection.closed_by_client() {
+            return false;
+        }
+        let Some(token) = self.connection.resume_token().map(str::to_string) else {
+            return false;
+        };
+        log::info!(
+            "User '{}' lost their connection; waiting for them to resume the session.",
+            self.connection.username()
+        );
+        let mut connection_rx = self.suspended_sessions.suspend(token.clone());
+        self.step_away().await;
+        let grace_period = time::sleep(Duration::from_secs(
+            self.suspended_sessions.config.grace_period,
+        ));
+        tokio::pin!(grace_period);
+        let mut missed = VecDeque::new();
+        loop {
+            tokio::select! {
+                connection = &mut connection_rx => {
+                    let Ok(connection) = connection else {
+                        return false;
+                    };
+                    return self.resume(connection, missed).await;
+                }
+                session_msg = self.message_rx.recv() => {
+                    let Some(msg) = session_msg.map(SessionMsg::unsequenced) else {
+                        self.room = None;
+                        self.playback_role = None;
+                        break;
+                    };
+                    // a room the session already left closing is no news
+                    if let SessionMsg::RoomClosed(id, ..) = &msg {
+                        if self.room.as_ref().map(|room| room.id) != Some(*id) {
+                            continue;
+                        }
+                    }
+                    if let SessionMsg::RoomClosed(..)
+                    | SessionMsg::Kicked(..)
+                    | SessionMsg::JoinRejected(..) = msg
+                    {
+                        self.room = None;
+                        self.playback_role = None;
+                        break;
+                    }
+                    // the client catches up on the rest when it comes back
+                    missed.retain(|earlier| !msg.supersedes(earlier));
+                    if missed.len() >= Self::MAX_MISSED_MESSAGES {
+                        missed.pop_front();
+                    }
+                    missed.push_back(msg);
+                }
+                _ = &mut grace_period => break,
+                _ = self.shed_signal.notified() => break,
+            }
+        }
+        self.suspended_sessions.forget(&token);
+        log::info!(
+            "Session of user '{}' expired without being resumed.",
+            self.connection.username()
+        );
+        false
+    }
+
+    /// Lets someone else host the room while the client is away, since nobody could run it
+    /// otherwise.
+    async fn step_away(&mut self) {
+        if self.room.is_none() {
+            return;
+        }
+        if let Err(err) = self.send_room_msg(RoomRequest::StepAway(self.id)).await {
+            log::error!("Failed to hand over hosting for suspended session: {err:?}");
+        }
+    }
+
+    /// Hands the client what it missed while it was away, then the current room and playback
+    /// state in case that changed in ways the missed messages don't tell.
+    async fn resume(&mut self, connection: Connection, missed: VecDeque<SessionMsg>) -> bool {
+        let token = self.suspended_sessions.issue_token();
+        if let Err(err) = self.connection.reattach(connection, token).await {
+            log::error!("Failed to resume session: {err:?}");
+            return false;
+        }
+        self.missed_pings = 0;
+        log::info!(
+            "User '{}' resumed their session.",
+            self.connection.username()
+        );
+        for msg in missed {
+            self.handle_session_msg(msg).await;
+        }
+        if self.room.is_some() {
+            if let Err(err) = self.request_state().await {
+                log::error!("Failed to request room state for resumed session: {err:?}");
             }
         }
-        if let Err(error) = self.leave_room().await {
-            log::error!("Failed to leave room after session termination: {error:?}");
+        if self.playback_role == Some(PlaybackRole::Subscriber) {
+            if let Err(err) = self
+                .send_room_msg(RoomRequest::PlaybackConnect(self.id))
+                .await
+            {
+                log::error!("Failed to catch up on the playback for resumed session: {err:?}");
+            }
+        }
+        true
+    }
+
+    /// Why the session's user is no longer around once the session has ended.
+    fn departure_reason(&self) -> LeaveReason {
+        if self.connection.closed_by_client() {
+            LeaveReason::Left
+        } else if self.keepalive.max_missed_pings != 0
+            && self.missed_pings >= self.keepalive.max_missed_pings
+        {
+            LeaveReason::Timeout
+        } else {
+            LeaveReason::ConnectionLost
         }
     }
 
+    /// Clients that are busy sending messages are obviously still there, so they are only pinged
+    /// now and then to update their clock offset.
+    fn next_ping_at(&self) -> Instant {
+        self.keepalive
+            .next_ping_at(self.last_ping_at, self.last_message_at)
+    }
+
     async fn ping(&mut self) {
+        self.last_ping_at = Instant::now();
         match self.connection.ping().await {
-            Ok(Some(result)) => self
-                .time_offset
-                .store(result.time_offset, Ordering::Relaxed),
+            Ok(Some(result)) => {
+                self.missed_pings = 0;
+                self.store_ping(&result);
+                if self.periodic_stats {
+                    if let Err(err) = self.send_connection_stats().await {
+                        log::debug!("Failed to send connection stats: {err:?}");
+                    }
+                }
+            }
             Ok(None) => (), // the connection was closed; this is handled separately
-            Err(err) => log::debug!("Failed to ping client: {err:?}"),
+            Err(err) => {
+                log::debug!("Failed to ping client: {err:?}");
+                self.missed_pings += 1;
+                if self.keepalive.max_missed_pings != 0
+                    && self.missed_pings >= self.keepalive.max_missed_pings
+                {
+                    self.time_out().await;
+                }
+            }
+        };
+    }
+
+    /// Closes the connection to make room for more important clients, or because an operator
+    /// kicked the session. Unlike a lost connection, the session can't be resumed.
+    async fn shed(&mut self) {
+        let (reason, message) = if self.load_shedder.is_shutting_down() {
+            log::info!(
+                "Ending session of user '{}' because the server is shutting down.",
+                self.connection.username()
+            );
+            (CloseReason::Restart, "The server is restarting")
+        } else if self.load_shedder.was_kicked(self.id) {
+            log::info!(
+                "Ending session of user '{}' on an operator's request.",
+                self.connection.username()
+            );
+            (CloseReason::Kicked, "An operator ended your session")
+        } else {
+            log::info!(
+                "Shedding session of user '{}' because the server is overloaded.",
+                self.connection.username()
+            );
+            (CloseReason::Overloaded, "The server is overloaded")
+        };
+        self.running = false;
+        self.shed = true;
+        if let Err(err) = self.connection.close(reason, message).await {
+            log::debug!("Failed to close shed connection: {err:?}");
+        }
+    }
+
+    /// Closes the connection of a client that stopped answering pings. Like any other lost
+    /// connection, the session can still be resumed within the grace period.
+    async fn time_out(&mut self) {
+        log::info!(
+            "User '{}' missed {} pings in a row; closing their connection.",
+            self.connection.username(),
+            self.missed_pings
+        );
+        self.running = false;
+        if let Err(err) = self
+            .connection
+            .close(CloseReason::Timeout, "The client stopped answering pings")
+            .await
+        {
+            log::debug!("Failed to close timed out connection: {err:?}");
+        }
+    }
+
+    fn store_ping(&self, result: &PingResult) {
+        self.time_offset
+            .store(result.time_offset, Ordering::Relaxed);
+        let previous = self.latency.swap(result.latency, Ordering::Relaxed);
+        if previous != 0 {
+            // smoothed over the last few pings, like the interarrival jitter of RTP
+            let jitter = self.jitter.load(Ordering::Relaxed);
+            let deviation = previous.abs_diff(result.latency);
+            self.jitter
+                .store((jitter * 3 + deviation) / 4, Ordering::Relaxed);
+        }
+    }
+
+    async fn probe(&mut self) -> anyhow::Result<()> {
+        log::debug!("Session {} requested a latency probe", self.id);
+        let Some(result) = self.connection.ping().await? else {
+            return Ok(());
         };
+        self.store_ping(&result);
+
+        self.send_message(MessageBody::ConnectionProbeV1(
+            dto::ConnectionProbeMsgBodyV1 {
+                latency: result.latency,
+                transport_latency: result.transport_latency,
+                time_offset: result.time_offset,
+                sync_quality: result.sync_quality().into(),
+            },
+        ))
+        .await
+    }
+
+    /// Tells the client what the last pings measured, so that it can show the quality of its
+    /// connection and size its own buffers. This is the part of `connection::my_stats/v1` that
+    /// changes with every ping, so the two never disagree.
+    async fn send_connection_stats(&mut self) -> anyhow::Result<()> {
+        let stats = self.my_stats();
+        let latency = stats.latency.unwrap_or_default();
+        self.send_message(MessageBody::ConnectionStatsV1(
+            dto::ConnectionStatsMsgBodyV1 {
+                latency,
+                jitter: self.jitter.load(Ordering::Relaxed),
+                time_offset: stats.time_offset,
+                sync_quality: stats
+                    .sync_quality
+                    .unwrap_or_else(|| SyncQuality::from_latency(latency).into()),
+            },
+        ))
+        .await
+    }
+
+    async fn send_stats(&mut self) -> anyhow::Result<()> {
+        let stats = self.my_stats();
+        self.send_message(MessageBody::ConnectionMyStatsAckV1(stats))
+            .await
+    }
+
+    fn my_stats(&self) -> dto::ConnectionMyStatsAckMsgBodyV1 {
+        let stats = self.connection.stats();
+        dto::ConnectionMyStatsAckMsgBodyV1 {
+            user_id: self.id.into(),
+            connected_for: stats
+                .connected_for
+                .as_millis()
+                .try_into()
+                .unwrap_or(u64::MAX),
+            latency: stats.last_ping.as_ref().map(|ping| ping.latency),
+            transport_latency: stats
+                .last_ping
+                .as_ref()
+                .and_then(|ping| ping.transport_latency),
+            time_offset: self.time_offset.load(Ordering::Relaxed),
+            sync_quality: stats
+                .last_ping
+                .as_ref()
+                .map(|ping| ping.sync_quality().into()),
+            errors: stats.errors_sent,
+            room: self.room.as_ref().map(|room| room.id.into()),
+            role: self.room.as_ref().map(|room| room.role.into()),
+            playback: self
+                .playback_role
+                .map_or(dto::PlaybackSubscriptionV1::None, From::from),
+        }
+    }
+
+    /// Lets a client swap its credentials, e.g. for an upgraded API key, without reconnecting.
+    async fn reauth(&mut self, body: dto::ConnectionReauthMsgBodyV1) -> anyhow::Result<()> {
+        log::debug!("Session {} is authenticating again", self.id);
+        self.connection
+            .reauth(&*self.auth_provider, body.api_key, body.token)
+            .await?;
+        self.load_shedder
+            .set_priority(self.id, self.connection.permissions().priority);
+        log::info!(
+            "User '{}' has updated their permissions",
+            self.connection.username()
+        );
+        self.send_message(MessageBody::ConnectionReauthAckV1(
+            dto::ConnectionReauthAckMsgBodyV1 {
+                permissions: self.connection.permissions().clone().into(),
+            },
+        ))
+        .await
+    }
+
+    async fn rename(&mut self, name: String) -> anyhow::Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ClientError::invalid("Names can't be empty").into());
+        }
+        if name.chars().count() > Self::MAX_NAME_LEN {
+            return Err(ClientError::invalid(format!(
+                "Names can't be longer than {} characters",
+                Self::MAX_NAME_LEN
+            ))
+            .into());
+        }
+        let name = name.to_string();
+        self.connection.check_rename()?;
+        // someone else may have used the new name before
+        let unverified = self.check_fingerprint(&name).await;
+        // observers aren't listed in the room, so there's nothing to update
+        if self.room.as_ref().is_some_and(|room| !room.observer) {
+            // the room answers with the name it gave the user, which is acknowledged then
+            self.send_room_msg(RoomRequest::Rename(self.id, name, unverified))
+                .await?;
+            self.unverified = unverified;
+            return Ok(());
+        }
+        self.unverified = unverified;
+        self.renamed(name).await
+    }
+
+    async fn renamed(&mut self, name: String) -> anyhow::Result<()> {
+        log::info!(
+            "User '{}' is now called '{name}'",
+            self.connection.username()
+        );
+        self.connection.rename(name.clone())?;
+        self.send_message(MessageBody::ConnectionRenameAckV1(
+            dto::ConnectionRenameAckMsgBodyV1 { name },
+        ))
+        .await
     }
 
-    async fn create_room(&mut self, name: String, password: String) -> anyhow::Result<()> {
+    async fn create_room(&mut self, template: RoomTemplate) -> anyhow::Result<()> {
+        let settings = &template.settings;
         log::debug!(
-            "Session {} requested to create a room named '{name}'",
-            self.id
+            "Session {} requested to create a room named '{}'",
+            self.id,
+            settings.name
         );
         if !self.connection.permissions().host {
-            return Err(anyhow!("Your account is not permitted to host rooms"));
+            return Err(
+                ClientError::not_authorized("Your account is not permitted to host rooms").into(),
+            );
+        }
+        if !self.connection.permissions().allows_room(&settings.name) {
+            return Err(ClientError::not_authorized(format!(
+                "Your account is not permitted to create a room named '{}'",
+                settings.name
+            ))
+            .into());
         }
 
-        self.leave_room()
+        self.leave_room(LeaveReason::Left)
             .await
             .context("Failed to leave current room before opening a new one")?;
 
         log::info!(
-            "User '{}' is creating room '{name}'",
-            self.connection.username()
+            "User '{}' is creating room '{}'",
+            self.connection.username(),
+            settings.name
         );
 
         let room_handle = self
             .room_manager
-            .lock()
-            .await
-            .create_room(name, password, self.get_handle())
+            .create_room(template, self.connection.subject(), self.get_handle())
             .await?;
         self.room = Some(room_handle);
 
@@ -185,7 +1003,7 @@ impl Session {
         };
 
         if !room_handle.role.permissions().can_close {
-            return Err(anyhow!("Not authorized to close the room"));
+            return Err(ClientError::not_authorized("Not authorized to close the room").into());
         }
 
         log::info!(
@@ -195,8 +1013,6 @@ impl Session {
         );
 
         self.room_manager
-            .lock()
-            .await
             .close_room(room_handle.id, RoomCloseReason::ClosedByHost)
             .await?;
         self.room = None;
@@ -209,43 +1025,251 @@ impl Session {
         Ok(())
     }
 
-    async fn join_room(&mut self, room_id: RoomId, password: String) -> anyhow::Result<()> {
-        log::debug!("Session {} requested to join room {room_id}", self.id);
-        self.leave_room()
-            .await
-            .context("Failed to leave current room before joining a new one")?;
-
-        let mut room_mgr = self.room_manager.lock().await;
+    async fn list_rooms(&mut self) -> anyhow::Result<()> {
+        let rooms = self.room_manager.list_public_rooms().await;
+        let permissions = self.connection.permissions();
+        let rooms = rooms
+            .into_iter()
+            .filter(|room| permissions.allows_room(&room.name))
+            .map(From::from)
+            .collect();
+        self.send_message(MessageBody::RoomListAckV1(dto::RoomListAckMsgBodyV1 {
+            rooms,
+            sync_quality: self.connection.sync_quality().map(From::from),
+        }))
+        .await
+    }
 
-        if Some(password) != room_mgr.get_room_password(room_id) {
-            return Err(anyhow!("Incorrect password"));
+    async fn peek_room(&mut self, room: RoomRef) -> anyhow::Result<()> {
+        let room_mgr = &self.room_manager;
+        let Some(info) = room_mgr
+            .resolve_room(&room)
+            .and_then(|id| room_mgr.peek_room(id))
+        else {
+            return Err(ClientError::new(
+                ErrorCode::RoomNotFound,
+                format!("Room {room} does not exist"),
+            )
+            .into());
+        };
+        if !self.connection.permissions().allows_room(&info.name) {
+            return Err(ClientError::not_authorized(
+                "Your account is not permitted to join this room",
+            )
+            .into());
         }
+        self.send_message(MessageBody::RoomPeekAckV1(dto::RoomPeekAckMsgBodyV1 {
+            room: info.into(),
+            sync_quality: self.connection.sync_quality().map(From::from),
+        }))
+        .await
+    }
 
-        let room_handle = room_mgr.join_room(room_id, self.get_handle()).await?;
+    fn check_admin(&self) -> anyhow::Result<()> {
+        if !self.connection.permissions().admin {
+            return Err(
+                ClientError::not_authorized("Not authorized to administer the server").into(),
+            );
+        }
+        Ok(())
+    }
 
-        if let Some(handle) = room_handle {
-            self.room = Some(handle);
+    async fn admin_list_rooms(&mut self) -> anyhow::Result<()> {
+        self.check_admin()?;
+        let rooms = self.room_manager.list_all_rooms().await;
+        self.send_message(MessageBody::AdminListRoomsAckV1(
+            dto::AdminListRoomsAckMsgBodyV1 {
+                rooms: rooms.into_iter().map(From::from).collect(),
+            },
+        ))
+        .await
+    }
+
+    async fn admin_close_room(&mut self, room_id: RoomId) -> anyhow::Result<()> {
+        self.check_admin()?;
+        let room_manager = Arc::clone(&self.room_manager);
+        let Some(name) = room_manager.get_room_name(room_id) else {
+            return Err(ClientError::new(
+                ErrorCode::RoomNotFound,
+                room_manager.describe_missing_room(room_id),
+            )
+            .into());
+        };
+        log::info!(
+            "Operator '{}' is closing room '{name}'",
+            self.connection.username()
+        );
+        room_manager
+            .close_room(room_id, RoomCloseReason::ClosedByOperator)
+            .await?;
+        self.send_message(MessageBody::AdminCloseRoomAckV1).await
+    }
+
+    async fn admin_kick_session(&mut self, session_id: SessionId) -> anyhow::Result<()> {
+        self.check_admin()?;
+        if session_id == self.id {
+            return Err(ClientError::invalid("You can't kick yourself").into());
+        }
+        if !self.load_shedder.kick(session_id) {
+            return Err(ClientError::invalid(format!("Session {session_id} isn't running")).into());
+        }
+        log::info!(
+            "Operator '{}' kicked session {session_id}",
+            self.connection.username()
+        );
+        self.send_message(MessageBody::AdminKickSessionAckV1).await
+    }
+
+    async fn admin_stats(&mut self) -> anyhow::Result<()> {
+        self.check_admin()?;
+        let rooms = self.room_manager.list_all_rooms().await;
+        self.send_message(MessageBody::AdminStatsAckV1(
+            dto::AdminStatsAckMsgBodyV1::new(self.load_shedder.session_count(), &rooms),
+        ))
+        .await
+    }
+
+    async fn admin_set_log_filter(&mut self, filter: Option<String>) -> anyhow::Result<()> {
+        self.check_admin()?;
+        log::info!(
+            "Operator '{}' set the log filter to {filter:?}",
+            self.connection.username()
+        );
+        self.log_controller.set_filter(filter.as_deref());
+        self.send_message(MessageBody::AdminSetLogFilterAckV1).await
+    }
+
+    async fn join_room(
+        &mut self,
+        room: RoomRef,
+        password: Option<String>,
+        invite: Option<String>,
+        anonymous: bool,
+    ) -> anyhow::Result<()> {
+        log::debug!("Session {} requested to join room {room}", self.id);
+        self.leave_room(LeaveReason::Left)
+            .await
+            .context("Failed to leave current room before joining a new one")?;
+
+        let room_mgr = Arc::clone(&self.room_manager);
+
+        let Some(room_id) = room_mgr.resolve_room(&room) else {
+            self.connection
+                .send_error(
+                    ErrorCode::RoomNotFound,
+                    format!("Room {room} does not exist"),
+                )
+                .await;
+            return Ok(());
+        };
+        let Some(name) = room_mgr.get_room_name(room_id) else {
+            let message = room_mgr.describe_missing_room(room_id);
+            self.connection
+                .send_error(ErrorCode::RoomNotFound, message)
+                .await;
+            return Ok(());
+        };
+        if !self.connection.permissions().allows_room(&name) {
+            return Err(ClientError::not_authorized(
+                "Your account is not permitted to join this room",
+            )
+            .into());
+        }
+
+        let room_handle = room_mgr
+            .join_room(
+                room_id,
+                password.as_deref(),
+                invite.as_deref(),
+                self.connection.subject(),
+                self.get_handle(),
+                anonymous,
+            )
+            .await?;
+
+        if let Some(handle) = room_handle {
+            self.room = Some(handle);
             self.connection
                 .send(Message::new(MessageBody::RoomJoinAckV1))
                 .await
                 .context("Failed to send ACK message")?;
         } else {
+            let message = room_mgr.describe_missing_room(room_id);
             self.connection
-                .send_error(format!("Room {room_id} does not exist"))
+                .send_error(ErrorCode::RoomNotFound, message)
                 .await;
         }
 
         Ok(())
     }
 
-    async fn leave_room(&mut self) -> anyhow::Result<()> {
+    async fn observe_room(&mut self, room: RoomRef) -> anyhow::Result<()> {
+        log::debug!("Session {} requested to observe room {room}", self.id);
+        if !self.connection.permissions().observe {
+            return Err(ClientError::not_authorized(
+                "Your account is not permitted to observe rooms",
+            )
+            .into());
+        }
+        self.leave_room(LeaveReason::Left)
+            .await
+            .context("Failed to leave current room before observing another one")?;
+
+        let room_mgr = Arc::clone(&self.room_manager);
+
+        let Some(room_id) = room_mgr.resolve_room(&room) else {
+            self.connection
+                .send_error(
+                    ErrorCode::RoomNotFound,
+                    format!("Room {room} does not exist"),
+                )
+                .await;
+            return Ok(());
+        };
+
+        let Some(name) = room_mgr.get_room_name(room_id) else {
+            let message = room_mgr.describe_missing_room(room_id);
+            self.connection
+                .send_error(ErrorCode::RoomNotFound, message)
+                .await;
+            return Ok(());
+        };
+        if !self.connection.permissions().allows_room(&name) {
+            return Err(ClientError::not_authorized(
+                "Your account is not permitted to observe this room",
+            )
+            .into());
+        }
+
+        let Some(handle) = room_mgr.observe_room(room_id, self.get_handle()).await? else {
+            let message = room_mgr.describe_missing_room(room_id);
+            self.connection
+                .send_error(ErrorCode::RoomNotFound, message)
+                .await;
+            return Ok(());
+        };
+        log::info!(
+            "User '{}' is observing room '{}'",
+            self.connection.username(),
+            handle.name
+        );
+        self.room = Some(handle);
+        self.connection
+            .send(Message::new(MessageBody::RoomObserveAckV1))
+            .await
+            .context("Failed to send ACK message")
+    }
+
+    async fn leave_room(&mut self, reason: LeaveReason) -> anyhow::Result<()> {
         if self.room.is_none() {
             return Ok(());
         }
 
         log::debug!("Session {} requested to leave its room", self.id);
-        self.send_room_msg(RoomRequest::Leave(self.id)).await?;
+        self.send_room_msg(RoomRequest::Leave(self.id, reason))
+            .await?;
         self.room = None;
+        self.playback_role = None;
         let result = self
             .connection
             .send(Message::new(MessageBody::RoomLeaveAckV1))
@@ -262,11 +1286,12 @@ impl Session {
         };
 
         if !room.role.permissions().can_kick {
-            return Err(anyhow!("Not authorized to kick users"));
+            return Err(ClientError::not_authorized("Not authorized to kick users").into());
         }
 
         log::debug!("Session {} requested to kick {}", self.id, session_id);
-        self.send_room_msg(RoomRequest::Leave(session_id)).await?;
+        self.send_room_msg(RoomRequest::Kick(self.id, session_id))
+            .await?;
         Ok(())
     }
 
@@ -276,7 +1301,7 @@ impl Session {
         };
 
         if !room.role.permissions().can_set_roles {
-            return Err(anyhow!("Not authorized to set user roles"));
+            return Err(ClientError::not_authorized("Not authorized to set user roles").into());
         }
 
         log::debug!(
@@ -285,14 +1310,82 @@ impl Session {
             session_id,
             role
         );
-        self.send_room_msg(RoomRequest::SetRole(session_id, role))
+        self.send_room_msg(RoomRequest::SetRole(self.id, session_id, role))
             .await?;
         Ok(())
     }
 
+    async fn transfer_host(&mut self, session_id: SessionId) -> anyhow::Result<()> {
+        let Some(room) = &self.room else {
+            return Err(ClientError::not_in_room().into());
+        };
+
+        if room.role != UserRole::Host {
+            return Err(ClientError::not_authorized("Only the host can hand over the room").into());
+        }
+
+        log::debug!(
+            "Session {} requested to hand its room over to {session_id}",
+            self.id
+        );
+        self.send_room_msg(RoomRequest::TransferHost(self.id, session_id))
+            .await
+    }
+
+    async fn create_invite(&mut self, role: UserRole) -> anyhow::Result<()> {
+        let Some(room) = &self.room else {
+            return Err(ClientError::not_in_room().into());
+        };
+
+        if !room.role.permissions().can_set_roles {
+            return Err(ClientError::not_authorized("Not authorized to invite users").into());
+        }
+
+        log::debug!("Session {} requested an invite for role {role}", self.id);
+        let room_id = room.id;
+        let token = self.room_manager.create_invite(room_id, role)?;
+        self.send_message(MessageBody::RoomInviteV1(dto::RoomInviteMsgBodyV1 {
+            room_id: room_id.into(),
+            token,
+            role: role.into(),
+        }))
+        .await
+    }
+
+    /// The room checks whether the user is the host, so the room manager only learns about the
+    /// new password once the room has accepted it.
+    async fn set_room_password(
+        &mut self,
+        password: Option<String>,
+        open: bool,
+    ) -> anyhow::Result<()> {
+        if password.is_some() == open {
+            return Err(ClientError::invalid(
+                "Either give the room a password or open it, but not both",
+            )
+            .into());
+        }
+        let Some(room) = &self.room else {
+            return Err(ClientError::not_in_room().into());
+        };
+        let room_id = room.id;
+
+        log::debug!(
+            "Session {} requested to change its room's password",
+            self.id
+        );
+        self.send_room_msg(RoomRequest::SetPassword(self.id, password.clone()))
+            .await?;
+        if self.room.is_none() {
+            // the room closed in the meantime
+            return Ok(());
+        }
+        self.room_manager.set_password(room_id, password)
+    }
+
     async fn send_room_permissions(&mut self) -> anyhow::Result<()> {
         let Some(room) = &self.room else {
-            return Err(anyhow!("Not currently in a room"));
+            return Err(ClientError::not_in_room().into());
         };
 
         log::debug!(
@@ -312,11 +1405,11 @@ impl Session {
 
     async fn host_playback(&mut self) -> anyhow::Result<()> {
         let Some(room) = &self.room else {
-            return Err(anyhow!("Not currently in a room"));
+            return Err(ClientError::not_in_room().into());
         };
 
         if !room.role.permissions().can_host {
-            return Err(anyhow!("Not authorized to host playback"));
+            return Err(ClientError::not_authorized("Not authorized to host playback").into());
         }
 
         log::debug!("Session {} requested to host playback", self.id);
@@ -328,11 +1421,11 @@ impl Session {
 
     async fn connect_playback(&mut self) -> anyhow::Result<()> {
         let Some(room) = &self.room else {
-            return Err(anyhow!("Not currently in a room"));
+            return Err(ClientError::not_in_room().into());
         };
 
-        if !room.role.permissions().can_host {
-            return Err(anyhow!("Not authorized to host playback"));
+        if !room.role.permissions().can_watch {
+            return Err(ClientError::not_authorized("Not authorized to watch playback").into());
         }
 
         log::debug!("Session {} requested to connect to playback", self.id);
@@ -351,22 +1444,36 @@ impl Session {
 
     async fn send_room_msg(&mut self, msg: RoomRequest) -> anyhow::Result<()> {
         let Some(room_handle) = &mut self.room else {
-            return Err(anyhow!("Not currently in a room"));
+            return Err(ClientError::not_in_room().into());
         };
-        if !room_handle.send_request(msg).await? {
-            log::warn!("Room {} was unexpectedly closed", room_handle.id);
-            self.room = None;
-            self.connection
-                .send(Message::new(MessageBody::RoomDisconnectedV1(
-                    dto::RoomDisconnectedMsgBodyV1 {
-                        reason: dto::RoomDisconnectedReasonV1::ServerError,
-                    },
-                )))
-                .await
-                .context("Failed to send disconnect message")?;
+        if room_handle.observer && !msg.is_allowed_for_observers() {
+            return Err(
+                ClientError::not_authorized("Observers can't take part in the room").into(),
+            );
+        }
+        let room_id = room_handle.id;
+        let Err(err) = room_handle.send_request(msg).await else {
             return Ok(());
         };
-        Ok(())
+        // the room closed before we heard about it; tell the client right away
+        if let Some(RoomError::Closed(reason)) = err.downcast_ref::<RoomError>() {
+            log::debug!("Session {} used a handle of closed room {room_id}", self.id);
+            return self
+                .room_closed(room_id, *reason)
+                .await
+                .context("Failed to send disconnect message");
+        }
+        Err(err)
+    }
+
+    async fn relay_peer_probe(&mut self, peer: SessionId, probe: PeerProbe) -> anyhow::Result<()> {
+        self.send_room_msg(RoomRequest::RelayPeerProbe(self.id, peer, probe))
+            .await
+    }
+
+    async fn set_quiet_hours(&mut self, windows: Vec<QuietWindow>) -> anyhow::Result<()> {
+        self.send_room_msg(RoomRequest::SetQuietHours(self.id, windows))
+            .await
     }
 
     async fn request_state(&mut self) -> anyhow::Result<()> {
@@ -375,10 +1482,89 @@ impl Session {
 
     async fn handle_client_msg(&mut self, msg: Message) {
         let result = match msg.body {
-            MessageBody::RoomCreateV1(body) => self.create_room(body.name, body.password).await,
+            MessageBody::BatchV1(body) => self.handle_batch(body).await,
+            body => self.dispatch_client_msg(body).await,
+        };
+        if let Some(err) = result.err() {
+            log::error!("Failed to handle message: {err:?}");
+            self.connection.send_error(error_code(&err), err).await;
+        }
+    }
+
+    /// Handles the messages of a batch in order, and reports how each of them went in a single
+    /// acknowledgement instead of separate errors.
+    async fn handle_batch(&mut self, body: dto::BatchMsgBodyV1) -> anyhow::Result<()> {
+        if body.messages.len() > Self::MAX_BATCH_LEN {
+            return Err(ClientError::invalid(format!(
+                "Batches can have at most {} messages",
+                Self::MAX_BATCH_LEN
+            ))
+            .into());
+        }
+        let batched_login = self.connection.take_batched_login();
+        let mut results = Vec::with_capacity(body.messages.len());
+        for (index, msg) in body.messages.into_iter().enumerate() {
+            let result = match msg.body {
+                // the connection already handled the login
+                MessageBody::ConnectionLoginV1(..) if index == 0 && batched_login => Ok(()),
+                MessageBody::BatchV1(..) => {
+                    Err(ClientError::invalid("Batches can't be nested").into())
+                }
+                body => self.dispatch_client_msg(body).await,
+            };
+            let error = result.err().map(|err| {
+                log::debug!("Failed to handle batched message: {err:?}");
+                dto::ConnectionClientErrorMsgBodyV1 {
+                    code: error_code(&err).into(),
+                    message: err.to_string(),
+                }
+            });
+            results.push(dto::BatchResultV1 { error });
+        }
+        self.send_message(MessageBody::BatchAckV1(dto::BatchAckMsgBodyV1 { results }))
+            .await
+    }
+
+    async fn dispatch_client_msg(&mut self, body: MessageBody) -> anyhow::Result<()> {
+        if let Some(feature) = Feature::of(&body) {
+            self.features.check(feature)?;
+        }
+        match body {
+            MessageBody::ConnectionKeepaliveV1(body) => match body.telemetry {
+                // only the players of subscribers are compared with the playback
+                Some(telemetry) if self.playback_role == Some(PlaybackRole::Subscriber) => {
+                    self.playback_request(PlaybackRequest::Telemetry(telemetry.into()))
+                        .await
+                }
+                _ => Ok(()),
+            },
+            MessageBody::ConnectionRequestProbeV1 => self.probe().await,
+            MessageBody::ConnectionRequestStatsV1(body) => {
+                self.periodic_stats = body.periodic;
+                self.send_connection_stats().await
+            }
+            MessageBody::ConnectionMyStatsV1 => self.send_stats().await,
+            MessageBody::ConnectionReauthV1(body) => self.reauth(body).await,
+            MessageBody::ConnectionRenameV1(body) => self.rename(body.name).await,
+            MessageBody::RoomCreateV1(body) => {
+                self.create_room(RoomSettings::from(body).into()).await
+            }
+            MessageBody::RoomCreateFromTemplateV1(body) => {
+                match RoomTemplate::parse(&body.template) {
+                    Ok(template) => self.create_room(template).await,
+                    Err(err) => Err(err.into()),
+                }
+            }
             MessageBody::RoomCloseV1 => self.close_room().await,
-            MessageBody::RoomJoinV1(body) => self.join_room(body.id.into(), body.password).await,
-            MessageBody::RoomLeaveV1 => self.leave_room().await,
+            MessageBody::RoomListV1 => self.list_rooms().await,
+            MessageBody::RoomPeekV1(body) => self.peek_room(body.id.into()).await,
+            MessageBody::RoomJoinV1(body) => {
+                self.join_room(body.id.into(), body.password, body.invite, body.anonymous)
+                    .await
+            }
+            MessageBody::RoomCreateInviteV1(body) => self.create_invite(body.role.into()).await,
+            MessageBody::RoomObserveV1(body) => self.observe_room(body.id.into()).await,
+            MessageBody::RoomLeaveV1 => self.leave_room(LeaveReason::Left).await,
             MessageBody::RoomRequestStateV1 => self.request_state().await,
             MessageBody::RoomRequestPermissionsV1 => self.send_room_permissions().await,
             MessageBody::RoomSetUserRole(body) => {
@@ -386,46 +1572,226 @@ impl Session {
                     .await
             }
             MessageBody::RoomKickUser(body) => self.kick(body.user_id.into()).await,
+            MessageBody::RoomTransferHostV1(body) => self.transfer_host(body.user_id.into()).await,
+            MessageBody::RoomIntermissionV1(body) => {
+                match Intermission::new(body.notice, body.countdown) {
+                    Ok(intermission) => {
+                        self.send_room_msg(RoomRequest::SetIntermission(
+                            self.id,
+                            Some(intermission),
+                        ))
+                        .await
+                    }
+                    Err(err) => Err(err.into()),
+                }
+            }
+            MessageBody::RoomEndIntermissionV1 => {
+                self.send_room_msg(RoomRequest::SetIntermission(self.id, None))
+                    .await
+            }
+            MessageBody::RoomChatV1(body) => {
+                self.send_room_msg(RoomRequest::Chat(self.id, body.text))
+                    .await
+            }
+            MessageBody::RoomShareLinkV1(body) => {
+                self.send_room_msg(RoomRequest::ShareLink(self.id, body.url))
+                    .await
+            }
+            MessageBody::RoomSetQuietHoursV1(body) => {
+                match body
+                    .windows
+                    .into_iter()
+                    .map(QuietWindow::try_from)
+                    .collect()
+                {
+                    Ok(windows) => self.set_quiet_hours(windows).await,
+                    Err(err) => Err(err),
+                }
+            }
+            MessageBody::RoomUpdateV1(body) => {
+                self.send_room_msg(RoomRequest::UpdateMetadata(self.id, body.into()))
+                    .await
+            }
+            MessageBody::RoomSetPasswordV1(body) => {
+                self.set_room_password(body.password, body.open).await
+            }
+            MessageBody::RoomPeerProbeV1(body) => {
+                let probe = PeerProbe::Request {
+                    probe_id: body.probe_id,
+                    sent_at: body.sent_at,
+                };
+                self.relay_peer_probe(body.peer.into(), probe).await
+            }
+            MessageBody::RoomPeerProbeReplyV1(body) => {
+                let probe = PeerProbe::Reply {
+                    probe_id: body.probe_id,
+                    sent_at: body.sent_at,
+                    received_at: body.received_at,
+                    replied_at: body.replied_at,
+                };
+                self.relay_peer_probe(body.peer.into(), probe).await
+            }
+            MessageBody::PlaybackRequestInfoV1 => {
+                self.send_room_msg(RoomRequest::PlaybackInfo(self.id)).await
+            }
+            MessageBody::RoomModerationLogV1(body) => {
+                self.send_room_msg(RoomRequest::ModerationLog(
+                    self.id,
+                    body.before,
+                    body.limit.map(|limit| limit as usize),
+                ))
+                .await
+            }
+            MessageBody::RoomRequestPlaybackHistoryV1 => {
+                self.send_room_msg(RoomRequest::PlaybackHistory(self.id))
+                    .await
+            }
             MessageBody::PlaybackRequestHostV1 => self.host_playback().await,
             MessageBody::PlaybackRequestConnectV1 => self.connect_playback().await,
             MessageBody::PlaybackRequestStartV1(body) => {
-                self.playback_request(PlaybackRequest::Start(body.source.into()))
+                self.playback_request(PlaybackRequest::Start(Some(body.source.into())))
+                    .await
+            }
+            MessageBody::PlaybackRequestStartV2(body) => {
+                self.playback_request(PlaybackRequest::Start(body.source.map(From::from)))
                     .await
             }
             MessageBody::PlaybackSyncV1(body) => {
                 self.playback_request(PlaybackRequest::Sync(body.state.into()))
                     .await
             }
+            MessageBody::PlaybackSeekHintsV1(body) => {
+                self.playback_request(PlaybackRequest::SeekHints(SeekHints::new(body.points)))
+                    .await
+            }
             MessageBody::PlaybackRequestStopV1 => {
                 self.playback_request(PlaybackRequest::Stop(StopReason::StoppedByHost))
                     .await
             }
+            MessageBody::PlaybackFinishedV1 => self.playback_request(PlaybackRequest::Finish).await,
+            MessageBody::PlaybackEndedV1 => self.playback_request(PlaybackRequest::Ended).await,
+            MessageBody::PlaybackQueueAddV1(body) => {
+                self.playback_request(PlaybackRequest::QueueAdd(body.source.into(), body.index))
+                    .await
+            }
+            MessageBody::PlaybackQueueRemoveV1(body) => {
+                self.playback_request(PlaybackRequest::QueueRemove(body.id.into()))
+                    .await
+            }
+            MessageBody::PlaybackQueueMoveV1(body) => {
+                self.playback_request(PlaybackRequest::QueueMove(body.id.into(), body.index))
+                    .await
+            }
+            MessageBody::PlaybackRollCallV1(body) => {
+                self.playback_request(PlaybackRequest::RollCall(body.roll_call_id))
+                    .await
+            }
+            MessageBody::PlaybackRollCallReplyV1(body) => {
+                self.playback_request(PlaybackRequest::RollCallReply(
+                    body.roll_call_id,
+                    body.state.into(),
+                ))
+                .await
+            }
             MessageBody::PlaybackRequestDisconnectV1 => {
                 self.playback_request(PlaybackRequest::Disconnect(DisconnectReason::User))
                     .await
             }
-            _ => Ok(()),
-        };
-        if let Some(err) = result.err() {
-            log::error!("Failed to handle message: {err:?}");
-            self.connection.send_error(err).await;
+            MessageBody::PlaybackDelegateTimingV1(body) => {
+                self.playback_request(PlaybackRequest::DelegateTiming(body.delegate))
+                    .await
+            }
+            MessageBody::PlaybackRequestStatsV1 => {
+                self.playback_request(PlaybackRequest::Stats).await
+            }
+            MessageBody::PlaybackReadyV1 => self.playback_request(PlaybackRequest::Ready).await,
+            MessageBody::PlaybackRequestDeltaSyncV1(body) => {
+                self.delta_sync = body.enabled.then(DeltaSync::default);
+                Ok(())
+            }
+            MessageBody::PlaybackSyncAckV1(body) => match &mut self.delta_sync {
+                Some(delta_sync) => {
+                    delta_sync.acknowledge(body.seq);
+                    Ok(())
+                }
+                None => Err(ClientError::invalid("Delta syncs aren't turned on").into()),
+            },
+            MessageBody::AdminListRoomsV1 => self.admin_list_rooms().await,
+            MessageBody::AdminCloseRoomV1(body) => self.admin_close_room(body.room_id.into()).await,
+            MessageBody::AdminKickSessionV1(body) => {
+                self.admin_kick_session(body.user_id.into()).await
+            }
+            MessageBody::AdminStatsV1 => self.admin_stats().await,
+            MessageBody::AdminSetLogFilterV1(body) => self.admin_set_log_filter(body.filter).await,
+            MessageBody::ConnectionLoginV1(..) => {
+                Err(ClientError::invalid("Already logged in").into())
+            }
+            _ => Err(ClientError::invalid("Clients can't send this message").into()),
         }
     }
 
-    async fn send_room_state(&mut self, state: RoomState) -> anyhow::Result<()> {
-        self.send_message(MessageBody::RoomStateV1(dto::RoomStateMsgBodyV1::from(
-            state,
-        )))
+    async fn send_room_state(&mut self, state: Arc<dto::RoomStateMsgBodyV1>) -> anyhow::Result<()> {
+        self.send_message(MessageBody::RoomStateV1(state)).await
+    }
+
+    /// Keeps the session's idea of its role in sync with the room, and tells the client what it
+    /// may do now.
+    async fn role_changed(&mut self, role: UserRole) -> anyhow::Result<()> {
+        let Some(room) = &mut self.room else {
+            return Ok(());
+        };
+        room.role = role;
+        self.send_room_permissions().await
+    }
+
+    async fn kicked(&mut self, kicked_by: String) -> anyhow::Result<()> {
+        if let Some(room) = self.room.take() {
+            log::info!(
+                "User '{}' was kicked from room '{}' by '{kicked_by}'",
+                self.connection.username(),
+                room.name
+            );
+        }
+        self.playback_role = None;
+        self.send_message(MessageBody::RoomKickedV1(dto::RoomKickedMsgBodyV1 {
+            kicked_by,
+        }))
         .await
     }
 
-    async fn room_closed(&mut self, reason: RoomCloseReason) -> anyhow::Result<()> {
+    async fn join_rejected(&mut self, id: RoomId, err: ClientError) -> anyhow::Result<()> {
+        if self.room.as_ref().map(|room| room.id) != Some(id) {
+            return Ok(());
+        }
+        log::info!(
+            "User '{}' couldn't join room {id}: {err}",
+            self.connection.username()
+        );
+        self.room = None;
+        self.playback_role = None;
+        self.connection.send_error(err.code, err.to_string()).await;
+        Ok(())
+    }
+
+    async fn room_closed(&mut self, id: RoomId, reason: RoomCloseReason) -> anyhow::Result<()> {
+        // the session may have heard about it already, or moved on to another room since
+        if self.room.as_ref().map(|room| room.id) != Some(id) {
+            return Ok(());
+        }
         self.room = None;
+        self.playback_role = None;
         self.send_message(MessageBody::RoomDisconnectedV1(
             dto::RoomDisconnectedMsgBodyV1 {
                 reason: match reason {
                     RoomCloseReason::ServerError => dto::RoomDisconnectedReasonV1::ServerError,
-                    RoomCloseReason::ClosedByHost => dto::RoomDisconnectedReasonV1::ClosedByHost,
+                    RoomCloseReason::Expired => dto::RoomDisconnectedReasonV1::Expired,
+                    RoomCloseReason::Denied => dto::RoomDisconnectedReasonV1::Denied,
+                    RoomCloseReason::ClosedByOperator => {
+                        dto::RoomDisconnectedReasonV1::ClosedByOperator
+                    }
+                    RoomCloseReason::ClosedByHost | RoomCloseReason::Empty => {
+                        dto::RoomDisconnectedReasonV1::ClosedByHost
+                    }
                 },
             },
         ))
@@ -433,31 +1799,165 @@ impl Session {
     }
 
     async fn send_message(&mut self, body: MessageBody) -> anyhow::Result<()> {
-        self.connection.send(Message::new(body)).await
+        let mut message = Message::new(body);
+        message.seq = self.broadcast_seq;
+        self.connection.send(message).await
     }
 
     async fn handle_session_msg(&mut self, msg: SessionMsg) {
         let result = match msg {
+            SessionMsg::Broadcast(seq, msg) => {
+                self.broadcast_seq = Some(seq);
+                Box::pin(self.handle_session_msg(*msg)).await;
+                self.broadcast_seq = None;
+                Ok(())
+            }
             SessionMsg::RoomState(state) => self.send_room_state(state).await,
-            SessionMsg::RoomClosed(reason) => self.room_closed(reason).await,
-            SessionMsg::PlaybackHosting => self.send_message(MessageBody::PlaybackHosting).await,
+            SessionMsg::RoomClosed(id, reason) => self.room_closed(id, reason).await,
+            SessionMsg::Kicked(kicked_by) => self.kicked(kicked_by).await,
+            SessionMsg::JoinRejected(id, err) => self.join_rejected(id, err).await,
+            SessionMsg::BroadcastAck(event) => {
+                self.send_message(MessageBody::RoomBroadcastAckV1(
+                    dto::RoomBroadcastAckMsgBodyV1 {
+                        event: event.into(),
+                    },
+                ))
+                .await
+            }
+            SessionMsg::IdentityWarning(user) => {
+                self.send_message(MessageBody::RoomIdentityWarningV1(
+                    dto::RoomIdentityWarningMsgBodyV1 {
+                        user_id: user.id.into(),
+                        name: user.name,
+                    },
+                ))
+                .await
+            }
+            SessionMsg::RoleChanged(role) => self.role_changed(role).await,
+            SessionMsg::Renamed(name) => self.renamed(name).await,
+            SessionMsg::HostChanged(host) => {
+                self.send_message(MessageBody::RoomHostChangedV1(
+                    dto::RoomHostChangedMsgBodyV1 {
+                        user_id: host.id.into(),
+                        name: host.name,
+                    },
+                ))
+                .await
+            }
+            SessionMsg::UserJoined(user) => {
+                self.send_message(MessageBody::RoomUserJoinedV1(
+                    dto::RoomUserJoinedMsgBodyV1 {
+                        user_id: user.id.into(),
+                        name: user.name,
+                        role: user.role.into(),
+                    },
+                ))
+                .await
+            }
+            SessionMsg::UserLeft(user, reason) => {
+                self.send_message(MessageBody::RoomUserLeftV1(dto::RoomUserLeftMsgBodyV1 {
+                    user_id: user.id.into(),
+                    name: user.name,
+                    reason: reason.into(),
+                }))
+                .await
+            }
+            SessionMsg::Chat(message) => {
+                self.send_message(MessageBody::RoomChatMessageV1(message.into()))
+                    .await
+            }
+            SessionMsg::LinkShared(link) => {
+                self.send_message(MessageBody::RoomLinkSharedV1(link.into()))
+                    .await
+            }
+            SessionMsg::RoomPendingApproval => {
+                self.send_message(MessageBody::RoomPendingApprovalV1).await
+            }
+            SessionMsg::RoomApproved => self.send_message(MessageBody::RoomApprovedV1).await,
+            SessionMsg::PlaybackHosting => {
+                self.playback_role = Some(PlaybackRole::Host);
+                self.send_message(MessageBody::PlaybackHosting).await
+            }
             SessionMsg::PlaybackAvailable(info) => {
                 self.send_message(MessageBody::PlaybackAvailableV1(
                     dto::PlaybackAvailableMsgBodyV1 { info: info.into() },
                 ))
                 .await
             }
+            SessionMsg::PlaybackOverview(overview) => {
+                self.send_message(MessageBody::PlaybackInfoV1(overview.into()))
+                    .await
+            }
+            SessionMsg::PlaybackHistory(history) => {
+                self.send_message(MessageBody::RoomPlaybackHistoryV1(
+                    dto::RoomPlaybackHistoryMsgBodyV1 {
+                        entries: history.into_iter().map(From::from).collect(),
+                    },
+                ))
+                .await
+            }
+            SessionMsg::ModerationLog(page) => {
+                self.send_message(MessageBody::RoomModerationLogAckV1(page.into()))
+                    .await
+            }
             SessionMsg::PlaybackStarted => self.send_message(MessageBody::PlaybackStartedV1).await,
             SessionMsg::PlaybackConnected => {
+                self.playback_role = Some(PlaybackRole::Subscriber);
                 self.send_message(MessageBody::PlaybackConnectedV1).await
             }
-            SessionMsg::PlaybackSync(state) => {
-                self.send_message(MessageBody::PlaybackSyncV1(dto::PlaybackSyncMsgBodyV1 {
-                    state: state.into(),
-                }))
+            SessionMsg::PlaybackSourceChanged(source) => {
+                self.send_message(MessageBody::PlaybackSourceChangedV2(
+                    dto::PlaybackSourceChangedMsgBodyV2 {
+                        source: source.map(From::from),
+                    },
+                ))
                 .await
             }
+            SessionMsg::PlaybackStartAt(start_at) => {
+                self.send_message(MessageBody::PlaybackStartAtV1(start_at.into()))
+                    .await
+            }
+            SessionMsg::PlaybackLastPosition(position) => {
+                self.send_message(MessageBody::PlaybackLastPositionV1(
+                    dto::PlaybackLastPositionMsgBodyV1 { position },
+                ))
+                .await
+            }
+            SessionMsg::PlaybackQueue(entries) => {
+                self.send_message(MessageBody::PlaybackQueueStateV1(
+                    dto::PlaybackQueueStateMsgBodyV1 {
+                        entries: entries.into_iter().map(From::from).collect(),
+                    },
+                ))
+                .await
+            }
+            SessionMsg::PlaybackRollCall(roll_call_id) => {
+                self.send_message(MessageBody::PlaybackRollCallV1(
+                    dto::PlaybackRollCallMsgBodyV1 { roll_call_id },
+                ))
+                .await
+            }
+            SessionMsg::PlaybackRollCallReport(report) => {
+                self.send_message(MessageBody::PlaybackRollCallReportV1(report.into()))
+                    .await
+            }
+            SessionMsg::PlaybackSync(state) => match &mut self.delta_sync {
+                Some(delta_sync) => {
+                    let delta = delta_sync.encode(state);
+                    self.send_message(MessageBody::PlaybackSyncDeltaV1(delta.into()))
+                        .await
+                }
+                None => {
+                    self.send_message(MessageBody::PlaybackSyncV1(dto::PlaybackSyncMsgBodyV1 {
+                        state: state.into(),
+                    }))
+                    .await
+                }
+            },
             SessionMsg::PlaybackStopped(reason) => {
+                if matches!(reason, StopReason::Superseded) {
+                    self.playback_role = None;
+                }
                 self.send_message(MessageBody::PlaybackStoppedV1(
                     dto::PlaybackStoppedMsgBodyV1 {
                         reason: reason.into(),
@@ -466,6 +1966,7 @@ impl Session {
                 .await
             }
             SessionMsg::PlaybackDisconnected(reason) => {
+                self.playback_role = None;
                 self.send_message(MessageBody::PlaybackDisconnectedV1(
                     dto::PlaybackDisconnectedMsgBodyV1 {
                         reason: reason.into(),
@@ -473,18 +1974,874 @@ impl Session {
                 ))
                 .await
             }
+            SessionMsg::PlaybackStats(stats) => {
+                self.send_message(MessageBody::PlaybackStatsV1(dto::PlaybackStatsMsgBodyV1 {
+                    subscribers: stats.into_iter().map(From::from).collect(),
+                }))
+                .await
+            }
+            SessionMsg::PlaybackWaitingFor(waiting_for) => {
+                self.send_message(MessageBody::PlaybackWaitingForV1(waiting_for.into()))
+                    .await
+            }
+            SessionMsg::PlaybackQualityHint(hint) => {
+                self.send_message(MessageBody::PlaybackQualityHintV1(
+                    dto::PlaybackQualityHintMsgBodyV1 { hint: hint.into() },
+                ))
+                .await
+            }
+            SessionMsg::PlaybackLeader(leader) => {
+                self.send_message(MessageBody::PlaybackLeaderV1(
+                    dto::PlaybackLeaderMsgBodyV1 {
+                        user_id: leader.map(From::from),
+                    },
+                ))
+                .await
+            }
+            SessionMsg::PeerProbe(from, probe) => self.send_message(probe.into_message(from)).await,
         };
         if let Some(err) = result.err() {
             log::error!("Failed to handle session message: {err:?}");
         }
     }
 
+    /// Whether the name was first used from another client, which is then the only one trusted
+    /// with it. Accounts and guests don't need this, and clients without a fingerprint can't be
+    /// checked, so their sessions stay as verified as they were.
+    async fn check_fingerprint(&mut self, username: &str) -> bool {
+        let Some(fingerprint) = self.connection.fingerprint() else {
+            return self.unverified;
+        };
+        if self.connection.subject().is_some()
+            || self.connection.is_guest()
+            || fingerprint.len() > Self::MAX_FINGERPRINT_LEN
+        {
+            return self.unverified;
+        }
+        match self
+            .storage
+            .trust_fingerprint(username.to_string(), fingerprint.to_string())
+            .await
+        {
+            Ok(trusted) => {
+                if !trusted {
+                    log::info!("User '{username}' connected from an unknown client");
+                }
+                !trusted
+            }
+            Err(err) => {
+                log::error!("Failed to check the fingerprint of '{username}': {err:?}");
+                self.unverified
+            }
+        }
+    }
+
     fn get_handle(&self) -> SessionHandle {
         SessionHandle {
-            id: self.id,
-            name: self.connection.username().to_string(),
-            time_offset: Arc::downgrade(&self.time_offset),
-            message_tx: self.message_tx.clone().downgrade(),
+            unverified: self.unverified,
+            ..SessionHandle::new(
+                self.id,
+                self.connection.username().to_string(),
+                self.connection.subject().map(str::to_string),
+                Arc::new(SessionChannel {
+                    time_offset: Arc::downgrade(&self.time_offset),
+                    latency: Arc::downgrade(&self.latency),
+                    jitter: Arc::downgrade(&self.jitter),
+                    message_tx: self.message_tx.clone().downgrade(),
+                }),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::MaybeTlsStream;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        testing::{StreamClient, TestServer},
+        utils::timestamp,
+    };
+
+    fn channel(message_tx: &mpsc::Sender<SessionMsg>) -> SessionChannel {
+        SessionChannel {
+            time_offset: Weak::new(),
+            latency: Weak::new(),
+            jitter: Weak::new(),
+            message_tx: message_tx.downgrade(),
         }
     }
+
+    fn sync() -> SessionMsg {
+        SessionMsg::PlaybackSync(PlaybackState {
+            timestamp: 1_700_000_000_000,
+            playing: true,
+            time: 0.0,
+            duration: None,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_drop_syncs_instead_of_waiting_for_a_session_that_is_behind() {
+        // given
+        let (message_tx, mut message_rx) = mpsc::channel(1);
+        let channel = channel(&message_tx);
+        channel
+            .send_message(SessionMsg::RoomApproved)
+            .await
+            .unwrap();
+        let started_at = time::Instant::now();
+
+        // when
+        let sync = channel.send_message(sync()).await;
+        let approval = channel.send_message(SessionMsg::RoomApproved).await;
+
+        // then
+        assert_eq!(started_at.elapsed(), Duration::ZERO);
+        assert!(sync.unwrap());
+        assert!(approval.is_err());
+        assert!(matches!(
+            message_rx.try_recv(),
+            Ok(SessionMsg::RoomApproved)
+        ));
+        assert!(message_rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_wait_for_the_session_to_catch_up_when_delivering() {
+        // given
+        let (message_tx, mut message_rx) = mpsc::channel(1);
+        let channel = channel(&message_tx);
+        channel
+            .send_message(SessionMsg::RoomApproved)
+            .await
+            .unwrap();
+        let receiver = tokio::spawn(async move {
+            time::sleep(Duration::from_secs(1)).await;
+            message_rx.recv().await;
+            message_rx
+        });
+        let started_at = time::Instant::now();
+
+        // when
+        let delivered = channel.deliver_message(sync()).await;
+
+        // then
+        assert!(delivered.unwrap());
+        assert!(started_at.elapsed() >= Duration::from_secs(1));
+        assert!(matches!(
+            receiver.await.unwrap().try_recv(),
+            Ok(SessionMsg::PlaybackSync(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_tell_when_the_session_is_gone() {
+        // given
+        let (message_tx, message_rx) = mpsc::channel(1);
+        let channel = channel(&message_tx);
+        drop(message_rx);
+
+        // when
+        let sent = channel.send_message(SessionMsg::RoomApproved).await;
+        let delivered = channel.deliver_message(SessionMsg::RoomApproved).await;
+
+        // then
+        assert!(!sent.unwrap());
+        assert!(!delivered.unwrap());
+    }
+
+    /// A logged in client of a session with the given keepalive config, which only answers pings
+    /// when the test does so itself.
+    async fn keepalive_client(keepalive: KeepaliveConfig) -> StreamClient {
+        let mut config = Config::default();
+        config.sessions.keepalive = keepalive;
+        let server_config = config.server.clone();
+        let services = SessionServices::from_config(config).await.unwrap();
+        let mut client = StreamClient::over(services.connect_in_process(&server_config)).await;
+        client.login("alice").await;
+        client
+    }
+
+    #[tokio::test]
+    async fn should_tell_clients_about_restarts_and_keep_their_rooms() {
+        // given
+        let config = Config::default();
+        let server_config = config.server.clone();
+        let services = SessionServices::from_config(config).await.unwrap();
+        let mut client = StreamClient::over(services.connect_in_process(&server_config)).await;
+        client.login("alice").await;
+        client.create_room("Movie night").await;
+
+        // when
+        services.load_shedder.shut_down();
+        let closed = client
+            .expect(|body| match body {
+                MessageBody::ConnectionClosedV1(closed) => Some(closed),
+                _ => None,
+            })
+            .await;
+        time::timeout(Duration::from_secs(5), async {
+            while services.load_shedder.session_count() != 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // then
+        assert_eq!(closed.reason, dto::ConnectionClosedReasonV1::Restart);
+        let rooms = services.room_mgr.list_all_rooms().await;
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].users, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_close_connections_that_miss_too_many_pings() {
+        // given
+        let mut client = keepalive_client(KeepaliveConfig {
+            max_missed_pings: 3,
+            ..KeepaliveConfig::default()
+        })
+        .await;
+
+        // when
+        let mut pings = 0;
+        let closed = time::timeout(Duration::from_secs(600), async {
+            loop {
+                match client.recv().await.map(|msg| msg.body) {
+                    Some(MessageBody::ConnectionPingV1) => pings += 1,
+                    Some(MessageBody::ConnectionClosedV1(closed)) => return Some(closed),
+                    Some(_) => (),
+                    None => return None,
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        // then
+        assert_eq!(pings, 3);
+        assert_eq!(
+            closed.unwrap().reason,
+            dto::ConnectionClosedReasonV1::Timeout
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_keep_connections_that_answer_pings_open() {
+        // given
+        let mut client = keepalive_client(KeepaliveConfig {
+            max_missed_pings: 3,
+            ..KeepaliveConfig::default()
+        })
+        .await;
+
+        // when
+        let mut pings = 0;
+        let result = time::timeout(Duration::from_secs(600), async {
+            loop {
+                match client.recv().await.map(|msg| msg.body) {
+                    Some(MessageBody::ConnectionPingV1) => {
+                        pings += 1;
+                        client.send(MessageBody::ConnectionPongV1).await;
+                    }
+                    Some(MessageBody::ConnectionClosedV1(closed)) => return Some(closed),
+                    Some(_) => (),
+                    None => return None,
+                }
+            }
+        })
+        .await;
+
+        // then
+        assert!(result.is_err(), "The connection ended: {result:?}");
+        assert!(pings > 3);
+    }
+
+    /// Answers every ping for the given time, and counts the pings and the stats that arrive.
+    async fn answer_pings(client: &mut StreamClient, duration: Duration) -> (u32, u32) {
+        let (mut pings, mut stats) = (0, 0);
+        let _ = time::timeout(duration, async {
+            while let Some(msg) = client.recv().await {
+                match msg.body {
+                    MessageBody::ConnectionPingV1 => {
+                        pings += 1;
+                        client.send(MessageBody::ConnectionPongV1).await;
+                    }
+                    MessageBody::ConnectionStatsV1(..) => stats += 1,
+                    _ => (),
+                }
+            }
+        })
+        .await;
+        (pings, stats)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_send_stats_once_from_the_same_numbers_as_my_stats() {
+        // given
+        let mut client = keepalive_client(KeepaliveConfig::default()).await;
+        answer_pings(&mut client, Duration::from_secs(12)).await;
+
+        // when
+        client
+            .send(MessageBody::ConnectionRequestStatsV1(
+                dto::ConnectionRequestStatsMsgBodyV1 { periodic: false },
+            ))
+            .await;
+        let stats = client
+            .expect(|body| match body {
+                MessageBody::ConnectionStatsV1(stats) => Some(stats),
+                _ => None,
+            })
+            .await;
+        client.send(MessageBody::ConnectionMyStatsV1).await;
+        let my_stats = client
+            .expect(|body| match body {
+                MessageBody::ConnectionMyStatsAckV1(stats) => Some(stats),
+                _ => None,
+            })
+            .await;
+        let (pings, later_stats) = answer_pings(&mut client, Duration::from_secs(62)).await;
+
+        // then
+        assert_eq!(Some(stats.latency), my_stats.latency);
+        assert_eq!(Some(stats.sync_quality), my_stats.sync_quality);
+        assert_eq!(stats.time_offset, my_stats.time_offset);
+        assert!(pings > 0);
+        assert_eq!(later_stats, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_send_stats_after_every_ping_when_asked_to() {
+        // given
+        let mut client = keepalive_client(KeepaliveConfig::default()).await;
+
+        // when
+        client
+            .send(MessageBody::ConnectionRequestStatsV1(
+                dto::ConnectionRequestStatsMsgBodyV1 { periodic: true },
+            ))
+            .await;
+        client
+            .expect(|body| matches!(body, MessageBody::ConnectionStatsV1(..)).then_some(()))
+            .await;
+        let (pings, stats) = answer_pings(&mut client, Duration::from_secs(62)).await;
+
+        // then
+        assert!(pings > 0);
+        assert_eq!(stats, pings);
+    }
+
+    #[test]
+    fn should_ping_silent_clients_after_silent_interval() {
+        // given
+        let config = KeepaliveConfig::default();
+        let last_ping_at = Instant::now();
+
+        // when
+        let next_ping_at =
+            config.next_ping_at(last_ping_at, last_ping_at - Duration::from_secs(60));
+
+        // then
+        assert_eq!(next_ping_at, last_ping_at + Duration::from_secs(5));
+    }
+
+    #[test]
+    fn should_postpone_pings_for_busy_clients() {
+        // given
+        let config = KeepaliveConfig::default();
+        let last_ping_at = Instant::now();
+
+        // when
+        let after_message =
+            config.next_ping_at(last_ping_at, last_ping_at + Duration::from_secs(4));
+        let much_later = config.next_ping_at(last_ping_at, last_ping_at + Duration::from_secs(28));
+
+        // then
+        assert_eq!(after_message, last_ping_at + Duration::from_secs(9));
+        assert_eq!(much_later, last_ping_at + Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn should_let_clients_meet_in_a_room_over_the_network() {
+        // given
+        let server = TestServer::start().await;
+        let mut alice = server.connect().await;
+        let mut bob = server.connect().await;
+        alice.login("alice").await;
+        bob.login("bob").await;
+
+        // when
+        let created = alice.create_room("Movie night").await;
+        let joined = bob.join_room(created.id).await;
+
+        // then
+        assert_eq!(joined.id, created.id);
+        assert_eq!(joined.name, "Movie night");
+        let mut names: Vec<&str> = joined.users.iter().map(|user| &*user.name).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["alice", "bob"]);
+    }
+
+    #[tokio::test]
+    async fn should_list_rooms_that_only_run_a_stopwatch_as_active() {
+        // given
+        let server = TestServer::start().await;
+        let mut host = server.connect().await;
+        let mut browser = server.connect().await;
+        host.login("alice").await;
+        browser.login("bob").await;
+        host.send(MessageBody::RoomCreateV1(dto::RoomCreateMsgBodyV1 {
+            name: "Movie night".to_string(),
+            password: None,
+            public: true,
+            host_succession: Vec::new(),
+            default_role: None,
+            topic: None,
+            tags: Vec::new(),
+            image_url: None,
+            utc_offset: None,
+            vanity_id: None,
+            chat: None,
+            ready_quorum: None,
+        }))
+        .await;
+        host.expect(|body| matches!(body, MessageBody::RoomCreateAckV1).then_some(()))
+            .await;
+        host.send(MessageBody::PlaybackRequestHostV1).await;
+        host.expect(|body| matches!(body, MessageBody::PlaybackHosting).then_some(()))
+            .await;
+        host.send(MessageBody::PlaybackRequestStartV2(
+            dto::PlaybackStartMsgBodyV2 { source: None },
+        ))
+        .await;
+        host.expect(|body| matches!(body, MessageBody::PlaybackStartedV1).then_some(()))
+            .await;
+        browser.send(MessageBody::ConnectionRequestProbeV1).await;
+        browser
+            .expect(|body| matches!(body, MessageBody::ConnectionProbeV1(..)).then_some(()))
+            .await;
+
+        // when
+        browser.send(MessageBody::RoomListV1).await;
+        let listing = browser
+            .expect(|body| match body {
+                MessageBody::RoomListAckV1(ack) => Some(ack),
+                _ => None,
+            })
+            .await;
+
+        // then
+        assert_eq!(listing.rooms.len(), 1);
+        assert!(listing.rooms[0].playback_active);
+        assert_eq!(listing.sync_quality, Some(dto::SyncQualityV1::Good));
+    }
+
+    #[tokio::test]
+    async fn should_let_clients_peek_at_rooms_with_their_sync_quality() {
+        // given
+        let server = TestServer::start().await;
+        let mut host = server.connect().await;
+        let mut visitor = server.connect().await;
+        host.login("alice").await;
+        visitor.login("bob").await;
+        let room = host.create_room("Movie night").await;
+        visitor.send(MessageBody::ConnectionRequestProbeV1).await;
+        visitor
+            .expect(|body| matches!(body, MessageBody::ConnectionProbeV1(..)).then_some(()))
+            .await;
+
+        // when
+        visitor
+            .send(MessageBody::RoomPeekV1(dto::RoomPeekMsgBodyV1 {
+                id: dto::RoomRefV1::Id(room.id),
+            }))
+            .await;
+        let peek = visitor
+            .expect(|body| match body {
+                MessageBody::RoomPeekAckV1(ack) => Some(ack),
+                _ => None,
+            })
+            .await;
+
+        // then
+        assert_eq!(peek.room.name, "Movie night");
+        assert_eq!(peek.room.users, 1);
+        assert!(!peek.room.playback_active);
+        assert_eq!(peek.sync_quality, Some(dto::SyncQualityV1::Good));
+    }
+
+    #[tokio::test]
+    async fn should_only_open_rooms_when_asked_to_explicitly() {
+        // given
+        let server = TestServer::start().await;
+        let mut host = server.connect().await;
+        host.login("alice").await;
+        host.create_room("Movie night").await;
+        let set_password = |password: Option<&str>, open| {
+            Message::new(MessageBody::RoomSetPasswordV1(
+                dto::RoomSetPasswordMsgBodyV1 {
+                    password: password.map(str::to_string),
+                    open,
+                },
+            ))
+        };
+
+        // when
+        host.send(MessageBody::BatchV1(dto::BatchMsgBodyV1 {
+            messages: vec![
+                set_password(Some("hunter2"), false),
+                set_password(None, false),
+                set_password(Some("hunter2"), true),
+                set_password(None, true),
+            ],
+        }))
+        .await;
+        let ack = host
+            .expect(|body| match body {
+                MessageBody::BatchAckV1(ack) => Some(ack),
+                _ => None,
+            })
+            .await;
+
+        // then
+        let errors: Vec<_> = ack
+            .results
+            .into_iter()
+            .map(|result| result.error.map(|error| error.code))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                None,
+                Some(dto::ClientErrorCodeV1::InvalidRequest),
+                Some(dto::ClientErrorCodeV1::InvalidRequest),
+                None
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_acknowledge_the_name_the_room_gave() {
+        // given
+        let server = TestServer::start().await;
+        let mut host = server.connect().await;
+        let mut guest = server.connect().await;
+        host.login("alice").await;
+        guest.login("bob").await;
+        let room = host.create_room("Movie night").await;
+        guest.join_room(room.id).await;
+
+        // when
+        guest
+            .send(MessageBody::ConnectionRenameV1(
+                dto::ConnectionRenameMsgBodyV1 {
+                    name: "alice".to_string(),
+                },
+            ))
+            .await;
+        let ack = guest
+            .expect(|body| match body {
+                MessageBody::ConnectionRenameAckV1(ack) => Some(ack),
+                _ => None,
+            })
+            .await;
+        let own_room = guest.create_room("Bob's room").await;
+
+        // then
+        assert_eq!(ack.name, "alice (2)");
+        assert_eq!(own_room.name, "Bob's room");
+        assert_eq!(own_room.users[0].name, "alice (2)");
+    }
+
+    #[tokio::test]
+    async fn should_relay_playback_syncs_over_the_network() {
+        // given
+        let server = TestServer::start().await;
+        let mut host = server.connect().await;
+        let mut guest = server.connect().await;
+        host.login("alice").await;
+        guest.login("bob").await;
+        let room = host.create_room("Movie night").await;
+        guest.join_room(room.id).await;
+        host.send(MessageBody::PlaybackRequestHostV1).await;
+        host.expect(|body| matches!(body, MessageBody::PlaybackHosting).then_some(()))
+            .await;
+        host.send(MessageBody::PlaybackRequestStartV2(
+            dto::PlaybackStartMsgBodyV2 { source: None },
+        ))
+        .await;
+        host.expect(|body| matches!(body, MessageBody::PlaybackStartedV1).then_some(()))
+            .await;
+        guest.send(MessageBody::PlaybackRequestConnectV1).await;
+        guest
+            .expect(|body| matches!(body, MessageBody::PlaybackConnectedV1).then_some(()))
+            .await;
+
+        // when
+        host.send(MessageBody::PlaybackSyncV1(dto::PlaybackSyncMsgBodyV1 {
+            state: dto::PlaybackStateV1 {
+                timestamp: timestamp(),
+                playing: true,
+                time: 42.0,
+                duration: Some(5400.0),
+            },
+        }))
+        .await;
+
+        // then
+        let state = guest.expect_sync().await;
+        assert!(state.playing);
+        assert!((state.time - 42.0).abs() < 1.0);
+        assert_eq!(state.duration, Some(5400.0));
+    }
+
+    #[tokio::test]
+    async fn should_send_delta_syncs_to_clients_that_ask_for_them() {
+        // given
+        let server = TestServer::start().await;
+        let mut host = server.connect().await;
+        let mut guest = server.connect().await;
+        host.login("alice").await;
+        guest.login("bob").await;
+        let room = host.create_room("Movie night").await;
+        guest.join_room(room.id).await;
+        host.send(MessageBody::PlaybackRequestHostV1).await;
+        host.expect(|body| matches!(body, MessageBody::PlaybackHosting).then_some(()))
+            .await;
+        host.send(MessageBody::PlaybackRequestStartV2(
+            dto::PlaybackStartMsgBodyV2 { source: None },
+        ))
+        .await;
+        host.expect(|body| matches!(body, MessageBody::PlaybackStartedV1).then_some(()))
+            .await;
+        guest
+            .send(MessageBody::PlaybackRequestDeltaSyncV1(
+                dto::PlaybackRequestDeltaSyncMsgBodyV1 { enabled: true },
+            ))
+            .await;
+        guest.send(MessageBody::PlaybackRequestConnectV1).await;
+        guest
+            .expect(|body| matches!(body, MessageBody::PlaybackConnectedV1).then_some(()))
+            .await;
+
+        // when
+        host.send(MessageBody::PlaybackSyncV1(dto::PlaybackSyncMsgBodyV1 {
+            state: dto::PlaybackStateV1 {
+                timestamp: timestamp(),
+                playing: true,
+                time: 42.0,
+                duration: Some(5400.0),
+            },
+        }))
+        .await;
+
+        // then
+        let delta = guest
+            .expect(|body| match body {
+                MessageBody::PlaybackSyncDeltaV1(delta) => Some(delta),
+                _ => None,
+            })
+            .await;
+        assert_eq!(delta.base, None);
+        assert_eq!(delta.delta[0] & 0x01, 0x01);
+    }
+
+    #[tokio::test]
+    async fn should_only_accept_client_messages_in_batches() {
+        // given
+        let server = TestServer::start().await;
+        let mut client = server.connect().await;
+        let login = Message::new(MessageBody::ConnectionLoginV1(
+            dto::ConnectionLoginMsgBodyV1 {
+                username: "alice".to_string(),
+                api_key: None,
+                token: None,
+                compression: Vec::new(),
+                protocol_versions: Vec::new(),
+                format: None,
+                fingerprint: None,
+            },
+        ));
+        let batch = |messages| MessageBody::BatchV1(dto::BatchMsgBodyV1 { messages });
+        let errors = |ack: dto::BatchAckMsgBodyV1| {
+            ack.results
+                .into_iter()
+                .map(|result| result.error.map(|error| error.code))
+                .collect::<Vec<_>>()
+        };
+
+        // when
+        client
+            .send(batch(vec![
+                login.clone(),
+                Message::new(MessageBody::RoomListV1),
+            ]))
+            .await;
+        let first = client
+            .expect(|body| match body {
+                MessageBody::BatchAckV1(ack) => Some(ack),
+                _ => None,
+            })
+            .await;
+        client
+            .send(batch(vec![
+                login,
+                Message::new(MessageBody::PlaybackHosting),
+                Message::new(MessageBody::RoomListV1),
+            ]))
+            .await;
+        let second = client
+            .expect(|body| match body {
+                MessageBody::BatchAckV1(ack) => Some(ack),
+                _ => None,
+            })
+            .await;
+
+        // then
+        assert_eq!(errors(first), vec![None, None]);
+        assert_eq!(
+            errors(second),
+            vec![
+                Some(dto::ClientErrorCodeV1::InvalidRequest),
+                Some(dto::ClientErrorCodeV1::InvalidRequest),
+                None
+            ]
+        );
+    }
+
+    async fn resume(server: &TestServer, token: String) -> StreamClient<MaybeTlsStream<TcpStream>> {
+        let mut client = server.connect().await;
+        client
+            .send(MessageBody::ConnectionResumeV1(
+                dto::ConnectionResumeMsgBodyV1 { token },
+            ))
+            .await;
+        client
+            .expect(|body| matches!(body, MessageBody::ConnectionResumeAckV1(..)).then_some(()))
+            .await;
+        client
+    }
+
+    #[tokio::test]
+    async fn should_catch_resumed_sessions_up_on_what_they_missed() {
+        // given
+        let server = TestServer::start().await;
+        let mut alice = server.connect().await;
+        let mut bob = server.connect().await;
+        alice.login("alice").await;
+        let token = bob.login("bob").await.resume_token.unwrap();
+        let room = alice.create_room("Movie night").await;
+        bob.join_room(room.id).await;
+
+        // when
+        drop(bob);
+        alice
+            .send(MessageBody::RoomChatV1(dto::RoomChatMsgBodyV1 {
+                text: "Popcorn is ready".to_string(),
+            }))
+            .await;
+        alice
+            .expect(|body| matches!(body, MessageBody::RoomChatMessageV1(..)).then_some(()))
+            .await;
+        let mut bob = resume(&server, token).await;
+
+        // then
+        let chat = bob
+            .expect(|body| match body {
+                MessageBody::RoomChatMessageV1(chat) => Some(chat),
+                _ => None,
+            })
+            .await;
+        assert_eq!(chat.text, "Popcorn is ready");
+        let state = bob
+            .expect(|body| match body {
+                MessageBody::RoomStateV1(state) => Some(state),
+                _ => None,
+            })
+            .await;
+        assert_eq!(state.id, room.id);
+        assert_eq!(state.users.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_let_someone_else_host_while_the_host_is_away() {
+        // given
+        let server = TestServer::start().await;
+        let mut alice = server.connect().await;
+        let mut bob = server.connect().await;
+        let token = alice.login("alice").await.resume_token.unwrap();
+        bob.login("bob").await;
+        let room = alice.create_room("Movie night").await;
+        bob.join_room(room.id).await;
+
+        // when
+        drop(alice);
+        let new_host = bob
+            .expect(|body| match body {
+                MessageBody::RoomHostChangedV1(host) => Some(host.name),
+                _ => None,
+            })
+            .await;
+        let mut alice = resume(&server, token).await;
+
+        // then
+        assert_eq!(new_host, "bob");
+        let state = alice
+            .expect(|body| match body {
+                MessageBody::RoomStateV1(state) => Some(state),
+                _ => None,
+            })
+            .await;
+        let roles: Vec<(&str, dto::RoomUserRoleV1)> = state
+            .users
+            .iter()
+            .map(|user| (&*user.name, user.role.clone()))
+            .collect();
+        assert!(roles.contains(&("alice", dto::RoomUserRoleV1::Guest)));
+        assert!(roles.contains(&("bob", dto::RoomUserRoleV1::Host)));
+    }
+
+    #[tokio::test]
+    async fn should_leave_the_room_once_a_suspended_session_expires() {
+        // given
+        let mut config = Config::default();
+        config.sessions.resume.grace_period = 0;
+        let server = TestServer::start_with_config(config).await;
+        let mut alice = server.connect().await;
+        let mut bob = server.connect().await;
+        alice.login("alice").await;
+        let token = bob.login("bob").await.resume_token.unwrap();
+        let room = alice.create_room("Movie night").await;
+        bob.join_room(room.id).await;
+
+        // when
+        drop(bob);
+        let left = alice
+            .expect(|body| match body {
+                MessageBody::RoomUserLeftV1(left) => Some(left.name),
+                _ => None,
+            })
+            .await;
+        let mut bob = server.connect().await;
+        bob.send(MessageBody::ConnectionResumeV1(
+            dto::ConnectionResumeMsgBodyV1 { token },
+        ))
+        .await;
+
+        // then
+        assert_eq!(left, "bob");
+        let closed = bob
+            .expect(|body| match body {
+                MessageBody::ConnectionClosedV1(closed) => Some(closed.reason),
+                _ => None,
+            })
+            .await;
+        assert_eq!(closed, dto::ConnectionClosedReasonV1::Unauthorized);
+    }
 }
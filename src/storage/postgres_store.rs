@@ -0,0 +1,206 @@
+use anyhow::Context;
+use futures::future::BoxFuture;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, NoTls};
+
+use uuid::Uuid;
+
+use super::{PostgresConfig, RoomEventRecord, RoomSnapshot, Storage};
+
+/// Stores rooms in a Postgres table, as one JSON document per room. Room events and client
+/// fingerprints get tables of their own.
+pub struct PostgresStorage {
+    client: Mutex<Client>,
+}
+
+impl PostgresStorage {
+    pub async fn connect(config: PostgresConfig) -> anyhow::Result<Self> {
+        let (client, connection) = tokio_postgres::connect(&config.url, NoTls)
+            .await
+            .context("Failed to connect to Postgres")?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                log::error!("Postgres connection failed: {err:?}");
+            }
+        });
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS palantir_rooms (
+                    id TEXT PRIMARY KEY,
+                    snapshot TEXT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS palantir_room_events (
+                    id BIGSERIAL PRIMARY KEY,
+                    room_id TEXT NOT NULL,
+                    seq BIGINT NOT NULL,
+                    event TEXT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS palantir_fingerprints (
+                    username TEXT PRIMARY KEY,
+                    fingerprint TEXT NOT NULL
+                )",
+            )
+            .await
+            .context("Failed to create Postgres tables")?;
+        Ok(Self {
+            client: Mutex::new(client),
+        })
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn load_rooms(&self) -> BoxFuture<'_, anyhow::Result<Vec<RoomSnapshot>>> {
+        Box::pin(async move {
+            let rows = self
+                .client
+                .lock()
+                .await
+                .query("SELECT snapshot FROM palantir_rooms", &[])
+                .await
+                .context("Failed to load rooms from Postgres")?;
+            rows.iter()
+                .map(|row| {
+                    serde_json::from_str(row.get::<_, &str>(0))
+                        .context("Failed to parse stored room")
+                })
+                .collect()
+        })
+    }
+
+    fn save_rooms(&self, rooms: Vec<RoomSnapshot>) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut client = self.client.lock().await;
+            let transaction = client
+                .transaction()
+                .await
+                .context("Failed to start Postgres transaction")?;
+            transaction
+                .execute("DELETE FROM palantir_rooms", &[])
+                .await
+                .context("Failed to clear stored rooms")?;
+            for room in &rooms {
+                let snapshot = serde_json::to_string(room).context("Failed to serialize room")?;
+                transaction
+                    .execute(
+                        "INSERT INTO palantir_rooms (id, snapshot) VALUES ($1, $2)",
+                        &[&room.id.to_string(), &snapshot],
+                    )
+                    .await
+                    .context("Failed to store room")?;
+            }
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit stored rooms")?;
+            Ok(())
+        })
+    }
+
+    fn append_room_events(
+        &self,
+        room: Uuid,
+        events: Vec<RoomEventRecord>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut client = self.client.lock().await;
+            let transaction = client
+                .transaction()
+                .await
+                .context("Failed to start Postgres transaction")?;
+            for record in &events {
+                let event = serde_json::to_string(record).context("Failed to serialize event")?;
+                let seq = i64::try_from(record.seq).unwrap_or(i64::MAX);
+                transaction
+                    .execute(
+                        "INSERT INTO palantir_room_events (room_id, seq, event) VALUES ($1, $2, $3)",
+                        &[&room.to_string(), &seq, &event],
+                    )
+                    .await
+                    .context("Failed to store room event")?;
+            }
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit room events")?;
+            Ok(())
+        })
+    }
+
+    fn load_room_events(&self, room: Uuid) -> BoxFuture<'_, anyhow::Result<Vec<RoomEventRecord>>> {
+        Box::pin(async move {
+            let rows = self
+                .client
+                .lock()
+                .await
+                .query(
+                    "SELECT event FROM palantir_room_events WHERE room_id = $1 ORDER BY id",
+                    &[&room.to_string()],
+                )
+                .await
+                .context("Failed to load room events from Postgres")?;
+            rows.iter()
+                .map(|row| {
+                    serde_json::from_str(row.get::<_, &str>(0))
+                        .context("Failed to parse stored room event")
+                })
+                .collect()
+        })
+    }
+
+    fn trim_room_events(&self, room: Uuid, before: u64) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let before = i64::try_from(before).unwrap_or(i64::MAX);
+            self.client
+                .lock()
+                .await
+                .execute(
+                    "DELETE FROM palantir_room_events WHERE room_id = $1 AND seq < $2",
+                    &[&room.to_string(), &before],
+                )
+                .await
+                .context("Failed to trim room events")?;
+            Ok(())
+        })
+    }
+
+    fn delete_room_events(&self, room: Uuid) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.client
+                .lock()
+                .await
+                .execute(
+                    "DELETE FROM palantir_room_events WHERE room_id = $1",
+                    &[&room.to_string()],
+                )
+                .await
+                .context("Failed to delete room events")?;
+            Ok(())
+        })
+    }
+
+    fn trust_fingerprint(
+        &self,
+        username: String,
+        fingerprint: String,
+    ) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(async move {
+            let client = self.client.lock().await;
+            client
+                .execute(
+                    "INSERT INTO palantir_fingerprints (username, fingerprint) VALUES ($1, $2)
+                        ON CONFLICT (username) DO NOTHING",
+                    &[&username, &fingerprint],
+                )
+                .await
+                .context("Failed to store fingerprint")?;
+            let row = client
+                .query_one(
+                    "SELECT fingerprint FROM palantir_fingerprints WHERE username = $1",
+                    &[&username],
+                )
+                .await
+                .context("Failed to load fingerprint")?;
+            Ok(row.get::<_, &str>(0) == fingerprint)
+        })
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::Context;
+use futures::future::BoxFuture;
+use tokio::task;
+use uuid::Uuid;
+
+use super::{RoomEventRecord, RoomSnapshot, SledConfig, Storage};
+
+/// Stores rooms in an embedded sled database, one entry per room. Room events are keyed by their
+/// room followed by an id from sled, which keeps them in order even across restarts.
+pub struct SledStorage {
+    db: sled::Db,
+    rooms: sled::Tree,
+    room_events: sled::Tree,
+    fingerprints: sled::Tree,
+}
+
+impl SledStorage {
+    pub fn open(config: SledConfig) -> anyhow::Result<Self> {
+        let db = sled::open(&config.path).context(format!(
+            "Failed to open sled database at {}",
+            config.path.display()
+        ))?;
+        let rooms = db
+            .open_tree("rooms")
+            .context("Failed to open sled tree for rooms")?;
+        let room_events = db
+            .open_tree("room_events")
+            .context("Failed to open sled tree for room events")?;
+        let fingerprints = db
+            .open_tree("fingerprints")
+            .context("Failed to open sled tree for fingerprints")?;
+        Ok(Self {
+            db,
+            rooms,
+            room_events,
+            fingerprints,
+        })
+    }
+}
+
+impl Storage for SledStorage {
+    fn load_rooms(&self) -> BoxFuture<'_, anyhow::Result<Vec<RoomSnapshot>>> {
+        let rooms = self.rooms.clone();
+        Box::pin(async move {
+            task::spawn_blocking(move || {
+                rooms
+                    .iter()
+                    .values()
+                    .map(|value| {
+                        let value = value.context("Failed to read room from sled")?;
+                        serde_json::from_slice(&value).context("Failed to parse stored room")
+                    })
+                    .collect()
+            })
+            .await?
+        })
+    }
+
+    fn save_rooms(&self, snapshots: Vec<RoomSnapshot>) -> BoxFuture<'_, anyhow::Result<()>> {
+        let rooms = self.rooms.clone();
+        Box::pin(async move {
+            task::spawn_blocking(move || {
+                let mut batch = sled::Batch::default();
+                for key in rooms.iter().keys() {
+                    batch.remove(key.context("Failed to read room from sled")?);
+                }
+                for snapshot in &snapshots {
+                    let value = serde_json::to_vec(snapshot).context("Failed to serialize room")?;
+                    batch.insert(snapshot.id.as_bytes(), value);
+                }
+                rooms
+                    .apply_batch(batch)
+                    .context("Failed to save rooms to sled")?;
+                rooms.flush().context("Failed to flush sled database")?;
+                Ok(())
+            })
+            .await?
+        })
+    }
+
+    fn append_room_events(
+        &self,
+        room: Uuid,
+        events: Vec<RoomEventRecord>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let db = self.db.clone();
+        let room_events = self.room_events.clone();
+        Box::pin(async move {
+            task::spawn_blocking(move || {
+                let mut batch = sled::Batch::default();
+                for record in &events {
+                    let id = db.generate_id().context("Failed to generate sled id")?;
+                    let key = [room.as_bytes().as_slice(), &id.to_be_bytes()].concat();
+                    let value =
+                        serde_json::to_vec(record).context("Failed to serialize room event")?;
+                    batch.insert(key, value);
+                }
+                room_events
+                    .apply_batch(batch)
+                    .context("Failed to save room events to sled")?;
+                Ok(())
+            })
+            .await?
+        })
+    }
+
+    fn load_room_events(&self, room: Uuid) -> BoxFuture<'_, anyhow::Result<Vec<RoomEventRecord>>> {
+        let room_events = self.room_events.clone();
+        Box::pin(async move {
+            task::spawn_blocking(move || {
+                room_events
+                    .scan_prefix(room.as_bytes())
+                    .values()
+                    .map(|value| {
+                        let value = value.context("Failed to read room event from sled")?;
+                        serde_json::from_slice(&value).context("Failed to parse stored room event")
+                    })
+                    .collect()
+            })
+            .await?
+        })
+    }
+
+    fn trim_room_events(&self, room: Uuid, before: u64) -> BoxFuture<'_, anyhow::Result<()>> {
+        let room_events = self.room_events.clone();
+        Box::pin(async move {
+            task::spawn_blocking(move || {
+                let mut batch = sled::Batch::default();
+                for entry in room_events.scan_prefix(room.as_bytes()) {
+                    let (key, value) = entry.context("Failed to read room event from sled")?;
+                    let record: RoomEventRecord = serde_json::from_slice(&value)
+                        .context("Failed to parse stored room event")?;
+                    if record.seq < before {
+                        batch.remove(key);
+                    }
+                }
+                room_events
+                    .apply_batch(batch)
+                    .context("Failed to trim room events in sled")
+            })
+            .await?
+        })
+    }
+
+    fn delete_room_events(&self, room: Uuid) -> BoxFuture<'_, anyhow::Result<()>> {
+        let room_events = self.room_events.clone();
+        Box::pin(async move {
+            task::spawn_blocking(move || {
+                let mut batch = sled::Batch::default();
+                for key in room_events.scan_prefix(room.as_bytes()).keys() {
+                    batch.remove(key.context("Failed to read room event from sled")?);
+                }
+                room_events
+                    .apply_batch(batch)
+                    .context("Failed to delete room events from sled")
+            })
+            .await?
+        })
+    }
+
+    fn trust_fingerprint(
+        &self,
+        username: String,
+        fingerprint: String,
+    ) -> BoxFuture<'_, anyhow::Result<bool>> {
+        let fingerprints = self.fingerprints.clone();
+        Box::pin(async move {
+            task::spawn_blocking(move || {
+                let swapped = fingerprints
+                    .compare_and_swap(
+                        username.as_bytes(),
+                        None::<&[u8]>,
+                        Some(fingerprint.as_bytes()),
+                    )
+                    .context("Failed to save fingerprint to sled")?;
+                Ok(match swapped {
+                    Ok(()) => true,
+                    Err(err) => err.current.as_deref() == Some(fingerprint.as_bytes()),
+                })
+            })
+            .await?
+        })
+    }
+}
@@ -0,0 +1,428 @@
+//! In-memory stand-ins for clients and sessions, so that rooms, playback and connections can be
+//! tested without any WebSockets, and a whole server with real clients for tests that go through
+//! the entire protocol.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{future::BoxFuture, SinkExt, StreamExt};
+use parking_lot::Mutex;
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, DuplexStream},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+    time::timeout,
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        protocol::{CloseFrame, Role},
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+use uuid::Uuid;
+
+use crate::{
+    app::SessionServices,
+    config::Config,
+    connection::{ClientTransport, Connection, ConnectionListener, ServerConfig},
+    messages::{dto, Compression, Message, MessageBody, MIN_PROTOCOL_VERSION},
+    session::{SessionHandle, SessionId, SessionMsg, SessionSink},
+};
+
+/// A session that just collects the messages it receives.
+#[derive(Debug, Default)]
+pub struct FakeSession {
+    messages: Mutex<Vec<SessionMsg>>,
+    gone: AtomicBool,
+    time_offset: i64,
+    latency: u64,
+    jitter: AtomicU64,
+}
+
+impl FakeSession {
+    pub fn new(time_offset: i64) -> Arc<Self> {
+        Arc::new(Self {
+            time_offset,
+            ..Self::default()
+        })
+    }
+
+    pub fn with_latency(time_offset: i64, latency: u64) -> Arc<Self> {
+        Arc::new(Self {
+            time_offset,
+            latency,
+            ..Self::default()
+        })
+    }
+
+    pub fn handle(self: &Arc<Self>, id: u128, name: &str) -> SessionHandle {
+        SessionHandle::new(
+            SessionId::from(Uuid::from_u128(id)),
+            name.to_string(),
+            None,
+            Arc::clone(self) as Arc<dyn SessionSink>,
+        )
+    }
+
+    /// Takes all messages received so far, without the numbers of the broadcasts they were part
+    /// of.
+    pub fn take_messages(&self) -> Vec<SessionMsg> {
+        self.take_sequenced_messages()
+            .into_iter()
+            .map(SessionMsg::unsequenced)
+            .collect()
+    }
+
+    /// Takes all messages received so far, exactly as they were received.
+    pub fn take_sequenced_messages(&self) -> Vec<SessionMsg> {
+        std::mem::take(&mut self.messages.lock())
+    }
+
+    pub fn set_jitter(&self, jitter: u64) {
+        self.jitter.store(jitter, Ordering::Relaxed);
+    }
+
+    /// Makes the session behave like one whose client went away.
+    pub fn disconnect(&self) {
+        self.gone.store(true, Ordering::Relaxed);
+    }
+}
+
+impl SessionSink for FakeSession {
+    fn send_message(&self, msg: SessionMsg) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(async move {
+            if self.gone.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            self.messages.lock().push(msg);
+            Ok(true)
+        })
+    }
+
+    fn try_send_message(&self, msg: SessionMsg) -> Result<bool, Box<SessionMsg>> {
+        if self.gone.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        self.messages.lock().push(msg);
+        Ok(true)
+    }
+
+    fn time_offset(&self) -> i64 {
+        self.time_offset
+    }
+
+    fn latency(&self) -> u64 {
+        self.latency
+    }
+
+    fn jitter(&self) -> u64 {
+        self.jitter.load(Ordering::Relaxed)
+    }
+}
+
+/// A client transport whose other end is a [`FakeClient`].
+pub struct FakeTransport {
+    incoming: mpsc::UnboundedReceiver<Message>,
+    outgoing: mpsc::UnboundedSender<Message>,
+    compression: Option<Compression>,
+    format: Option<dto::MessageFormatV1>,
+    protocol_version: u32,
+    hung_up: bool,
+}
+
+/// The client side of a [`FakeTransport`].
+pub struct FakeClient {
+    pub incoming: mpsc::UnboundedReceiver<Message>,
+    pub outgoing: mpsc::UnboundedSender<Message>,
+}
+
+impl FakeTransport {
+    pub fn new() -> (Self, FakeClient) {
+        let (client_tx, server_rx) = mpsc::unbounded_channel();
+        let (server_tx, client_rx) = mpsc::unbounded_channel();
+        let transport = Self {
+            incoming: server_rx,
+            outgoing: server_tx,
+            compression: None,
+            format: None,
+            protocol_version: MIN_PROTOCOL_VERSION,
+            hung_up: false,
+        };
+        let client = FakeClient {
+            incoming: client_rx,
+            outgoing: client_tx,
+        };
+        (transport, client)
+    }
+}
+
+impl ClientTransport for FakeTransport {
+    fn send(&mut self, message: Message) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.outgoing.send(message)?;
+            Ok(())
+        })
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Option<anyhow::Result<Message>>> {
+        Box::pin(async move {
+            let message = self.incoming.recv().await;
+            // dropping the client hangs up
+            self.hung_up = message.is_none();
+            message.map(Ok)
+        })
+    }
+
+    fn close(&mut self, _frame: Option<CloseFrame<'static>>) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn send_ping(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn transport_latency(&self) -> Option<Duration> {
+        None
+    }
+
+    fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compression = compression;
+    }
+
+    fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    fn pin_format(&mut self, format: dto::MessageFormatV1) {
+        self.format = Some(format);
+    }
+
+    fn pinned_format(&self) -> Option<dto::MessageFormatV1> {
+        self.format
+    }
+
+    fn set_protocol_version(&mut self, version: u32) {
+        self.protocol_version = version;
+    }
+
+    fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    fn close_received(&self) -> bool {
+        self.hung_up
+    }
+}
+
+/// A client at the other end of a WebSocket, which speaks JSON messages just like a browser
+/// would. By default, it talks to a connection over an in-process stream.
+pub struct StreamClient<S = DuplexStream> {
+    ws: WebSocketStream<S>,
+}
+
+impl StreamClient {
+    const BUFFER_SIZE: usize = 64 * 1024;
+
+    /// Connects a new client to a connection on the server side, without any sockets.
+    pub async fn connect() -> (Connection, Self) {
+        Self::connect_with_config(&ServerConfig::default()).await
+    }
+
+    /// Like [`StreamClient::connect`], but with the server side configured as given.
+    pub async fn connect_with_config(config: &ServerConfig) -> (Connection, Self) {
+        let (server, client) = io::duplex(Self::BUFFER_SIZE);
+        let connection = Connection::over_stream("in-process".to_string(), server, config).await;
+        (connection, Self::over(client).await)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> StreamClient<S> {
+    /// How long [`StreamClient::expect`] waits before the test fails.
+    const EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A client for a stream that carries WebSocket frames without a handshake.
+    pub async fn over(stream: S) -> Self {
+        let ws = WebSocketStream::from_raw_socket(stream, Role::Client, None).await;
+        Self { ws }
+    }
+
+    pub async fn send(&mut self, body: MessageBody) {
+        let json = serde_json::to_string(&Message::new(body)).unwrap();
+        self.ws
+            .send(tungstenite::Message::text(json))
+            .await
+            .unwrap();
+    }
+
+    /// The next message from the server, or `None` once the server hung up.
+    pub async fn recv(&mut self) -> Option<Message> {
+        loop {
+            match self.ws.next().await?.ok()? {
+                tungstenite::Message::Text(json) => {
+                    return Some(serde_json::from_str(&json).unwrap())
+                }
+                // the server answers in msgpack until it has seen a message from the client
+                tungstenite::Message::Binary(data) => {
+                    return Some(rmp_serde::from_slice(&data).unwrap())
+                }
+                tungstenite::Message::Close(_) => return None,
+                _ => (),
+            }
+        }
+    }
+
+    /// Waits for the first message that `select` picks, skipping everything else the server
+    /// sends in the meantime and answering pings. Errors from the server fail the test right
+    /// away.
+    pub async fn expect<T>(&mut self, mut select: impl FnMut(MessageBody) -> Option<T>) -> T {
+        let expected = async {
+            loop {
+                let message = self.recv().await.expect("The server hung up");
+                match &message.body {
+                    // the session waits for the answer, like it would for a real client
+                    MessageBody::ConnectionPingV1 => {
+                        self.send(MessageBody::ConnectionPongV1).await;
+                        continue;
+                    }
+                    MessageBody::ConnectionClientErrorV1(error) => {
+                        panic!("The server reported an error: {error:?}")
+                    }
+                    _ => (),
+                }
+                if let Some(selected) = select(message.body) {
+                    return selected;
+                }
+            }
+        };
+        timeout(Self::EXPECT_TIMEOUT, expected)
+            .await
+            .expect("Timed out waiting for a message")
+    }
+
+    pub async fn login(&mut self, username: &str) -> dto::ConnectionLoginAckMsgBodyV1 {
+        self.send(MessageBody::ConnectionLoginV1(
+            dto::ConnectionLoginMsgBodyV1 {
+                username: username.to_string(),
+                api_key: None,
+                token: None,
+                compression: Vec::new(),
+                protocol_versions: Vec::new(),
+                format: None,
+                fingerprint: None,
+            },
+        ))
+        .await;
+        self.expect(|body| match body {
+            MessageBody::ConnectionLoginAckV1(ack) => Some(ack),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Opens a room that anyone can join, and returns its state.
+    pub async fn create_room(&mut self, name: &str) -> Arc<dto::RoomStateMsgBodyV1> {
+        self.send(MessageBody::RoomCreateV1(dto::RoomCreateMsgBodyV1 {
+            name: name.to_string(),
+            password: None,
+            public: false,
+            host_succession: Vec::new(),
+            default_role: None,
+            topic: None,
+            tags: Vec::new(),
+            image_url: None,
+            utc_offset: None,
+            vanity_id: None,
+            chat: None,
+            ready_quorum: None,
+        }))
+        .await;
+        self.expect(|body| matches!(body, MessageBody::RoomCreateAckV1).then_some(()))
+            .await;
+        self.room_state().await
+    }
+
+    pub async fn join_room(&mut self, id: dto::RoomIdV1) -> Arc<dto::RoomStateMsgBodyV1> {
+        self.send(MessageBody::RoomJoinV1(dto::RoomJoinMsgBodyV1 {
+            id: dto::RoomRefV1::Id(id),
+            password: None,
+            invite: None,
+            anonymous: false,
+        }))
+        .await;
+        self.expect(|body| matches!(body, MessageBody::RoomJoinAckV1).then_some(()))
+            .await;
+        self.room_state().await
+    }
+
+    async fn room_state(&mut self) -> Arc<dto::RoomStateMsgBodyV1> {
+        self.send(MessageBody::RoomRequestStateV1).await;
+        self.expect(|body| match body {
+            MessageBody::RoomStateV1(state) => Some(state),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Waits for the next playback state that the server relays.
+    pub async fn expect_sync(&mut self) -> dto::PlaybackStateV1 {
+        self.expect(|body| match body {
+            MessageBody::PlaybackSyncV1(sync) => Some(sync.state),
+            _ => None,
+        })
+        .await
+    }
+}
+
+/// A whole server that listens on an ephemeral port, for tests that go through the entire
+/// protocol, from the handshake through sessions and rooms down to playback.
+pub struct TestServer {
+    addr: SocketAddr,
+    listener: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_with_config(Config::default()).await
+    }
+
+    /// Starts a server with the given config, apart from the address it listens on.
+    pub async fn start_with_config(mut config: Config) -> Self {
+        config.server.listen_on = "127.0.0.1:0".to_string();
+        let server_config = config.server.clone();
+        let services = SessionServices::from_config(config).await.unwrap();
+        let listener = ConnectionListener::bind(server_config, None, Arc::default())
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = tokio::spawn(async move {
+            let result = listener
+                .listen(move |conn| services.clone().serve(conn))
+                .await;
+            if let Err(err) = result {
+                log::error!("Test server stopped: {err:?}");
+            }
+        });
+        Self { addr, listener }
+    }
+
+    pub async fn connect(&self) -> StreamClient<MaybeTlsStream<TcpStream>> {
+        let (ws, _) = tokio_tungstenite::connect_async(format!("ws://{}", self.addr))
+            .await
+            .unwrap();
+        StreamClient { ws }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
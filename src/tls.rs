@@ -0,0 +1,77 @@
+use std::{path::PathBuf, sync::Arc};
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+#[cfg(feature = "acme")]
+mod acme;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct AcmeConfig {
+    /// The domains that certificates are requested for.
+    pub domains: Vec<String>,
+
+    /// Email addresses the certificate authority can use to reach the operator.
+    pub contact: Vec<String>,
+
+    /// Where certificates and account keys are kept between restarts. Without a cache, a new
+    /// certificate is requested on every start, which quickly runs into Let's Encrypt's rate
+    /// limits.
+    pub cache_dir: Option<PathBuf>,
+
+    /// Use the Let's Encrypt staging environment, which is useful for trying out a setup.
+    pub staging: bool,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            domains: Vec::new(),
+            contact: Vec::new(),
+            cache_dir: Some(PathBuf::from("acme-cache")),
+            staging: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Serve the WebSocket server over TLS with certificates obtained via ACME. Only available if
+    /// the server was built with the `acme` feature.
+    pub acme: Option<AcmeConfig>,
+}
+
+/// A stream a client is connected through, which may or may not be encrypted.
+pub trait ClientStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ClientStream for T {}
+
+pub trait TlsAcceptor: Send + Sync {
+    /// Performs the TLS handshake on a new connection. Connections that only answer a
+    /// certificate challenge are handled completely by the acceptor, which returns `None`.
+    fn accept(
+        &self,
+        stream: TcpStream,
+    ) -> BoxFuture<'_, anyhow::Result<Option<Box<dyn ClientStream>>>>;
+}
+
+pub fn create_acceptor(config: TlsConfig) -> anyhow::Result<Option<Arc<dyn TlsAcceptor>>> {
+    let Some(acme_config) = config.acme else {
+        return Ok(None);
+    };
+    #[cfg(feature = "acme")]
+    {
+        Ok(Some(Arc::new(acme::AcmeAcceptor::start(acme_config)?)))
+    }
+    #[cfg(not(feature = "acme"))]
+    {
+        let _ = acme_config;
+        anyhow::bail!("This server was built without ACME support")
+    }
+}
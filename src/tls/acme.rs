@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use futures::{future::BoxFuture, StreamExt};
+use rustls_acme::{caches::DirCache, is_tls_alpn_challenge};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tokio_rustls::{rustls::ServerConfig, LazyConfigAcceptor};
+
+use super::{AcmeConfig, ClientStream, TlsAcceptor};
+
+/// Obtains and renews certificates in the background and answers TLS-ALPN-01 challenges on the
+/// regular listener, so no extra port has to be opened.
+pub struct AcmeAcceptor {
+    challenge_config: Arc<ServerConfig>,
+    default_config: Arc<ServerConfig>,
+}
+
+impl AcmeAcceptor {
+    pub fn start(config: AcmeConfig) -> anyhow::Result<Self> {
+        if config.domains.is_empty() {
+            return Err(anyhow!("ACME needs at least one domain"));
+        }
+        log::info!("Requesting certificates for {:?} via ACME", config.domains);
+        let mut state = rustls_acme::AcmeConfig::new(config.domains)
+            .contact(config.contact.iter().map(|email| format!("mailto:{email}")))
+            .cache_option(config.cache_dir.map(DirCache::new))
+            .directory_lets_encrypt(!config.staging)
+            .state();
+        let challenge_config = state.challenge_rustls_config();
+        let default_config = state.default_rustls_config();
+        tokio::spawn(async move {
+            while let Some(event) = state.next().await {
+                match event {
+                    Ok(event) => log::info!("ACME: {event:?}"),
+                    Err(err) => log::error!("ACME certificate management failed: {err:?}"),
+                }
+            }
+        });
+        Ok(Self {
+            challenge_config,
+            default_config,
+        })
+    }
+}
+
+impl TlsAcceptor for AcmeAcceptor {
+    fn accept(
+        &self,
+        stream: TcpStream,
+    ) -> BoxFuture<'_, anyhow::Result<Option<Box<dyn ClientStream>>>> {
+        Box::pin(async move {
+            let handshake = LazyConfigAcceptor::new(Default::default(), stream)
+                .await
+                .context("Failed to start TLS handshake")?;
+            if is_tls_alpn_challenge(&handshake.client_hello()) {
+                log::debug!("Answering ACME TLS-ALPN-01 challenge");
+                let mut tls = handshake
+                    .into_stream(Arc::clone(&self.challenge_config))
+                    .await
+                    .context("Failed to answer ACME challenge")?;
+                tls.shutdown().await?;
+                return Ok(None);
+            }
+            let tls = handshake
+                .into_stream(Arc::clone(&self.default_config))
+                .await
+                .context("TLS handshake failed")?;
+            Ok(Some(Box::new(tls) as Box<dyn ClientStream>))
+        })
+    }
+}
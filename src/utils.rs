@@ -1,4 +1,4 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub fn timestamp() -> u64 {
     let duration_since_epoch = SystemTime::now()
@@ -22,6 +22,10 @@ macro_rules! id_type {
             fn new() -> Self {
                 Self(::uuid::Uuid::new_v4())
             }
+
+            fn generate(ids: &dyn $crate::ids::IdGenerator) -> Self {
+                Self(ids.generate())
+            }
         }
 
         impl From<::uuid::Uuid> for $name {
@@ -45,3 +49,280 @@ macro_rules! id_type {
         }
     };
 }
+
+/// Formats a duration in the past for humans, e.g. "5 minutes ago".
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let (amount, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{amount} {unit}{plural} ago")
+}
+
+/// Formats a timestamp in milliseconds as an ISO 8601 time at the given offset from UTC in
+/// minutes, e.g. "2023-11-14T23:13:20+01:00".
+pub fn format_iso8601(timestamp: u64, utc_offset: i16) -> String {
+    let secs = (timestamp / 1000) as i64 + i64::from(utc_offset) * 60;
+    let (days, secs_of_day) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // days to a civil date in the proleptic Gregorian calendar, after Howard Hinnant
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    let offset = match utc_offset {
+        0 => "Z".to_string(),
+        offset => format!(
+            "{}{:02}:{:02}",
+            if offset < 0 { '-' } else { '+' },
+            offset.unsigned_abs() / 60,
+            offset.unsigned_abs() % 60
+        ),
+    };
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}{offset}",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Matches `text` against a glob pattern, where `*` matches any sequence of characters and `?`
+/// matches any single character.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => {
+                // let the last `*` consume one more character
+                let Some((star_p, star_t)) = backtrack else {
+                    return false;
+                };
+                backtrack = Some((star_p, star_t + 1));
+                p = star_p + 1;
+                t = star_t + 1;
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// A simple token bucket rate limiter.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        Self {
+            capacity: capacity.into(),
+            refill_per_sec,
+            tokens: capacity.into(),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Tries to take a token from the bucket, returning whether that was possible.
+    pub fn try_take(&mut self) -> bool {
+        self.try_take_at(Instant::now())
+    }
+
+    /// Tries to take several tokens at once, e.g. one for each byte of a message. Either all of
+    /// them are taken or none.
+    pub fn try_take_many(&mut self, count: u32) -> bool {
+        self.try_take_many_at(count, Instant::now())
+    }
+
+    fn try_take_at(&mut self, now: Instant) -> bool {
+        self.try_take_many_at(1, now)
+    }
+
+    fn try_take_many_at(&mut self, count: u32, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = f64::min(
+            self.capacity,
+            self.tokens + elapsed.as_secs_f64() * self.refill_per_sec,
+        );
+        self.last_refill = now;
+        if self.tokens < f64::from(count) {
+            return false;
+        }
+        self.tokens -= f64::from(count);
+        true
+    }
+
+    /// Whether the bucket has completely refilled, i.e. it no longer carries any state worth
+    /// keeping around.
+    pub fn is_full(&self) -> bool {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.tokens + elapsed * self.refill_per_sec >= self.capacity
+    }
+}
+
+/// Lets other tasks run every so often during a long loop, so that a task sending to many users
+/// doesn't hog its worker thread.
+#[derive(Debug, Default)]
+pub struct YieldPoint {
+    count: usize,
+}
+
+impl YieldPoint {
+    const EVERY: usize = 32;
+
+    pub async fn tick(&mut self) {
+        self.count += 1;
+        if self.count.is_multiple_of(Self::EVERY) {
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_format_elapsed_time() {
+        // given
+        let cases = [
+            (Duration::from_secs(12), "just now"),
+            (Duration::from_secs(60), "1 minute ago"),
+            (Duration::from_secs(5 * 60 + 30), "5 minutes ago"),
+            (Duration::from_secs(2 * 3600), "2 hours ago"),
+            (Duration::from_secs(3 * 86400), "3 days ago"),
+        ];
+
+        for (elapsed, expected) in cases {
+            // when
+            let formatted = format_elapsed(elapsed);
+
+            // then
+            assert_eq!(formatted, expected);
+        }
+    }
+
+    #[test]
+    fn should_format_iso8601_times() {
+        // given
+        let cases = [
+            (0, 0, "1970-01-01T00:00:00Z"),
+            (1_699_999_999_999, 0, "2023-11-14T22:13:19Z"),
+            (1_700_000_000_000, 60, "2023-11-14T23:13:20+01:00"),
+            (1_700_000_000_000, -330, "2023-11-14T16:43:20-05:30"),
+            (1_709_164_800_000, 0, "2024-02-29T00:00:00Z"),
+            (1_709_164_800_000, -1, "2024-02-28T23:59:00-00:01"),
+        ];
+
+        for (timestamp, utc_offset, expected) in cases {
+            // when
+            let formatted = format_iso8601(timestamp, utc_offset);
+
+            // then
+            assert_eq!(formatted, expected);
+        }
+    }
+
+    #[test]
+    fn should_match_glob_patterns() {
+        // given
+        let cases = [
+            ("community-a/*", "community-a/movie night", true),
+            ("community-a/*", "community-b/movie night", false),
+            ("*night", "movie night", true),
+            ("movie ?ight", "movie night", true),
+            ("movie ?ight", "movie  night", false),
+            ("a*b*c", "a-b-b-c", true),
+            ("exact", "exact", true),
+            ("exact", "exactly", false),
+        ];
+
+        for (pattern, text, expected) in cases {
+            // when
+            let matches = glob_matches(pattern, text);
+
+            // then
+            assert_eq!(matches, expected, "'{pattern}' against '{text}'");
+        }
+    }
+
+    #[test]
+    fn should_limit_token_bucket_to_capacity() {
+        // given
+        let mut bucket = TokenBucket::new(2, 1.0);
+        let now = Instant::now();
+
+        // when
+        let results = [
+            bucket.try_take_at(now),
+            bucket.try_take_at(now),
+            bucket.try_take_at(now),
+        ];
+
+        // then
+        assert_eq!(results, [true, true, false]);
+    }
+
+    #[test]
+    fn should_refill_token_bucket_over_time() {
+        // given
+        let mut bucket = TokenBucket::new(1, 2.0);
+        let now = Instant::now();
+        bucket.try_take_at(now);
+
+        // when
+        let too_early = bucket.try_take_at(now + Duration::from_millis(100));
+        let refilled = bucket.try_take_at(now + Duration::from_millis(600));
+
+        // then
+        assert!(!too_early);
+        assert!(refilled);
+    }
+
+    #[test]
+    fn should_take_many_tokens_at_once() {
+        // given
+        let mut bucket = TokenBucket::new(10, 1.0);
+        let now = Instant::now();
+
+        // when
+        let results = [
+            bucket.try_take_many_at(8, now),
+            bucket.try_take_many_at(3, now),
+            bucket.try_take_many_at(2, now),
+        ];
+
+        // then
+        assert_eq!(results, [true, false, true]);
+    }
+}